@@ -0,0 +1,13 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Generate the gRPC service from proto/agent.proto when the grpc feature is enabled
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/agent.proto");
+        // Use the vendored protoc so no system install is needed
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("No vendored protoc");
+        std::env::set_var("PROTOC", protoc);
+        tonic_build::compile_protos("proto/agent.proto").expect("Failed to compile agent.proto");
+    }
+}
@@ -0,0 +1,1957 @@
+//! The agent itself: a provider answering the messages of its inputs on its outputs,
+//! with the config's tools, conversation history and commands, for embedding in
+//! other programs as well as running from the command line
+
+use anyhow::{Context, Result};
+use serde_json::json;
+use tokio::sync::broadcast;
+
+use crate::config::{self, Config};
+use crate::tools::{build_tools, evaluate_expression};
+use crate::{
+    budget, commands, credentials, daemon, export, guardrails, health, history, injection, io,
+    memory, moderation, prompt, providers, queue, redact, session, tools,
+};
+use providers::Tool;
+
+// What the outputs are told while a reply is on its way, unless `processing_message`
+// says otherwise
+const DEFAULT_PROCESSING_MESSAGE: &str = "Processing your request...";
+
+/// What the agent answers `exit` with before it stops
+pub const GOODBYE: &str = "Goodbye!";
+
+/// A configured agent, ready to run
+///
+/// ```no_run
+/// # async fn example(input: impl agent::io::InputSource + 'static) -> anyhow::Result<()> {
+/// let config = agent::config::Config::load("config.yaml", None)?;
+/// agent::Agent::builder()
+///     .config(config)
+///     .input(input)
+///     .run()
+///     .await
+/// # }
+/// ```
+pub struct Agent {
+    config: Config,
+    config_path: Option<String>,
+    provider: Box<dyn providers::Provider>,
+    inputs: Vec<Box<dyn io::InputSource>>,
+    outputs: Vec<Box<dyn io::OutputDestination>>,
+    shutdown: broadcast::Sender<()>,
+    admin: bool,
+}
+
+/// Builds an [`Agent`] from a config, with a provider, inputs and outputs of the
+/// embedding program's own if it has them
+pub struct AgentBuilder {
+    config: Option<Config>,
+    config_path: Option<String>,
+    provider: Option<Box<dyn providers::Provider>>,
+    inputs: Vec<Box<dyn io::InputSource>>,
+    outputs: Vec<Box<dyn io::OutputDestination>>,
+    shutdown: Option<broadcast::Sender<()>>,
+    admin: bool,
+}
+
+impl Default for AgentBuilder {
+    fn default() -> Self {
+        AgentBuilder {
+            config: None,
+            config_path: None,
+            provider: None,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            shutdown: None,
+            admin: true,
+        }
+    }
+}
+
+impl AgentBuilder {
+    /// The settings to run with (default: the config file if one is given, otherwise
+    /// an empty config)
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// The config file to load, unless a config is given, and to re-read on reloads.
+    /// Without one, reloading is an error.
+    pub fn config_file(mut self, path: impl Into<String>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    /// Answer with this provider instead of the config's. Reloading the config, or
+    /// switching to a model alias of another provider, replaces it with the config's.
+    pub fn provider(mut self, provider: impl providers::Provider + 'static) -> Self {
+        self.provider = Some(Box::new(provider));
+        self
+    }
+
+    /// Read messages from this input as well as the config's
+    pub fn input(mut self, input: impl io::InputSource + 'static) -> Self {
+        self.inputs.push(Box::new(input));
+        self
+    }
+
+    /// Send messages to this output as well as the config's; it stays through reloads
+    pub fn output(mut self, output: impl io::OutputDestination + 'static) -> Self {
+        self.outputs.push(Box::new(output));
+        self
+    }
+
+    /// Stop when a message is sent on this channel (default: a channel of the agent's
+    /// own, see [`Agent::shutdown_sender`])
+    pub fn shutdown(mut self, sender: broadcast::Sender<()>) -> Self {
+        self.shutdown = Some(sender);
+        self
+    }
+
+    /// Whether this agent answers the webhook server's admin API (default: true), at
+    /// `/admin/<name>/...` under its `agents` entry's name, or else its `agent_name`
+    pub fn admin(mut self, admin: bool) -> Self {
+        self.admin = admin;
+        self
+    }
+
+    /// Load and check the config and create the provider
+    pub fn build(self) -> Result<Agent> {
+        let config = match (self.config, &self.config_path) {
+            (Some(config), _) => config,
+            (None, Some(path)) => Config::load(path, None)?,
+            (None, None) => Config::default(),
+        };
+        config.validate().context("Invalid configuration")?;
+
+        let provider = match self.provider {
+            Some(provider) => provider,
+            None => {
+                let api_key = credentials::load_api_key(&config)?;
+                providers::get_provider(
+                    &config.provider,
+                    &api_key,
+                    &config.provider_settings(&config.provider),
+                )?
+            }
+        };
+
+        Ok(Agent {
+            config,
+            config_path: self.config_path,
+            provider,
+            inputs: self.inputs,
+            outputs: self.outputs,
+            shutdown: self.shutdown.unwrap_or_else(|| broadcast::channel(1).0),
+            admin: self.admin,
+        })
+    }
+
+    /// Build the agent and run it until shutdown
+    pub async fn run(self) -> Result<()> {
+        self.build()?.run().await
+    }
+}
+
+// Trim the history to fit the model's context window, less the tokens kept for the
+// response and the tool definitions, by dropping the oldest messages after the
+// system message. The latest user message is always kept, and cut short if it
+// doesn't fit on its own, so a long paste can't push a request over the limit.
+fn manage_message_history(
+    messages: &mut Vec<providers::Message>,
+    config: &Config,
+    tools: Option<&[Tool]>,
+) {
+    use providers::tokens;
+
+    if messages.len() <= 1 {
+        return;
+    }
+    let before = messages.len();
+    let window = config
+        .context_tokens
+        .or_else(|| tokens::context_window(&config.model))
+        .unwrap_or(tokens::DEFAULT_CONTEXT_WINDOW);
+    let budget = window
+        .saturating_sub(
+            config
+                .response_tokens
+                .unwrap_or(tokens::DEFAULT_RESPONSE_TOKENS),
+        )
+        .saturating_sub(tokens::tools_tokens(tools));
+
+    // Messages before the latest user message can go
+    let keep_from = messages
+        .iter()
+        .rposition(|message| message.role == "user")
+        .unwrap_or(messages.len() - 1)
+        .max(1);
+    let mut removable = keep_from - 1;
+    let mut total: usize = messages.iter().map(tokens::message_tokens).sum();
+    let max_messages = config.max_history_messages.unwrap_or(usize::MAX).max(2);
+    while removable > 0 && (total > budget || messages.len() > max_messages) {
+        total -= tokens::message_tokens(&messages.remove(1));
+        removable -= 1;
+    }
+    // What's left starts with a user message, as some providers require, and so
+    // never with tool results cut off from the call they answer
+    while removable > 0 && messages[1].role != "user" {
+        total -= tokens::message_tokens(&messages.remove(1));
+        removable -= 1;
+    }
+    if messages.len() < before {
+        tracing::info!(
+            "Trimmed message history to {} messages (~{} of {} tokens)",
+            messages.len(),
+            total,
+            budget
+        );
+    }
+
+    // Only a message about to be sent is cut, not one already answered
+    if total > budget && removable + 2 == messages.len() {
+        let latest = &mut messages[removable + 1];
+        let excess = total - budget;
+        let keep = tokens::estimate(&latest.content).saturating_sub(excess);
+        let note = "\n\n[Cut short to fit the context window]";
+        latest.content = format!("{}{}", tokens::truncate(&latest.content, keep), note);
+        tracing::warn!(
+            "Message is too long for the context window, cut it short by ~{} tokens",
+            excess
+        );
+    }
+}
+
+// Add this function to handle retries for API calls
+async fn with_retries<F, Fut, T>(
+    operation: F,
+    max_retries: usize,
+    operation_name: &str,
+) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = Result<T>>,
+{
+    let mut retries = 0;
+    let mut backoff = tokio::time::Duration::from_millis(1000);
+
+    loop {
+        match operation().await {
+            Ok(result) => return Ok(result),
+            Err(e) => {
+                retries += 1;
+                if retries > max_retries {
+                    tracing::error!(
+                        "Operation '{}' failed after {} retries: {}",
+                        operation_name,
+                        max_retries,
+                        e
+                    );
+                    return Err(e);
+                }
+
+                tracing::warn!(
+                    "Operation '{}' failed (attempt {}/{}): {}",
+                    operation_name,
+                    retries,
+                    max_retries,
+                    e
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = std::cmp::min(backoff * 2, tokio::time::Duration::from_secs(30));
+            }
+        }
+    }
+}
+
+// Add this function to send messages to all outputs
+async fn send_to_all_outputs(
+    outputs: &[Box<dyn io::OutputDestination + '_>],
+    role: &str,
+    content: &str,
+    message_type: &str,
+) {
+    tracing::info!("Sending {} message to all outputs", message_type);
+
+    let mut futures = Vec::new();
+    for output in outputs {
+        let output_name = output.name().to_string();
+        let future = async move {
+            match output.write_message(role, content).await {
+                Ok(_) => tracing::info!(
+                    "Successfully sent {} message to output: {}",
+                    message_type,
+                    output_name
+                ),
+                Err(e) => tracing::error!(
+                    "Failed to send {} message to output {}: {}",
+                    message_type,
+                    output_name,
+                    e
+                ),
+            }
+        };
+        futures.push(future);
+    }
+
+    // Execute all futures concurrently
+    futures::future::join_all(futures).await;
+}
+
+// Await `completion`, telling the outputs a reply is on its way once it has taken
+// `processing_delay_ms`, unless `processing_message` is empty
+async fn with_processing_notice<T>(
+    outputs: &[Box<dyn io::OutputDestination + '_>],
+    config: &Config,
+    completion: impl std::future::Future<Output = T>,
+) -> T {
+    let notice = config
+        .processing_message
+        .as_deref()
+        .unwrap_or(DEFAULT_PROCESSING_MESSAGE);
+    if notice.is_empty() {
+        return completion.await;
+    }
+    tokio::pin!(completion);
+    let delay = tokio::time::Duration::from_millis(config.processing_delay_ms.unwrap_or(0));
+    if !delay.is_zero() {
+        if let Ok(result) = tokio::time::timeout(delay, &mut completion).await {
+            return result;
+        }
+    }
+    tracing::info!("Sending processing notice to all outputs");
+    for_all_outputs(outputs, |output| output.typing(notice)).await;
+    completion.await
+}
+
+// Get a completion with retries. Its text goes to every output's write_chunk as it is
+// generated with `stream` set, or in one piece once it is complete otherwise; either
+// way the outputs' write_message gets the whole text afterwards.
+async fn complete(
+    provider: &dyn providers::Provider,
+    config: &Config,
+    request: &[providers::Message],
+    tools: Option<&[providers::Tool]>,
+    outputs: &[Box<dyn io::OutputDestination + '_>],
+    operation_name: &str,
+) -> Result<providers::ChatCompletionResponse> {
+    // Once a budget is spent, the fallback model answers or nothing does
+    let config = &*budget::within_budget(config)?;
+    let response = with_retries(
+        || async {
+            let (deltas, mut received) = tokio::sync::mpsc::unbounded_channel();
+            let completion = async move {
+                if config.stream {
+                    provider
+                        .chat_completion_stream(&config.model, request, tools, deltas)
+                        .await
+                } else {
+                    let response = provider
+                        .chat_completion(&config.model, request, tools)
+                        .await?;
+                    if !response.message.content.is_empty() {
+                        let _ = deltas.send(response.message.content.clone());
+                    }
+                    Ok(response)
+                }
+            };
+            let forward = async {
+                let mut started = false;
+                while let Some(delta) = received.recv().await {
+                    started = true;
+                    for_all_outputs(outputs, |output| output.write_chunk("assistant", &delta))
+                        .await;
+                }
+                // Ended even when the completion fails part way, so a retry starts afresh
+                if started {
+                    for_all_outputs(outputs, |output| output.end_message()).await;
+                }
+            };
+            let (response, ()) = tokio::join!(completion, forward);
+            response
+        },
+        3,
+        operation_name,
+    )
+    .await?;
+    if let Some(usage) = &response.usage {
+        budget::record(config, usage);
+    }
+    Ok(response)
+}
+
+// Run `write` on every output at once, logging the ones that fail
+async fn for_all_outputs<'a, F, Fut>(outputs: &'a [Box<dyn io::OutputDestination + 'a>], write: F)
+where
+    F: Fn(&'a dyn io::OutputDestination) -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    let results =
+        futures::future::join_all(outputs.iter().map(|output| write(output.as_ref()))).await;
+    for (output, result) in outputs.iter().zip(results) {
+        if let Err(e) = result {
+            tracing::warn!("Failed to write to output {}: {}", output.name(), e);
+        }
+    }
+}
+
+impl Agent {
+    /// Start building an agent
+    pub fn builder() -> AgentBuilder {
+        AgentBuilder::default()
+    }
+
+    /// A sender that stops the agent when a message is sent on it, as Ctrl-C does
+    pub fn shutdown_sender(&self) -> broadcast::Sender<()> {
+        self.shutdown.clone()
+    }
+
+    /// Run until shutdown: start the inputs and outputs, then answer messages
+    pub async fn run(self) -> Result<()> {
+        let Agent {
+            config,
+            config_path,
+            provider,
+            inputs,
+            outputs,
+            shutdown,
+            admin,
+        } = self;
+        run_agent(
+            config,
+            config_path.as_deref(),
+            provider,
+            inputs,
+            outputs,
+            shutdown,
+            admin,
+        )
+        .await
+    }
+}
+
+// Run one agent until shutdown: its provider, inputs, outputs and message loop.
+// `take_admin` makes it answer the webhook server's admin API under its name.
+async fn run_agent(
+    mut config: Config,
+    config_path: Option<&str>,
+    mut provider: Box<dyn providers::Provider>,
+    extra_inputs: Vec<Box<dyn io::InputSource>>,
+    extra_outputs: Vec<Box<dyn io::OutputDestination>>,
+    shutdown_tx: broadcast::Sender<()>,
+    take_admin: bool,
+) -> Result<()> {
+    // Reload the config and API key on SIGHUP, or when the config file changes if
+    // config_watch is set
+    let (reload_tx, mut reload_rx) = tokio::sync::mpsc::channel::<()>(1);
+    let _watcher = if let (true, Some(config_path)) = (config.config_watch, config_path) {
+        let mut files = vec![config_path.to_string()];
+        files.extend(config.included.iter().cloned());
+        files.extend(config.context_files.iter().cloned());
+        match config::watch(&files, reload_tx.clone()) {
+            Ok(watcher) => {
+                tracing::info!("Reloading the config when {} changes", files.join(", "));
+                Some(watcher)
+            }
+            Err(e) => {
+                tracing::error!("Failed to watch config file: {:#}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(err) => {
+                tracing::error!("Failed to listen for SIGHUP: {}", err);
+                return;
+            }
+        };
+
+        while hangup.recv().await.is_some() {
+            tracing::info!("Received SIGHUP, reloading config");
+            // A reload is already pending if the channel is full
+            let _ = reload_tx.try_send(());
+        }
+    });
+    #[cfg(not(unix))]
+    drop(reload_tx);
+
+    // Print the selected provider and model
+    tracing::info!(
+        "Using provider: {} with model: {}",
+        provider.name(),
+        config.model
+    );
+
+    // Initialize tools if enabled
+    let mut tools = build_tools(&config);
+    let mut redactor = build_redactor(&config);
+    let mut moderator = build_moderator(&config);
+    let mut guardrails = build_guardrails(&config);
+
+    // Create input sources using the new function
+    tracing::info!(
+        "Creating input sources: {:?}",
+        config::kinds(&config.input_entries())
+    );
+    let mut inputs = io::create_input_sources(&config).await;
+    inputs.extend(extra_inputs);
+    tracing::info!("Successfully created {} input sources", inputs.len());
+
+    // Create output destinations using the new function, remembering which entry
+    // each came from so a config reload can add and drop them; those given to the
+    // builder have none and stay
+    tracing::info!(
+        "Configuring output destinations: {:?}",
+        config::kinds(&config.output_entries())
+    );
+    let mut output_entries = vec![None; extra_outputs.len()];
+    let mut outputs = Vec::new();
+    for output in extra_outputs {
+        let kind = output.name().to_string();
+        outputs.push(
+            Box::new(io::isolation::Isolated::new(&config, &kind, output)?)
+                as Box<dyn io::OutputDestination>,
+        );
+    }
+    route_outputs(&config, &mut outputs, &mut output_entries).await;
+    tracing::info!("Successfully created {} output destinations", outputs.len());
+
+    // Accept administration commands over MQTT when enabled; without it the closed
+    // channel keeps that branch of the main loop idle
+    let mut control = None;
+    let mut control_rx = tokio::sync::mpsc::channel(1).1;
+    if config.mqtt_control {
+        match io::MqttSettings::from_config(&config) {
+            Ok(settings) => match io::MqttControl::new(&settings).await {
+                Ok((mqtt_control, commands)) => {
+                    tracing::info!("Accepting control commands on {}", settings.control_topic);
+                    control = Some(mqtt_control);
+                    control_rx = commands;
+                }
+                Err(e) => tracing::error!("Failed to start MQTT control: {:#}", e),
+            },
+            Err(e) => tracing::error!("Failed to start MQTT control: {:#}", e),
+        }
+    }
+    let mut paused = false;
+    // The conversation whose answer a newer message or /stop just cut short
+    let mut interrupted: Option<session::SessionKey> = None;
+    // Requests from the webhook server's admin API, when it has one and this agent
+    // takes them
+    let mut admin_rx = if take_admin {
+        io::admin::requests(config.agent.as_ref().unwrap_or(&config.agent_name))
+    } else {
+        tokio::sync::mpsc::channel(1).1
+    };
+
+    // Conversation histories, one for each input source and user, so people talking
+    // to the agent through a shared input don't see each other's conversations
+    let mut system_prompt = prompt::SystemPrompt::load(&config);
+    let mut sessions = session::SessionManager::new(&config.system_message);
+    // Conversations are kept per input, so messages are matched to theirs by its name
+    let input_names: Vec<String> = inputs
+        .iter()
+        .map(|input| input.name().to_string())
+        .collect();
+
+    // Pick up the conversations from before a restart, and keep them from now on
+    let history_store = match &config.history_db {
+        Some(path) => {
+            let store = history::HistoryStore::open(path, &config.agent_name)?;
+            let restored = store.load()?;
+            tracing::info!("Restored {} conversation(s) from {}", restored.len(), path);
+            for (id, history) in restored {
+                sessions.restore(session::SessionKey::parse(&id, &input_names), history);
+            }
+            Some(store)
+        }
+        None => None,
+    };
+
+    // Budgets count this month's usage from before a restart
+    if let Some(path) = &config.usage_log {
+        let count = budget::load_log(&config)?;
+        tracing::info!("Counted {} completion(s) this month from {}", count, path);
+    }
+
+    // Recall past exchanges into the prompt of each new message
+    let mut memory = if config.memory {
+        let memory = memory::Memory::new(&config)?;
+        tracing::info!(
+            "Memory enabled with {} exchanges in {}",
+            memory.count(),
+            memory.path().display()
+        );
+        Some(memory)
+    } else {
+        None
+    };
+
+    // First, create proper channels for input sources
+    tracing::debug!("Setting up message channels...");
+    let queue = queue::InputQueue::new(
+        &config.agent_name,
+        config.input_queue_size.unwrap_or(queue::DEFAULT_SIZE),
+        queue::OverflowPolicy::new(config.input_queue_policy.as_deref())?,
+    );
+
+    // Spawn tasks for each input source
+    let mut input_tasks = tokio::task::JoinSet::new();
+
+    // Seed a conversation from a transcript: the one it was exported from, or else
+    // the first input's
+    if let Some(path) = &config.resume {
+        let (id, messages) = export::load(path)?;
+        let key = match id {
+            Some(id) => session::SessionKey::parse(&id, &input_names),
+            None => {
+                session::SessionKey::new(input_names.first().map_or("stdin", String::as_str), None)
+            }
+        };
+        if sessions.get(&key).is_some() {
+            tracing::warn!(
+                "Not resuming {}: conversation {} is already in the history database",
+                path,
+                key
+            );
+        } else {
+            tracing::info!(
+                "Resuming conversation {} with {} messages from {}",
+                key,
+                messages.len(),
+                path
+            );
+            sessions.restore(key.clone(), messages);
+            if let (Some(store), Some(history)) = (&history_store, sessions.get(&key)) {
+                if let Err(e) = store.save(&key.to_string(), history) {
+                    tracing::warn!("Failed to save conversation history: {:#}", e);
+                }
+            }
+        }
+    }
+
+    // Health checks of agents sharing the process are told apart by name
+    let check_prefix = match &config.agent {
+        Some(_) => format!("{}:", config.agent_name),
+        None => String::new(),
+    };
+    for (i, mut input_source) in inputs.into_iter().enumerate() {
+        let input_tx = queue.sender();
+        let check_prefix = check_prefix.clone();
+        let input_name = input_source.name().to_string();
+        let mut shutdown_rx = shutdown_tx.subscribe();
+
+        tracing::debug!("Starting listener for input source {}: {}", i, input_name);
+
+        // Create a task to monitor this input
+        input_tasks.spawn(async move {
+            tracing::debug!("Starting listener task for input source {}: {}", i, input_name);
+            let health_check = format!("{}input:{}:{}", check_prefix, i, input_name);
+            health::report(&health_check, true, "running");
+
+            // Implement exponential backoff for error recovery
+            let mut backoff = tokio::time::Duration::from_millis(100);
+
+            loop {
+                tokio::select! {
+                    // Check for shutdown signal
+                    _ = shutdown_rx.recv() => {
+                        tracing::info!("Shutting down input source {}: {}", i, input_name);
+                        break;
+                    }
+                    // Try to read a message
+                    result = input_source.read_message() => {
+                        match result {
+                            Ok(Some(mut msg)) => {
+                                tracing::debug!("Input {}: Received message: {}", i, msg.content);
+                                // Say where it came from and send it to the main loop
+                                msg.source = input_name.clone();
+                                msg.instance = i;
+                                if msg.reply_to.is_empty() {
+                                    msg.reply_to = input_source.reply_to();
+                                }
+                                if let Err(e) = input_tx.send(msg.clone()).await {
+                                    tracing::warn!("Rejected message from input {}: {}", i, e);
+                                    if let Err(e) = input_source.reject(&msg, &e.to_string()).await {
+                                        tracing::error!("Failed to tell input {} of the rejection: {}", i, e);
+                                    }
+                                }
+                                // Reset backoff on success
+                                backoff = tokio::time::Duration::from_millis(100);
+                            },
+                            Ok(None) => {
+                                // No message, wait a bit before checking again
+                                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                            },
+                            Err(e) => {
+                                tracing::error!("Error reading from input {}: {}", i, e);
+                                // Use exponential backoff with a maximum delay
+                                tokio::time::sleep(backoff).await;
+                                backoff = std::cmp::min(backoff * 2, tokio::time::Duration::from_secs(30));
+                            }
+                        }
+                    }
+                }
+            }
+
+            health::report(&health_check, false, "stopped");
+            tracing::info!("Input source task {} completed", i);
+        });
+    }
+
+    // Main event loop - truly event-driven
+    tracing::info!("Starting event-driven message processing...");
+    daemon::notify("READY=1");
+    let mut shutdown_rx = shutdown_tx.subscribe();
+
+    loop {
+        tokio::select! {
+            // Check for shutdown signal
+            _ = shutdown_rx.recv() => {
+                tracing::info!("Main loop received shutdown signal, exiting...");
+                break;
+            }
+            // Reload the config and rebuild the provider with a freshly loaded key; queued
+            // messages stay in the channel
+            Some(()) = reload_rx.recv() => {
+                // Editors save in several steps, so let them finish and take one reload
+                tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+                while reload_rx.try_recv().is_ok() {}
+
+                match apply_control(&io::ControlCommand::ReloadConfig, &mut config, config_path, &mut provider, &mut sessions, &mut paused) {
+                    Ok(message) => {
+                        tracing::info!("{}", message);
+                        tools = build_tools(&config);
+                        redactor = build_redactor(&config);
+                        moderator = build_moderator(&config);
+                        guardrails = build_guardrails(&config);
+                        system_prompt = prompt::SystemPrompt::load(&config);
+                        route_outputs(&config, &mut outputs, &mut output_entries).await;
+                    }
+                    // A broken config shouldn't stop the key from rotating
+                    Err(e) => {
+                        tracing::error!("Failed to reload config, keeping current one: {:#}", e);
+                        match credentials::load_api_key(&config)
+                            .and_then(|key| providers::get_provider(&config.provider, &key, &config.provider_settings(&config.provider)))
+                        {
+                            Ok(new_provider) => {
+                                provider = new_provider;
+                                tracing::info!("Reloaded API key for provider: {}", provider.name());
+                            }
+                            Err(e) => tracing::error!("Failed to reload API key, keeping current one: {}", e),
+                        }
+                    }
+                }
+            }
+            // Apply administration commands between messages
+            Some(command) = control_rx.recv() => {
+                let result = apply_control(&command, &mut config, config_path, &mut provider, &mut sessions, &mut paused);
+                match &result {
+                    Ok(message) => tracing::info!("{}", message),
+                    Err(e) => tracing::error!("Control command {} failed: {:#}", command.name(), e),
+                }
+                if result.is_ok() && matches!(command, io::ControlCommand::ReloadConfig) {
+                    tools = build_tools(&config);
+                    redactor = build_redactor(&config);
+                    moderator = build_moderator(&config);
+                    guardrails = build_guardrails(&config);
+                    system_prompt = prompt::SystemPrompt::load(&config);
+                    route_outputs(&config, &mut outputs, &mut output_entries).await;
+                }
+                if result.is_ok() {
+                    save_history(history_store.as_ref(), &sessions);
+                }
+                if let Some(control) = &control {
+                    control.acknowledge(&command, &result).await;
+                }
+            }
+            Some(request) = admin_rx.recv() => {
+                let result = apply_admin(&request.command, &mut config, config_path, &mut provider, &mut sessions, &mut paused);
+                if let Err(e) = &result {
+                    tracing::error!("Admin request {:?} failed: {:#}", request.command, e);
+                }
+                if result.is_ok() && matches!(request.command, io::AdminCommand::Control(io::ControlCommand::ReloadConfig)) {
+                    tools = build_tools(&config);
+                    redactor = build_redactor(&config);
+                    moderator = build_moderator(&config);
+                    guardrails = build_guardrails(&config);
+                    system_prompt = prompt::SystemPrompt::load(&config);
+                    route_outputs(&config, &mut outputs, &mut output_entries).await;
+                }
+                if result.is_ok() {
+                    save_history(history_store.as_ref(), &sessions);
+                }
+                let _ = request.reply.send(result);
+            }
+            // Wait for a message from any input source; while paused they queue up
+            msg = queue.recv(), if !paused => {
+                match msg {
+                    Some(message) => {
+                        // Outputs that address each answer to its request learn which one this is
+                        for output in &outputs {
+                            output.answering(&message);
+                        }
+                        let io::InboundMessage { source, instance: idx, content, sender, correlation_id, attachments, hops, reply_to, remote_addr, credential, .. } = message;
+                        let key = session::SessionKey::new(&source, sender.clone());
+                        // The message that cut an answer short is always the next one out
+                        let was_interrupted = interrupted.take().is_some_and(|interrupted| interrupted == key);
+
+                        // Secrets are replaced before the message is logged, stored or
+                        // sent anywhere
+                        let content = match &redactor {
+                            Some(redactor) => redactor.redact(&content, &key.to_string(), "message"),
+                            None => content,
+                        };
+
+                        tracing::info!("\n\n=== MESSAGE RECEIVED ===");
+                        match &sender {
+                            Some(sender) => tracing::info!("From input source {} ({}): {}", idx, sender, content),
+                            None => tracing::info!("From input source {}: {}", idx, content),
+                        }
+                        tracing::info!("Correlation ID: {}", correlation_id);
+                        if let Some(remote_addr) = remote_addr {
+                            tracing::info!("Remote address: {}", remote_addr);
+                        }
+                        if let Some(credential) = credential {
+                            tracing::info!("Webhook token: {}", credential);
+                        }
+                        tracing::info!("==========================\n\n");
+                        // Nothing passes files on to the model yet
+                        if !attachments.is_empty() {
+                            tracing::warn!("Ignoring {} attachment(s) of message {}", attachments.len(), correlation_id);
+                        }
+
+                        // Replies go back where the message came from, or the way the
+                        // input's routes say
+                        let routed = io::route::outputs_for(&config, &source, &reply_to, &outputs);
+
+                        // Check for exit command
+                        if content.to_lowercase() == "exit" {
+                            tracing::info!("Received exit command, shutting down");
+                            for output in &routed {
+                                let _ = output.write_message("system", GOODBYE).await;
+                            }
+                            // Trigger shutdown
+                            let _ = shutdown_tx.send(());
+                            break;
+                        }
+
+                        // Commands are answered by the agent instead of the model
+                        if let Some(command) = commands::SlashCommand::parse(&content, config.command_prefix()) {
+                            let result = match command {
+                                Ok(commands::SlashCommand::Stop) if was_interrupted => Ok(Some("Stopped the reply in progress".to_string())),
+                                Ok(command) => run_command(&command, &key, &mut config, &mut provider, &mut sessions, &mut tools, &routed).await,
+                                Err(e) => Err(e),
+                            };
+                            match result {
+                                Ok(reply) => {
+                                    if let Some(reply) = reply {
+                                        send_to_all_outputs(&routed, "system", &reply, "command").await;
+                                    }
+                                    save_history(history_store.as_ref(), &sessions);
+                                }
+                                Err(e) => {
+                                    tracing::error!("Command {} failed: {:#}", content, e);
+                                    send_to_all_outputs(&routed, "system", &format!("{:#}", e), "command").await;
+                                }
+                            }
+                            continue;
+                        }
+
+                        // Flagged messages are turned away, or go on marked or redacted
+                        let content = match &moderator {
+                            Some(moderator) => match moderator.check_input(&content, &key.to_string()).await {
+                                moderation::Verdict::Allow(content) => content,
+                                moderation::Verdict::Block(reason) => {
+                                    send_to_all_outputs(&routed, "system", &reason, "system").await;
+                                    continue;
+                                }
+                            },
+                            None => content,
+                        };
+
+                        // A conversation's first message starts it from the system message
+                        let history = sessions.history(&key);
+
+                        let recalled = match &memory {
+                            Some(memory) => memory
+                                .recall(&key.to_string(), &content, history)
+                                .await
+                                .unwrap_or_else(|e| {
+                                    tracing::warn!("Failed to recall memories: {:#}", e);
+                                    None
+                                }),
+                            None => None,
+                        };
+
+                        // Recalled exchanges can hold what tools and pages said earlier
+                        let recalled = recalled.map(|recalled| injection::guard(&config, "memory", &recalled));
+
+                        // Process the message - dereference the provider to get &dyn Provider
+                        let session = key.to_string();
+                        let turn = Turn {
+                            session: &session,
+                            prompt: &system_prompt,
+                            recalled: recalled.as_deref(),
+                            redactor: redactor.as_ref(),
+                            moderator: moderator.as_ref(),
+                            guardrails: guardrails.as_ref(),
+                            hops,
+                        };
+                        // A newer message from the same sender, or /stop, drops the
+                        // provider request and tool calls in flight
+                        let newer = |next: &io::InboundMessage| {
+                            next.source == source
+                                && next.sender == sender
+                                && (config.interrupt_replies
+                                    || matches!(commands::SlashCommand::parse(&next.content, config.command_prefix()), Some(Ok(commands::SlashCommand::Stop))))
+                        };
+                        // Shutting down drops the provider request in flight; the
+                        // conversation so far is still saved
+                        let result = tokio::select! {
+                            result = process_message(content.clone(), provider.as_ref(), &config, history, &routed, tools.as_deref(), &turn) => Some(result),
+                            _ = queue.promote(newer) => Some(Err(Interrupted.into())),
+                            _ = shutdown_rx.recv() => None,
+                        };
+                        match &result {
+                            Some(Err(e)) if e.is::<Interrupted>() => {
+                                tracing::info!("Stopped answering in {} for a newer message", key);
+                                interrupted = Some(key.clone());
+                                // The question stays, without the half-finished answer
+                                // or tool calls whose results never came
+                                if let Some(question) = history.iter().rposition(|message| message.role == "user") {
+                                    history.truncate(question + 1);
+                                }
+                                if config.stream {
+                                    for_all_outputs(&routed, |output| output.end_message()).await;
+                                }
+                            }
+                            Some(Ok(())) => {
+                                let reply = history.last().filter(|message| message.role == "assistant");
+                                if let (Some(memory), Some(reply)) = (&mut memory, reply) {
+                                    if let Err(e) = memory.remember(&key.to_string(), &content, &reply.content).await {
+                                        tracing::warn!("Failed to remember exchange: {:#}", e);
+                                    }
+                                }
+                            }
+                            Some(Err(e)) => tracing::error!("Error processing message: {}", e),
+                            None => tracing::info!("Shutdown cancelled the message being processed"),
+                        }
+                        if let Some(store) = &history_store {
+                            if let Err(e) = store.save(&key.to_string(), history) {
+                                tracing::warn!("Failed to save conversation history: {:#}", e);
+                            }
+                        }
+                        if result.is_none() {
+                            break;
+                        }
+                    },
+                    None => {
+                        tracing::info!("All input channels closed, exiting");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    // Wait for all input tasks to complete
+    tracing::info!("Waiting for input tasks to complete...");
+    let shutdown_timeout = tokio::time::Duration::from_secs(5);
+    let wait_for_tasks = async {
+        while let Some(res) = input_tasks.join_next().await {
+            if let Err(e) = res {
+                tracing::error!("Error joining input task: {}", e);
+            }
+        }
+    };
+
+    // Add a timeout to prevent hanging
+    match tokio::time::timeout(shutdown_timeout, wait_for_tasks).await {
+        Ok(_) => tracing::info!("All input tasks completed successfully"),
+        Err(_) => tracing::warn!("Timed out waiting for some input tasks to complete"),
+    }
+
+    save_history(history_store.as_ref(), &sessions);
+
+    // Let the outputs deliver what they still hold, e.g. MQTT messages awaiting acks
+    tracing::info!("Closing outputs...");
+    let close_all = futures::future::join_all(outputs.iter().map(|output| async move {
+        if let Err(e) = output.close().await {
+            tracing::warn!("Failed to close output {}: {:#}", output.name(), e);
+        }
+    }));
+    if tokio::time::timeout(shutdown_timeout, close_all)
+        .await
+        .is_err()
+    {
+        tracing::warn!("Timed out closing some outputs");
+    }
+
+    Ok(())
+}
+
+// Answer a command from an input, returning the reply for the outputs, if any.
+// Commands other than clear, history and export apply to the whole agent.
+async fn run_command(
+    command: &commands::SlashCommand,
+    key: &session::SessionKey,
+    config: &mut Config,
+    provider: &mut Box<dyn providers::Provider>,
+    sessions: &mut session::SessionManager,
+    tools: &mut Option<Vec<Tool>>,
+    outputs: &[Box<dyn io::OutputDestination + '_>],
+) -> Result<Option<String>> {
+    use commands::SlashCommand;
+
+    let reply = match command {
+        SlashCommand::Help => commands::help(config.command_prefix()),
+        // The main loop answers a /stop that cut a reply short
+        SlashCommand::Stop => "Nothing to stop".to_string(),
+        SlashCommand::Clear => {
+            sessions.remove(key);
+            "Conversation cleared".to_string()
+        }
+        SlashCommand::History => {
+            let history = sessions.history(key);
+            let lines: Vec<String> = history
+                .iter()
+                .skip(1)
+                .enumerate()
+                .map(|(i, message)| {
+                    let content = match message.content.char_indices().nth(200) {
+                        Some((end, _)) => format!("{}...", &message.content[..end]),
+                        None => message.content.clone(),
+                    };
+                    format!("{}. {}: {}", i + 1, message.role, content)
+                })
+                .collect();
+            if lines.is_empty() {
+                "No messages in this conversation yet".to_string()
+            } else {
+                lines.join("\n")
+            }
+        }
+        SlashCommand::Model(None) => format!("Model: {}:{}", config.provider, config.model),
+        SlashCommand::Model(Some(model)) => set_model(config, provider, model)?,
+        SlashCommand::Provider(None) => format!(
+            "Provider: {} (available: {})",
+            config.provider,
+            providers::PROVIDERS.join(", ")
+        ),
+        SlashCommand::Provider(Some((name, model))) => {
+            set_provider(config, provider, name, model.as_deref())?
+        }
+        SlashCommand::System(None) => format!("System message: {}", config.system_message),
+        SlashCommand::System(Some(prompt)) => {
+            config.system_message = prompt.clone();
+            sessions.set_system_message(prompt);
+            "System message replaced".to_string()
+        }
+        SlashCommand::Tools(enable) => {
+            if let Some(enable) = enable {
+                config.enable_tools = *enable;
+                *tools = build_tools(config);
+            }
+            match tools {
+                Some(tools) => format!("Tools on ({} tools)", tools.len()),
+                None => "Tools off".to_string(),
+            }
+        }
+        SlashCommand::Usage => {
+            let usage = providers::total_usage();
+            let context: usize = sessions
+                .history(key)
+                .iter()
+                .map(providers::tokens::message_tokens)
+                .sum();
+            let mut reply = format!(
+                "{} prompt and {} completion tokens used since start; this conversation is about {} tokens",
+                usage.prompt_tokens, usage.completion_tokens, context
+            );
+            let today = chrono::Local::now().date_naive();
+            let spent = budget::spent_since(config, today);
+            reply.push_str(&format!(
+                "\nToday: {} tokens, ${:.2}",
+                spent.tokens, spent.usd
+            ));
+            if let Some(reason) = budget::exceeded(config) {
+                reply.push_str(&format!("\n{}", reason));
+            }
+            reply
+        }
+        SlashCommand::Export(format) => {
+            return export_session(
+                key,
+                sessions.history(key),
+                format.as_deref(),
+                config,
+                outputs,
+            )
+            .await;
+        }
+    };
+    Ok(Some(reply))
+}
+
+// Save the conversation to export_dir when it is set, returning where, otherwise
+// send it to the outputs
+async fn export_session(
+    key: &session::SessionKey,
+    history: &[providers::Message],
+    format: Option<&str>,
+    config: &Config,
+    outputs: &[Box<dyn io::OutputDestination + '_>],
+) -> Result<Option<String>> {
+    let format = export::ExportFormat::parse(format.unwrap_or("md"))?;
+    let text = export::render(&key.to_string(), history, format)?;
+    match &config.export_dir {
+        Some(dir) => {
+            let path = std::path::Path::new(dir).join(export::file_name(&key.to_string(), format));
+            export::write(&path, &text)?;
+            tracing::info!("Exported conversation {} to {}", key, path.display());
+            Ok(Some(format!("Conversation exported to {}", path.display())))
+        }
+        None => {
+            send_to_all_outputs(outputs, "export", &text, "export").await;
+            Ok(None)
+        }
+    }
+}
+
+// Store every conversation, after a command that may have cleared some and at shutdown
+fn save_history(store: Option<&history::HistoryStore>, sessions: &session::SessionManager) {
+    if let Some(store) = store {
+        if let Err(e) = store.save_all(sessions) {
+            tracing::warn!("Failed to save conversation history: {:#}", e);
+        }
+    }
+}
+
+// Make the outputs match the config: destinations whose entry is unchanged keep
+// running, with their connections, and new or changed entries get new destinations.
+// Outputs without an entry were given to the builder and always stay.
+async fn route_outputs(
+    config: &Config,
+    outputs: &mut Vec<Box<dyn io::OutputDestination>>,
+    output_entries: &mut Vec<Option<config::IoEntry>>,
+) {
+    let entries = config.output_entries();
+    let mut fixed = Vec::new();
+    let mut kept = Vec::new();
+    let mut kept_entries = Vec::new();
+    for (output, entry) in outputs.drain(..).zip(output_entries.drain(..)) {
+        match entry {
+            None => fixed.push(output),
+            Some(entry) if entries.contains(&entry) => {
+                kept.push(output);
+                kept_entries.push(entry);
+            }
+            Some(entry) => tracing::info!("Removed {} output", entry.kind),
+        }
+    }
+    output_entries.extend(fixed.iter().map(|_| None));
+    outputs.extend(fixed);
+
+    for entry in entries {
+        if let Some(position) = kept_entries.iter().position(|kept| *kept == entry) {
+            outputs.push(kept.remove(position));
+            output_entries.push(Some(kept_entries.remove(position)));
+            continue;
+        }
+        // Each entry is created on its own, so its destinations can be told apart
+        let single = Config {
+            outputs_vec: Vec::new(),
+            outputs: vec![entry.clone()],
+            ..config.clone()
+        };
+        for output in io::create_output_destinations(&single).await {
+            outputs.push(output);
+            output_entries.push(Some(entry.clone()));
+        }
+    }
+}
+
+// The redactor for the config's patterns, or None when redaction is off. Patterns are
+// checked when the config loads, so this only fails on a config that skipped that.
+fn build_redactor(config: &Config) -> Option<redact::Redactor> {
+    redact::Redactor::new(config).unwrap_or_else(|e| {
+        tracing::error!("Failed to build redactor, not redacting: {:#}", e);
+        None
+    })
+}
+
+// The moderator for the config's classifier, or None when moderation is off or its
+// classifier can't be set up
+fn build_moderator(config: &Config) -> Option<moderation::Moderator> {
+    moderation::Moderator::new(config).unwrap_or_else(|e| {
+        tracing::error!("Failed to set up moderation, not moderating: {:#}", e);
+        None
+    })
+}
+
+// The output guardrails, or None when the config sets none. Patterns are checked when
+// the config loads, so this only fails on a config that skipped that.
+fn build_guardrails(config: &Config) -> Option<guardrails::Guardrails> {
+    guardrails::Guardrails::new(config).unwrap_or_else(|e| {
+        tracing::error!(
+            "Failed to build output guardrails, not applying them: {:#}",
+            e
+        );
+        None
+    })
+}
+
+// Apply a command from the MQTT control topic, describing the outcome
+fn apply_control(
+    command: &io::ControlCommand,
+    config: &mut Config,
+    config_path: Option<&str>,
+    provider: &mut Box<dyn providers::Provider>,
+    sessions: &mut session::SessionManager,
+    paused: &mut bool,
+) -> Result<String> {
+    match command {
+        io::ControlCommand::ClearHistory => {
+            sessions.clear();
+            Ok("Conversation history cleared".to_string())
+        }
+        io::ControlCommand::SetModel(model) => set_model(config, provider, model),
+        io::ControlCommand::SetProvider {
+            provider: name,
+            model,
+        } => set_provider(config, provider, name, model.as_deref()),
+        io::ControlCommand::ReloadConfig => {
+            // Inputs keep running as they are, under the same name; the caller routes
+            // outputs to match the new outputs
+            let config_path = config_path.context("No config file to reload")?;
+            let reloaded = config.reload(config_path)?;
+            let key = credentials::load_api_key(&reloaded)?;
+            *provider = providers::get_provider(
+                &reloaded.provider,
+                &key,
+                &reloaded.provider_settings(&reloaded.provider),
+            )?;
+            *config = reloaded;
+            sessions.set_system_message(&config.system_message);
+            Ok(format!(
+                "Reloaded {}: provider {}, model {}",
+                config_path, config.provider, config.model
+            ))
+        }
+        io::ControlCommand::Pause => {
+            *paused = true;
+            Ok("Paused; incoming messages are queued".to_string())
+        }
+        io::ControlCommand::Resume => {
+            *paused = false;
+            Ok("Resumed".to_string())
+        }
+        io::ControlCommand::Status => {
+            let queue = queue::stats(&config.agent_name).unwrap_or_default();
+            Ok(format!(
+                "provider {}, model {}, {}, {} messages in history, {} sessions, {}/{} messages queued",
+                config.provider,
+                config.model,
+                if *paused { "paused" } else { "running" },
+                sessions.message_count(),
+                sessions.count(),
+                queue.depth,
+                queue.capacity
+            ))
+        }
+    }
+}
+
+// Switch model; an alias naming another provider switches to it
+fn set_model(
+    config: &mut Config,
+    provider: &mut Box<dyn providers::Provider>,
+    model: &str,
+) -> Result<String> {
+    let (provider_name, resolved) = config.resolve_model(model);
+    if let Some(name) = provider_name.filter(|name| *name != config.provider) {
+        let key = credentials::load_api_key_for(config, &name)?;
+        *provider = providers::get_provider(&name, &key, &config.provider_settings(&name))?;
+        config.provider = name;
+    }
+    config.model = resolved;
+    if config.model == model {
+        Ok(format!("Model set to {}", model))
+    } else {
+        Ok(format!(
+            "Model set to {} ({}:{})",
+            model, config.provider, config.model
+        ))
+    }
+}
+
+// Switch provider, and to `model` if given. Otherwise the model is kept unless it
+// belongs to another provider, when the new provider's first model takes over
+fn set_provider(
+    config: &mut Config,
+    provider: &mut Box<dyn providers::Provider>,
+    name: &str,
+    model: Option<&str>,
+) -> Result<String> {
+    let name = name.to_lowercase();
+    if !providers::PROVIDERS.contains(&name.as_str()) {
+        anyhow::bail!(
+            "Unknown provider {}, expected one of {}",
+            name,
+            providers::PROVIDERS.join(", ")
+        );
+    }
+    let key = credentials::load_api_key_for(config, &name)?;
+    let switched = providers::get_provider(&name, &key, &config.provider_settings(&name))?;
+    let model = match model {
+        Some(model) => config.resolve_model(model).1,
+        None if providers::provider_for_model(&config.model).is_some_and(|owner| owner != name) => {
+            switched
+                .available_models()
+                .into_iter()
+                .next()
+                .context(format!("{} offers no models; name one", name))?
+        }
+        None => config.model.clone(),
+    };
+    *provider = switched;
+    config.provider = name;
+    config.model = model;
+    Ok(format!(
+        "Provider set to {}, model {}",
+        config.provider, config.model
+    ))
+}
+
+// Answer a request from the admin API; control commands are shared with MQTT
+fn apply_admin(
+    command: &io::AdminCommand,
+    config: &mut Config,
+    config_path: Option<&str>,
+    provider: &mut Box<dyn providers::Provider>,
+    sessions: &mut session::SessionManager,
+    paused: &mut bool,
+) -> Result<serde_json::Value> {
+    match command {
+        io::AdminCommand::Sessions => {
+            let list: Vec<_> = sessions
+                .iter()
+                .map(|(key, history)| {
+                    json!({
+                        "session": key.to_string(),
+                        "source": key.source,
+                        "user": key.user,
+                        "messages": history.len(),
+                    })
+                })
+                .collect();
+            Ok(json!({ "sessions": list }))
+        }
+        io::AdminCommand::History(Some(id)) => {
+            let history = sessions
+                .get(&sessions.key(id))
+                .ok_or_else(|| anyhow::anyhow!("No conversation {}", id))?;
+            Ok(serde_json::to_value(history)?)
+        }
+        io::AdminCommand::History(None) => {
+            let all: serde_json::Map<_, _> = sessions
+                .iter()
+                .map(|(key, history)| Ok((key.to_string(), serde_json::to_value(history)?)))
+                .collect::<Result<_>>()?;
+            Ok(serde_json::Value::Object(all))
+        }
+        io::AdminCommand::ClearHistory(Some(id)) => {
+            sessions
+                .remove(&sessions.key(id))
+                .ok_or_else(|| anyhow::anyhow!("No conversation {}", id))?;
+            Ok(json!({ "message": format!("Conversation {} cleared", id) }))
+        }
+        io::AdminCommand::ClearHistory(None) => {
+            let command = io::ControlCommand::ClearHistory;
+            let message = apply_control(&command, config, config_path, provider, sessions, paused)?;
+            Ok(json!({ "message": message }))
+        }
+        io::AdminCommand::Export { session, format } => {
+            let format = export::ExportFormat::parse(format)?;
+            let key = match session {
+                Some(id) => sessions.key(id),
+                None if sessions.count() == 1 => sessions.iter().next().unwrap().0.clone(),
+                None => anyhow::bail!("Choose a conversation with ?session="),
+            };
+            let history = sessions
+                .get(&key)
+                .ok_or_else(|| anyhow::anyhow!("No conversation {}", key))?;
+            Ok(json!({
+                "session": key.to_string(),
+                "format": format.extension(),
+                "content": export::render(&key.to_string(), history, format)?,
+            }))
+        }
+        io::AdminCommand::Config => Ok(config.redacted()),
+        io::AdminCommand::Control(command) => {
+            let message = apply_control(command, config, config_path, provider, sessions, paused)?;
+            Ok(json!({ "message": message }))
+        }
+    }
+}
+
+// Why an answer ended early when a newer message cut it short, told apart from failures
+#[derive(Debug)]
+struct Interrupted;
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Interrupted by a newer message")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+// What process_message needs to know about a message besides its conversation
+struct Turn<'a> {
+    session: &'a str,
+    // Renders the system message for this request
+    prompt: &'a prompt::SystemPrompt,
+    // Memories added to the system message for this message only
+    recalled: Option<&'a str>,
+    // Redacts tool results before they are shown or added to the history
+    redactor: Option<&'a redact::Redactor>,
+    // Screens responses before they are sent to the outputs
+    moderator: Option<&'a moderation::Moderator>,
+    // Rules responses have to follow before they are sent
+    guardrails: Option<&'a guardrails::Guardrails>,
+    // Agents the message passed through before this one, if another agent asked it
+    hops: u32,
+}
+
+// The messages to send, with recalled memories added to the system message for this
+// request only, so they don't pile up in the history
+fn with_recalled<'a>(
+    messages: &'a [providers::Message],
+    recalled: Option<&str>,
+) -> std::borrow::Cow<'a, [providers::Message]> {
+    match (recalled, messages.first()) {
+        (Some(recalled), Some(system)) if system.role == "system" => {
+            let mut request = messages.to_vec();
+            request[0].content = format!("{}\n\n{}", system.content, recalled);
+            std::borrow::Cow::Owned(request)
+        }
+        _ => std::borrow::Cow::Borrowed(messages),
+    }
+}
+
+// Run the generate_image tool and describe the outcome for the model
+async fn generate_image(
+    args: &serde_json::Value,
+    config: &Config,
+    outputs: &[Box<dyn io::OutputDestination + '_>],
+) -> String {
+    let generator = match tools::image::ImageGenerator::from_config(config) {
+        Ok(generator) => generator,
+        Err(e) => return format!("Image generation unavailable: {}", e),
+    };
+
+    let prompt = args["prompt"].as_str().unwrap_or("");
+    let size = args["size"].as_str();
+    let count = args["n"].as_u64().map(|n| n as usize);
+
+    let images = match generator.generate(prompt, size, count).await {
+        Ok(images) => images,
+        Err(e) => return format!("Image generation failed: {}", e),
+    };
+
+    if generator.output_dir().is_some() {
+        match generator.save(&images) {
+            Ok(paths) => {
+                let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+                format!("Saved {} image(s): {}", paths.len(), paths.join(", "))
+            }
+            Err(e) => format!("Failed to save images: {}", e),
+        }
+    } else {
+        for image in &images {
+            send_to_all_outputs(outputs, "image", image, "image").await;
+        }
+        format!("Published {} image(s) to outputs", images.len())
+    }
+}
+
+// Run one tool call and return its result for the model, redacted. `asked` counts the
+// questions put to other agents while answering the message
+async fn run_tool(
+    function: &providers::FunctionCall,
+    provider: &dyn providers::Provider,
+    config: &Config,
+    outputs: &[Box<dyn io::OutputDestination + '_>],
+    turn: &Turn<'_>,
+    asked: &mut u32,
+) -> String {
+    if function.name != "delegate_task" {
+        return call_tool(function, config, outputs, turn, asked).await;
+    }
+    let result = match serde_json::from_str(&function.arguments) {
+        Ok(args) => delegate_task(&args, provider, config, outputs, turn).await,
+        Err(e) => {
+            tracing::warn!("Invalid arguments for {}: {}", function.name, e);
+            format!("Invalid JSON arguments: {}", function.arguments)
+        }
+    };
+    redact_result(turn, &function.name, result)
+}
+
+// Run any tool but delegate_task, which runs tools itself
+async fn call_tool(
+    function: &providers::FunctionCall,
+    config: &Config,
+    outputs: &[Box<dyn io::OutputDestination + '_>],
+    turn: &Turn<'_>,
+    asked: &mut u32,
+) -> String {
+    let function_name = &function.name;
+    let function_args = &function.arguments;
+
+    // Arguments that don't parse are reported back as the call's result, so
+    // the call is still answered and the conversation stays valid
+    let args: Option<serde_json::Value> = match serde_json::from_str(function_args) {
+        Ok(args) => Some(args),
+        Err(e) => {
+            tracing::warn!("Invalid arguments for {}: {}", function_name, e);
+            None
+        }
+    };
+
+    // Execute the function
+    let result = match (function_name.as_str(), &args) {
+        (_, None) => format!("Invalid JSON arguments: {}", function_args),
+        ("get_current_weather", Some(args)) => {
+            let location = args["location"].as_str().unwrap_or("unknown");
+            format!("Weather in {}: Sunny, 72°F", location)
+        }
+        ("calculate", Some(args)) => {
+            let expression = args["expression"].as_str().unwrap_or("0");
+            let result = evaluate_expression(expression);
+            format!("Result: {}", result)
+        }
+        ("generate_image", Some(args)) => generate_image(args, config, outputs).await,
+        ("ask_agent", Some(args)) => {
+            tools::agents::ask_agent(args, config, turn.session, turn.hops, asked).await
+        }
+        ("search_documents", Some(args)) => {
+            let query = args["query"].as_str().unwrap_or("");
+            tools::documents::search_documents(config, query)
+                .await
+                .unwrap_or_else(|e| format!("Document search failed: {:#}", e))
+        }
+        _ => format!("Unknown function: {}", function_name),
+    };
+    redact_result(turn, function_name, result)
+}
+
+fn redact_result(turn: &Turn<'_>, function_name: &str, result: String) -> String {
+    match turn.redactor {
+        Some(redactor) => {
+            redactor.redact(&result, turn.session, &format!("tool {}", function_name))
+        }
+        None => result,
+    }
+}
+
+// Run the delegate_task tool: a session of its own works on the task, with the
+// delegated model and tools, and its final answer is the result
+async fn delegate_task(
+    args: &serde_json::Value,
+    provider: &dyn providers::Provider,
+    config: &Config,
+    outputs: &[Box<dyn io::OutputDestination + '_>],
+    turn: &Turn<'_>,
+) -> String {
+    let task = args["task"].as_str().unwrap_or("");
+    let mut task_config = config.clone();
+    // Nothing of the sub-task is streamed; its result comes back as one tool result
+    task_config.stream = false;
+    let mut own_provider = None;
+    if let Some(model) = &config.delegate_model {
+        let (provider_name, resolved) = config.resolve_model(model);
+        if let Some(name) = provider_name.filter(|name| *name != config.provider) {
+            let created = credentials::load_api_key_for(config, &name).and_then(|key| {
+                providers::get_provider(&name, &key, &config.provider_settings(&name))
+            });
+            match created {
+                Ok(created) => own_provider = Some(created),
+                Err(e) => return format!("Failed to delegate the task: {:#}", e),
+            }
+            task_config.provider = name;
+        }
+        task_config.model = resolved;
+    }
+    let provider = own_provider.as_deref().unwrap_or(provider);
+    let task_tools = tools::delegate::tools(config);
+    let max_steps = config
+        .delegate_max_steps
+        .unwrap_or(tools::delegate::DEFAULT_MAX_STEPS);
+
+    tracing::info!("Delegating task to {}: {}", task_config.model, task);
+    let mut messages = vec![
+        providers::Message {
+            role: "system".to_string(),
+            content: if config.injection_guard {
+                format!(
+                    "{}\n\n{}",
+                    tools::delegate::SYSTEM_MESSAGE,
+                    injection::GUARD
+                )
+            } else {
+                tools::delegate::SYSTEM_MESSAGE.to_string()
+            },
+            tool_calls: None,
+            tool_call_id: None,
+        },
+        providers::Message {
+            role: "user".to_string(),
+            content: task.to_string(),
+            tool_calls: None,
+            tool_call_id: None,
+        },
+    ];
+    let mut asked = 0;
+    for step in 1..=max_steps {
+        // The last step has to answer, without tools
+        let step_tools = task_tools.as_deref().filter(|_| step < max_steps);
+        manage_message_history(&mut messages, &task_config, step_tools);
+        let response = match complete(
+            provider,
+            &task_config,
+            &messages,
+            step_tools,
+            &[],
+            "delegated_task_completion",
+        )
+        .await
+        {
+            Ok(response) => response,
+            Err(e) => return format!("The delegated task failed: {:#}", e),
+        };
+        let Some(tool_calls) = response.tool_calls.filter(|calls| !calls.is_empty()) else {
+            tracing::info!("Delegated task finished after {} step(s)", step);
+            return response.message.content;
+        };
+
+        messages.push(providers::Message {
+            role: "assistant".to_string(),
+            content: response.message.content,
+            tool_calls: Some(tool_calls.clone()),
+            tool_call_id: None,
+        });
+        for tool_call in &tool_calls {
+            let Some(function) = &tool_call.function else {
+                continue;
+            };
+            // Only the delegated tools run, whatever the model asks for
+            let allowed = task_tools
+                .iter()
+                .flatten()
+                .any(|tool| tool.function.name == function.name);
+            let result = if allowed {
+                call_tool(function, &task_config, outputs, turn, &mut asked).await
+            } else {
+                format!("Unknown function: {}", function.name)
+            };
+            let activity = format!(
+                "delegate_task: {}({}) -> {}",
+                function.name, function.arguments, result
+            );
+            send_to_all_outputs(outputs, "tool", &activity, "tool").await;
+            messages.push(providers::Message {
+                role: "tool".to_string(),
+                content: injection::guard(config, &format!("tool {}", function.name), &result),
+                tool_calls: None,
+                tool_call_id: tool_call.id.clone(),
+            });
+        }
+    }
+    "The delegated task ran out of steps without an answer".to_string()
+}
+
+// Update the process_message function to fix the tool_call structure and provider type
+async fn process_message(
+    content: String,
+    provider: &dyn providers::Provider,
+    config: &Config,
+    messages: &mut Vec<providers::Message>,
+    outputs: &[Box<dyn io::OutputDestination + '_>],
+    tools: Option<&[providers::Tool]>,
+    turn: &Turn<'_>,
+) -> Result<()> {
+    // With a budget spent and no fallback model, say so rather than leave the message
+    // unanswered
+    if let Some(reason) =
+        budget::exceeded(config).filter(|_| config.budget_fallback_model.is_none())
+    {
+        send_to_all_outputs(outputs, "system", &reason, "system").await;
+        anyhow::bail!("{}", reason);
+    }
+
+    // Responses are checked whole, so none is streamed before the guardrails and
+    // moderation have seen it
+    let checked = turn.guardrails.is_some()
+        || turn
+            .moderator
+            .is_some_and(|moderator| moderator.checks_output());
+    let stream_to = if checked { &[] } else { outputs };
+
+    // Render the system message afresh, so its date and context files are current
+    if let Some(system) = messages
+        .first_mut()
+        .filter(|message| message.role == "system")
+    {
+        system.content = turn
+            .prompt
+            .render(&config.system_message, config, tools)
+            .unwrap_or_else(|e| {
+                tracing::warn!("{:#}; sending the system message unrendered", e);
+                config.system_message.clone()
+            });
+        if config.injection_guard {
+            system.content = format!("{}\n\n{}", system.content, injection::GUARD);
+        }
+    }
+
+    // Add user message to history
+    messages.push(providers::Message {
+        role: "user".to_string(),
+        content: content.clone(),
+        tool_calls: None,
+        tool_call_id: None,
+    });
+
+    // Make room for it within the context window
+    manage_message_history(messages, config, tools);
+
+    // Send user message to all outputs
+    send_to_all_outputs(outputs, "user", &content, "user").await;
+
+    // Get chat completion with retries
+    tracing::info!("Getting chat completion from AI");
+    let request = with_recalled(messages, turn.recalled);
+    let response = with_processing_notice(
+        outputs,
+        config,
+        complete(
+            provider,
+            config,
+            &request,
+            tools,
+            stream_to,
+            "chat_completion",
+        ),
+    )
+    .await;
+    drop(request);
+    health::report_result("provider", &response);
+    let response = response?;
+
+    // Log the AI's response
+    if let Some(tool_calls) = &response.tool_calls {
+        tracing::info!("AI responded with tool calls: {:?}", tool_calls);
+    } else {
+        tracing::info!("AI responded: {}", response.message.content);
+    }
+
+    // Handle tool calls if present
+    if let Some(tool_calls) = &response.tool_calls {
+        // Add the assistant's response to the conversation
+        messages.push(providers::Message {
+            role: "assistant".to_string(),
+            content: response.message.content.clone(),
+            tool_calls: response.tool_calls.clone(),
+            tool_call_id: None,
+        });
+
+        // Questions to other agents while answering this message
+        let mut asked = 0;
+
+        // Process each tool call
+        for tool_call in tool_calls {
+            // Unwrap the function since it's an Option
+            if let Some(function) = &tool_call.function {
+                let function_name = &function.name;
+                let function_args = &function.arguments;
+
+                tracing::info!(
+                    "Processing tool call: {} with args: {}",
+                    function_name,
+                    function_args
+                );
+                let result = run_tool(function, provider, config, outputs, turn, &mut asked).await;
+
+                // Show the tool activity on outputs that display it
+                let activity = format!("{}({}) -> {}", function_name, function_args, result);
+                send_to_all_outputs(outputs, "tool", &activity, "tool").await;
+
+                // Add the tool result to the conversation, marked as data
+                messages.push(providers::Message {
+                    role: "tool".to_string(),
+                    content: injection::guard(config, &format!("tool {}", function_name), &result),
+                    tool_calls: None,
+                    tool_call_id: tool_call.id.clone(),
+                });
+            }
+        }
+
+        // Tool results count towards the context window too
+        manage_message_history(messages, config, None);
+
+        // Get a follow-up response from the AI with retries
+        tracing::info!("Getting follow-up response from AI");
+        let request = with_recalled(messages, turn.recalled);
+        let follow_up = complete(
+            provider,
+            config,
+            &request,
+            None,
+            stream_to,
+            "follow_up_chat_completion",
+        )
+        .await;
+        drop(request);
+        health::report_result("provider", &follow_up);
+        let follow_up = follow_up?;
+
+        // Send the assistant's response to all outputs
+        let content = follow_up.message.content.clone();
+        let content = apply_guardrails(content, provider, config, messages, turn).await;
+        let content = send_reply(outputs, turn, &content).await;
+
+        // Add the follow-up response to the conversation
+        messages.push(providers::Message {
+            role: "assistant".to_string(),
+            content,
+            tool_calls: None,
+            tool_call_id: None,
+        });
+
+        // Manage message history to prevent excessive memory usage
+        manage_message_history(messages, config, tools);
+
+        tracing::info!("AI follow-up response: {}", follow_up.message.content);
+    } else {
+        // Send the assistant's response to all outputs
+        let content = response.message.content.clone();
+        let content = apply_guardrails(content, provider, config, messages, turn).await;
+        let content = send_reply(outputs, turn, &content).await;
+
+        // Add the assistant's response to the conversation
+        messages.push(providers::Message {
+            role: "assistant".to_string(),
+            content,
+            tool_calls: None,
+            tool_call_id: None,
+        });
+
+        // Manage message history to prevent excessive memory usage
+        manage_message_history(messages, config, tools);
+    }
+
+    Ok(())
+}
+
+// The response made to follow the output guardrails. With the regenerate action the
+// model is told what was wrong and asked again, until a response follows them or the
+// retries run out; whatever still breaks them then is redacted or cut
+async fn apply_guardrails(
+    mut content: String,
+    provider: &dyn providers::Provider,
+    config: &Config,
+    messages: &[providers::Message],
+    turn: &Turn<'_>,
+) -> String {
+    let Some(guardrails) = turn.guardrails else {
+        return content;
+    };
+    for attempt in 0..=guardrails.retries() {
+        let violations = guardrails.violations(&content);
+        if violations.is_empty() {
+            return content;
+        }
+        tracing::warn!(
+            "Response in session {} {}",
+            turn.session,
+            violations.join("; ")
+        );
+        if attempt == guardrails.retries() {
+            break;
+        }
+
+        let mut request = with_recalled(messages, turn.recalled).into_owned();
+        request.push(providers::Message {
+            role: "assistant".to_string(),
+            content: content.clone(),
+            tool_calls: None,
+            tool_call_id: None,
+        });
+        request.push(providers::Message {
+            role: "user".to_string(),
+            content: format!(
+                "Your last reply broke these rules: it {}. Write it again so it follows them, without mentioning the rules.",
+                violations.join("; it ")
+            ),
+            tool_calls: None,
+            tool_call_id: None,
+        });
+        tracing::info!(
+            "Asking for the response again ({} of {})",
+            attempt + 1,
+            guardrails.retries()
+        );
+        match complete(
+            provider,
+            config,
+            &request,
+            None,
+            &[],
+            "guardrail_regeneration",
+        )
+        .await
+        {
+            Ok(response) => content = response.message.content,
+            Err(e) => {
+                tracing::warn!("Failed to get a new response: {:#}", e);
+                break;
+            }
+        }
+    }
+    guardrails.enforce(&content)
+}
+
+// Send a response to the outputs once moderation allows it, or a note that it was
+// blocked, and return what the conversation keeps of it
+async fn send_reply(
+    outputs: &[Box<dyn io::OutputDestination + '_>],
+    turn: &Turn<'_>,
+    content: &str,
+) -> String {
+    let verdict = match turn.moderator {
+        Some(moderator) => moderator.check_output(content, turn.session).await,
+        None => moderation::Verdict::Allow(content.to_string()),
+    };
+    match verdict {
+        moderation::Verdict::Allow(content) => {
+            send_to_all_outputs(outputs, "assistant", &content, "assistant").await;
+            content
+        }
+        moderation::Verdict::Block(reason) => {
+            send_to_all_outputs(outputs, "system", &reason, "system").await;
+            moderation::PLACEHOLDER.to_string()
+        }
+    }
+}
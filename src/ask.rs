@@ -0,0 +1,243 @@
+//! The `ask` subcommand: one prompt through the agent, with the answer on stdout and
+//! an exit status saying how it went, for shell scripts
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::agent::{Agent, GOODBYE};
+use crate::config::Config;
+use crate::io::{InboundMessage, InputSource, OutputDestination};
+use crate::providers;
+
+/// Exit status when the agent answered with a notice instead, e.g. a moderation
+/// block, an exceeded budget or a command's reply
+pub const EXIT_NOTICE: i32 = 3;
+
+/// Exit status when no answer came, e.g. because the provider failed
+pub const EXIT_FAILED: i32 = 1;
+
+// What came back for the prompt
+#[derive(Default)]
+struct Outcome {
+    answer: Vec<String>,
+    notices: Vec<String>,
+    tool_calls: Vec<ToolCall>,
+    // The answer streamed so far, so the whole message isn't printed again
+    streamed: String,
+}
+
+/// What `--json` prints: the answer or the notices given instead, and how it went
+#[derive(Serialize)]
+pub struct Report {
+    pub answer: Option<String>,
+    pub notices: Vec<String>,
+    pub tool_calls: Vec<ToolCall>,
+    pub usage: Usage,
+    pub provider: String,
+    pub model: String,
+    pub duration_ms: u128,
+    pub status: i32,
+}
+
+/// A tool the agent called while answering
+#[derive(Serialize)]
+pub struct ToolCall {
+    pub name: String,
+    /// The arguments as JSON, or as the model wrote them when they aren't
+    pub arguments: serde_json::Value,
+    pub result: String,
+    /// Called by a helper from `delegate_task` rather than the agent itself
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub delegated: bool,
+}
+
+impl ToolCall {
+    // Read the activity the agent sends its outputs, `name(arguments) -> result`
+    fn parse(activity: &str) -> Option<Self> {
+        let (activity, delegated) = match activity.strip_prefix("delegate_task: ") {
+            Some(activity) => (activity, true),
+            None => (activity, false),
+        };
+        let (name, rest) = activity.split_once('(')?;
+        let (arguments, result) = rest.split_once(") -> ")?;
+        Some(Self {
+            name: name.to_string(),
+            arguments: serde_json::from_str(arguments)
+                .unwrap_or_else(|_| serde_json::Value::String(arguments.to_string())),
+            result: result.to_string(),
+            delegated,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+// Hands the agent the prompt, then the exit command so it stops once it is answered
+struct AskSource {
+    messages: Vec<InboundMessage>,
+}
+
+#[async_trait]
+impl InputSource for AskSource {
+    fn name(&self) -> &str {
+        "ask"
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        if self.messages.is_empty() {
+            std::future::pending::<()>().await;
+        }
+        Ok(Some(self.messages.remove(0)))
+    }
+}
+
+// Prints the answer alone, without a role label, and keeps notices for stderr. With
+// json, nothing is printed until the report at the end
+struct AskDestination {
+    outcome: Arc<Mutex<Outcome>>,
+    json: bool,
+}
+
+#[async_trait]
+impl OutputDestination for AskDestination {
+    fn name(&self) -> &str {
+        "ask"
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        if self.json {
+            &["assistant", "system", "tool"]
+        } else {
+            &["assistant", "system"]
+        }
+    }
+
+    async fn write_message(&self, role: &str, content: &str) -> Result<()> {
+        let mut outcome = self.outcome.lock().unwrap();
+        match role {
+            "assistant" => {
+                outcome.answer.push(content.to_string());
+                if std::mem::take(&mut outcome.streamed) != content && !self.json {
+                    println!("{}", content);
+                }
+            }
+            "system" if content != GOODBYE => outcome.notices.push(content.to_string()),
+            "tool" => outcome.tool_calls.extend(ToolCall::parse(content)),
+            _ => {}
+        }
+        std::io::stdout().flush()?;
+        Ok(())
+    }
+
+    async fn write_chunk(&self, role: &str, delta: &str) -> Result<()> {
+        if role == "assistant" && !self.json {
+            self.outcome.lock().unwrap().streamed.push_str(delta);
+            print!("{}", delta);
+            std::io::stdout().flush()?;
+        }
+        Ok(())
+    }
+
+    async fn end_message(&self) -> Result<()> {
+        if !self.outcome.lock().unwrap().streamed.is_empty() {
+            println!();
+        }
+        Ok(())
+    }
+}
+
+/// The question to ask: `question`, or stdin when it is empty or `-`. With `pipe`,
+/// all of stdin always follows the question as one message, lines and all
+pub fn prompt(question: &[String], pipe: bool) -> Result<String> {
+    let mut question = question.join(" ");
+    if question.trim() == "-" {
+        question.clear();
+    }
+    let mut input = String::new();
+    if pipe || question.trim().is_empty() {
+        std::io::stdin()
+            .read_to_string(&mut input)
+            .context("Failed to read the question from stdin")?;
+    }
+    let prompt = match (question.trim(), input.trim()) {
+        ("", "") => anyhow::bail!("No question given, as an argument or on stdin"),
+        (question, "") => question.to_string(),
+        ("", input) => input.to_string(),
+        (question, input) => format!("{}\n\n{}", question, input),
+    };
+    Ok(prompt)
+}
+
+/// Run an `ask` subcommand or `--pipe`: send `prompt` to the agent with only this
+/// command as its input and output, printing a [`Report`] with `json`. Returns the
+/// exit status
+pub async fn run(mut config: Config, prompt: String, stream: bool, json: bool) -> Result<i32> {
+    // The config's own inputs, outputs and routes would make it a server
+    config.inputs_vec.clear();
+    config.inputs.clear();
+    config.outputs_vec.clear();
+    config.outputs.clear();
+    config.routes.clear();
+    config.processing_message = Some(String::new());
+    // The exit command after the question mustn't cut its answer off
+    config.interrupt_replies = false;
+    // Nothing is printed until the end with json, so there's nothing to stream
+    config.stream = (config.stream || stream) && !json;
+    let (provider, model) = (config.provider.clone(), config.model.clone());
+
+    let started = Instant::now();
+    let outcome = Arc::new(Mutex::new(Outcome::default()));
+    Agent::builder()
+        .config(config)
+        .input(AskSource {
+            messages: vec![InboundMessage::new(prompt), InboundMessage::new("exit")],
+        })
+        .output(AskDestination {
+            outcome: outcome.clone(),
+            json,
+        })
+        .run()
+        .await?;
+
+    let mut outcome = outcome.lock().unwrap();
+    let status = if !outcome.answer.is_empty() {
+        0
+    } else if !outcome.notices.is_empty() {
+        EXIT_NOTICE
+    } else {
+        eprintln!("No answer came back; the log above says why");
+        EXIT_FAILED
+    };
+    if json {
+        let usage = providers::total_usage();
+        let report = Report {
+            answer: (!outcome.answer.is_empty()).then(|| outcome.answer.join("\n")),
+            notices: std::mem::take(&mut outcome.notices),
+            tool_calls: std::mem::take(&mut outcome.tool_calls),
+            usage: Usage {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+                total_tokens: usage.prompt_tokens + usage.completion_tokens,
+            },
+            provider,
+            model,
+            duration_ms: started.elapsed().as_millis(),
+            status,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for notice in &outcome.notices {
+            eprintln!("{}", notice);
+        }
+    }
+    Ok(status)
+}
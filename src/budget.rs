@@ -0,0 +1,177 @@
+//! Daily and monthly spending limits, counted from the usage the providers report.
+//! Once one is spent, completions run on the fallback model or are refused until the
+//! day or month is over
+
+use anyhow::{Context, Result};
+use chrono::{Datelike, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+use crate::config::Config;
+use crate::providers::{self, Usage};
+
+// One completion, a line of the usage log
+#[derive(Serialize, Deserialize)]
+struct LogEntry {
+    timestamp: String,
+    agent: String,
+    model: String,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+/// Tokens and dollars spent over some time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Spending {
+    pub tokens: u64,
+    pub usd: f64,
+}
+
+/// What the agent spent from `since` on; models without a price in `model_prices`
+/// cost nothing
+pub fn spent_since(config: &Config, since: NaiveDate) -> Spending {
+    let mut spending = Spending::default();
+    for (model, usage) in providers::usage_since(&config.agent_name, since) {
+        spending.tokens += usage.prompt_tokens + usage.completion_tokens;
+        if let Some(price) = config.model_prices.get(&model) {
+            spending.usd += (usage.prompt_tokens as f64 * price.prompt
+                + usage.completion_tokens as f64 * price.completion)
+                / 1_000_000.0;
+        }
+    }
+    spending
+}
+
+/// Why no more completions can run, if a budget is spent
+pub fn exceeded(config: &Config) -> Option<String> {
+    let today = Local::now().date_naive();
+    let month = today.with_day(1)?;
+    let periods = [
+        (
+            "daily",
+            today,
+            config.budget_daily_tokens,
+            config.budget_daily_usd,
+            "midnight",
+        ),
+        (
+            "monthly",
+            month,
+            config.budget_monthly_tokens,
+            config.budget_monthly_usd,
+            "the start of next month",
+        ),
+    ];
+    for (period, since, tokens, usd, resets) in periods {
+        if tokens.is_none() && usd.is_none() {
+            continue;
+        }
+        let spent = spent_since(config, since);
+        if let Some(limit) = tokens.filter(|limit| spent.tokens >= *limit) {
+            return Some(format!(
+                "The {} budget of {} tokens is spent; it renews at {}",
+                period, limit, resets
+            ));
+        }
+        if let Some(limit) = usd.filter(|limit| spent.usd >= *limit) {
+            return Some(format!(
+                "The {} budget of ${:.2} is spent; it renews at {}",
+                period, limit, resets
+            ));
+        }
+    }
+    None
+}
+
+/// The config to run a completion with: unchanged while the budgets last, then on
+/// `budget_fallback_model` if set, or else an error saying which budget is spent
+pub fn within_budget(config: &Config) -> Result<Cow<'_, Config>> {
+    let Some(reason) = exceeded(config) else {
+        return Ok(Cow::Borrowed(config));
+    };
+    let Some(fallback) = &config.budget_fallback_model else {
+        anyhow::bail!("{}", reason);
+    };
+    let (provider, model) = config.resolve_model(fallback);
+    if provider.is_some_and(|provider| provider != config.provider) {
+        anyhow::bail!(
+            "{}. The fallback model {} isn't on {}",
+            reason,
+            fallback,
+            config.provider
+        );
+    }
+    tracing::info!("{}. Answering with {} until then", reason, model);
+    let mut fallback = config.clone();
+    fallback.model = model;
+    Ok(Cow::Owned(fallback))
+}
+
+/// Record a completion's usage, appending it to `usage_log` when that is set
+pub fn record(config: &Config, usage: &Usage) {
+    providers::record_usage(&config.agent_name, &config.model, usage);
+    let Some(path) = &config.usage_log else {
+        return;
+    };
+    let entry = LogEntry {
+        timestamp: Local::now().to_rfc3339(),
+        agent: config.agent_name.clone(),
+        model: config.model.clone(),
+        prompt_tokens: usage.prompt_tokens,
+        completion_tokens: usage.completion_tokens,
+    };
+    let written = serde_json::to_string(&entry)
+        .map_err(anyhow::Error::from)
+        .and_then(|line| {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            writeln!(file, "{}", line)?;
+            Ok(())
+        });
+    if let Err(e) = written {
+        tracing::warn!("Failed to write to usage log {}: {:#}", path, e);
+    }
+}
+
+/// Count this month's usage from `usage_log`, so budgets carry over a restart;
+/// returns how many completions it held
+pub fn load_log(config: &Config) -> Result<usize> {
+    let Some(path) = &config.usage_log else {
+        return Ok(0);
+    };
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e).context(format!("Failed to open usage log: {}", path)),
+    };
+    let today = Local::now().date_naive();
+    let month = today.with_day(1).unwrap_or(today);
+    let mut usage: BTreeMap<(NaiveDate, String), Usage> = BTreeMap::new();
+    let mut count = 0;
+    for line in std::io::BufReader::new(file).lines() {
+        let line = line.context(format!("Failed to read usage log: {}", path))?;
+        let Ok(entry) = serde_json::from_str::<LogEntry>(&line) else {
+            tracing::warn!("Skipping an unreadable line in usage log {}", path);
+            continue;
+        };
+        let Ok(timestamp) = chrono::DateTime::parse_from_rfc3339(&entry.timestamp) else {
+            continue;
+        };
+        let day = timestamp.with_timezone(&Local).date_naive();
+        if entry.agent != config.agent_name || day < month {
+            continue;
+        }
+        let total = usage.entry((day, entry.model)).or_default();
+        total.prompt_tokens += entry.prompt_tokens;
+        total.completion_tokens += entry.completion_tokens;
+        count += 1;
+    }
+    for ((day, model), usage) in usage {
+        providers::add_to_ledger(day, &config.agent_name, &model, &usage);
+    }
+    Ok(count)
+}
@@ -1,41 +1,82 @@
-use clap::Parser;
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+
+use crate::config::Config;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    /// Path to YAML configuration file
-    #[arg(short, long)]
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to the YAML, TOML or JSON configuration file (default: config.yaml, config.toml or config.json in $XDG_CONFIG_HOME/agent, ~/.config/agent, /etc/agent, then the working directory)
+    #[arg(short, long, global = true)]
     pub config: Option<String>,
 
+    /// Config profile to overlay on the base config, from its profiles section
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
+    /// Settings laid over the config file's
+    #[command(flatten)]
+    pub overrides: Overrides,
+
+    /// Enable verbose logging (debug level)
+    #[arg(short, long, default_value = "false")]
+    pub verbose: bool,
+
+    /// Answer all of stdin as one message, after PROMPT if given, and exit, as `ask` does
+    #[arg(long, value_name = "PROMPT", num_args = 0..=1, default_missing_value = "")]
+    pub pipe: Option<String>,
+
+    /// Print JSON on stdout instead of text, for ask, --pipe, validate and models
+    #[arg(long, global = true, default_value = "false")]
+    pub json: bool,
+
+    /// Run under the Windows service manager, as `agent service install` sets up
+    #[arg(long, hide = true, default_value = "false")]
+    pub windows_service: bool,
+
+    /// What to log per module, in RUST_LOG syntax, e.g. info,rumqttc=warn,agent::io=debug (default: RUST_LOG, else info)
+    #[arg(long)]
+    pub log_filter: Option<String>,
+}
+
+/// The settings given on the command line that replace the config's. Each is left
+/// out when not given, so the config file, its profile and the built-in defaults
+/// show through
+#[derive(clap::Args, Debug, Clone, Default)]
+#[command(about = None, long_about = None)]
+pub struct Overrides {
+    /// Override a config setting by its key or dotted path, e.g. mqtt_input_qos=2 or providers.openai.base_url=http://localhost:8080/v1 (repeatable)
+    #[arg(long = "set", value_name = "KEY=VALUE", global = true)]
+    pub set: Vec<String>,
+
     /// Name of the agent instance (default: "agent")
-    #[arg(short = 'n', long, default_value = "agent")]
-    pub agent_name: String,
+    #[arg(short = 'n', long, global = true)]
+    pub agent_name: Option<String>,
 
-    /// Provider to use (e.g., openai, anthropic)
-    #[arg(short, long, default_value = "openai")]
-    pub provider: String,
+    /// Provider to use (e.g., openai, anthropic; default: openai)
+    #[arg(short, long, global = true)]
+    pub provider: Option<String>,
 
-    /// Model to use (e.g., gpt-4o, claude-3-opus-20240229)
-    #[arg(short, long, default_value = "gpt-4o")]
-    pub model: String,
+    /// Model to use (e.g., gpt-4o, claude-3-opus-20240229), or a model alias from the config (default: gpt-4o)
+    #[arg(short, long, global = true)]
+    pub model: Option<String>,
 
     /// System message to set the behavior of the assistant
-    #[arg(
-        short,
-        long,
-        default_value = "You are a helpful AI assistant with access to tools for retrieving weather information and performing calculations. You can answer questions, provide information, and assist with various tasks. When asked about weather or calculations, use the appropriate tools to provide accurate responses. Be concise, helpful, and friendly in your interactions."
-    )]
-    pub system_message: String,
+    #[arg(short, long, global = true)]
+    pub system_message: Option<String>,
 
     /// Enable tool usage (e.g., functions)
     #[arg(short, long, default_value = "false")]
     pub enable_tools: bool,
 
-    /// Input sources (comma-separated list: stdin, mqtt)
+    /// Input sources (comma-separated list: stdin, tui, command, http_poll, mqtt, mqtt:<name>, webhook, api, tcp, grpc, nats, pubsub, matrix, xmpp, twitch, nostr, email, voice)
     #[arg(long, required = false)]
     pub inputs: Option<String>,
 
-    /// Output destinations (comma-separated list: stdout, mqtt)
+    /// Output destinations (comma-separated list: stdout, tui, mqtt, mqtt:<name>, webhook, chat_webhook, sse, github, api, tcp, grpc, nats, pubsub, exec, file, sqlite, matrix, xmpp, twitch, nostr, email)
     #[arg(long, required = false)]
     pub outputs: Option<String>,
 
@@ -59,11 +100,295 @@ pub struct Args {
     #[arg(long)]
     pub mqtt_output_topic: Option<String>,
 
-    /// Maximum number of messages to keep in history (default: 50)
+    /// Address the webhook input server binds to (default: 0.0.0.0)
+    #[arg(long)]
+    pub webhook_bind: Option<String>,
+
+    /// Webhook input server port (default: random available port)
+    #[arg(long)]
+    pub webhook_port: Option<u16>,
+
+    /// URL to send webhook output to (required when using webhook output)
+    #[arg(long)]
+    pub webhook_url: Option<String>,
+
+    /// Maximum number of messages to keep in history, on top of the token budget (default: no limit)
     #[arg(long)]
     pub max_history_messages: Option<usize>,
 
-    /// Enable verbose logging (debug level)
-    #[arg(short, long, default_value = "false")]
-    pub verbose: bool,
+    /// JSON transcript from `agent export` to start its conversation from
+    #[arg(long)]
+    pub resume: Option<String>,
+
+    /// Directory to save generated images to (default: publish to outputs)
+    #[arg(long)]
+    pub image_output_dir: Option<String>,
+
+    /// Chat in a full-screen terminal UI instead of plain stdin/stdout
+    #[arg(long, default_value = "false")]
+    pub tui: bool,
+}
+
+impl Args {
+    /// The config to run with: the file at `path` with --profile applied, and then
+    /// the settings given on the command line
+    pub fn load_config(&self, path: &str) -> Result<Config> {
+        self.overrides
+            .apply(Config::load(path, self.profile.as_deref())?)
+    }
+
+    /// The config of one agent for a subcommand: its agents entry laid over the
+    /// merged config, or the whole merged config when there is no agents map. The
+    /// agent is `agent`, or else the merged config's agent_name
+    pub fn load_agent_config(&self, path: &str, agent: Option<&str>) -> Result<Config> {
+        let config = self.load_config(path)?;
+        if config.agents.is_empty() {
+            return Ok(config);
+        }
+        config.for_agent(agent.unwrap_or(&config.agent_name))
+    }
+}
+
+impl Overrides {
+    /// `config` with these settings laid over it, --set last so it can change
+    /// anything, and model aliases resolved once everything is merged. The config
+    /// keeps them, so a reload can lay them over the reloaded file the same way
+    pub fn apply(&self, mut config: Config) -> Result<Config> {
+        if let Some(name) = &self.agent_name {
+            config.agent_name = name.clone();
+        }
+        if let Some(provider) = &self.provider {
+            config.provider = provider.clone();
+        }
+        if let Some(model) = &self.model {
+            config.model = model.clone();
+        }
+        if let Some(system_message) = &self.system_message {
+            config.system_message = system_message.clone();
+        }
+
+        let list = |items: &str| items.split(',').map(|s| s.trim().to_string()).collect();
+        if let Some(inputs) = &self.inputs {
+            config.inputs_vec = list(inputs);
+            config.inputs.clear();
+        }
+        if let Some(outputs) = &self.outputs {
+            config.outputs_vec = list(outputs);
+            config.outputs.clear();
+        }
+
+        // The terminal UI takes the place of stdin and stdout
+        if self.tui {
+            config.inputs_vec.retain(|input| input != "stdin");
+            config.inputs.retain(|input| input.kind != "stdin");
+            config.outputs_vec.retain(|output| output != "stdout");
+            config.outputs.retain(|output| output.kind != "stdout");
+            if !config.has_input("tui") {
+                config.inputs_vec.push("tui".to_string());
+            }
+            if !config
+                .output_entries()
+                .iter()
+                .any(|output| output.kind == "tui")
+            {
+                config.outputs_vec.push("tui".to_string());
+            }
+        }
+
+        if self.enable_tools {
+            config.enable_tools = true;
+        }
+        if self.daemon {
+            config.daemon = true;
+        }
+        if let Some(broker) = &self.mqtt_broker {
+            config.mqtt_broker = Some(broker.clone());
+        }
+        if let Some(port) = self.mqtt_port {
+            config.mqtt_port = Some(port);
+        }
+        if let Some(input_topic) = &self.mqtt_input_topic {
+            config.mqtt_input_topic = Some(input_topic.clone());
+        }
+        if let Some(output_topic) = &self.mqtt_output_topic {
+            config.mqtt_output_topic = Some(output_topic.clone());
+        }
+        if let Some(bind) = &self.webhook_bind {
+            config.webhook_bind = Some(bind.clone());
+        }
+        if let Some(port) = self.webhook_port {
+            config.webhook_port = Some(port);
+        }
+        if let Some(url) = &self.webhook_url {
+            config.webhook_url = Some(url.clone());
+        }
+        if let Some(max_history) = self.max_history_messages {
+            config.max_history_messages = Some(max_history);
+        }
+        if let Some(transcript) = &self.resume {
+            config.resume = Some(transcript.clone());
+        }
+        if let Some(image_dir) = &self.image_output_dir {
+            config.image_output_dir = Some(image_dir.clone());
+        }
+
+        let mut config = config.with_overrides(&self.set)?;
+        config.resolve_models();
+        config.cli = self.clone();
+        Ok(config)
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Manage the agent as a platform service (systemd, launchd, Windows)
+    Service {
+        #[command(subcommand)]
+        action: ServiceAction,
+    },
+
+    /// Run the agent (the default when no subcommand is given)
+    Run {
+        /// Run the agent as a child process and restart it when it crashes
+        #[arg(long, default_value = "false")]
+        supervise: bool,
+
+        /// Crashes allowed within the restart window before giving up
+        #[arg(long, default_value = "5")]
+        max_restarts: u32,
+
+        /// Restart window in seconds for the crash-loop circuit breaker
+        #[arg(long, default_value = "300")]
+        restart_window: u64,
+    },
+
+    /// Write a commented starter config to --config (default: config.yaml)
+    Init {
+        /// Agent name (default: agent)
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Comma-separated input sources (default: stdin)
+        #[arg(long)]
+        inputs: Option<String>,
+
+        /// Comma-separated output destinations (default: stdout)
+        #[arg(long)]
+        outputs: Option<String>,
+
+        /// Enable tool usage (default: true)
+        #[arg(long)]
+        tools: Option<bool>,
+
+        /// Don't ask for anything not given; use the defaults
+        #[arg(short, long, default_value = "false")]
+        yes: bool,
+
+        /// Replace the file if it exists
+        #[arg(long, default_value = "false")]
+        force: bool,
+    },
+
+    /// Check the config, API key and input/output settings without starting the agent
+    Validate,
+
+    /// List the models the provider offers, from its API
+    Models,
+
+    /// Store or remove provider API keys in the OS keyring (requires the keyring feature)
+    Keyring {
+        #[command(subcommand)]
+        action: KeyringAction,
+    },
+
+    /// Send one question to the agent, print the answer and exit
+    Ask {
+        /// The question (default: read from stdin, as with -)
+        question: Vec<String>,
+
+        /// Print the answer as it is generated
+        #[arg(long)]
+        stream: bool,
+
+        /// Agent to ask, by name or agents entry (default: --agent-name)
+        #[arg(long)]
+        agent: Option<String>,
+    },
+
+    /// Run the same prompt through several models and diff the responses
+    Compare {
+        /// Comma-separated models, optionally prefixed with a provider (e.g. gpt-4o,anthropic:claude-3-5-sonnet-latest)
+        #[arg(long)]
+        models: String,
+
+        /// File containing the prompt to send
+        #[arg(long)]
+        prompt_file: String,
+
+        /// JSON file with prior conversation messages to send before the prompt
+        #[arg(long)]
+        history_file: Option<String>,
+
+        /// Output format (text, json)
+        #[arg(long, default_value = "text")]
+        format: String,
+
+        /// Total width of the side-by-side text output
+        #[arg(long, default_value = "120")]
+        width: usize,
+    },
+
+    /// Chunk, embed and index files and URLs for the search_documents tool
+    Ingest {
+        /// Files, directories or URLs to index (default: the config's documents)
+        sources: Vec<String>,
+
+        /// Agent whose documents to index, by name or agents entry (default: --agent-name)
+        #[arg(long)]
+        agent: Option<String>,
+    },
+
+    /// Write a conversation from the history database to Markdown or JSON
+    Export {
+        /// Session ID of the conversation, e.g. stdin or mqtt:alice (default: the only one)
+        #[arg(long)]
+        session: Option<String>,
+
+        /// Output format (md, json)
+        #[arg(long, default_value = "md")]
+        format: String,
+
+        /// File to write to (default: stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Agent whose conversation to export, by name or agents entry (default: --agent-name)
+        #[arg(long)]
+        agent: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ServiceAction {
+    /// Generate and register a service for the current binary and config
+    Install {
+        /// Print the service definition instead of installing it
+        #[arg(long, default_value = "false")]
+        print: bool,
+    },
+
+    /// Stop and remove the registered service
+    Uninstall,
+
+    /// Show the status of the registered service
+    Status,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KeyringAction {
+    /// Store the configured provider's API key, read from stdin
+    Set,
+
+    /// Remove the configured provider's stored API key
+    Delete,
 }
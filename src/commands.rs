@@ -0,0 +1,107 @@
+use anyhow::Result;
+
+/// Prefix of runtime commands unless the config sets `command_prefix`
+pub const DEFAULT_PREFIX: &str = "/";
+
+/// A command typed into any input, answered by the agent instead of the model
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlashCommand {
+    Help,
+    // Forget the sender's conversation
+    Clear,
+    // Show the sender's conversation
+    History,
+    // Stop answering the sender's last message
+    Stop,
+    // Show the model, or switch to another model or alias
+    Model(Option<String>),
+    // Show the provider, or switch to another, with a model of its own if given
+    Provider(Option<(String, Option<String>)>),
+    // Show or replace the system message
+    System(Option<String>),
+    // Show whether tools are offered, or turn them on or off
+    Tools(Option<bool>),
+    // Tokens used so far
+    Usage,
+    // Export the sender's conversation as md or json
+    Export(Option<String>),
+}
+
+/// Commands and what they do, for `help`
+pub const HELP: &[(&str, &str)] = &[
+    ("clear", "forget this conversation"),
+    ("history", "show this conversation"),
+    ("stop", "stop the reply in progress"),
+    ("model [name]", "show or switch the model"),
+    ("provider [name] [model]", "show or switch the provider"),
+    ("system [prompt]", "show or replace the system message"),
+    ("tools [on|off]", "show or switch tool use"),
+    ("usage", "show tokens used"),
+    ("export [md|json]", "export this conversation"),
+    ("help", "list commands"),
+];
+
+impl SlashCommand {
+    /// Read a message as a command: `None` unless it is `prefix` followed by a word,
+    /// so messages such as `/etc/hosts is missing` still go to the model
+    pub fn parse(message: &str, prefix: &str) -> Option<Result<Self>> {
+        if prefix.is_empty() {
+            return None;
+        }
+        let rest = message.trim().strip_prefix(prefix)?;
+        let (name, argument) = match rest.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, Some(argument.trim().to_string())),
+            None => (rest, None),
+        };
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return None;
+        }
+        let argument = argument.filter(|argument| !argument.is_empty());
+
+        Some(match name.to_lowercase().as_str() {
+            "help" => Ok(Self::Help),
+            "clear" => Ok(Self::Clear),
+            "history" => Ok(Self::History),
+            "stop" => Ok(Self::Stop),
+            "model" => Ok(Self::Model(argument)),
+            "provider" => Ok(Self::Provider(argument.map(|argument| {
+                match argument.split_once(char::is_whitespace) {
+                    Some((name, model)) => (name.to_string(), Some(model.trim().to_string())),
+                    None => (argument, None),
+                }
+            }))),
+            "system" => Ok(Self::System(argument)),
+            "tools" => match argument.as_deref() {
+                None => Ok(Self::Tools(None)),
+                Some("on") => Ok(Self::Tools(Some(true))),
+                Some("off") => Ok(Self::Tools(Some(false))),
+                Some(other) => Err(anyhow::anyhow!(
+                    "{}tools takes on or off, not {}",
+                    prefix,
+                    other
+                )),
+            },
+            "usage" => Ok(Self::Usage),
+            "export" => Ok(Self::Export(argument)),
+            other => Err(anyhow::anyhow!(
+                "Unknown command {}{}; {}help lists them",
+                prefix,
+                other,
+                prefix
+            )),
+        })
+    }
+}
+
+/// The list of commands, written with `prefix`
+pub fn help(prefix: &str) -> String {
+    let lines: Vec<String> = HELP
+        .iter()
+        .map(|(usage, description)| format!("{}{} - {}", prefix, usage, description))
+        .collect();
+    format!("Commands:\n{}", lines.join("\n"))
+}
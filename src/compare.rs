@@ -0,0 +1,335 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::time::Instant;
+
+use crate::config::Config;
+use crate::credentials;
+use crate::prompt;
+use crate::providers::{self, Message};
+
+/// Outcome of running the prompt through one model
+#[derive(Debug, Serialize)]
+pub struct ModelResult {
+    pub provider: String,
+    pub model: String,
+    pub latency_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A single line-level diff operation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "op", content = "line", rename_all = "lowercase")]
+pub enum DiffOp {
+    Equal(String),
+    Delete(String),
+    Insert(String),
+}
+
+/// Diff of one model's response against the baseline (first) model
+#[derive(Debug, Serialize)]
+pub struct ModelDiff {
+    pub from: String,
+    pub to: String,
+    pub ops: Vec<DiffOp>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Comparison {
+    pub results: Vec<ModelResult>,
+    pub diffs: Vec<ModelDiff>,
+}
+
+/// Parse a model spec: `provider:model`, a bare model with an inferred provider, or a
+/// model alias
+fn parse_model_spec(spec: &str, config: &Config) -> (String, String) {
+    if config.model_aliases.contains_key(spec) {
+        let (provider, model) = config.resolve_model(spec);
+        let provider = provider
+            .or_else(|| providers::provider_for_model(&model).map(String::from))
+            .unwrap_or_else(|| config.provider.clone());
+        return (provider, model);
+    }
+    match spec.split_once(':') {
+        Some((provider, model)) => (provider.to_string(), model.to_string()),
+        None => (
+            providers::provider_for_model(spec)
+                .unwrap_or(&config.provider)
+                .to_string(),
+            spec.to_string(),
+        ),
+    }
+}
+
+/// Run a `compare` subcommand
+pub async fn run(
+    config: &Config,
+    models: &str,
+    prompt_file: &str,
+    history_file: Option<&str>,
+    format: &str,
+    width: usize,
+) -> Result<()> {
+    let prompt = std::fs::read_to_string(prompt_file)
+        .context(format!("Failed to read prompt file: {}", prompt_file))?;
+
+    let mut messages = Vec::new();
+    let system_message =
+        prompt::SystemPrompt::load(config).render(&config.system_message, config, None)?;
+    if !system_message.is_empty() {
+        messages.push(Message {
+            role: "system".to_string(),
+            content: system_message,
+            tool_calls: None,
+            tool_call_id: None,
+        });
+    }
+    if let Some(path) = history_file {
+        let contents = std::fs::read_to_string(path)
+            .context(format!("Failed to read history file: {}", path))?;
+        let history: Vec<Message> =
+            serde_json::from_str(&contents).context("Failed to parse history file")?;
+        messages.extend(history);
+    }
+    messages.push(Message {
+        role: "user".to_string(),
+        content: prompt.trim().to_string(),
+        tool_calls: None,
+        tool_call_id: None,
+    });
+
+    let specs: Vec<(String, String)> = models
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| parse_model_spec(s, config))
+        .collect();
+
+    if specs.len() < 2 {
+        anyhow::bail!("At least two models are required to compare");
+    }
+
+    // Run all models concurrently
+    let futures = specs
+        .into_iter()
+        .map(|(provider, model)| run_model(config, provider, model, &messages));
+    let results = futures::future::join_all(futures).await;
+
+    let comparison = compare(results);
+
+    match format {
+        "json" => println!("{}", serde_json::to_string_pretty(&comparison)?),
+        "text" => print!("{}", render_text(&comparison, width)),
+        _ => anyhow::bail!("Unsupported format: {} (expected text or json)", format),
+    }
+
+    Ok(())
+}
+
+async fn run_model(
+    config: &Config,
+    provider_name: String,
+    model: String,
+    messages: &[Message],
+) -> ModelResult {
+    let start = Instant::now();
+
+    let outcome = async {
+        let api_key = credentials::load_api_key_for(config, &provider_name)?;
+        let provider = providers::get_provider(
+            &provider_name,
+            &api_key,
+            &config.provider_settings(&provider_name),
+        )?;
+        provider.chat_completion(&model, messages, None).await
+    }
+    .await;
+
+    let latency_ms = start.elapsed().as_millis();
+    match outcome {
+        Ok(response) => ModelResult {
+            provider: provider_name,
+            model,
+            latency_ms,
+            content: Some(response.message.content),
+            error: None,
+        },
+        Err(e) => ModelResult {
+            provider: provider_name,
+            model,
+            latency_ms,
+            content: None,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+/// Diff every result against the first one
+pub fn compare(results: Vec<ModelResult>) -> Comparison {
+    let mut diffs = Vec::new();
+
+    if let Some((baseline, others)) = results.split_first() {
+        let baseline_text = baseline.content.as_deref().unwrap_or("");
+        let baseline_lines: Vec<&str> = baseline_text.lines().collect();
+
+        for other in others {
+            let other_lines: Vec<&str> = other.content.as_deref().unwrap_or("").lines().collect();
+            diffs.push(ModelDiff {
+                from: baseline.model.clone(),
+                to: other.model.clone(),
+                ops: diff_lines(&baseline_lines, &other_lines),
+            });
+        }
+    }
+
+    Comparison { results, diffs }
+}
+
+/// Line-level diff using the longest common subsequence
+pub fn diff_lines(a: &[&str], b: &[&str]) -> Vec<DiffOp> {
+    // lcs[i][j] is the LCS length of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            ops.push(DiffOp::Equal(a[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            ops.push(DiffOp::Delete(a[i].to_string()));
+            i += 1;
+        } else {
+            ops.push(DiffOp::Insert(b[j].to_string()));
+            j += 1;
+        }
+    }
+    ops.extend(a[i..].iter().map(|line| DiffOp::Delete(line.to_string())));
+    ops.extend(b[j..].iter().map(|line| DiffOp::Insert(line.to_string())));
+
+    ops
+}
+
+/// Render results side by side, followed by a diff against the baseline
+pub fn render_text(comparison: &Comparison, width: usize) -> String {
+    let count = comparison.results.len().max(1);
+    let separator = " | ";
+    let column_width = width
+        .saturating_sub(separator.len() * (count - 1))
+        .checked_div(count)
+        .unwrap_or(0)
+        .max(10);
+
+    let headers: Vec<String> = comparison
+        .results
+        .iter()
+        .map(|r| format!("{}:{} ({} ms)", r.provider, r.model, r.latency_ms))
+        .collect();
+
+    let columns: Vec<Vec<String>> = comparison
+        .results
+        .iter()
+        .map(|r| {
+            let text = match (&r.content, &r.error) {
+                (Some(content), _) => content.clone(),
+                (None, Some(error)) => format!("ERROR: {}", error),
+                (None, None) => String::new(),
+            };
+            wrap(&text, column_width)
+        })
+        .collect();
+
+    let mut out = String::new();
+    let row = |cells: Vec<String>| -> String {
+        let padded: Vec<String> = cells
+            .iter()
+            .map(|cell| format!("{:<width$}", cell, width = column_width))
+            .collect();
+        format!("{}\n", padded.join(separator).trim_end())
+    };
+
+    out.push_str(&row(headers
+        .iter()
+        .map(|h| truncate(h, column_width))
+        .collect()));
+    out.push_str(&row(vec!["-".repeat(column_width); count]));
+
+    let height = columns.iter().map(Vec::len).max().unwrap_or(0);
+    for line in 0..height {
+        out.push_str(&row(columns
+            .iter()
+            .map(|column| column.get(line).cloned().unwrap_or_default())
+            .collect()));
+    }
+
+    for diff in &comparison.diffs {
+        out.push_str(&format!("\n--- {}\n+++ {}\n", diff.from, diff.to));
+        for op in &diff.ops {
+            let line = match op {
+                DiffOp::Equal(line) => format!("  {}", line),
+                DiffOp::Delete(line) => format!("- {}", line),
+                DiffOp::Insert(line) => format!("+ {}", line),
+            };
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+// Wrap text on word boundaries to the given width, hard-splitting long words
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for paragraph in text.lines() {
+        let mut current = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word = word;
+            while word.chars().count() > width {
+                if !current.is_empty() {
+                    lines.push(std::mem::take(&mut current));
+                }
+                let split = word
+                    .char_indices()
+                    .nth(width)
+                    .map(|(idx, _)| idx)
+                    .unwrap_or(word.len());
+                lines.push(word[..split].to_string());
+                word = &word[split..];
+            }
+
+            let needed = if current.is_empty() {
+                word.chars().count()
+            } else {
+                current.chars().count() + 1 + word.chars().count()
+            };
+            if needed > width && !current.is_empty() {
+                lines.push(std::mem::take(&mut current));
+            }
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(word);
+        }
+        lines.push(current);
+    }
+
+    lines
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
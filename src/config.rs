@@ -1,32 +1,176 @@
 use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::providers::{ProviderSettings, PROVIDERS};
 
 /// Configuration for the agent
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     /// Name of the agent instance (default: "agent")
+    #[serde(default = "default_agent_name")]
     pub agent_name: String,
 
+    /// Named sets of settings that overlay the rest of the config when selected
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, serde_json::Value>,
+
+    /// The profile applied to this config, if any
+    #[serde(skip)]
+    pub profile: Option<String>,
+
+    /// Agents run together in one process, each with its own settings laid over the
+    /// rest of the config; empty runs a single agent
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub agents: BTreeMap<String, serde_json::Value>,
+
+    /// The agents entry this config was made from, if any
+    #[serde(skip)]
+    pub agent: Option<String>,
+
+    /// Other config files merged under this one, relative to this file's directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
+    /// Every file merged into this config through include, in the order applied
+    #[serde(skip)]
+    pub included: Vec<String>,
+
+    /// The command line settings applied to this config, `--set` among them, kept
+    /// so reloads apply them again
+    #[serde(skip)]
+    pub cli: crate::cli::Overrides,
+
     /// Provider to use (e.g., openai, anthropic)
+    #[serde(default = "default_provider")]
     pub provider: String,
 
     /// Model to use (e.g., gpt-4o, claude-3-opus-20240229)
+    #[serde(default = "default_model")]
     pub model: String,
 
+    /// Names usable wherever a model is given, e.g. fast: gpt-4o-mini; a value may
+    /// name its provider too, as in smart: anthropic:claude-3-5-sonnet-latest
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub model_aliases: BTreeMap<String, String>,
+
+    /// Stream completions, so outputs show responses as they are generated
+    /// (default: false, each response arrives in one piece)
+    #[serde(default)]
+    pub stream: bool,
+
+    /// Notice the outputs get while a reply is on its way (default: Processing your
+    /// request...); empty sends none
+    pub processing_message: Option<String>,
+
+    /// How long a reply may take before the notice is sent, in milliseconds (default: 0)
+    pub processing_delay_ms: Option<u64>,
+
+    /// File to read the provider API key from (default: {PROVIDER}_API_KEY env var)
+    pub api_key_file: Option<String>,
+
+    /// Command whose output is the provider API key, e.g. a secret manager CLI
+    pub api_key_command: Option<String>,
+
+    /// API key sources and endpoints for each provider, keyed by provider name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub providers: BTreeMap<String, ProviderSettings>,
+
+    /// OS keyring service holding provider API keys, stored under each provider's name
+    /// (requires the keyring feature)
+    pub api_key_keyring: Option<String>,
+
+    /// YAML file mapping provider names to API keys, readable only by its owner
+    pub secrets_file: Option<String>,
+
     /// System message to set the behavior of the assistant
+    #[serde(default = "default_system_message")]
     pub system_message: String,
 
+    /// Files whose contents are added to the system message, where it says
+    /// {{ context }} or after it; both are templates rendered for every request
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub context_files: Vec<String>,
+
+    /// Variables of your own for the system message and context files, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub prompt_variables: BTreeMap<String, serde_json::Value>,
+
     /// Enable tool usage (e.g., functions)
+    #[serde(default)]
     pub enable_tools: bool,
 
-    /// Input sources (list: stdin, mqtt)
+    /// Input sources (list: stdin, mqtt, webhook, api, tcp, grpc, nats, matrix, email)
+    #[serde(default)]
     pub inputs_vec: Vec<String>,
 
-    /// Output destinations (list: stdout, mqtt)
+    /// Output destinations (list: stdout, mqtt, webhook, sse, api, tcp, grpc, nats, file, matrix, email)
+    #[serde(default)]
     pub outputs_vec: Vec<String>,
 
+    /// Inputs with their own settings, in addition to inputs_vec
+    #[serde(default)]
+    pub inputs: Vec<IoEntry>,
+
+    /// Outputs with their own settings, in addition to outputs_vec
+    #[serde(default)]
+    pub outputs: Vec<IoEntry>,
+
+    /// Which outputs get the messages of each input (default: inputs without a route
+    /// are answered on their own output)
+    #[serde(default)]
+    pub routes: Vec<Route>,
+
+    /// Send every conversation to every output, instead of answering each message on
+    /// the output of the input it came from (default: false)
+    #[serde(default)]
+    pub broadcast_replies: bool,
+
+    /// Roles each output type writes, e.g. `{stdout: [user, assistant]}`, where a
+    /// broker's output such as `mqtt:cloud` takes the place of `mqtt` (default: all
+    /// for stdout, tui, file and sqlite; assistant, plus image where it can be sent,
+    /// for the rest)
+    #[serde(default)]
+    pub output_roles: BTreeMap<String, Vec<String>>,
+
+    /// Messages from the inputs waiting for the agent (default: 10)
+    pub input_queue_size: Option<usize>,
+
+    /// What to do when the input queue is full: block (default), drop_oldest or reject
+    pub input_queue_policy: Option<String>,
+
     /// Run as a daemon (fork to background)
+    #[serde(default)]
     pub daemon: bool,
 
+    /// File the daemon writes its process ID to (default: none)
+    pub pid_file: Option<String>,
+
+    /// User the daemon runs as, when started as root (default: the current user)
+    pub daemon_user: Option<String>,
+
+    /// Group the daemon runs as, when started as root (default: the current group)
+    pub daemon_group: Option<String>,
+
+    /// File to write the logs to instead of the terminal (default: none); read once
+    /// at startup
+    pub log_file: Option<String>,
+
+    /// When the log file starts over: daily, hourly, size or never (default: daily)
+    pub log_rotation: Option<String>,
+
+    /// Megabytes the log file grows to with size rotation (default: 10)
+    pub log_max_size_mb: Option<u64>,
+
+    /// Rotated log files kept (default: 7); 0 keeps all of them with daily or hourly
+    /// rotation and none with size rotation
+    pub log_max_files: Option<usize>,
+
+    /// Reload the config when the file changes, as on SIGHUP
+    #[serde(default)]
+    pub config_watch: bool,
+
     /// MQTT broker address (default: localhost)
     pub mqtt_broker: Option<String>,
 
@@ -39,76 +183,2098 @@ pub struct Config {
     /// MQTT output topic (default: agent/{agent_name}/output)
     pub mqtt_output_topic: Option<String>,
 
-    /// Maximum number of messages to keep in history (default: 50)
+    /// QoS level (0, 1 or 2) for the input subscription (default: 1)
+    pub mqtt_input_qos: Option<u8>,
+
+    /// QoS level (0, 1 or 2) for published output (default: 1)
+    pub mqtt_output_qos: Option<u8>,
+
+    /// Publish output with the retained flag set
+    #[serde(default)]
+    pub mqtt_retain: bool,
+
+    /// Fixed MQTT client ID prefix; "-input" and "-output" are appended (default: random)
+    pub mqtt_client_id: Option<String>,
+
+    /// Start a fresh MQTT session on connect (default: true); false keeps queued messages
+    pub mqtt_clean_session: Option<bool>,
+
+    /// Seconds the broker keeps a persistent session after disconnect (default: 86400)
+    pub mqtt_session_expiry: Option<u32>,
+
+    /// MQTT input payload format: json (default), plain, or a JSON pointer such as /data/text
+    pub mqtt_input_format: Option<String>,
+
+    /// MQTT output payload format: json (default), plain, or a template with {{content}}
+    pub mqtt_output_format: Option<String>,
+
+    /// Accept administration commands on the MQTT control topic
+    #[serde(default)]
+    pub mqtt_control: bool,
+
+    /// MQTT control topic (default: agent/{agent_name}/control)
+    pub mqtt_control_topic: Option<String>,
+
+    /// MQTT topic for control command acknowledgments (default: agent/{agent_name}/status)
+    pub mqtt_status_topic: Option<String>,
+
+    /// Publish streamed responses as chunks on {mqtt_output_topic}/stream
+    #[serde(default)]
+    pub mqtt_stream: bool,
+
+    /// Extra named MQTT connections, used by the mqtt:<name> input and output
+    #[serde(
+        default,
+        serialize_with = "serialize_mqtt_brokers",
+        deserialize_with = "mqtt_brokers"
+    )]
+    pub mqtt_brokers: Vec<MqttBroker>,
+
+    /// Outbound MQTT messages held while the broker is unreachable (default: 100)
+    pub mqtt_queue_size: Option<usize>,
+
+    /// What to do when the outbound MQTT queue is full: block (default), drop_oldest or drop_newest
+    pub mqtt_queue_policy: Option<String>,
+
+    /// Address the webhook input server binds to (default: 0.0.0.0)
+    pub webhook_bind: Option<String>,
+
+    /// Webhook input server port (default: random available port)
+    pub webhook_port: Option<u16>,
+
+    /// Bearer token required by the webhook input (or WEBHOOK_TOKEN)
+    pub webhook_token: Option<String>,
+
+    /// Named bearer tokens the webhook input accepts, by name; messages are tagged
+    /// with the name of the token they came with
+    #[serde(default)]
+    pub webhook_tokens: BTreeMap<String, String>,
+
+    /// Secret for HMAC-SHA256 signatures required by the webhook input (or WEBHOOK_SECRET)
+    pub webhook_secret: Option<String>,
+
+    /// Prompt and admin requests a minute allowed per client address (default: 60, 0 for no limit)
+    pub webhook_rate_limit: Option<u32>,
+
+    /// Largest request body the webhook server accepts, in bytes (default: 1048576)
+    pub webhook_max_body: Option<usize>,
+
+    /// Bearer token for the webhook server's /admin API (or ADMIN_TOKEN); unset disables it
+    pub admin_token: Option<String>,
+
+    /// Browser origins allowed to call the webhook and API servers ("*" for any)
+    #[serde(default)]
+    pub cors_origins: Vec<String>,
+
+    /// Reverse proxy addresses or CIDR ranges whose X-Forwarded-For header is trusted
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+
+    /// Senders whose messages the inputs accept; empty accepts anyone
+    #[serde(default)]
+    pub allowed_senders: Vec<String>,
+
+    /// Client addresses or CIDR ranges whose messages the inputs accept; empty accepts any
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
+
+    /// Seconds an output may take to write a message before it is given up on
+    /// (default: 30, 0 for no limit)
+    pub output_timeout_secs: Option<u64>,
+
+    /// Failed writes in a row that get an output left out for a while (default: 5,
+    /// 0 never)
+    pub output_max_failures: Option<u32>,
+
+    /// Seconds a failing output is left out before it is tried again (default: 60)
+    pub output_quarantine_secs: Option<u64>,
+
+    /// Directory to keep messages outputs failed to deliver in, to try them again;
+    /// unset drops them
+    pub retry_dir: Option<String>,
+
+    /// Seconds a failed message is retried for before it is dropped (default: 86400)
+    pub retry_max_age_secs: Option<u64>,
+
+    /// URL to send webhook output to
+    pub webhook_url: Option<String>,
+
+    /// Maximum number of messages to keep in history, on top of the token budget
+    /// (default: no limit)
     pub max_history_messages: Option<usize>,
+
+    /// Context window of the model in tokens, which the history is trimmed to fit
+    /// (default: known for common models, otherwise 8192)
+    pub context_tokens: Option<usize>,
+
+    /// Tokens of the context window kept free for the response (default: 4096)
+    pub response_tokens: Option<usize>,
+
+    /// SQLite database to keep conversations in across restarts (requires the
+    /// sqlite feature)
+    pub history_db: Option<String>,
+
+    /// Embedding model for memory and document search (default:
+    /// text-embedding-3-small)
+    pub embedding_model: Option<String>,
+
+    /// Embeddings endpoint (default: the openai provider's base URL + /embeddings)
+    pub embedding_url: Option<String>,
+
+    /// Recall past exchanges similar to each new message into its prompt, from a
+    /// local index of embeddings
+    #[serde(default)]
+    pub memory: bool,
+
+    /// Memory index file (default: {agent_name}-memory.jsonl)
+    pub memory_path: Option<String>,
+
+    /// Most past exchanges recalled per message (default: 3)
+    pub memory_top_k: Option<usize>,
+
+    /// Least cosine similarity for an exchange to be recalled (default: 0.3)
+    pub memory_min_score: Option<f32>,
+
+    /// Prefix of commands such as /clear typed into an input (default: /); empty
+    /// turns commands off
+    pub command_prefix: Option<String>,
+
+    /// Stop answering a message when its sender sends another, and answer the new one
+    /// instead; /stop does so either way
+    #[serde(default)]
+    pub interrupt_replies: bool,
+
+    /// Files, directories and URLs `agent ingest` indexes for the search_documents
+    /// tool
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub documents: Vec<String>,
+
+    /// Document index file (default: {agent_name}-documents.json)
+    pub documents_index: Option<String>,
+
+    /// Size of the chunks documents are split into, in tokens (default: 400)
+    pub documents_chunk_tokens: Option<usize>,
+
+    /// Tokens each chunk repeats from the end of the one before (default: 50)
+    pub documents_chunk_overlap: Option<usize>,
+
+    /// Most chunks search_documents returns (default: 4)
+    pub documents_top_k: Option<usize>,
+
+    /// Other agents on the MQTT broker the ask_agent tool can put questions to, by
+    /// their agent_name
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub agent_peers: Vec<String>,
+
+    /// How many agents deep a chain of questions between agents can go (default: 3)
+    pub agent_max_hops: Option<u32>,
+
+    /// Questions to other agents while answering one message (default: 5)
+    pub agent_max_turns: Option<u32>,
+
+    /// Seconds to wait for another agent's answer (default: 120)
+    pub agent_reply_timeout: Option<u64>,
+
+    /// Offer the delegate_task tool, which hands a sub-task to a session of its own and
+    /// gets a summary of the result back (default: false)
+    #[serde(default)]
+    pub delegate_tasks: bool,
+
+    /// Model, or model alias, delegated tasks run on (default: model)
+    pub delegate_model: Option<String>,
+
+    /// Tools delegated tasks may use, of those the agent offers (default: none)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub delegate_tools: Vec<String>,
+
+    /// Completions a delegated task gets, the last one without tools (default: 5)
+    pub delegate_max_steps: Option<usize>,
+
+    /// What models cost, by model name, for the dollar budgets
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub model_prices: BTreeMap<String, ModelPrice>,
+
+    /// Tokens the agent may use in a day, local time (default: no limit)
+    pub budget_daily_tokens: Option<u64>,
+
+    /// Tokens the agent may use in a calendar month (default: no limit)
+    pub budget_monthly_tokens: Option<u64>,
+
+    /// Dollars the agent may spend in a day, priced with model_prices (default: no limit)
+    pub budget_daily_usd: Option<f64>,
+
+    /// Dollars the agent may spend in a calendar month (default: no limit)
+    pub budget_monthly_usd: Option<f64>,
+
+    /// Model, or model alias on the same provider, that answers once a budget is
+    /// spent (default: none, messages are refused)
+    pub budget_fallback_model: Option<String>,
+
+    /// JSONL file every completion's token usage is appended to, so budgets count
+    /// usage from before a restart (default: none)
+    pub usage_log: Option<String>,
+
+    /// JSON transcript to start a conversation from, as written by `agent export`;
+    /// skipped if the history database already holds that conversation
+    pub resume: Option<String>,
+
+    /// Directory `/export` saves conversations to (default: send them to the outputs)
+    pub export_dir: Option<String>,
+
+    /// Built-in patterns redacted from messages and tool results before they reach
+    /// the history or a provider: api_key, email, card_number
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact: Vec<String>,
+
+    /// More patterns to redact, as regular expressions by name; matches become
+    /// [REDACTED:<name>]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub redact_patterns: BTreeMap<String, String>,
+
+    /// JSON-lines file recording each redaction, without the redacted text (default:
+    /// only logged)
+    pub redact_audit_log: Option<String>,
+
+    /// Regular expressions responses must not match, by name; matches become
+    /// [REDACTED:<name>] with the redact action
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub guardrail_patterns: BTreeMap<String, String>,
+
+    /// Phrases responses must not contain, in any case
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub guardrail_forbidden: Vec<String>,
+
+    /// Phrases every response must contain, in any case
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub guardrail_required: Vec<String>,
+
+    /// Most characters in a response (default: no limit)
+    pub guardrail_max_chars: Option<usize>,
+
+    /// What happens to a response that breaks a guardrail: redact, truncate or
+    /// regenerate (default: redact)
+    pub guardrail_action: Option<String>,
+
+    /// Times a response is asked for again with the regenerate action (default: 2)
+    pub guardrail_max_retries: Option<usize>,
+
+    /// Put tool results, fetched pages and recalled memories in delimited blocks the
+    /// model is told not to take instructions from, and flag ones that look like
+    /// instructions (default: false)
+    #[serde(default)]
+    pub injection_guard: bool,
+
+    /// Screen messages and responses with a moderation classifier: openai for the
+    /// OpenAI moderation endpoint, or command (default: off)
+    pub moderation: Option<String>,
+
+    /// Classifier for `moderation: command`; it gets the text on stdin and prints the
+    /// categories it flags, nothing when there are none
+    pub moderation_command: Option<String>,
+
+    /// Moderation endpoint (default: the openai provider's base URL + /moderations)
+    pub moderation_url: Option<String>,
+
+    /// Model for the moderation endpoint (default: omni-moderation-latest)
+    pub moderation_model: Option<String>,
+
+    /// Categories that count as flagged, e.g. [harassment, violence] (default: all)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub moderation_categories: Vec<String>,
+
+    /// What happens to a flagged message: block, flag, redact or off (default: block)
+    pub moderation_input_action: Option<String>,
+
+    /// What happens to a flagged response: block, flag, redact or off (default: block)
+    pub moderation_output_action: Option<String>,
+
+    /// Directory to save generated images to (default: publish to outputs)
+    pub image_output_dir: Option<String>,
+
+    /// Image generation endpoint (default: OpenAI Images API)
+    pub image_api_url: Option<String>,
+
+    /// Image generation model (default: dall-e-3)
+    pub image_model: Option<String>,
+
+    /// Maximum number of images per generate_image call (default: 1)
+    pub image_max_count: Option<usize>,
+
+    /// Address the TCP server binds to (default: 127.0.0.1:7878)
+    pub tcp_bind: Option<String>,
+
+    /// PEM certificate for TLS on the TCP server
+    pub tcp_tls_cert: Option<String>,
+
+    /// PEM PKCS#8 private key for TLS on the TCP server
+    pub tcp_tls_key: Option<String>,
+
+    /// Transcript file for the file output (default: transcript.jsonl)
+    pub transcript_path: Option<String>,
+
+    /// Transcript format: jsonl or markdown (default: from the file extension)
+    pub transcript_format: Option<String>,
+
+    /// Rotate the transcript when it would grow past this many bytes
+    pub transcript_max_bytes: Option<u64>,
+
+    /// Rotate the transcript when the date changes (UTC)
+    #[serde(default)]
+    pub transcript_rotate_daily: bool,
+
+    /// Matrix homeserver URL, e.g. https://matrix.org
+    #[cfg_attr(not(feature = "matrix"), allow(dead_code))]
+    pub matrix_homeserver: Option<String>,
+
+    /// Matrix user to log in as, e.g. @agent:matrix.org
+    #[cfg_attr(not(feature = "matrix"), allow(dead_code))]
+    pub matrix_user: Option<String>,
+
+    /// Matrix password (default: MATRIX_PASSWORD env var)
+    #[cfg_attr(not(feature = "matrix"), allow(dead_code))]
+    pub matrix_password: Option<String>,
+
+    /// Matrix rooms to join, by ID or alias
+    #[cfg_attr(not(feature = "matrix"), allow(dead_code))]
+    #[serde(default)]
+    pub matrix_rooms: Vec<String>,
+
+    /// Directory for the Matrix session and encryption keys (default: matrix-store-{agent_name})
+    #[cfg_attr(not(feature = "matrix"), allow(dead_code))]
+    pub matrix_store_path: Option<String>,
+
+    /// IMAP server to poll for incoming email
+    #[cfg_attr(not(feature = "email"), allow(dead_code))]
+    pub email_imap_server: Option<String>,
+
+    /// IMAP server port, implicit TLS (default: 993)
+    #[cfg_attr(not(feature = "email"), allow(dead_code))]
+    pub email_imap_port: Option<u16>,
+
+    /// SMTP server to send replies through
+    #[cfg_attr(not(feature = "email"), allow(dead_code))]
+    pub email_smtp_server: Option<String>,
+
+    /// SMTP server port, 465 for implicit TLS, otherwise STARTTLS (default: 587)
+    #[cfg_attr(not(feature = "email"), allow(dead_code))]
+    pub email_smtp_port: Option<u16>,
+
+    /// Username for both the IMAP and SMTP servers
+    #[cfg_attr(not(feature = "email"), allow(dead_code))]
+    pub email_username: Option<String>,
+
+    /// Email password (default: EMAIL_PASSWORD env var)
+    #[cfg_attr(not(feature = "email"), allow(dead_code))]
+    pub email_password: Option<String>,
+
+    /// Address replies are sent from (default: email_username)
+    #[cfg_attr(not(feature = "email"), allow(dead_code))]
+    pub email_address: Option<String>,
+
+    /// Mailbox to poll (default: INBOX)
+    #[cfg_attr(not(feature = "email"), allow(dead_code))]
+    pub email_mailbox: Option<String>,
+
+    /// Seconds between mailbox polls (default: 60)
+    #[cfg_attr(not(feature = "email"), allow(dead_code))]
+    pub email_poll_interval: Option<u64>,
+
+    /// NATS server URL (default: nats://localhost:4222)
+    #[cfg_attr(not(feature = "nats"), allow(dead_code))]
+    pub nats_url: Option<String>,
+
+    /// NATS credentials file for authentication
+    #[cfg_attr(not(feature = "nats"), allow(dead_code))]
+    pub nats_credentials_file: Option<String>,
+
+    /// NATS input subject (default: agent.{agent_name}.input)
+    #[cfg_attr(not(feature = "nats"), allow(dead_code))]
+    pub nats_input_subject: Option<String>,
+
+    /// NATS output subject (default: agent.{agent_name}.output)
+    #[cfg_attr(not(feature = "nats"), allow(dead_code))]
+    pub nats_output_subject: Option<String>,
+
+    /// NATS queue group to share requests between agents
+    #[cfg_attr(not(feature = "nats"), allow(dead_code))]
+    pub nats_queue_group: Option<String>,
+
+    /// Address the gRPC server binds to (default: 127.0.0.1:50051)
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub grpc_bind: Option<String>,
+
+    /// Address the OpenAI-compatible API binds to (default: 127.0.0.1:8001)
+    pub api_bind: Option<String>,
+
+    /// Bearer token clients must send to the API (default: no authentication)
+    pub api_server_key: Option<String>,
+
+    /// File to keep interactive stdin history in across runs (default: none)
+    pub stdin_history_file: Option<String>,
+
+    /// Audio input device name (default: the system default input)
+    #[cfg_attr(not(feature = "voice"), allow(dead_code))]
+    pub voice_device: Option<String>,
+
+    /// Speech-to-text backend: api (Whisper API) or local (whisper.cpp) (default: api)
+    #[cfg_attr(not(feature = "voice"), allow(dead_code))]
+    pub voice_transcriber: Option<String>,
+
+    /// Transcription endpoint for the api backend (default: the openai provider's
+    /// base URL)
+    #[cfg_attr(not(feature = "voice"), allow(dead_code))]
+    pub voice_api_url: Option<String>,
+
+    /// Transcription model for the api backend (default: whisper-1)
+    #[cfg_attr(not(feature = "voice"), allow(dead_code))]
+    pub voice_model: Option<String>,
+
+    /// whisper.cpp command for the local backend (default: whisper-cli)
+    #[cfg_attr(not(feature = "voice"), allow(dead_code))]
+    pub voice_whisper_binary: Option<String>,
+
+    /// ggml model file for the local backend
+    #[cfg_attr(not(feature = "voice"), allow(dead_code))]
+    pub voice_whisper_model: Option<String>,
+
+    /// Spoken language as an ISO-639-1 code (default: detected)
+    #[cfg_attr(not(feature = "voice"), allow(dead_code))]
+    pub voice_language: Option<String>,
+
+    /// RMS level above which audio counts as speech (default: 0.02)
+    #[cfg_attr(not(feature = "voice"), allow(dead_code))]
+    pub voice_threshold: Option<f64>,
+
+    /// Silence that ends an utterance, in milliseconds (default: 800)
+    #[cfg_attr(not(feature = "voice"), allow(dead_code))]
+    pub voice_silence_ms: Option<u64>,
+
+    /// Longest utterance before it is cut off, in seconds (default: 30)
+    #[cfg_attr(not(feature = "voice"), allow(dead_code))]
+    pub voice_max_segment_secs: Option<u64>,
+
+    /// Database file for the sqlite output (default: agent.db)
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    pub sqlite_path: Option<String>,
+
+    /// Account the agent logs in as, e.g. agent@example.com
+    #[cfg_attr(not(feature = "xmpp"), allow(dead_code))]
+    pub xmpp_jid: Option<String>,
+
+    /// Account password (default: XMPP_PASSWORD env var)
+    #[cfg_attr(not(feature = "xmpp"), allow(dead_code))]
+    pub xmpp_password: Option<String>,
+
+    /// Multi-user chat rooms to join, e.g. room@conference.example.com
+    #[cfg_attr(not(feature = "xmpp"), allow(dead_code))]
+    #[serde(default)]
+    pub xmpp_rooms: Vec<String>,
+
+    /// Nickname in rooms (default: agent name)
+    #[cfg_attr(not(feature = "xmpp"), allow(dead_code))]
+    pub xmpp_nick: Option<String>,
+
+    /// Twitch account the agent chats as
+    pub twitch_username: Option<String>,
+
+    /// Chat OAuth token for the account (default: TWITCH_OAUTH_TOKEN env var)
+    pub twitch_oauth_token: Option<String>,
+
+    /// Channels to join, e.g. mychannel
+    #[serde(default)]
+    pub twitch_channels: Vec<String>,
+
+    /// Chat command that addresses the agent (default: !ask)
+    pub twitch_prefix: Option<String>,
+
+    /// Seconds a viewer must wait between commands (default: 30)
+    pub twitch_user_cooldown: Option<u64>,
+
+    /// Incoming webhook URL for the chat_webhook output
+    pub chat_webhook_url: Option<String>,
+
+    /// Payload preset: mattermost, rocketchat, slack, googlechat, teams or custom (default: mattermost)
+    pub chat_webhook_platform: Option<String>,
+
+    /// JSON payload for the custom platform, with {{content}} and {{agent}} placeholders
+    pub chat_webhook_template: Option<String>,
+
+    /// Shell command the exec output pipes each response to
+    pub exec_command: Option<String>,
+
+    /// Seconds the exec command may run before it is killed (default: 30)
+    pub exec_timeout: Option<u64>,
+
+    /// Shell command the command input runs on a schedule
+    pub command_input: Option<String>,
+
+    /// Seconds between runs of the command input (default: 600)
+    pub command_interval: Option<u64>,
+
+    /// Prompt wrapping the command output, with an {{output}} placeholder
+    pub command_template: Option<String>,
+
+    /// Cron expression for the schedule input, in local time, e.g. "0 7 * * *"
+    pub schedule_cron: Option<String>,
+
+    /// Prompt the schedule input sends, a template with date, time, weekday and last_run
+    pub schedule_prompt: Option<String>,
+
+    /// Gives the schedule input's prompts a conversation of their own under this name
+    pub schedule_name: Option<String>,
+
+    /// URL the HTTP polling input fetches
+    pub http_poll_url: Option<String>,
+
+    /// Seconds between fetches of the HTTP polling input (default: 300)
+    pub http_poll_interval: Option<u64>,
+
+    /// Prompt wrapping new content, with a {{content}} placeholder
+    pub http_poll_template: Option<String>,
+
+    /// GitHub login the agent answers mentions of, enabling the /github route
+    pub github_bot_login: Option<String>,
+
+    /// Secret for verifying GitHub webhook signatures (or GITHUB_WEBHOOK_SECRET env var)
+    pub github_webhook_secret: Option<String>,
+
+    /// Token for posting replies through the GitHub API (or GITHUB_TOKEN env var)
+    pub github_token: Option<String>,
+
+    /// Secret key as nsec or hex (or NOSTR_SECRET_KEY env var)
+    #[cfg_attr(not(feature = "nostr"), allow(dead_code))]
+    pub nostr_secret_key: Option<String>,
+
+    /// Relay URLs to read and publish DMs through
+    #[cfg_attr(not(feature = "nostr"), allow(dead_code))]
+    #[serde(default)]
+    pub nostr_relays: Vec<String>,
+
+    /// Public keys allowed to message the agent; empty allows anyone
+    #[cfg_attr(not(feature = "nostr"), allow(dead_code))]
+    #[serde(default)]
+    pub nostr_allowed_pubkeys: Vec<String>,
+
+    /// Google Cloud project (default: project_id from the credentials file)
+    #[cfg_attr(not(feature = "pubsub"), allow(dead_code))]
+    pub pubsub_project: Option<String>,
+
+    /// Subscription the Pub/Sub input pulls from
+    #[cfg_attr(not(feature = "pubsub"), allow(dead_code))]
+    pub pubsub_subscription: Option<String>,
+
+    /// Topic the Pub/Sub output publishes to
+    #[cfg_attr(not(feature = "pubsub"), allow(dead_code))]
+    pub pubsub_topic: Option<String>,
+
+    /// Service account key file (default: GOOGLE_APPLICATION_CREDENTIALS, then the metadata server)
+    #[cfg_attr(not(feature = "pubsub"), allow(dead_code))]
+    pub pubsub_credentials: Option<String>,
+}
+
+/// A named MQTT connection; settings it doesn't set come from the global mqtt_* options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MqttBroker {
+    // The key it's listed under in mqtt_brokers
+    #[serde(skip)]
+    pub name: String,
+    pub broker: String,
+    pub port: Option<u16>,
+    pub input_topic: Option<String>,
+    pub output_topic: Option<String>,
+    pub client_id: Option<String>,
+}
+
+/// The outputs one input's conversation goes to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    /// Input type, e.g. stdin or mqtt, or one broker's input, e.g. mqtt:cloud
+    pub from: String,
+    /// Output types or brokers that get its messages
+    pub to: Vec<String>,
+    /// Roles they get, e.g. [assistant] (default: all)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+}
+
+/// What a model costs, in dollars per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelPrice {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+/// One input or output with settings of its own, e.g. `{type: mqtt, input_topic: sensors}`
+///
+/// Settings are named like the top-level keys, with or without the type prefix and
+/// direction, so `topic` in an mqtt input sets `mqtt_input_topic`. Anything not set
+/// comes from the top-level keys.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IoEntry {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(flatten)]
+    pub settings: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Route {
+    // What's wrong with the route's input, outputs and roles, by field
+    fn problems(&self, config: &Config, inputs: &[String], outputs: &[String]) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(problem) = config.check_io(&self.from, inputs) {
+            problems.push(format!("from: {}", problem));
+        }
+        for (i, to) in self.to.iter().enumerate() {
+            if let Some(problem) = config.check_io(to, outputs) {
+                problems.push(format!("to[{}]: {}", i, problem));
+            }
+        }
+        problems.extend(unknown_roles(&self.roles).map(|problem| format!("roles{}", problem)));
+        problems
+    }
+}
+
+// Roles in `roles` no output writes, as `[index]: problem`
+fn unknown_roles(roles: &[String]) -> impl Iterator<Item = String> + '_ {
+    roles
+        .iter()
+        .enumerate()
+        .filter(|(_, role)| !crate::io::route::ROLES.contains(&role.as_str()))
+        .map(|(i, role)| {
+            format!(
+                "[{}]: unknown role `{}`, expected one of {}",
+                i,
+                role,
+                crate::io::route::ROLES.join(", ")
+            )
+        })
+}
+
+impl IoEntry {
+    // An entry for a name from inputs_vec or outputs_vec, with no settings of its own
+    fn plain(kind: &str) -> Self {
+        Self {
+            kind: kind.to_string(),
+            settings: serde_json::Map::new(),
+        }
+    }
+}
+
+/// The config of an empty file: every setting at its default, and no inputs or outputs
+impl Default for Config {
+    fn default() -> Self {
+        serde_json::from_value(serde_json::json!({})).expect("every setting has a default")
+    }
 }
 
 impl Config {
-    /// Load configuration from a YAML file
-    pub fn from_yaml(path: &str) -> Result<Self> {
+    /// Load configuration from a YAML, TOML or JSON file, chosen by its extension
+    /// (`.toml`, `.json`, anything else is YAML), with `profile` from its profiles
+    /// section applied
+    ///
+    /// Unknown keys, values of the wrong type and invalid settings are errors that name
+    /// the offending key, rather than falling back to defaults.
+    pub fn load(path: &str, profile: Option<&str>) -> Result<Self> {
         let contents = std::fs::read_to_string(path)
             .context(format!("Failed to read config file: {}", path))?;
+        let mut config: Config =
+            parse(path, &contents).context(format!("Invalid config file: {}", path))?;
+        if !config.include.is_empty() {
+            let mut included = Vec::new();
+            let fields = layer(std::path::Path::new(path), &mut Vec::new(), &mut included)?;
+            config = serde_json::from_value(serde_json::Value::Object(fields))
+                .context(format!("Invalid config file: {}", path))?;
+            // The file itself is the last layer
+            included.pop();
+            config.included = included;
+        }
+        if let Some(profile) = profile {
+            config = config
+                .with_profile(profile)
+                .context(format!("Invalid config file: {}", path))?;
+        }
 
-        let config: serde_yaml::Value =
-            serde_yaml::from_str(&contents).context("Failed to parse YAML config")?;
-
-        // Extract values with defaults
-        let agent_name = config["agent_name"].as_str().unwrap_or("agent").to_string();
-        let provider = config["provider"].as_str().unwrap_or("openai").to_string();
-        let model = config["model"].as_str().unwrap_or("gpt-4o").to_string();
-        let system_message = config["system_message"].as_str().unwrap_or("").to_string();
-        let enable_tools = config["enable_tools"].as_bool().unwrap_or(false);
-        let daemon = config["daemon"].as_bool().unwrap_or(false);
-        let mqtt_broker = config["mqtt_broker"].as_str().map(|s| s.to_string());
-        let mqtt_port = config["mqtt_port"].as_u64().map(|p| p as u16);
-        let mqtt_input_topic = config["mqtt_input_topic"].as_str().map(|s| s.to_string());
-        let mqtt_output_topic = config["mqtt_output_topic"].as_str().map(|s| s.to_string());
-
-        // Extract max_history_messages with default
-        let max_history_messages = config["max_history_messages"].as_u64().map(|m| m as usize);
-
-        // Parse inputs and outputs from YAML
-        let mut inputs_vec = Vec::new();
-        let mut outputs_vec = Vec::new();
-
-        if let Some(inputs) = config["inputs_vec"].as_sequence() {
-            inputs_vec = inputs
-                .iter()
-                .filter_map(|v| v.as_str())
-                .map(|s| s.to_string())
-                .collect();
+        // With no inputs or outputs of either kind, use the defaults
+        if config.inputs_vec.is_empty() && config.inputs.is_empty() {
+            config.inputs_vec = default_inputs();
+        }
+        if config.outputs_vec.is_empty() && config.outputs.is_empty() {
+            config.outputs_vec = default_outputs();
+        }
+
+        config
+            .validate()
+            .context(format!("Invalid config file: {}", path))?;
+        Ok(config)
+    }
+
+    /// Check settings that parse but can't work, reporting every problem at once
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        // Types are looked up in the io registry, which other crates can add to
+        let inputs = crate::io::registry::kinds("input");
+        let outputs = crate::io::registry::kinds("output");
+        for (i, input) in self.inputs_vec.iter().enumerate() {
+            if let Some(problem) = self.check_io(input, &inputs) {
+                problems.push(format!("inputs_vec[{}]: {}", i, problem));
+            }
+        }
+        for (i, output) in self.outputs_vec.iter().enumerate() {
+            if let Some(problem) = self.check_io(output, &outputs) {
+                problems.push(format!("outputs_vec[{}]: {}", i, problem));
+            }
+        }
+        for (key, entries, known, direction) in [
+            ("inputs", &self.inputs, &inputs, "input"),
+            ("outputs", &self.outputs, &outputs, "output"),
+        ] {
+            for (i, entry) in entries.iter().enumerate() {
+                if let Some(problem) = self.check_io(&entry.kind, known) {
+                    problems.push(format!("{}[{}].type: {}", key, i, problem));
+                } else if let Err(e) = crate::io::registry::context(self, entry, direction) {
+                    problems.push(format!("{}[{}].{:#}", key, i, e));
+                }
+            }
+        }
+
+        for (i, route) in self.routes.iter().enumerate() {
+            problems.extend(
+                route
+                    .problems(self, &inputs, &outputs)
+                    .into_iter()
+                    .map(|problem| format!("routes[{}].{}", i, problem)),
+            );
+        }
+
+        for (output, roles) in &self.output_roles {
+            if let Some(problem) = self.check_io(output, &outputs) {
+                problems.push(format!("output_roles.{}: {}", output, problem));
+            }
+            problems.extend(
+                unknown_roles(roles).map(|problem| format!("output_roles.{}{}", output, problem)),
+            );
         }
 
-        if let Some(outputs) = config["outputs_vec"].as_sequence() {
-            outputs_vec = outputs
+        for (key, qos) in [
+            ("mqtt_input_qos", self.mqtt_input_qos),
+            ("mqtt_output_qos", self.mqtt_output_qos),
+        ] {
+            if qos.is_some_and(|qos| qos > 2) {
+                problems.push(format!("{}: must be 0, 1 or 2", key));
+            }
+        }
+        for name in self.profiles.keys() {
+            if let Err(e) = self.with_profile(name) {
+                problems.push(format!("{:#}", e));
+            }
+        }
+        let mut terminal_agents = Vec::new();
+        let mut agent_connections = Vec::new();
+        for name in self.agents.keys() {
+            match self.for_agent(name) {
+                Ok(agent) => {
+                    if let Err(e) = agent.validate() {
+                        for problem in format!("{:#}", e).lines() {
+                            problems.push(format!("agents.{}: {}", name, problem));
+                        }
+                    }
+                    if agent.has_input("stdin") || agent.has_input("tui") {
+                        terminal_agents.push(name.as_str());
+                    }
+                    let connections = agent.shared_connections().into_iter();
+                    agent_connections.extend(connections.map(|c| (name.as_str(), c)));
+                }
+                Err(e) => problems.push(format!("{:#}", e)),
+            }
+        }
+        // A shared server or account has one input, so agents would take each other's
+        // messages and the first to connect would decide its settings
+        let mut reported = Vec::new();
+        for (i, (name, connection)) in agent_connections.iter().enumerate() {
+            let Some((first_name, first)) = agent_connections[..i].iter().find(|(other, c)| {
+                other != name && c.kind == connection.kind && c.key == connection.key
+            }) else {
+                continue;
+            };
+            if reported.contains(&(first_name, name, connection.kind)) {
+                continue;
+            }
+            reported.push((first_name, name, connection.kind));
+            problems.push(format!(
+                "agents.{}.{}: uses the {} connection of agents.{}.{} (same {}), \
+                 which agents can't share; give each agent its own",
+                name,
+                connection.entry,
+                connection.kind,
+                first_name,
+                first.entry,
+                connection.keys.join(", ")
+            ));
+        }
+        let connections = self.shared_connections();
+        for (i, connection) in connections.iter().enumerate() {
+            let Some(first) = connections[..i]
+                .iter()
+                .find(|first| first.kind == connection.kind && first.key == connection.key)
+            else {
+                continue;
+            };
+            let differing: Vec<&str> = connection
+                .agreed
                 .iter()
-                .filter_map(|v| v.as_str())
-                .map(|s| s.to_string())
+                .zip(first.settings.iter().zip(&connection.settings))
+                .filter(|(_, (a, b))| a != b)
+                .map(|(name, _)| *name)
                 .collect();
+            if !differing.is_empty() {
+                problems.push(format!(
+                    "{}: shares the {} connection of {} (same {}), so it must not change {}",
+                    connection.entry,
+                    connection.kind,
+                    first.entry,
+                    connection.keys.join(", "),
+                    differing.join(", ")
+                ));
+            }
+        }
+        if terminal_agents.len() > 1 {
+            problems.push(format!(
+                "agents: only one agent can read the terminal (stdin or tui), but {} do",
+                terminal_agents.join(", ")
+            ));
+        }
+        for broker in &self.mqtt_brokers {
+            if broker.broker.is_empty() {
+                problems.push(format!(
+                    "mqtt_brokers.{}.broker: must not be empty",
+                    broker.name
+                ));
+            }
+        }
+        if self.input_queue_size == Some(0) {
+            problems.push("input_queue_size: must be at least 1".to_string());
+        }
+        if self.mqtt_queue_size == Some(0) {
+            problems.push("mqtt_queue_size: must be at least 1".to_string());
+        }
+        if self.max_history_messages == Some(0) {
+            problems.push("max_history_messages: must be at least 1".to_string());
+        }
+        if let (Some(context), Some(response)) = (self.context_tokens, self.response_tokens) {
+            if response >= context {
+                problems.push(format!(
+                    "response_tokens: must be less than context_tokens ({})",
+                    context
+                ));
+            }
+        }
+        if let (Some(size), Some(overlap)) =
+            (self.documents_chunk_tokens, self.documents_chunk_overlap)
+        {
+            if overlap >= size {
+                problems.push(format!(
+                    "documents_chunk_overlap: must be less than documents_chunk_tokens ({})",
+                    size
+                ));
+            }
+        }
+        for peer in &self.agent_peers {
+            // Peers are addressed on agent/<name>/input
+            if peer.is_empty() || peer.contains(['/', '+', '#']) {
+                problems.push(format!(
+                    "agent_peers: {:?} can't be part of an MQTT topic",
+                    peer
+                ));
+            } else if *peer == self.agent_name {
+                problems.push(format!("agent_peers: {} is this agent", peer));
+            }
+        }
+        for tool in &self.delegate_tools {
+            if tool == "delegate_task" {
+                problems.push("delegate_tools: delegated tasks can't delegate".to_string());
+            } else if !crate::tools::NAMES.contains(&tool.as_str()) {
+                problems.push(format!(
+                    "delegate_tools: unknown tool {}, expected one of {}",
+                    tool,
+                    crate::tools::NAMES.join(", ")
+                ));
+            }
+        }
+        if self.delegate_max_steps == Some(0) {
+            problems.push("delegate_max_steps: must be at least 1".to_string());
+        }
+        for (key, value) in [
+            ("budget_daily_tokens", self.budget_daily_tokens),
+            ("budget_monthly_tokens", self.budget_monthly_tokens),
+        ] {
+            if value == Some(0) {
+                problems.push(format!("{}: must be at least 1", key));
+            }
+        }
+        for (key, value) in [
+            ("budget_daily_usd", self.budget_daily_usd),
+            ("budget_monthly_usd", self.budget_monthly_usd),
+        ] {
+            if value.is_some_and(|value| value.is_nan() || value <= 0.0) {
+                problems.push(format!("{}: must be more than 0", key));
+            }
+        }
+        for (model, price) in &self.model_prices {
+            if price.prompt < 0.0 || price.completion < 0.0 {
+                problems.push(format!("model_prices: {}: prices can't be negative", model));
+            }
+        }
+        if self.budget_daily_usd.is_some() || self.budget_monthly_usd.is_some() {
+            let model = self.resolve_model(&self.model).1;
+            if !self.model_prices.contains_key(&model) {
+                problems.push(format!(
+                    "model_prices: dollar budgets need a price for {}",
+                    model
+                ));
+            }
+        }
+        if let Some(fallback) = &self.budget_fallback_model {
+            if let (Some(provider), _) = self.resolve_model(fallback) {
+                let (main, _) = self.resolve_model(&self.model);
+                if provider != *main.as_ref().unwrap_or(&self.provider) {
+                    problems.push(format!(
+                        "budget_fallback_model: {} is on {}, not the agent's provider",
+                        fallback, provider
+                    ));
+                }
+            }
+        }
+        if self.agent_reply_timeout == Some(0) {
+            problems.push("agent_reply_timeout: must be at least 1".to_string());
+        }
+        for (key, value) in [
+            ("documents_chunk_tokens", self.documents_chunk_tokens),
+            ("documents_top_k", self.documents_top_k),
+            ("memory_top_k", self.memory_top_k),
+        ] {
+            if value == Some(0) {
+                problems.push(format!("{}: must be at least 1", key));
+            }
+        }
+        if self
+            .memory_min_score
+            .is_some_and(|score| !(-1.0..=1.0).contains(&score))
+        {
+            problems.push("memory_min_score: must be between -1 and 1".to_string());
+        }
+        for name in &self.redact {
+            if !crate::redact::BUILTIN
+                .iter()
+                .any(|(builtin, _)| builtin == name)
+            {
+                problems.push(format!(
+                    "redact: unknown pattern {}; expected one of {}",
+                    name,
+                    crate::redact::builtin_names()
+                ));
+            }
+        }
+        for (name, pattern) in &self.guardrail_patterns {
+            if let Err(e) = regex::Regex::new(pattern) {
+                problems.push(format!("guardrail_patterns: {}: {}", name, e));
+            }
+        }
+        if self.guardrail_max_chars == Some(0) {
+            problems.push("guardrail_max_chars: must be at least 1".to_string());
+        }
+        for range in &self.allowed_ips {
+            if let Err(e) = crate::io::http_server::IpRange::parse(range) {
+                problems.push(format!("allowed_ips: {:#}", e));
+            }
+        }
+        for (name, token) in &self.webhook_tokens {
+            if token.is_empty() {
+                problems.push(format!("webhook_tokens: {}: token is empty", name));
+            }
+        }
+        if self.moderation.as_deref() == Some("command") && self.moderation_command.is_none() {
+            problems.push("moderation: command requires moderation_command".to_string());
+        }
+        if let Err(e) = crate::redact::compile(&self.redact_patterns) {
+            problems.push(format!("redact_patterns: {:#}", e));
+        }
+        for path in &self.context_files {
+            if !std::path::Path::new(path).is_file() {
+                problems.push(format!("context_files: no such file: {}", path));
+            }
+        }
+        problems.extend(crate::prompt::check(self));
+        if cfg!(not(feature = "sqlite")) && self.history_db.is_some() {
+            problems.push("history_db: requires building with --features sqlite".to_string());
         }
 
-        // If inputs_vec or outputs_vec are empty, use defaults
-        if inputs_vec.is_empty() {
-            inputs_vec = vec!["mqtt".to_string(), "stdin".to_string()];
-        }
-        if outputs_vec.is_empty() {
-            outputs_vec = vec!["mqtt".to_string(), "stdout".to_string()];
-        }
-
-        Ok(Config {
-            agent_name,
-            provider,
-            model,
-            system_message,
-            enable_tools,
-            inputs_vec,
-            outputs_vec,
-            daemon,
-            mqtt_broker,
-            mqtt_port,
-            mqtt_input_topic,
-            mqtt_output_topic,
-            max_history_messages,
-        })
+        let choices: [(&str, &Option<String>, &[&str]); 10] = [
+            (
+                "input_queue_policy",
+                &self.input_queue_policy,
+                &["block", "drop_oldest", "reject"],
+            ),
+            (
+                "mqtt_queue_policy",
+                &self.mqtt_queue_policy,
+                &["block", "drop_oldest", "drop_newest"],
+            ),
+            (
+                "transcript_format",
+                &self.transcript_format,
+                &["jsonl", "markdown", "md"],
+            ),
+            (
+                "chat_webhook_platform",
+                &self.chat_webhook_platform,
+                &[
+                    "mattermost",
+                    "rocketchat",
+                    "slack",
+                    "googlechat",
+                    "teams",
+                    "custom",
+                ],
+            ),
+            (
+                "voice_transcriber",
+                &self.voice_transcriber,
+                &["api", "local"],
+            ),
+            ("moderation", &self.moderation, &["openai", "command"]),
+            (
+                "log_rotation",
+                &self.log_rotation,
+                crate::logging::ROTATIONS,
+            ),
+            (
+                "guardrail_action",
+                &self.guardrail_action,
+                crate::guardrails::ACTIONS,
+            ),
+            (
+                "moderation_input_action",
+                &self.moderation_input_action,
+                crate::moderation::ACTIONS,
+            ),
+            (
+                "moderation_output_action",
+                &self.moderation_output_action,
+                crate::moderation::ACTIONS,
+            ),
+        ];
+        for (key, value, allowed) in choices {
+            if let Some(value) = value {
+                if !allowed.contains(&value.as_str()) {
+                    problems.push(format!(
+                        "{}: unknown value `{}`, expected one of {}",
+                        key,
+                        value,
+                        allowed.join(", ")
+                    ));
+                }
+            }
+        }
+        if self.chat_webhook_platform.as_deref() == Some("custom")
+            && self.chat_webhook_template.is_none()
+        {
+            problems.push(
+                "chat_webhook_template: required when chat_webhook_platform is custom".to_string(),
+            );
+        }
+
+        for (name, settings) in &self.providers {
+            if !PROVIDERS.contains(&name.to_lowercase().as_str()) {
+                problems.push(format!(
+                    "providers.{}: unknown provider, expected one of {}",
+                    name,
+                    PROVIDERS.join(", ")
+                ));
+            }
+            if let Some(url) = &settings.base_url {
+                if let Err(e) = reqwest::Url::parse(url) {
+                    problems.push(format!("providers.{}.base_url: {}", name, e));
+                }
+            }
+            if let Err(e) = settings.client(reqwest::header::HeaderMap::new()) {
+                problems.push(format!("providers.{}.headers: {:#}", name, e));
+            }
+        }
+        for (alias, model) in &self.model_aliases {
+            if let Some((provider, _)) = model.split_once(':') {
+                if !PROVIDERS.contains(&provider.to_lowercase().as_str()) {
+                    problems.push(format!(
+                        "model_aliases.{}: unknown provider {}, expected one of {}",
+                        alias,
+                        provider,
+                        PROVIDERS.join(", ")
+                    ));
+                }
+            }
+            let target = model
+                .split_once(':')
+                .map_or(model.as_str(), |(_, model)| model);
+            if self.model_aliases.contains_key(target) {
+                problems.push(format!(
+                    "model_aliases.{}: names another alias, {}",
+                    alias, model
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!("{}", problems.join("\n"))
+        }
+    }
+
+    /// This config with a profile's settings laid over it; maps such as mqtt_brokers
+    /// are merged, and anything else a profile sets replaces the base value
+    pub fn with_profile(&self, name: &str) -> Result<Config> {
+        let mut config =
+            self.laid_over("profiles", &self.profiles, name, &["profiles", "include"])?;
+        config.profile = Some(name.to_string());
+        Ok(config)
+    }
+
+    /// The config of one of the agents: its settings laid over the rest of the config,
+    /// the same way as a profile's, named after its entry unless it sets agent_name
+    pub fn for_agent(&self, name: &str) -> Result<Config> {
+        // The daemon setting applies to the whole process
+        let reserved = ["profiles", "include", "agents", "daemon"];
+        let mut config = self.laid_over("agents", &self.agents, name, &reserved)?;
+        let sets_name = self.agents[name]
+            .as_object()
+            .is_some_and(|settings| settings.contains_key("agent_name"));
+        if !sets_name {
+            config.agent_name = name.to_string();
+        }
+        config.agents.clear();
+        config.profiles.clear();
+        config.agent = Some(name.to_string());
+        config.resolve_models();
+        Ok(config)
+    }
+
+    /// The config of every agent to run: one per agents entry, or just this one
+    pub fn agent_configs(&self) -> Result<Vec<Config>> {
+        if self.agents.is_empty() {
+            return Ok(vec![self.clone()]);
+        }
+        self.agents
+            .keys()
+            .map(|name| self.for_agent(name))
+            .collect()
+    }
+
+    // This config with the settings `sets[name]` from the `section` map laid over it;
+    // `reserved` are keys the section can't set
+    fn laid_over(
+        &self,
+        section: &str,
+        sets: &BTreeMap<String, serde_json::Value>,
+        name: &str,
+        reserved: &[&str],
+    ) -> Result<Config> {
+        let Some(settings) = sets.get(name) else {
+            let names: Vec<&str> = sets.keys().map(String::as_str).collect();
+            anyhow::bail!(
+                "No {} named `{}` in {} (available: {})",
+                section.trim_end_matches('s'),
+                name,
+                section,
+                if names.is_empty() {
+                    "none".to_string()
+                } else {
+                    names.join(", ")
+                }
+            );
+        };
+        let settings = match settings {
+            serde_json::Value::Object(settings) => settings.clone(),
+            serde_json::Value::Null => serde_json::Map::new(),
+            _ => anyhow::bail!("{}.{}: must be a map of settings", section, name),
+        };
+
+        let serde_json::Value::Object(mut base) = serde_json::to_value(self)? else {
+            anyhow::bail!("Config is not an object");
+        };
+        // Maps left out when empty can still be set
+        for key in ["providers", "model_aliases", "agents"] {
+            base.entry(key)
+                .or_insert(serde_json::Value::Object(serde_json::Map::new()));
+        }
+        let mut fields = base.clone();
+        for (key, setting) in settings {
+            if reserved.contains(&key.as_str()) {
+                anyhow::bail!("{}.{}.{}: can't be set here", section, name, key);
+            }
+            if !base.contains_key(&key) {
+                anyhow::bail!("{}.{}.{}: unknown setting", section, name, key);
+            }
+            let merged = overlay(base.get(&key), setting);
+
+            // Check each setting on its own, so a wrong type is reported with its key
+            let mut single = base.clone();
+            single.insert(key.clone(), merged.clone());
+            serde_json::from_value::<Config>(serde_json::Value::Object(single))
+                .map_err(|e| anyhow::anyhow!("{}.{}.{}: {}", section, name, key, e))?;
+            fields.insert(key, merged);
+        }
+
+        let mut config: Config = serde_json::from_value(serde_json::Value::Object(fields))?;
+        config.profile = self.profile.clone();
+        config.agent = self.agent.clone();
+        config.included = self.included.clone();
+        config.cli = self.cli.clone();
+        Ok(config)
+    }
+
+    /// This config with `key=value` overrides applied in order. The key is a dotted
+    /// path such as `mqtt_input_qos`, `mqtt_brokers.cloud.port` or `inputs.0.port`,
+    /// and the value is read as YAML, so `2`, `true` and `[stdin, webhook]` have
+    /// their types; a value that doesn't fit the setting's type is used as a string,
+    /// and an empty value unsets an optional setting
+    pub fn with_overrides(&self, overrides: &[String]) -> Result<Config> {
+        let mut fields = serde_json::to_value(self)?;
+        for set in overrides {
+            let (path, raw) = set
+                .split_once('=')
+                .context(format!("--set {}: expected key=value", set))?;
+            let parsed = serde_yaml::from_str(raw).unwrap_or(serde_json::Value::Null);
+            let mut candidates = vec![parsed];
+            if !candidates[0].is_string() && !raw.is_empty() {
+                candidates.push(serde_json::Value::String(raw.to_string()));
+            }
+
+            // Check each override on its own, so a wrong type is reported with its key
+            let mut error = None;
+            for setting in candidates {
+                let mut single = fields.clone();
+                set_path(&mut single, path, setting).context(format!("--set {}", path))?;
+                match serde_json::from_value::<Config>(single.clone()) {
+                    Ok(_) => {
+                        fields = single;
+                        error = None;
+                        break;
+                    }
+                    Err(e) => error = error.or(Some(e)),
+                }
+            }
+            if let Some(e) = error {
+                anyhow::bail!("--set {}: {}", path, describe_error(e.to_string(), None));
+            }
+        }
+
+        let mut config: Config = serde_json::from_value(fields)?;
+        config.profile = self.profile.clone();
+        config.included = self.included.clone();
+        config.cli = self.cli.clone();
+        Ok(config)
+    }
+
+    /// This config read again from `path`, with the same profile, command line
+    /// settings and agents entry laid over it as at startup. The agent name, inputs
+    /// and daemon setting stay as they are, since a running agent can't change them
+    pub fn reload(&self, path: &str) -> Result<Config> {
+        let mut reloaded = self
+            .cli
+            .apply(Config::load(path, self.profile.as_deref())?)?;
+        reloaded.validate()?;
+        if let Some(agent) = &self.agent {
+            reloaded = reloaded.for_agent(agent)?;
+        }
+        reloaded.agent_name = self.agent_name.clone();
+        reloaded.inputs_vec = self.inputs_vec.clone();
+        reloaded.inputs = self.inputs.clone();
+        reloaded.daemon = self.daemon;
+        Ok(reloaded)
+    }
+
+    /// The prefix of runtime commands, empty when they are off
+    pub fn command_prefix(&self) -> &str {
+        self.command_prefix
+            .as_deref()
+            .unwrap_or(crate::commands::DEFAULT_PREFIX)
+    }
+
+    /// The embedding model for memory and document search
+    pub fn embedding_model(&self) -> &str {
+        self.embedding_model
+            .as_deref()
+            .unwrap_or(crate::providers::embeddings::DEFAULT_MODEL)
+    }
+
+    /// The `providers` settings for a provider, or the defaults if it has none
+    pub fn provider_settings(&self, provider: &str) -> ProviderSettings {
+        self.providers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(provider))
+            .map(|(_, settings)| settings.clone())
+            .unwrap_or_default()
+    }
+
+    /// The provider, if the alias names one, and model that a model name or alias
+    /// stands for; anything that isn't an alias is a model name
+    pub fn resolve_model(&self, model: &str) -> (Option<String>, String) {
+        match self.model_aliases.get(model) {
+            Some(target) => match target.split_once(':') {
+                Some((provider, model)) => (Some(provider.to_lowercase()), model.to_string()),
+                None => (None, target.clone()),
+            },
+            None => (None, model.to_string()),
+        }
+    }
+
+    /// Replace aliases in the model settings with the models they stand for; an
+    /// alias naming a provider switches the provider too
+    pub fn resolve_models(&mut self) {
+        let (provider, model) = self.resolve_model(&self.model);
+        if let Some(provider) = provider {
+            self.provider = provider;
+        }
+        self.model = model;
+        if let Some(image_model) = &self.image_model {
+            self.image_model = Some(self.resolve_model(image_model).1);
+        }
+    }
+
+    /// Every input, from inputs_vec and then inputs
+    pub fn input_entries(&self) -> Vec<IoEntry> {
+        let plain = self.inputs_vec.iter().map(|kind| IoEntry::plain(kind));
+        plain.chain(self.inputs.iter().cloned()).collect()
+    }
+
+    /// Every output, from outputs_vec and then outputs
+    pub fn output_entries(&self) -> Vec<IoEntry> {
+        let plain = self.outputs_vec.iter().map(|kind| IoEntry::plain(kind));
+        plain.chain(self.outputs.iter().cloned()).collect()
+    }
+
+    /// Whether an input of this type is configured, either way
+    pub fn has_input(&self, kind: &str) -> bool {
+        self.inputs_vec.iter().any(|input| input == kind)
+            || self.inputs.iter().any(|input| input.kind == kind)
+    }
+
+    /// This config with an input or output entry's own settings applied, for creating it;
+    /// `direction` is "input" or "output"
+    pub fn with_settings(&self, entry: &IoEntry, direction: &str) -> Result<Config> {
+        if entry.settings.is_empty() {
+            return Ok(self.clone());
+        }
+
+        let serde_json::Value::Object(base) = serde_json::to_value(self)? else {
+            anyhow::bail!("Config is not an object");
+        };
+        // mqtt:<name> entries take the mqtt settings
+        let prefix = entry.kind.split(':').next().unwrap_or_default();
+        let mut fields = base.clone();
+        for (key, setting) in &entry.settings {
+            let field = [
+                format!("{}_{}_{}", prefix, direction, key),
+                format!("{}_{}", prefix, key),
+                key.clone(),
+            ]
+            .into_iter()
+            .find(|field| base.contains_key(field) && !IO_LISTS.contains(&field.as_str()))
+            .with_context(|| format!("{}: unknown setting for {}", key, entry.kind))?;
+
+            // Check each setting on its own, so a wrong type is reported with its key
+            let mut single = base.clone();
+            single.insert(field.clone(), setting.clone());
+            serde_json::from_value::<Config>(serde_json::Value::Object(single))
+                .map_err(|e| anyhow::anyhow!("{}: {}", key, e))?;
+            fields.insert(field, setting.clone());
+        }
+        Ok(serde_json::from_value(serde_json::Value::Object(fields))?)
+    }
+
+    // The entries whose type shares a connection between its inputs and outputs,
+    // see SHARED_CONNECTIONS
+    fn shared_connections(&self) -> Vec<SharedConnection> {
+        let plain = |kinds: &[String]| kinds.iter().map(|kind| IoEntry::plain(kind)).collect();
+        let mut connections = Vec::new();
+        for (list, entries, direction) in [
+            ("inputs_vec", plain(&self.inputs_vec), "input"),
+            ("outputs_vec", plain(&self.outputs_vec), "output"),
+            ("inputs", self.inputs.clone(), "input"),
+            ("outputs", self.outputs.clone(), "output"),
+        ] {
+            for (i, entry) in entries.iter().enumerate() {
+                let base = entry.kind.split(':').next().unwrap_or_default();
+                let Some(&(kind, keys, agreed)) =
+                    SHARED_CONNECTIONS.iter().find(|(kind, ..)| *kind == base)
+                else {
+                    continue;
+                };
+                // Settings that don't apply are reported on their own
+                let Ok(config) = self.with_settings(entry, direction) else {
+                    continue;
+                };
+                let values = serde_json::to_value(&config).unwrap_or_default();
+                let pick =
+                    |names: &[&str]| names.iter().map(|name| values[*name].clone()).collect();
+                connections.push(SharedConnection {
+                    entry: format!("{}[{}]", list, i),
+                    kind,
+                    keys,
+                    key: pick(keys),
+                    agreed,
+                    settings: pick(agreed),
+                });
+            }
+        }
+        connections
+    }
+
+    // Why an input or output name can't be used, if it can't
+    fn check_io(&self, name: &str, known: &[String]) -> Option<String> {
+        if let Some(broker) = name.strip_prefix("mqtt:") {
+            if self.mqtt_brokers.iter().any(|b| b.name == broker) {
+                return None;
+            }
+            return Some(format!("no MQTT broker named `{}` in mqtt_brokers", broker));
+        }
+        if known.iter().any(|kind| kind == name) {
+            None
+        } else {
+            Some(format!(
+                "unknown `{}`, expected one of {}",
+                name,
+                known.join(", ")
+            ))
+        }
+    }
+
+    /// The config as JSON with passwords, tokens and secrets replaced, for display
+    pub fn redacted(&self) -> serde_json::Value {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        if let Some(fields) = value.as_object_mut() {
+            for (name, field) in fields.iter_mut() {
+                redact(name, field);
+            }
+            for key in ["profiles", "agents"] {
+                let Some(sets) = fields.get_mut(key).and_then(|p| p.as_object_mut()) else {
+                    continue;
+                };
+                for settings in sets.values_mut() {
+                    for (name, field) in settings.as_object_mut().into_iter().flatten() {
+                        redact(name, field);
+                    }
+                }
+            }
+            // Extra provider headers usually carry credentials
+            if let Some(providers) = fields.get_mut("providers").and_then(|p| p.as_object_mut()) {
+                for provider in providers.values_mut() {
+                    let headers = provider.get_mut("headers").and_then(|h| h.as_object_mut());
+                    for value in headers.into_iter().flat_map(|h| h.values_mut()) {
+                        *value = serde_json::Value::String("[redacted]".to_string());
+                    }
+                }
+            }
+            // Entries' settings may be named without their prefix, e.g. `token`
+            for key in IO_LISTS {
+                let entries = fields.get_mut(*key).and_then(|e| e.as_array_mut());
+                for entry in entries.into_iter().flatten() {
+                    for (name, field) in entry.as_object_mut().into_iter().flatten() {
+                        redact(&format!("_{}", name), field);
+                    }
+                }
+            }
+        }
+        value
+    }
+}
+
+// Types whose inputs and outputs share one connection, with the settings that pick
+// the connection and the settings the entries sharing it must agree on, since the
+// first entry to connect decides them
+const SHARED_CONNECTIONS: &[(&str, &[&str], &[&str])] = &[
+    ("tcp", &["tcp_bind"], &["tcp_tls_cert", "tcp_tls_key"]),
+    (
+        "api",
+        &["api_bind"],
+        &["api_server_key", "agent_name", "cors_origins"],
+    ),
+    ("grpc", &["grpc_bind"], &[]),
+    // Input subjects default to one per agent
+    (
+        "nats",
+        &["nats_url", "nats_input_subject", "agent_name"],
+        &["nats_credentials_file"],
+    ),
+    (
+        "matrix",
+        &["matrix_homeserver", "matrix_user"],
+        &[
+            "matrix_password",
+            "matrix_rooms",
+            "matrix_store_path",
+            "agent_name",
+        ],
+    ),
+    (
+        "twitch",
+        &["twitch_username"],
+        &[
+            "twitch_oauth_token",
+            "twitch_channels",
+            "twitch_prefix",
+            "twitch_user_cooldown",
+        ],
+    ),
+    (
+        "xmpp",
+        &["xmpp_jid"],
+        &["xmpp_password", "xmpp_rooms", "xmpp_nick", "agent_name"],
+    ),
+    (
+        "nostr",
+        &["nostr_secret_key"],
+        &["nostr_relays", "nostr_allowed_pubkeys"],
+    ),
+];
+
+// An input or output entry on a connection its type shares
+struct SharedConnection {
+    // Where the entry is, e.g. `inputs[0]`
+    entry: String,
+    kind: &'static str,
+    keys: &'static [&'static str],
+    key: Vec<serde_json::Value>,
+    agreed: &'static [&'static str],
+    settings: Vec<serde_json::Value>,
+}
+
+// Keys listing inputs and outputs, which entries can't set
+const IO_LISTS: &[&str] = &["inputs_vec", "outputs_vec", "inputs", "outputs"];
+
+// Replace a setting's secret, or each secret of a map of them such as
+// `webhook_tokens`, keeping their names
+fn redact(name: &str, field: &mut serde_json::Value) {
+    if field.is_null() {
+        return;
+    }
+    if is_secret(name) {
+        *field = serde_json::Value::String("[redacted]".to_string());
+    } else if name.ends_with("_tokens") {
+        for value in field
+            .as_object_mut()
+            .into_iter()
+            .flat_map(|m| m.values_mut())
+        {
+            *value = serde_json::Value::String("[redacted]".to_string());
+        }
+    }
+}
+
+// Whether a setting holds a password, token or secret
+fn is_secret(name: &str) -> bool {
+    [
+        "_token",
+        "_secret",
+        "_password",
+        "_secret_key",
+        "_server_key",
+    ]
+    .iter()
+    .any(|suffix| name.ends_with(suffix))
+}
+
+fn default_agent_name() -> String {
+    "agent".to_string()
+}
+
+fn default_provider() -> String {
+    "openai".to_string()
+}
+
+fn default_model() -> String {
+    "gpt-4o".to_string()
+}
+
+fn default_system_message() -> String {
+    "You are a helpful AI assistant with access to tools for retrieving weather information and performing calculations. You can answer questions, provide information, and assist with various tasks. When asked about weather or calculations, use the appropriate tools to provide accurate responses. Be concise, helpful, and friendly in your interactions.".to_string()
+}
+
+fn default_inputs() -> Vec<String> {
+    vec!["mqtt".to_string(), "stdin".to_string()]
+}
+
+fn default_outputs() -> Vec<String> {
+    vec!["mqtt".to_string(), "stdout".to_string()]
+}
+
+// Write `mqtt_brokers` back as the map it's read from
+fn serialize_mqtt_brokers<S>(
+    brokers: &[MqttBroker],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    use serde::ser::SerializeMap;
+
+    let mut map = serializer.serialize_map(Some(brokers.len()))?;
+    for broker in brokers {
+        map.serialize_entry(&broker.name, broker)?;
+    }
+    map.end()
+}
+
+// Read the `mqtt_brokers` map of connection name to settings
+fn mqtt_brokers<'de, D>(deserializer: D) -> std::result::Result<Vec<MqttBroker>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let brokers = std::collections::BTreeMap::<String, MqttBroker>::deserialize(deserializer)?;
+    Ok(brokers
+        .into_iter()
+        .map(|(name, broker)| MqttBroker { name, ..broker })
+        .collect())
+}
+
+/// Directories searched for a config file when none is given, in order:
+/// $XDG_CONFIG_HOME/agent, ~/.config/agent, /etc/agent (%APPDATA%\agent on Windows),
+/// then the working directory
+pub fn search_dirs() -> Vec<std::path::PathBuf> {
+    let mut dirs = Vec::new();
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+    if let Some(config_home) = var("XDG_CONFIG_HOME") {
+        dirs.push(std::path::PathBuf::from(config_home).join("agent"));
+    }
+    if let Some(home) = var("HOME") {
+        let dir = std::path::PathBuf::from(home).join(".config").join("agent");
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    if cfg!(windows) {
+        if let Some(app_data) = var("APPDATA") {
+            dirs.push(std::path::PathBuf::from(app_data).join("agent"));
+        }
+    } else {
+        dirs.push(std::path::PathBuf::from("/etc/agent"));
+    }
+    // Relative, so paths in the working directory stay short in logs
+    dirs.push(std::path::PathBuf::new());
+    dirs
+}
+
+/// The types of inputs or outputs, for logging
+pub fn kinds(entries: &[IoEntry]) -> Vec<&str> {
+    entries.iter().map(|entry| entry.kind.as_str()).collect()
+}
+
+/// The config file used when none is given: the first config.yaml, config.toml or
+/// config.json found in the search_dirs, or config.yaml
+pub fn default_path() -> String {
+    search_dirs()
+        .iter()
+        .flat_map(|dir| ["config.yaml", "config.toml", "config.json"].map(|name| dir.join(name)))
+        .find(|path| path.exists())
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| "config.yaml".to_string())
+}
+
+/// Send on `changed` whenever one of the config files at `paths` is written, replaced
+/// or removed; changes stop being reported when the watcher is dropped
+pub fn watch(
+    paths: &[String],
+    changed: tokio::sync::mpsc::Sender<()>,
+) -> Result<notify::RecommendedWatcher> {
+    use notify::Watcher;
+
+    let mut files = Vec::new();
+    let mut directories = Vec::new();
+    for path in paths {
+        let path = std::path::absolute(path)
+            .context(format!("Failed to resolve config file: {}", path))?;
+        // Editors often save by writing a new file and renaming it over the old one,
+        // which a watch on the file itself would miss, so watch its directory
+        let directory = path
+            .parent()
+            .context(format!("Config file has no directory: {}", path.display()))?
+            .to_path_buf();
+        if !directories.contains(&directory) {
+            directories.push(directory);
+        }
+        files.push(path);
+    }
+
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        if event.kind.is_access() {
+            return;
+        }
+        if event.paths.iter().any(|changed| files.contains(changed)) {
+            // A reload is already pending if the channel is full
+            let _ = changed.try_send(());
+        }
+    })
+    .context("Failed to create config file watcher")?;
+    for directory in directories {
+        watcher
+            .watch(&directory, notify::RecursiveMode::NonRecursive)
+            .context(format!("Failed to watch {}", directory.display()))?;
+    }
+    Ok(watcher)
+}
+
+// Parse a config file by its extension: .toml, .json, or YAML for anything else
+fn parse<T: serde::de::DeserializeOwned>(path: &str, contents: &str) -> Result<T> {
+    let extension = std::path::Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    // An empty file is a config with every default
+    let empty = contents.trim().is_empty();
+    let parsed = match extension.as_str() {
+        "toml" => toml::from_str(contents).map_err(|e| {
+            let location = e.span().map(|span| line_column(contents, span.start));
+            describe_error(e.message().to_string(), location)
+        }),
+        "json" => serde_json::from_str(if empty { "{}" } else { contents })
+            .map_err(|e| describe_error(e.to_string(), Some((e.line(), e.column())))),
+        _ => serde_yaml::from_str(if empty { "{}" } else { contents }).map_err(|e| {
+            let location = e.location().map(|l| (l.line(), l.column()));
+            describe_error(e.to_string(), location)
+        }),
+    };
+    parsed.map_err(|e| anyhow::anyhow!(e))
+}
+
+// The settings of the config file at `path` laid over those of the files it includes,
+// in order, each of which is read the same way. `chain` holds the files being read, to
+// catch a file that includes itself, and `included` collects every file read.
+fn layer(
+    path: &std::path::Path,
+    chain: &mut Vec<std::path::PathBuf>,
+    included: &mut Vec<String>,
+) -> Result<serde_json::Map<String, serde_json::Value>> {
+    let name = path.display().to_string();
+    let contents =
+        std::fs::read_to_string(path).context(format!("Failed to read config file: {}", name))?;
+    // Check the file as a config of its own first, so mistakes are reported with the
+    // file, line and column they're on
+    let config: Config =
+        parse(&name, &contents).context(format!("Invalid config file: {}", name))?;
+    let fields: serde_json::Map<String, serde_json::Value> =
+        parse(&name, &contents).context(format!("Invalid config file: {}", name))?;
+
+    let canonical = path
+        .canonicalize()
+        .context(format!("Failed to resolve config file: {}", name))?;
+    if chain.contains(&canonical) {
+        anyhow::bail!("Config file includes itself: {}", name);
+    }
+    chain.push(canonical);
+    let directory = path.parent().unwrap_or(std::path::Path::new(""));
+    let mut merged = serde_json::Map::new();
+    for include in &config.include {
+        let settings = layer(&directory.join(include), chain, included)
+            .context(format!("Included from {}", name))?;
+        for (key, setting) in settings {
+            let setting = overlay(merged.get(&key), setting);
+            merged.insert(key, setting);
+        }
+    }
+    chain.pop();
+
+    for (key, setting) in fields {
+        let setting = overlay(merged.get(&key), setting);
+        merged.insert(key, setting);
+    }
+    included.push(name);
+    Ok(merged)
+}
+
+// Set the value at a dotted path, creating maps along the way; numeric parts index lists
+fn set_path(value: &mut serde_json::Value, path: &str, setting: serde_json::Value) -> Result<()> {
+    let mut current = value;
+    let mut parts = path.split('.').peekable();
+    while let Some(part) = parts.next() {
+        if part.is_empty() {
+            anyhow::bail!("empty key in path");
+        }
+        if current.is_null() {
+            *current = serde_json::Value::Object(serde_json::Map::new());
+        }
+        let next = match current {
+            serde_json::Value::Object(map) => map.entry(part).or_insert(serde_json::Value::Null),
+            serde_json::Value::Array(items) => {
+                let index: usize = part
+                    .parse()
+                    .context(format!("`{}` is a list, expected an index", part))?;
+                let len = items.len();
+                items.get_mut(index).context(format!(
+                    "index {} is out of range for a list of {}",
+                    index, len
+                ))?
+            }
+            _ => anyhow::bail!("`{}` is inside a setting that isn't a map or list", part),
+        };
+        if parts.peek().is_none() {
+            *next = setting;
+            return Ok(());
+        }
+        current = next;
+    }
+    Ok(())
+}
+
+// A setting laid over an earlier one: maps such as mqtt_brokers and profiles are merged
+// one level deep, and anything else replaces the earlier setting
+fn overlay(base: Option<&serde_json::Value>, setting: serde_json::Value) -> serde_json::Value {
+    match (base, setting) {
+        (Some(serde_json::Value::Object(base)), serde_json::Value::Object(setting)) => {
+            let mut merged = base.clone();
+            merged.extend(setting);
+            serde_json::Value::Object(merged)
+        }
+        (_, setting) => setting,
+    }
+}
+
+// "key.path: message (line N, column M)", without serde's list of every top-level key
+fn describe_error(mut message: String, location: Option<(usize, usize)>) -> String {
+    if let Some((line, column)) = location {
+        let suffix = format!(" at line {} column {}", line, column);
+        if let Some(stripped) = message.strip_suffix(&suffix) {
+            message = stripped.to_string();
+        }
+    }
+    // Nested YAML keys are prefixed with their path, so this only cuts the top-level list
+    if message.starts_with("unknown field") {
+        if let Some(end) = message.find(", expected") {
+            message.truncate(end);
+        }
+    }
+    if let Some((line, column)) = location {
+        message.push_str(&format!(" (line {}, column {})", line, column));
+    }
+    message
+}
+
+// 1-based line and column of a byte offset, for parsers that only report offsets
+fn line_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> Config {
+        serde_yaml::from_str(yaml).expect("test config parses")
+    }
+
+    #[test]
+    fn redacted_hides_every_token() {
+        let config = config(
+            "\
+webhook_token: token-1
+webhook_tokens:
+  ci: token-2
+admin_token: token-3
+inputs:
+  - type: webhook
+    tokens:
+      dashboard: token-4
+profiles:
+  prod:
+    webhook_tokens:
+      ops: token-5
+agents:
+  kitchen:
+    webhook_tokens:
+      oven: token-6
+providers:
+  openai:
+    headers:
+      X-Gateway-Key: token-7
+",
+        );
+
+        let shown = config.redacted().to_string();
+        for i in 1..=7 {
+            assert!(!shown.contains(&format!("token-{}", i)), "{}", shown);
+        }
+        // The names stay, to see which tokens there are
+        for name in ["ci", "dashboard", "ops", "oven"] {
+            assert!(
+                shown.contains(&format!("\"{}\":\"[redacted]\"", name)),
+                "{}",
+                shown
+            );
+        }
+    }
+
+    #[test]
+    fn entries_sharing_a_connection_must_agree() {
+        let problems = |yaml: &str| match config(yaml).validate() {
+            Ok(()) => String::new(),
+            Err(e) => format!("{:#}", e),
+        };
+
+        // An input and output on one server, and another server of their own
+        let shared = problems(
+            "\
+inputs_vec: [tcp]
+outputs_vec: [tcp]
+inputs:
+  - type: tcp
+    bind: 127.0.0.1:7000
+    tls_cert: cert.pem
+    tls_key: key.pem
+",
+        );
+        assert!(!shared.contains("connection"), "{}", shared);
+
+        let conflicting = problems(
+            "\
+inputs:
+  - type: tcp
+    bind: 127.0.0.1:7000
+outputs:
+  - type: tcp
+    bind: 127.0.0.1:7000
+    tls_cert: cert.pem
+    tls_key: key.pem
+",
+        );
+        assert!(
+            conflicting.contains(
+                "outputs[0]: shares the tcp connection of inputs[0] (same tcp_bind), \
+                 so it must not change tcp_tls_cert, tcp_tls_key"
+            ),
+            "{}",
+            conflicting
+        );
+    }
+
+    #[test]
+    fn agents_cannot_share_a_connection() {
+        let config = config(
+            "\
+inputs_vec: [tcp]
+outputs_vec: [tcp]
+agents:
+  kitchen: {}
+  garage: {}
+  porch:
+    tcp_bind: 127.0.0.1:7001
+",
+        );
+        let problems = format!("{:#}", config.validate().unwrap_err());
+        assert!(
+            problems.contains(
+                "agents.kitchen.inputs_vec[0]: uses the tcp connection of \
+                 agents.garage.inputs_vec[0] (same tcp_bind)"
+            ),
+            "{}",
+            problems
+        );
+        // Reported once per pair of agents, and not for the agent with its own port
+        assert_eq!(problems.matches("uses the tcp connection").count(), 1);
+        assert!(!problems.contains("agents.porch"), "{}", problems);
+    }
+
+    #[test]
+    fn typos_are_rejected_where_they_are() {
+        let e = parse::<Config>("config.yaml", "model: gpt-4o\nmodle: gpt-4o-mini\n").unwrap_err();
+        assert_eq!(e.to_string(), "unknown field `modle` (line 2, column 1)");
+        let e = parse::<Config>("config.toml", "temprature = 0.5\n").unwrap_err();
+        assert!(
+            e.to_string().starts_with("unknown field `temprature`"),
+            "{}",
+            e
+        );
+
+        // Within an entry, against the settings its type takes
+        let typo = config("inputs:\n  - type: mqtt\n    topc: agent/in\n");
+        let e = format!("{:#}", typo.validate().unwrap_err());
+        assert!(
+            e.contains("inputs[0].topc: unknown setting for mqtt"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn includes_and_profiles_are_laid_over_in_order() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("agent-layers-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(
+            dir.join("base.yaml"),
+            "model: base-model\nsystem_message: From the base\nmodel_aliases:\n  fast: base-fast\n",
+        )?;
+        let path = dir.join("config.yaml");
+        std::fs::write(
+            &path,
+            "\
+include: [base.yaml]
+model: file-model
+model_aliases:
+  smart: file-smart
+profiles:
+  prod:
+    model: prod-model
+",
+        )?;
+        let path = path.to_str().unwrap();
+
+        // The file over its include, with maps merged rather than replaced
+        let config = Config::load(path, None)?;
+        assert_eq!(config.model, "file-model");
+        assert_eq!(config.system_message, "From the base");
+        assert_eq!(config.model_aliases["fast"], "base-fast");
+        assert_eq!(config.model_aliases["smart"], "file-smart");
+        assert_eq!(config.included, [dir.join("base.yaml").to_str().unwrap()]);
+
+        // The profile over both
+        let config = Config::load(path, Some("prod"))?;
+        assert_eq!(config.model, "prod-model");
+        assert_eq!(config.system_message, "From the base");
+        assert!(Config::load(path, Some("staging")).is_err());
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn aliases_cannot_name_aliases() {
+        let chained = config("model_aliases:\n  fast: quick\n  quick: gpt-4o-mini\n");
+        let e = format!("{:#}", chained.validate().unwrap_err());
+        assert!(
+            e.contains("model_aliases.fast: names another alias"),
+            "{}",
+            e
+        );
+
+        let aliases = config("model_aliases:\n  smart: Anthropic:claude-3-5-sonnet-latest\n");
+        assert_eq!(
+            aliases.resolve_model("smart"),
+            (
+                Some("anthropic".to_string()),
+                "claude-3-5-sonnet-latest".to_string()
+            )
+        );
+        assert_eq!(
+            aliases.resolve_model("gpt-4o"),
+            (None, "gpt-4o".to_string())
+        );
     }
 }
@@ -0,0 +1,217 @@
+use anyhow::{Context, Result};
+use std::process::Command;
+
+use crate::cli::KeyringAction;
+use crate::config::Config;
+
+/// Keyring service that API keys are stored under when api_key_keyring isn't set
+pub const DEFAULT_KEYRING_SERVICE: &str = "agent";
+
+/// Load the API key for the configured provider.
+///
+/// Sources are tried in order: `api_key_command` (e.g. a secret manager CLI),
+/// `api_key_file`, the OS keyring with `api_key_keyring`, `secrets_file`, then
+/// the `{PROVIDER}_API_KEY` environment variable. A provider's entry under
+/// `providers` can set its own command, file and environment variable, which
+/// take the place of the top-level ones. Sources are re-read on every call so
+/// rotated keys are picked up on reload.
+pub fn load_api_key(config: &Config) -> Result<String> {
+    load_api_key_for(config, &config.provider)
+}
+
+/// Load the API key for any provider.
+///
+/// The top-level command and file only apply to the configured provider;
+/// the keyring and secrets file hold keys for every provider, and providers
+/// missing from them use their environment variable.
+pub fn load_api_key_for(config: &Config, provider: &str) -> Result<String> {
+    load_api_key_with_source(config, provider).map(|(key, _)| key)
+}
+
+/// Load the API key for a provider along with a description of where it came
+/// from, for reporting without showing the key
+pub fn load_api_key_with_source(config: &Config, provider: &str) -> Result<(String, String)> {
+    let is_configured_provider = provider.eq_ignore_ascii_case(&config.provider);
+    let settings = config.provider_settings(provider);
+
+    if let Some(command) = &settings.api_key_command {
+        let source = format!("providers.{}.api_key_command", provider);
+        return Ok((read_command(command)?, source));
+    }
+
+    if let Some(command) = config
+        .api_key_command
+        .as_ref()
+        .filter(|_| is_configured_provider && settings.api_key_file.is_none())
+    {
+        return Ok((read_command(command)?, "api_key_command".to_string()));
+    }
+
+    if let Some(path) = settings.api_key_file.as_ref().or(config
+        .api_key_file
+        .as_ref()
+        .filter(|_| is_configured_provider))
+    {
+        let key = std::fs::read_to_string(path)
+            .context(format!("Failed to read API key file: {}", path))?;
+        return Ok((non_empty(key.trim().to_string(), path)?, path.clone()));
+    }
+
+    if let Some(service) = &config.api_key_keyring {
+        if let Some(key) = read_keyring(service, provider)? {
+            let source = format!("the {} keyring entry for {}", service, provider);
+            return Ok((non_empty(key, &source)?, source));
+        }
+    }
+
+    if let Some(path) = &config.secrets_file {
+        if let Some(key) = read_secrets_file(path, provider)? {
+            let source = format!("{} in {}", provider, path);
+            return Ok((non_empty(key, &source)?, source));
+        }
+    }
+
+    let api_key_env_var = settings
+        .api_key_env
+        .unwrap_or_else(|| format!("{}_API_KEY", provider.to_uppercase()));
+    let key = std::env::var(&api_key_env_var)
+        .context(format!("{} environment variable not set", api_key_env_var))?;
+    Ok((non_empty(key, &api_key_env_var)?, api_key_env_var))
+}
+
+/// Store or remove a provider's API key in the OS keyring, for `agent keyring`
+pub fn run_keyring(action: &KeyringAction, config: &Config) -> Result<()> {
+    if !cfg!(feature = "keyring") {
+        anyhow::bail!("agent keyring requires building with --features keyring");
+    }
+    let service = config
+        .api_key_keyring
+        .as_deref()
+        .unwrap_or(DEFAULT_KEYRING_SERVICE);
+    match action {
+        KeyringAction::Set => {
+            let provider = &config.provider;
+            eprint!("API key for {}: ", provider);
+            let mut key = String::new();
+            std::io::stdin()
+                .read_line(&mut key)
+                .context("Failed to read API key from stdin")?;
+            let key = non_empty(key.trim().to_string(), "stdin")?;
+            write_keyring(service, provider, Some(&key))?;
+            println!(
+                "Stored the {} API key in the {} keyring entry",
+                provider, service
+            );
+        }
+        KeyringAction::Delete => {
+            let provider = &config.provider;
+            write_keyring(service, provider, None)?;
+            println!(
+                "Removed the {} API key from the {} keyring entry",
+                provider, service
+            );
+        }
+    }
+    Ok(())
+}
+
+// The provider's key from the OS keyring, or None if there's no entry for it
+#[cfg(feature = "keyring")]
+fn read_keyring(service: &str, provider: &str) -> Result<Option<String>> {
+    let entry = keyring::Entry::new(service, provider)
+        .context(format!("Failed to open the {} keyring entry", service))?;
+    match entry.get_password() {
+        Ok(key) => Ok(Some(key.trim().to_string())),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e).context(format!(
+            "Failed to read the {} API key from the {} keyring entry",
+            provider, service
+        )),
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+fn read_keyring(_service: &str, _provider: &str) -> Result<Option<String>> {
+    anyhow::bail!("api_key_keyring requires building with --features keyring")
+}
+
+// Store the provider's key in the OS keyring, or remove it with None
+#[cfg(feature = "keyring")]
+fn write_keyring(service: &str, provider: &str, key: Option<&str>) -> Result<()> {
+    let entry = keyring::Entry::new(service, provider)
+        .context(format!("Failed to open the {} keyring entry", service))?;
+    match key {
+        Some(key) => entry.set_password(key),
+        None => entry.delete_credential(),
+    }
+    .context(format!(
+        "Failed to update the {} API key in the {} keyring entry",
+        provider, service
+    ))
+}
+
+#[cfg(not(feature = "keyring"))]
+fn write_keyring(_service: &str, _provider: &str, _key: Option<&str>) -> Result<()> {
+    anyhow::bail!("agent keyring requires building with --features keyring")
+}
+
+// The provider's key from a YAML map of provider names to keys, or None if it has none.
+// The file must not be readable by other users, as it holds every provider's key.
+fn read_secrets_file(path: &str, provider: &str) -> Result<Option<String>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let metadata =
+            std::fs::metadata(path).context(format!("Failed to read secrets file: {}", path))?;
+        let mode = metadata.permissions().mode();
+        if mode & 0o077 != 0 {
+            anyhow::bail!(
+                "Secrets file {} is accessible by other users (mode {:o}); run chmod 600 {}",
+                path,
+                mode & 0o777,
+                path
+            );
+        }
+    }
+
+    let contents =
+        std::fs::read_to_string(path).context(format!("Failed to read secrets file: {}", path))?;
+    let keys: std::collections::BTreeMap<String, String> = serde_yaml::from_str(&contents)
+        .context(format!(
+            "Invalid secrets file (expected provider: key): {}",
+            path
+        ))?;
+    Ok(keys
+        .into_iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(provider))
+        .map(|(_, key)| key.trim().to_string()))
+}
+
+// Run a shell command and use its trimmed stdout as the key
+fn read_command(command: &str) -> Result<String> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()
+    } else {
+        Command::new("sh").args(["-c", command]).output()
+    }
+    .context(format!("Failed to run API key command: {}", command))?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "API key command exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let key = String::from_utf8(output.stdout).context("API key command output is not UTF-8")?;
+    non_empty(key.trim().to_string(), command)
+}
+
+fn non_empty(key: String, source: &str) -> Result<String> {
+    if key.is_empty() {
+        anyhow::bail!("API key from {} is empty", source);
+    }
+    Ok(key)
+}
@@ -0,0 +1,92 @@
+//! Running in the background: forking off as a daemon with a PID file and its output
+//! in a log file, and telling systemd when the agent is ready
+
+#[cfg(unix)]
+use anyhow::Context;
+use anyhow::Result;
+use std::path::Path;
+
+use crate::config::Config;
+
+/// Fork into the background and return in the child; the parent exits once it has
+/// started. Stdout and stderr go to `log_file`, or to `fallback_log` without one,
+/// so logs and panics aren't lost with the terminal. This has to happen before the
+/// async runtime starts, as only the forking thread carries on in the child
+#[cfg(unix)]
+pub fn start(config: &Config, fallback_log: &Path) -> Result<()> {
+    let log_path = config.log_file.as_deref().map_or(fallback_log, Path::new);
+    let stdout = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)
+        .context(format!("Failed to open log file: {}", log_path.display()))?;
+    let stderr = stdout.try_clone()?;
+
+    let mut daemon = daemonize::Daemonize::new()
+        .working_directory(".")
+        .stdout(stdout)
+        .stderr(stderr);
+    if let Some(pid_file) = &config.pid_file {
+        daemon = daemon.pid_file(pid_file).chown_pid_file(true);
+    }
+    if let Some(user) = &config.daemon_user {
+        daemon = daemon.user(user.as_str());
+    }
+    if let Some(group) = &config.daemon_group {
+        daemon = daemon.group(group.as_str());
+    }
+
+    tracing::info!(
+        "Starting in daemon mode, output goes to {}",
+        log_path.display()
+    );
+    daemon.start().context("Failed to start as a daemon")?;
+    tracing::info!("Daemon started with pid {}", std::process::id());
+    Ok(())
+}
+
+/// Daemon mode forks, which only unix systems do
+#[cfg(not(unix))]
+pub fn start(_config: &Config, _fallback_log: &Path) -> Result<()> {
+    anyhow::bail!(
+        "Daemon mode is only available on unix systems; on Windows, run the agent as a \
+         service with `agent service install`"
+    )
+}
+
+/// Tell the service manager about the agent, e.g. `READY=1` once it takes messages,
+/// when it runs the agent as a `Type=notify` unit; does nothing otherwise
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send(&socket, state) {
+        tracing::warn!("Failed to notify the service manager of {}: {}", state, e);
+    }
+}
+
+/// systemd only runs on Linux
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
+
+#[cfg(unix)]
+fn send(socket: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let sender = UnixDatagram::unbound()?;
+    // A leading @ names a socket in the abstract namespace
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::ffi::OsStrExt;
+
+        if let Some(name) = socket.as_bytes().strip_prefix(b"@") {
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            sender.send_to_addr(state.as_bytes(), &address)?;
+            return Ok(());
+        }
+    }
+    sender.send_to(state.as_bytes(), Path::new(socket))?;
+    Ok(())
+}
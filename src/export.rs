@@ -0,0 +1,208 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::config::Config;
+use crate::history::HistoryStore;
+use crate::providers::Message;
+
+/// Format a conversation is exported in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn parse(format: &str) -> Result<Self> {
+        match format.to_lowercase().as_str() {
+            "md" | "markdown" => Ok(ExportFormat::Markdown),
+            "json" => Ok(ExportFormat::Json),
+            other => anyhow::bail!("Unsupported export format: {} (use md or json)", other),
+        }
+    }
+
+    /// File extension for exports in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonExport<'a> {
+    session: &'a str,
+    exported_at: String,
+    messages: &'a [Message],
+}
+
+// A transcript to resume: an export, or just its messages
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Transcript {
+    Export {
+        session: Option<String>,
+        messages: Vec<Message>,
+    },
+    Messages(Vec<Message>),
+}
+
+/// Read a JSON transcript, as written by `agent export --format json` or as a plain
+/// array of messages, returning its session ID, if it has one, and its messages
+/// without system messages
+pub fn load(path: &str) -> Result<(Option<String>, Vec<Message>)> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read transcript: {}", path))?;
+    let transcript: Transcript = serde_json::from_str(&contents).with_context(|| {
+        format!(
+            "Failed to parse transcript {}; only JSON exports can be resumed",
+            path
+        )
+    })?;
+    let (session, messages) = match transcript {
+        Transcript::Export { session, messages } => (session, messages),
+        Transcript::Messages(messages) => (None, messages),
+    };
+    let messages = messages
+        .into_iter()
+        .filter(|message| message.role != "system")
+        .collect();
+    Ok((session, messages))
+}
+
+/// Render a conversation, with its tool calls and their results
+pub fn render(session: &str, messages: &[Message], format: ExportFormat) -> Result<String> {
+    let now = Utc::now();
+    match format {
+        ExportFormat::Json => {
+            let export = JsonExport {
+                session,
+                exported_at: now.to_rfc3339(),
+                messages,
+            };
+            Ok(format!("{}\n", serde_json::to_string_pretty(&export)?))
+        }
+        ExportFormat::Markdown => {
+            let mut text = format!(
+                "# Conversation {}\n\nExported {}\n\n",
+                session,
+                now.format("%Y-%m-%d %H:%M:%S UTC")
+            );
+            for message in messages {
+                match &message.tool_call_id {
+                    Some(id) => text.push_str(&format!("### {} (call {})\n\n", message.role, id)),
+                    None => text.push_str(&format!("### {}\n\n", message.role)),
+                }
+                if !message.content.is_empty() {
+                    text.push_str(&format!("{}\n\n", message.content));
+                }
+                for call in message.tool_calls.iter().flatten() {
+                    let Some(function) = &call.function else {
+                        continue;
+                    };
+                    let id = call.id.as_deref().unwrap_or("no id");
+                    text.push_str(&format!(
+                        "Tool call `{}` ({}):\n\n```json\n{}\n```\n\n",
+                        function.name,
+                        id,
+                        pretty_arguments(&function.arguments)
+                    ));
+                }
+            }
+            Ok(text)
+        }
+    }
+}
+
+// Tool arguments are JSON in a string; indent them when they parse
+fn pretty_arguments(arguments: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(arguments)
+        .and_then(|value| serde_json::to_string_pretty(&value))
+        .unwrap_or_else(|_| arguments.to_string())
+}
+
+/// File name for an export of `session` made now, e.g. `mqtt_alice-20250101T120000.md`
+pub fn file_name(session: &str, format: ExportFormat) -> String {
+    let session: String = session
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!(
+        "{}-{}.{}",
+        session,
+        Utc::now().format("%Y%m%dT%H%M%S"),
+        format.extension()
+    )
+}
+
+/// Export a conversation from the history database to `output`, or to stdout
+pub fn run(
+    config: &Config,
+    session: Option<&str>,
+    format: &str,
+    output: Option<&str>,
+) -> Result<()> {
+    let format = ExportFormat::parse(format)?;
+    let path = config.history_db.as_deref().context(
+        "Exporting needs history_db set, since conversations are only kept in memory without it",
+    )?;
+    let store = HistoryStore::open(path, &config.agent_name)?;
+    let mut conversations = store.load()?;
+
+    let session = match session {
+        Some(session) => session.to_string(),
+        None if conversations.len() == 1 => conversations.keys().next().unwrap().clone(),
+        None => {
+            let mut ids: Vec<_> = conversations.keys().cloned().collect();
+            ids.sort();
+            anyhow::bail!(
+                "Choose a conversation with --session: {}",
+                if ids.is_empty() {
+                    "none stored".to_string()
+                } else {
+                    ids.join(", ")
+                }
+            );
+        }
+    };
+    let messages = conversations.remove(&session).with_context(|| {
+        format!(
+            "No conversation {} stored for {}",
+            session, config.agent_name
+        )
+    })?;
+
+    let text = render(&session, &messages, format)?;
+    match output {
+        Some(output) => {
+            write(Path::new(output), &text)?;
+            println!(
+                "Exported {} messages of {} to {}",
+                messages.len(),
+                session,
+                output
+            );
+        }
+        None => print!("{}", text),
+    }
+    Ok(())
+}
+
+/// Write an export, creating its directory if needed
+pub fn write(path: &Path, text: &str) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    }
+    std::fs::write(path, text)
+        .with_context(|| format!("Failed to write export: {}", path.display()))
+}
@@ -0,0 +1,159 @@
+//! Rules every response has to follow before it is sent to the outputs: patterns and
+//! phrases it must not contain, phrases it must, and a length limit
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+use crate::config::Config;
+
+/// Times a response is asked for again with the regenerate action, unless
+/// `guardrail_max_retries` is set
+pub const DEFAULT_MAX_RETRIES: usize = 2;
+
+/// Accepted guardrail_action values
+pub const ACTIONS: &[&str] = &["redact", "truncate", "regenerate"];
+
+/// What happens to a response that breaks a rule
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    // Replace what matched a pattern or phrase and cut the rest to length
+    Redact,
+    // Cut the response off before the first match, or at the length limit
+    Truncate,
+    // Ask the model for another response, redacting whatever still breaks a rule after
+    // the last try
+    Regenerate,
+}
+
+// A deny pattern or forbidden phrase
+struct Denied {
+    // Shown in place of matches, as [REDACTED:<name>]
+    name: String,
+    description: String,
+    regex: Regex,
+}
+
+/// The configured output guardrails
+pub struct Guardrails {
+    denied: Vec<Denied>,
+    required: Vec<String>,
+    max_chars: Option<usize>,
+    action: Action,
+    max_retries: usize,
+}
+
+impl Guardrails {
+    /// The guardrails in the config, or `None` when it sets none
+    pub fn new(config: &Config) -> Result<Option<Self>> {
+        if config.guardrail_patterns.is_empty()
+            && config.guardrail_forbidden.is_empty()
+            && config.guardrail_required.is_empty()
+            && config.guardrail_max_chars.is_none()
+        {
+            return Ok(None);
+        }
+
+        let mut denied = Vec::new();
+        for (name, pattern) in &config.guardrail_patterns {
+            denied.push(Denied {
+                name: name.clone(),
+                description: format!("matches the {} pattern", name),
+                regex: Regex::new(pattern)
+                    .context(format!("Invalid guardrail pattern {}", name))?,
+            });
+        }
+        for phrase in &config.guardrail_forbidden {
+            denied.push(Denied {
+                name: "forbidden".to_string(),
+                description: format!("contains the forbidden phrase `{}`", phrase),
+                regex: Regex::new(&format!("(?i){}", regex::escape(phrase)))?,
+            });
+        }
+        let action = match config.guardrail_action.as_deref() {
+            None | Some("redact") => Action::Redact,
+            Some("truncate") => Action::Truncate,
+            Some("regenerate") => Action::Regenerate,
+            Some(other) => anyhow::bail!(
+                "Unknown guardrail action {}, expected one of {}",
+                other,
+                ACTIONS.join(", ")
+            ),
+        };
+
+        Ok(Some(Self {
+            denied,
+            required: config.guardrail_required.clone(),
+            max_chars: config.guardrail_max_chars,
+            action,
+            max_retries: config.guardrail_max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+        }))
+    }
+
+    /// How many times to ask for a response again while it breaks a rule
+    pub fn retries(&self) -> usize {
+        match self.action {
+            Action::Regenerate => self.max_retries,
+            _ => 0,
+        }
+    }
+
+    /// The rules `text` breaks, described for the log and for the model
+    pub fn violations(&self, text: &str) -> Vec<String> {
+        let mut violations: Vec<String> = self
+            .denied
+            .iter()
+            .filter(|denied| denied.regex.is_match(text))
+            .map(|denied| denied.description.clone())
+            .collect();
+        let lowercase = text.to_lowercase();
+        for phrase in &self.required {
+            if !lowercase.contains(&phrase.to_lowercase()) {
+                violations.push(format!("is missing the required phrase `{}`", phrase));
+            }
+        }
+        if let Some(max) = self.max_chars {
+            let length = text.chars().count();
+            if length > max {
+                violations.push(format!("is {} characters long, more than {}", length, max));
+            }
+        }
+        violations
+    }
+
+    /// `text` made to follow the rules without the model: matches redacted, or cut off
+    /// before the first one with truncate, and cut to the length limit. A missing
+    /// required phrase can't be fixed this way and is left as is
+    pub fn enforce(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        if self.action == Action::Truncate {
+            let first = self
+                .denied
+                .iter()
+                .filter_map(|denied| denied.regex.find(&text).map(|found| found.start()))
+                .min();
+            if let Some(start) = first {
+                text.truncate(start);
+                text = text.trim_end().to_string();
+            }
+        } else {
+            for denied in &self.denied {
+                let placeholder = format!("[REDACTED:{}]", denied.name);
+                text = denied
+                    .regex
+                    .replace_all(&text, regex::NoExpand(&placeholder))
+                    .into_owned();
+            }
+        }
+        if let Some(max) = self.max_chars {
+            if let Some((cut, _)) = text.char_indices().nth(max) {
+                // Room for the ellipsis
+                let cut = text[..cut]
+                    .char_indices()
+                    .last()
+                    .map_or(0, |(index, _)| index);
+                text = format!("{}…", &text[..cut]);
+            }
+        }
+        text
+    }
+}
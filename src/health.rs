@@ -0,0 +1,39 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+// Latest state of each component, reported as it changes and served by the
+// webhook server's /readyz route
+static CHECKS: Mutex<BTreeMap<String, Check>> = Mutex::new(BTreeMap::new());
+
+/// The state of one component
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    pub detail: String,
+}
+
+/// Record the state of a component, replacing what it reported before
+pub fn report(name: &str, ok: bool, detail: impl Into<String>) {
+    CHECKS.lock().unwrap().insert(
+        name.to_string(),
+        Check {
+            ok,
+            detail: detail.into(),
+        },
+    );
+}
+
+/// Record whether an operation worked, with the error as the detail if it didn't
+pub fn report_result<T>(name: &str, result: &anyhow::Result<T>) {
+    match result {
+        Ok(_) => report(name, true, "ok"),
+        Err(e) => report(name, false, format!("{:#}", e)),
+    }
+}
+
+/// Whether every component is ok, and the state of each
+pub fn checks() -> (bool, BTreeMap<String, Check>) {
+    let checks = CHECKS.lock().unwrap().clone();
+    (checks.values().all(|check| check.ok), checks)
+}
@@ -0,0 +1,135 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use crate::providers::Message;
+use crate::session::SessionManager;
+
+/// Conversations kept in a SQLite database, so they survive restarts and crashes
+///
+/// Each agent's conversations are stored under its name and their session ID
+/// (`<source>` or `<source>:<user>`). System messages aren't stored, since they come
+/// from the config.
+#[cfg(feature = "sqlite")]
+pub struct HistoryStore {
+    connection: std::sync::Mutex<rusqlite::Connection>,
+    agent: String,
+}
+
+/// Stand-in for builds without the sqlite feature, which can't open a database
+#[cfg(not(feature = "sqlite"))]
+pub struct HistoryStore;
+
+#[cfg(feature = "sqlite")]
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS history (
+    agent TEXT NOT NULL,
+    session TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    message TEXT NOT NULL,
+    PRIMARY KEY (agent, session, position)
+);
+";
+
+#[cfg(feature = "sqlite")]
+impl HistoryStore {
+    /// Open (or create) the database at `path` for the conversations of `agent`
+    pub fn open(path: &str, agent: &str) -> Result<Self> {
+        use anyhow::Context;
+
+        let connection = rusqlite::Connection::open(path)
+            .context(format!("Failed to open history database: {}", path))?;
+        connection
+            .execute_batch(SCHEMA)
+            .context("Failed to create the history table")?;
+        Ok(Self {
+            connection: std::sync::Mutex::new(connection),
+            agent: agent.to_string(),
+        })
+    }
+
+    /// Every stored conversation, keyed by session ID, without system messages
+    pub fn load(&self) -> Result<HashMap<String, Vec<Message>>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT session, message FROM history WHERE agent = ?1 ORDER BY session, position",
+        )?;
+        let rows = statement.query_map([&self.agent], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut conversations: HashMap<String, Vec<Message>> = HashMap::new();
+        for row in rows {
+            let (session, message) = row?;
+            conversations
+                .entry(session)
+                .or_default()
+                .push(serde_json::from_str(&message)?);
+        }
+        Ok(conversations)
+    }
+
+    /// Replace the stored conversation of one session; `messages` starts with the
+    /// system message, which is left out
+    pub fn save(&self, session: &str, messages: &[Message]) -> Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        Self::write(&transaction, &self.agent, session, messages)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Replace every stored conversation of this agent with those in `sessions`
+    pub fn save_all(&self, sessions: &SessionManager) -> Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        transaction.execute("DELETE FROM history WHERE agent = ?1", [&self.agent])?;
+        for (key, messages) in sessions.iter() {
+            Self::write(&transaction, &self.agent, &key.to_string(), messages)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn write(
+        connection: &rusqlite::Connection,
+        agent: &str,
+        session: &str,
+        messages: &[Message],
+    ) -> Result<()> {
+        connection.execute(
+            "DELETE FROM history WHERE agent = ?1 AND session = ?2",
+            [agent, session],
+        )?;
+        let mut statement = connection.prepare(
+            "INSERT INTO history (agent, session, position, message) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for (position, message) in messages.iter().skip(1).enumerate() {
+            statement.execute(rusqlite::params![
+                agent,
+                session,
+                position as i64,
+                serde_json::to_string(message)?
+            ])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+impl HistoryStore {
+    pub fn open(_path: &str, _agent: &str) -> Result<Self> {
+        anyhow::bail!("history_db requires building with --features sqlite")
+    }
+
+    pub fn load(&self) -> Result<HashMap<String, Vec<Message>>> {
+        Ok(HashMap::new())
+    }
+
+    pub fn save(&self, _session: &str, _messages: &[Message]) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn save_all(&self, _sessions: &SessionManager) -> Result<()> {
+        Ok(())
+    }
+}
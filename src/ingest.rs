@@ -0,0 +1,341 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::config::Config;
+use crate::providers::embeddings::Embedder;
+use crate::providers::tokens;
+use crate::tools::documents::{index_path, Chunk, DocumentIndex, IndexedSource};
+
+const DEFAULT_CHUNK_TOKENS: usize = 400;
+const DEFAULT_CHUNK_OVERLAP: usize = 50;
+
+// Files picked up when walking a directory; files named directly are read whatever
+// their extension
+const EXTENSIONS: &[&str] = &["txt", "md", "markdown", "pdf"];
+
+// What happened to a source on this run
+enum Outcome {
+    Added,
+    Updated,
+    Unchanged,
+}
+
+/// Chunk, embed and index `sources`, or the config's `documents` when none are given.
+///
+/// Files whose modification time or text hasn't changed since the last run keep their
+/// chunks. Indexing the config's list also drops sources that are no longer in it.
+pub async fn run(config: &Config, sources: &[String]) -> Result<()> {
+    let (targets, prune) = if sources.is_empty() {
+        if config.documents.is_empty() {
+            anyhow::bail!("Nothing to ingest: give files or URLs, or list them under documents");
+        }
+        (config.documents.clone(), true)
+    } else {
+        (sources.to_vec(), false)
+    };
+
+    let mut expanded = Vec::new();
+    for target in &targets {
+        if is_url(target) {
+            expanded.push(target.clone());
+        } else {
+            expand(Path::new(target), true, &mut expanded)?;
+        }
+    }
+
+    let path = index_path(config);
+    let mut index = DocumentIndex::load(&path)?;
+    let model = config.embedding_model();
+    if index.model != model {
+        if !index.sources.is_empty() {
+            println!(
+                "Embedding model changed from {} to {}; re-indexing everything",
+                index.model, model
+            );
+        }
+        index.sources.clear();
+        index.model = model.to_string();
+    }
+
+    let embedder = Embedder::new(config)?;
+    let chunk_tokens = config
+        .documents_chunk_tokens
+        .unwrap_or(DEFAULT_CHUNK_TOKENS);
+    let overlap = config
+        .documents_chunk_overlap
+        .unwrap_or(DEFAULT_CHUNK_OVERLAP)
+        .min(chunk_tokens.saturating_sub(1));
+
+    let (mut added, mut updated, mut unchanged, mut failed) = (0, 0, 0, 0);
+    for source in &expanded {
+        let result = ingest_source(&mut index, &embedder, source, chunk_tokens, overlap).await;
+        match result {
+            Ok(Outcome::Added) => {
+                added += 1;
+                println!("Added {}", source);
+            }
+            Ok(Outcome::Updated) => {
+                updated += 1;
+                println!("Updated {}", source);
+            }
+            Ok(Outcome::Unchanged) => unchanged += 1,
+            Err(e) => {
+                failed += 1;
+                eprintln!("Failed to ingest {}: {:#}", source, e);
+                continue;
+            }
+        }
+        // Save as we go, so an interrupted run keeps what it embedded
+        index.save(&path)?;
+    }
+
+    let mut removed = 0;
+    if prune {
+        let before = index.sources.len();
+        index
+            .sources
+            .retain(|indexed| expanded.contains(&indexed.source));
+        removed = before - index.sources.len();
+        index.save(&path)?;
+    }
+
+    println!(
+        "{} added, {} updated, {} unchanged, {} removed, {} failed; {} chunks from {} sources in {}",
+        added,
+        updated,
+        unchanged,
+        removed,
+        failed,
+        index.chunk_count(),
+        index.sources.len(),
+        path.display()
+    );
+    if failed > 0 {
+        anyhow::bail!("{} source(s) could not be ingested", failed);
+    }
+    Ok(())
+}
+
+async fn ingest_source(
+    index: &mut DocumentIndex,
+    embedder: &Embedder,
+    source: &str,
+    chunk_tokens: usize,
+    overlap: usize,
+) -> Result<Outcome> {
+    let position = index
+        .sources
+        .iter()
+        .position(|indexed| indexed.source == source);
+
+    // A file that hasn't been touched needn't even be read
+    let modified = if is_url(source) {
+        None
+    } else {
+        std::fs::metadata(source)?
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_millis() as u64)
+    };
+    if let Some(position) = position {
+        if modified.is_some() && index.sources[position].modified == modified {
+            return Ok(Outcome::Unchanged);
+        }
+    }
+
+    let text = if is_url(source) {
+        fetch(source).await?
+    } else {
+        read_file(Path::new(source))?
+    };
+    let hash = hex::encode(Sha256::digest(text.as_bytes()));
+    if let Some(position) = position {
+        if index.sources[position].hash == hash {
+            index.sources[position].modified = modified;
+            return Ok(Outcome::Unchanged);
+        }
+    }
+
+    let texts = chunk(&text, chunk_tokens, overlap);
+    let embeddings = embedder.embed_all(&texts).await?;
+    let indexed = IndexedSource {
+        source: source.to_string(),
+        modified,
+        hash,
+        chunks: texts
+            .into_iter()
+            .zip(embeddings)
+            .map(|(text, embedding)| Chunk { text, embedding })
+            .collect(),
+    };
+    match position {
+        Some(position) => {
+            index.sources[position] = indexed;
+            Ok(Outcome::Updated)
+        }
+        None => {
+            index.sources.push(indexed);
+            Ok(Outcome::Added)
+        }
+    }
+}
+
+fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+// Collect the files under `path`; `named` files are taken whatever their extension
+fn expand(path: &Path, named: bool, files: &mut Vec<String>) -> Result<()> {
+    let metadata = std::fs::metadata(path).context(format!("Can't read {}", path.display()))?;
+    if metadata.is_dir() {
+        let mut entries: Vec<_> = std::fs::read_dir(path)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                !path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with('.'))
+            })
+            .collect();
+        entries.sort();
+        for entry in entries {
+            expand(&entry, false, files)?;
+        }
+    } else if named || has_extension(path, EXTENSIONS) {
+        files.push(path.display().to_string());
+    }
+    Ok(())
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.contains(&ext.to_lowercase().as_str()))
+}
+
+// The text of a file; PDFs go through pdftotext from poppler-utils
+fn read_file(path: &Path) -> Result<String> {
+    if has_extension(path, &["pdf"]) {
+        let output = std::process::Command::new("pdftotext")
+            .arg("-layout")
+            .arg(path)
+            .arg("-")
+            .output()
+            .context("Failed to run pdftotext; install poppler-utils to ingest PDFs")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "pdftotext failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+    }
+    std::fs::read_to_string(path).context(format!("Failed to read {}", path.display()))
+}
+
+// The text of a web page, with the markup of HTML pages removed
+async fn fetch(url: &str) -> Result<String> {
+    let response = reqwest::get(url).await?.error_for_status()?;
+    let is_html = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("html"));
+    let body = response.text().await?;
+    Ok(if is_html { html_to_text(&body) } else { body })
+}
+
+// Drop tags, scripts and styles, keeping the text between them
+fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let skip_to = if starts_with_ignore_case(rest, "<script") {
+            "</script>"
+        } else if starts_with_ignore_case(rest, "<style") {
+            "</style>"
+        } else {
+            ">"
+        };
+        rest = match find_ignore_case(rest, skip_to) {
+            Some(end) => &rest[end + skip_to.len()..],
+            None => "",
+        };
+        text.push(' ');
+    }
+    text.push_str(rest);
+
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    // Collapse the whitespace the markup leaves, keeping paragraph breaks
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.as_bytes()
+        .get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix.as_bytes()))
+}
+
+// Byte offset of an ASCII `needle` in `text`, ignoring ASCII case
+fn find_ignore_case(text: &str, needle: &str) -> Option<usize> {
+    text.as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+// Split text into chunks of about `size` tokens, ending at a paragraph break or
+// whitespace where possible, each starting `overlap` tokens before the last ended
+fn chunk(text: &str, size: usize, overlap: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let mut piece = tokens::truncate(rest, size);
+        if piece.len() < rest.len() {
+            let cut = piece
+                .rfind("\n\n")
+                .filter(|&i| i > piece.len() / 2)
+                .or_else(|| piece.rfind(char::is_whitespace).filter(|&i| i > 0));
+            if let Some(cut) = cut {
+                piece = &piece[..cut];
+            }
+        }
+        if piece.is_empty() {
+            // A single character over the budget; take it anyway
+            piece = &rest[..rest.chars().next().map_or(0, char::len_utf8)];
+        }
+        chunks.push(piece.trim().to_string());
+        if piece.len() >= rest.len() {
+            break;
+        }
+
+        // Start the next chunk `overlap` tokens back, at a word boundary
+        let kept = tokens::truncate(piece, tokens::estimate(piece).saturating_sub(overlap));
+        let start = piece[kept.len()..]
+            .find(char::is_whitespace)
+            .map_or(piece.len(), |i| kept.len() + i);
+        let start = if start == 0 { piece.len() } else { start };
+        rest = rest[start..].trim_start();
+    }
+    chunks.retain(|chunk| !chunk.is_empty());
+    chunks
+}
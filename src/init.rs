@@ -0,0 +1,395 @@
+use anyhow::{Context, Result};
+use std::io::Write;
+
+use crate::config::Config;
+use crate::io;
+use crate::providers::{self, ProviderSettings, PROVIDERS};
+
+/// Choices for `agent init`; any left unset are asked for when stdin is a terminal
+#[derive(Debug, Default)]
+pub struct InitOptions {
+    pub agent_name: Option<String>,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub inputs: Option<Vec<String>>,
+    pub outputs: Option<Vec<String>>,
+    pub enable_tools: Option<bool>,
+    /// Use the defaults instead of asking
+    pub yes: bool,
+    /// Replace an existing file
+    pub force: bool,
+}
+
+// Commented example settings, with the inputs and outputs they belong to
+const SECTIONS: &[(&[&str], &str)] = &[
+    (
+        &["stdin"],
+        "# Keep interactive stdin history across runs
+# stdin_history_file: .agent_history
+",
+    ),
+    (
+        &["mqtt"],
+        "# MQTT settings
+# mqtt_broker: localhost
+# mqtt_port: 1883
+# mqtt_input_topic: agent/{agent}/input
+# mqtt_output_topic: agent/{agent}/output
+# mqtt_input_qos: 1   # 0 = at most once, 1 = at least once, 2 = exactly once
+# mqtt_output_qos: 1
+# mqtt_retain: false  # keep the last response on the broker for new subscribers
+# mqtt_clean_session: false  # queue messages sent while the agent is down
+# mqtt_control: true  # accept commands on agent/{agent}/control
+# mqtt_brokers:       # extra brokers, used as the mqtt:<name> input and output
+#   cloud:
+#     broker: broker.emqx.io
+#     output_topic: devices/{agent}/output
+",
+    ),
+    (
+        &["webhook", "sse", "github"],
+        "# Webhook server (the webhook input; the sse and github outputs use it too)
+# webhook_bind: 127.0.0.1  # default: all interfaces
+# webhook_port: 8080       # default: a random free port
+# webhook_token: ...       # require Authorization: Bearer <token>, or set WEBHOOK_TOKEN
+# webhook_secret: ...      # require X-Signature-256 HMAC signatures, or set WEBHOOK_SECRET
+# webhook_rate_limit: 60   # requests a minute per client, 0 for no limit
+# cors_origins: [https://app.example.com]
+# admin_token: ...         # serve the /admin API, or set ADMIN_TOKEN
+",
+    ),
+    (
+        &["webhook"],
+        "# Webhook output
+# webhook_url: http://localhost:8000/agent
+",
+    ),
+    (
+        &["github"],
+        "# GitHub mentions, through the webhook server's /github route
+# github_bot_login: my-agent-bot
+# github_webhook_secret: ...  # or GITHUB_WEBHOOK_SECRET
+# github_token: ...           # or GITHUB_TOKEN
+",
+    ),
+    (
+        &["chat_webhook"],
+        "# Chat webhook output
+# chat_webhook_url: https://chat.example.com/hooks/...
+# chat_webhook_platform: mattermost  # rocketchat, slack, googlechat, teams or custom
+",
+    ),
+    (
+        &["command"],
+        "# Command input, run on a schedule
+# command_input: df -h
+# command_interval: 600
+# command_template: \"Warn me if a disk is nearly full:\\n{{output}}\"
+",
+    ),
+    (
+        &["http_poll"],
+        "# HTTP polling input
+# http_poll_url: https://example.com/status.json
+# http_poll_interval: 300
+# http_poll_template: \"Summarize what changed:\\n{{content}}\"
+",
+    ),
+    (
+        &["exec"],
+        "# Exec output, piping each response to a command
+# exec_command: notify-send Agent
+# exec_timeout: 30
+",
+    ),
+    (
+        &["api"],
+        "# OpenAI-compatible API
+# api_bind: 127.0.0.1:8001
+# api_server_key: sk-local  # require Authorization: Bearer sk-local
+",
+    ),
+    (
+        &["tcp"],
+        "# TCP socket
+# tcp_bind: 127.0.0.1:7878
+# tcp_tls_cert: certs/agent.pem
+# tcp_tls_key: certs/agent.key
+",
+    ),
+    (
+        &["grpc"],
+        "# gRPC service (build with --features grpc)
+# grpc_bind: 127.0.0.1:50051
+",
+    ),
+    (
+        &["nats"],
+        "# NATS (build with --features nats)
+# nats_url: nats://localhost:4222
+# nats_input_subject: agent.{agent}.input
+# nats_output_subject: agent.{agent}.output
+# nats_queue_group: agents
+",
+    ),
+    (
+        &["pubsub"],
+        "# Google Cloud Pub/Sub (build with --features pubsub)
+# pubsub_subscription: agent-input
+# pubsub_topic: agent-output
+# pubsub_credentials: ./service-account.json  # default: GOOGLE_APPLICATION_CREDENTIALS
+",
+    ),
+    (
+        &["file"],
+        "# Transcript file output
+# transcript_path: transcript.jsonl
+# transcript_format: jsonl  # or markdown
+# transcript_rotate_daily: true
+",
+    ),
+    (
+        &["sqlite"],
+        "# SQLite analytics output (build with --features sqlite)
+# sqlite_path: agent.db
+",
+    ),
+    (
+        &["matrix"],
+        "# Matrix (build with --features matrix)
+# matrix_homeserver: https://matrix.org
+# matrix_user: \"@{agent}:matrix.org\"
+# matrix_password: ...  # or MATRIX_PASSWORD
+# matrix_rooms:
+#   - \"#agent-room:matrix.org\"
+",
+    ),
+    (
+        &["xmpp"],
+        "# XMPP (build with --features xmpp)
+# xmpp_jid: {agent}@example.com
+# xmpp_password: ...  # or XMPP_PASSWORD
+# xmpp_rooms:
+#   - agents@conference.example.com
+",
+    ),
+    (
+        &["twitch"],
+        "# Twitch chat
+# twitch_username: my_agent
+# twitch_oauth_token: ...  # or TWITCH_OAUTH_TOKEN
+# twitch_channels: [mychannel]
+# twitch_prefix: \"!ask\"
+",
+    ),
+    (
+        &["nostr"],
+        "# Nostr DMs (build with --features nostr)
+# nostr_secret_key: nsec1...  # or NOSTR_SECRET_KEY
+# nostr_relays: [wss://relay.damus.io]
+",
+    ),
+    (
+        &["email"],
+        "# Email (build with --features email)
+# email_imap_server: imap.example.com
+# email_smtp_server: smtp.example.com
+# email_username: {agent}@example.com
+# email_password: ...  # or EMAIL_PASSWORD
+",
+    ),
+    (
+        &["voice"],
+        "# Voice input (build with --features voice)
+# voice_transcriber: api  # Whisper API, or local for whisper.cpp
+# voice_language: en
+",
+    ),
+];
+
+/// Write a commented starter config to `path`, asking for anything `options` leaves
+/// unset when stdin is a terminal
+pub fn run(path: &str, mut options: InitOptions) -> Result<()> {
+    if std::path::Path::new(path).exists() && !options.force {
+        anyhow::bail!("{} already exists; pass --force to replace it", path);
+    }
+    let interactive = !options.yes && atty::is(atty::Stream::Stdin);
+
+    let agent_name = match options.agent_name.take() {
+        Some(name) => name,
+        None if interactive => ask("Agent name", "agent")?,
+        None => "agent".to_string(),
+    };
+    let provider = match options.provider.take() {
+        Some(provider) => provider,
+        None if interactive => ask(
+            &format!("Provider ({})", PROVIDERS.join(", ")),
+            PROVIDERS[0],
+        )?,
+        None => PROVIDERS[0].to_string(),
+    };
+    let models =
+        providers::get_provider(&provider, "", &ProviderSettings::default())?.available_models();
+    let default_model = models.first().cloned().unwrap_or_default();
+    let model = match options.model.take() {
+        Some(model) => model,
+        None if interactive => ask(&format!("Model ({})", models.join(", ")), &default_model)?,
+        None => default_model,
+    };
+    let inputs = match options.inputs.take() {
+        Some(inputs) => inputs,
+        None if interactive => split(&ask(
+            &format!("Inputs ({})", io::registry::kinds("input").join(", ")),
+            "stdin",
+        )?),
+        None => vec!["stdin".to_string()],
+    };
+    let outputs = match options.outputs.take() {
+        Some(outputs) => outputs,
+        None if interactive => split(&ask(
+            &format!("Outputs ({})", io::registry::kinds("output").join(", ")),
+            "stdout",
+        )?),
+        None => vec!["stdout".to_string()],
+    };
+    let enable_tools = match options.enable_tools {
+        Some(enable_tools) => enable_tools,
+        None if interactive => ask("Enable tools (y/n)", "y")?
+            .to_lowercase()
+            .starts_with('y'),
+        None => true,
+    };
+
+    let contents = render(
+        &agent_name,
+        &provider,
+        &model,
+        &inputs,
+        &outputs,
+        enable_tools,
+    );
+    // Catch a mistyped input or output before writing anything
+    let config: Config =
+        serde_yaml::from_str(&contents).context("Failed to generate a valid config")?;
+    config.validate().context("Invalid choices")?;
+
+    std::fs::write(path, contents).context(format!("Failed to write {}", path))?;
+    println!("Wrote {}", path);
+    println!(
+        "Set {}_API_KEY, then check the config with: agent validate --config {}",
+        provider.to_uppercase(),
+        path
+    );
+    Ok(())
+}
+
+// The starter config, with the examples for the chosen inputs and outputs first
+fn render(
+    agent_name: &str,
+    provider: &str,
+    model: &str,
+    inputs: &[String],
+    outputs: &[String],
+    enable_tools: bool,
+) -> String {
+    let list = |items: &[String]| {
+        items
+            .iter()
+            .map(|item| format!("  - {}\n", item))
+            .collect::<String>()
+    };
+    let mut contents = format!(
+        "# Agent configuration, generated by agent init
+# Check it with: agent validate --config <this file>
+
+# Agent identity, used in MQTT topics and service names
+agent_name: {agent_name}
+
+# Provider settings; the API key is read from {key}_API_KEY
+provider: {provider}
+model: {model}
+# api_key_file: /run/secrets/{provider}_api_key
+# api_key_command: pass show {provider}
+
+# System message
+system_message: |
+  You are a helpful assistant. Be concise and friendly.
+
+# Tools: get_current_weather, calculate and generate_image
+enable_tools: {enable_tools}
+# image_output_dir: ./images  # default: publish generated images to the outputs
+# image_model: dall-e-3
+# image_max_count: 1
+
+# Where messages come from and where responses go
+inputs_vec:
+{inputs}
+outputs_vec:
+{outputs}
+# Inputs and outputs with settings of their own, in addition to the lists above
+# inputs:
+#   - type: webhook
+#     port: 8080
+# outputs:
+#   - type: mqtt
+#     topic: replies
+
+# History settings: conversations are trimmed to fit the model's context window
+# context_tokens: 128000  # default: known for common models, otherwise 8192
+# response_tokens: 4096   # kept free for the response
+# max_history_messages: 50
+
+# Reload this file when it changes, as on SIGHUP
+# config_watch: true
+",
+        key = provider.to_uppercase(),
+        inputs = list(inputs),
+        outputs = list(outputs),
+    );
+
+    let chosen = |kinds: &[&str]| {
+        inputs
+            .iter()
+            .chain(outputs)
+            .map(|name| name.split(':').next().unwrap_or(name))
+            .any(|name| kinds.contains(&name))
+    };
+    let (selected, others): (Vec<_>, Vec<_>) =
+        SECTIONS.iter().partition(|(kinds, _)| chosen(kinds));
+    if !selected.is_empty() {
+        contents.push_str("\n# ---- Settings for the chosen inputs and outputs ----\n");
+        for (_, section) in selected {
+            contents.push('\n');
+            contents.push_str(&section.replace("{agent}", agent_name));
+        }
+    }
+    contents.push_str("\n# ---- Settings for other inputs and outputs ----\n");
+    for (_, section) in others {
+        contents.push('\n');
+        contents.push_str(&section.replace("{agent}", agent_name));
+    }
+    contents
+}
+
+// Ask on stderr and read a line from stdin, using `default` for an empty answer
+fn ask(question: &str, default: &str) -> Result<String> {
+    eprint!("{} [{}]: ", question, default);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .context("Failed to read from stdin")?;
+    let answer = answer.trim();
+    Ok(if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer.to_string()
+    })
+}
+
+fn split(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
@@ -0,0 +1,366 @@
+use anyhow::Result;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex as StdMutex, OnceLock};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info};
+
+use super::ControlCommand;
+
+// Senders of requests to each agent's main loop, which owns the state they touch,
+// by agent name
+type Agents = Arc<StdMutex<BTreeMap<String, mpsc::Sender<AdminRequest>>>>;
+
+// Agents register here as they start, so a server started by any of them reaches all
+static AGENTS: OnceLock<Agents> = OnceLock::new();
+
+fn agents() -> Agents {
+    AGENTS.get_or_init(Agents::default).clone()
+}
+
+/// An operation on the agent's runtime state
+#[derive(Debug, Clone)]
+pub enum AdminCommand {
+    // Every conversation and its length
+    Sessions,
+    // Messages of one sender's conversation, or the shared one
+    History(Option<String>),
+    // Forget one sender's conversation, or all of them
+    ClearHistory(Option<String>),
+    // One conversation rendered as md or json, like `agent export`
+    Export {
+        session: Option<String>,
+        format: String,
+    },
+    // The running config without secrets
+    Config,
+    // The same commands as the MQTT control topic
+    Control(ControlCommand),
+}
+
+/// An admin API request waiting for the main loop's answer
+pub struct AdminRequest {
+    pub command: AdminCommand,
+    pub reply: oneshot::Sender<Result<serde_json::Value>>,
+}
+
+/// Start taking admin API requests for the agent named `agent`, at `/admin/<agent>/...`
+/// and, while it is the only agent, at `/admin/...`; until then the API answers 503
+pub fn requests(agent: &str) -> mpsc::Receiver<AdminRequest> {
+    let (sender, receiver) = mpsc::channel(10);
+    // An agent of the same name that stopped is replaced
+    agents().lock().unwrap().insert(agent.to_string(), sender);
+    receiver
+}
+
+// What the admin routes need: the token and the agents to send requests to
+#[derive(Clone)]
+struct AdminState {
+    token: Arc<String>,
+    agents: Agents,
+}
+
+#[derive(Deserialize)]
+struct SessionQuery {
+    session: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    session: Option<String>,
+    format: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ModelRequest {
+    model: String,
+}
+
+#[derive(Deserialize)]
+struct ProviderRequest {
+    provider: String,
+    model: Option<String>,
+}
+
+/// Routes for the webhook server's `/admin` API, all requiring `token`. Each route is
+/// served for every agent under its name, as in `/admin/kitchen/status`, and without
+/// one for a process running a single agent
+pub(super) fn router(token: String) -> Router {
+    info!("Admin API enabled at /admin");
+    let state = AdminState {
+        token: Arc::new(token),
+        agents: agents(),
+    };
+    routes().nest("/:agent", routes()).with_state(state)
+}
+
+fn routes() -> Router<AdminState> {
+    Router::new()
+        .route("/status", get(status))
+        .route("/sessions", get(sessions))
+        .route("/history", get(history).delete(clear_history))
+        .route("/export", get(export))
+        .route("/config", get(config))
+        .route("/model", put(set_model))
+        .route("/provider", put(set_provider))
+        .route("/pause", post(pause))
+        .route("/resume", post(resume))
+        .route("/reload", post(reload))
+}
+
+async fn status(
+    State(state): State<AdminState>,
+    agent: Option<Path<String>>,
+    headers: HeaderMap,
+) -> Response {
+    run(
+        &state,
+        agent,
+        &headers,
+        AdminCommand::Control(ControlCommand::Status),
+    )
+    .await
+}
+
+async fn sessions(
+    State(state): State<AdminState>,
+    agent: Option<Path<String>>,
+    headers: HeaderMap,
+) -> Response {
+    run(&state, agent, &headers, AdminCommand::Sessions).await
+}
+
+async fn history(
+    State(state): State<AdminState>,
+    agent: Option<Path<String>>,
+    headers: HeaderMap,
+    Query(query): Query<SessionQuery>,
+) -> Response {
+    run(
+        &state,
+        agent,
+        &headers,
+        AdminCommand::History(query.session),
+    )
+    .await
+}
+
+async fn clear_history(
+    State(state): State<AdminState>,
+    agent: Option<Path<String>>,
+    headers: HeaderMap,
+    Query(query): Query<SessionQuery>,
+) -> Response {
+    run(
+        &state,
+        agent,
+        &headers,
+        AdminCommand::ClearHistory(query.session),
+    )
+    .await
+}
+
+async fn export(
+    State(state): State<AdminState>,
+    agent: Option<Path<String>>,
+    headers: HeaderMap,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    let command = AdminCommand::Export {
+        session: query.session,
+        format: query.format.unwrap_or_else(|| "md".to_string()),
+    };
+    run(&state, agent, &headers, command).await
+}
+
+async fn config(
+    State(state): State<AdminState>,
+    agent: Option<Path<String>>,
+    headers: HeaderMap,
+) -> Response {
+    run(&state, agent, &headers, AdminCommand::Config).await
+}
+
+async fn set_model(
+    State(state): State<AdminState>,
+    agent: Option<Path<String>>,
+    headers: HeaderMap,
+    Json(request): Json<ModelRequest>,
+) -> Response {
+    let command = ControlCommand::SetModel(request.model);
+    run(&state, agent, &headers, AdminCommand::Control(command)).await
+}
+
+async fn set_provider(
+    State(state): State<AdminState>,
+    agent: Option<Path<String>>,
+    headers: HeaderMap,
+    Json(request): Json<ProviderRequest>,
+) -> Response {
+    let command = ControlCommand::SetProvider {
+        provider: request.provider,
+        model: request.model,
+    };
+    run(&state, agent, &headers, AdminCommand::Control(command)).await
+}
+
+async fn pause(
+    State(state): State<AdminState>,
+    agent: Option<Path<String>>,
+    headers: HeaderMap,
+) -> Response {
+    run(
+        &state,
+        agent,
+        &headers,
+        AdminCommand::Control(ControlCommand::Pause),
+    )
+    .await
+}
+
+async fn resume(
+    State(state): State<AdminState>,
+    agent: Option<Path<String>>,
+    headers: HeaderMap,
+) -> Response {
+    run(
+        &state,
+        agent,
+        &headers,
+        AdminCommand::Control(ControlCommand::Resume),
+    )
+    .await
+}
+
+async fn reload(
+    State(state): State<AdminState>,
+    agent: Option<Path<String>>,
+    headers: HeaderMap,
+) -> Response {
+    run(
+        &state,
+        agent,
+        &headers,
+        AdminCommand::Control(ControlCommand::ReloadConfig),
+    )
+    .await
+}
+
+// Check the token, then hand the command to the agent's main loop and wait for its
+// answer
+async fn run(
+    state: &AdminState,
+    agent: Option<Path<String>>,
+    headers: &HeaderMap,
+    command: AdminCommand,
+) -> Response {
+    let given = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !super::webhook::constant_time_eq(given.as_bytes(), state.token.as_bytes()) {
+        error!("Rejected admin API request with a missing or invalid token");
+        return reply(StatusCode::UNAUTHORIZED, "Unauthorized");
+    }
+
+    let requests = {
+        let agents = state.agents.lock().unwrap();
+        match agent {
+            Some(Path(name)) => match agents.get(&name) {
+                Some(requests) => requests.clone(),
+                None => {
+                    let names: Vec<&str> = agents.keys().map(String::as_str).collect();
+                    let error =
+                        format!("No agent named `{}` (running: {})", name, names.join(", "));
+                    return reply(StatusCode::NOT_FOUND, &error);
+                }
+            },
+            None => match agents.len() {
+                0 => return reply(StatusCode::SERVICE_UNAVAILABLE, "Agent is not running"),
+                1 => agents.values().next().expect("one agent").clone(),
+                _ => {
+                    let names: Vec<&str> = agents.keys().map(String::as_str).collect();
+                    let error = format!(
+                        "Several agents are running, so name one, as in /admin/{}/status",
+                        names[0]
+                    );
+                    return reply(StatusCode::BAD_REQUEST, &error);
+                }
+            },
+        }
+    };
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let request = AdminRequest {
+        command,
+        reply: reply_tx,
+    };
+    if requests.send(request).await.is_err() {
+        return reply(StatusCode::SERVICE_UNAVAILABLE, "Agent is shutting down");
+    }
+
+    match reply_rx.await {
+        Ok(Ok(value)) => (StatusCode::OK, Json(value)).into_response(),
+        Ok(Err(e)) => reply(StatusCode::BAD_REQUEST, &format!("{:#}", e)),
+        Err(_) => reply(StatusCode::SERVICE_UNAVAILABLE, "Agent is shutting down"),
+    }
+}
+
+fn reply(status: StatusCode, error: &str) -> Response {
+    (status, Json(serde_json::json!({ "error": error }))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An agent that answers every request with its name
+    fn answer_as(name: &'static str) {
+        let mut requests = requests(name);
+        tokio::spawn(async move {
+            while let Some(request) = requests.recv().await {
+                let _ = request.reply.send(Ok(serde_json::json!({ "agent": name })));
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn requests_go_to_the_named_agent() -> Result<()> {
+        answer_as("kitchen");
+        answer_as("garage");
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let app = Router::new().nest("/admin", router("admin-token".to_string()));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = reqwest::Client::new();
+        let get = |path: &str| {
+            client
+                .get(format!("{}{}", url, path))
+                .bearer_auth("admin-token")
+        };
+        for name in ["kitchen", "garage"] {
+            let response = get(&format!("/admin/{}/status", name)).send().await?;
+            assert_eq!(response.status().as_u16(), 200);
+            let body: serde_json::Value = response.json().await?;
+            assert_eq!(body["agent"], name);
+        }
+
+        // Without a name there's no telling which agent is meant
+        let response = get("/admin/status").send().await?;
+        assert_eq!(response.status().as_u16(), 400);
+        let response = get("/admin/cellar/status").send().await?;
+        assert_eq!(response.status().as_u16(), 404);
+        let response = client
+            .get(format!("{}/admin/kitchen/status", url))
+            .send()
+            .await?;
+        assert_eq!(response.status().as_u16(), 401);
+        Ok(())
+    }
+}
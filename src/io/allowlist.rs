@@ -0,0 +1,196 @@
+//! Who may drive the agent through an input: messages from senders or addresses the
+//! config doesn't allow are dropped before the agent sees them
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+
+use super::http_server::IpRange;
+use super::{InboundMessage, InputSource};
+use crate::config::Config;
+
+/// The senders and addresses an input accepts messages from
+pub struct Allowlist {
+    senders: Vec<String>,
+    ranges: Vec<IpRange>,
+}
+
+impl Allowlist {
+    /// The allowlist in the config, or `None` when it allows everyone
+    pub fn new(config: &Config) -> Result<Option<Self>> {
+        if config.allowed_senders.is_empty() && config.allowed_ips.is_empty() {
+            return Ok(None);
+        }
+        let mut ranges = Vec::new();
+        for range in &config.allowed_ips {
+            ranges.push(IpRange::parse(range).context("allowed_ips")?);
+        }
+        Ok(Some(Self {
+            senders: config.allowed_senders.clone(),
+            ranges,
+        }))
+    }
+
+    /// Why `message` isn't allowed, if it isn't. A message that doesn't say who sent it
+    /// or where from can't be checked, so it isn't allowed either
+    pub fn refusal(&self, message: &InboundMessage) -> Option<String> {
+        if !self.senders.is_empty() {
+            match &message.sender {
+                Some(sender) if self.senders.contains(sender) => {}
+                Some(sender) => return Some(format!("sender {} is not allowed", sender)),
+                None => return Some("the input doesn't report senders".to_string()),
+            }
+        }
+        if !self.ranges.is_empty() {
+            match message.remote_addr {
+                Some(ip) if self.ranges.iter().any(|range| range.contains(ip)) => {}
+                Some(ip) => return Some(format!("address {} is not allowed", ip)),
+                None => return Some("the input doesn't report addresses".to_string()),
+            }
+        }
+        None
+    }
+}
+
+/// An input that only passes on the messages its allowlist accepts
+pub struct Allowlisted {
+    input: Box<dyn InputSource>,
+    allowlist: Allowlist,
+}
+
+impl Allowlisted {
+    pub fn new(input: Box<dyn InputSource>, allowlist: Allowlist) -> Self {
+        Self { input, allowlist }
+    }
+}
+
+#[async_trait]
+impl InputSource for Allowlisted {
+    fn name(&self) -> &str {
+        self.input.name()
+    }
+
+    // Refused messages aren't answered, so strangers learn nothing about the agent
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        let Some(message) = self.input.read_message().await? else {
+            return Ok(None);
+        };
+        match self.allowlist.refusal(&message) {
+            Some(reason) => {
+                tracing::warn!(
+                    "Dropped {} message {}: {}",
+                    self.input.name(),
+                    message.correlation_id,
+                    reason
+                );
+                Ok(None)
+            }
+            None => Ok(Some(message)),
+        }
+    }
+
+    fn reply_to(&self) -> Vec<String> {
+        self.input.reply_to()
+    }
+
+    async fn reject(&mut self, message: &InboundMessage, reason: &str) -> Result<()> {
+        self.input.reject(message, reason).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+
+    fn message(sender: Option<&str>, addr: Option<&str>) -> InboundMessage {
+        let mut message = InboundMessage::new("hello").with_sender(sender.map(str::to_string));
+        message.remote_addr = addr.map(|addr| addr.parse::<IpAddr>().unwrap());
+        message
+    }
+
+    #[test]
+    fn an_empty_allowlist_allows_everyone() {
+        assert!(Allowlist::new(&Config::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn senders_must_be_listed() {
+        let config = Config {
+            allowed_senders: vec!["alice".to_string(), "bob".to_string()],
+            ..Default::default()
+        };
+        let allowlist = Allowlist::new(&config).unwrap().unwrap();
+        assert_eq!(allowlist.refusal(&message(Some("alice"), None)), None);
+        assert_eq!(
+            allowlist
+                .refusal(&message(Some("mallory"), None))
+                .as_deref(),
+            Some("sender mallory is not allowed")
+        );
+        // Exact names only
+        assert!(allowlist.refusal(&message(Some("Alice"), None)).is_some());
+        assert_eq!(
+            allowlist.refusal(&message(None, None)).as_deref(),
+            Some("the input doesn't report senders")
+        );
+    }
+
+    #[test]
+    fn addresses_must_be_in_a_range() {
+        let config = Config {
+            allowed_ips: vec!["10.0.0.0/8".to_string(), "2001:db8::1".to_string()],
+            ..Default::default()
+        };
+        let allowlist = Allowlist::new(&config).unwrap().unwrap();
+        assert_eq!(allowlist.refusal(&message(None, Some("10.1.2.3"))), None);
+        assert_eq!(allowlist.refusal(&message(None, Some("2001:db8::1"))), None);
+        // IPv4 clients of a dual-stack listener
+        assert_eq!(
+            allowlist.refusal(&message(None, Some("::ffff:10.1.2.3"))),
+            None
+        );
+        assert_eq!(
+            allowlist
+                .refusal(&message(None, Some("192.168.1.1")))
+                .as_deref(),
+            Some("address 192.168.1.1 is not allowed")
+        );
+        assert!(allowlist
+            .refusal(&message(None, Some("2001:db8::2")))
+            .is_some());
+        assert_eq!(
+            allowlist.refusal(&message(Some("alice"), None)).as_deref(),
+            Some("the input doesn't report addresses")
+        );
+    }
+
+    #[test]
+    fn both_lists_must_pass() {
+        let config = Config {
+            allowed_senders: vec!["alice".to_string()],
+            allowed_ips: vec!["10.0.0.0/8".to_string()],
+            ..Default::default()
+        };
+        let allowlist = Allowlist::new(&config).unwrap().unwrap();
+        assert_eq!(
+            allowlist.refusal(&message(Some("alice"), Some("10.0.0.1"))),
+            None
+        );
+        assert!(allowlist
+            .refusal(&message(Some("alice"), Some("11.0.0.1")))
+            .is_some());
+        assert!(allowlist
+            .refusal(&message(Some("bob"), Some("10.0.0.1")))
+            .is_some());
+    }
+
+    #[test]
+    fn bad_ranges_are_errors() {
+        let config = Config {
+            allowed_ips: vec!["10.0.0.0/33".to_string()],
+            ..Default::default()
+        };
+        let e = Allowlist::new(&config).err().unwrap();
+        assert!(format!("{:#}", e).starts_with("allowed_ips"), "{:#}", e);
+    }
+}
@@ -0,0 +1,329 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info};
+
+use super::http_server::HttpSettings;
+use super::shared::Shared;
+use super::{InboundMessage, InputSource, OutputDestination};
+
+// The source and destination on a bind address share one server: the source queues the prompt
+// and the destination answers the request that is waiting for it
+static SERVERS: Shared<ApiServer> = Shared::new();
+
+/// Settings for the OpenAI-compatible API server
+#[derive(Debug, Clone)]
+pub struct ApiSettings {
+    pub bind: String,
+    pub key: Option<String>,
+    pub model: String,
+    // Browser origins allowed to call the API
+    pub cors_origins: Vec<String>,
+}
+
+impl ApiSettings {
+    /// The agent is advertised as a model named after it
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self {
+            bind: config
+                .api_bind
+                .clone()
+                .unwrap_or_else(|| "127.0.0.1:8001".to_string()),
+            key: config.api_server_key.clone(),
+            model: config.agent_name.clone(),
+            cors_origins: config.cors_origins.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    // Set once an api input exists; without one, requests are rejected
+    inbound: Arc<StdMutex<Option<mpsc::Sender<String>>>>,
+    // Requests waiting for an answer, in the order their prompts were queued
+    pending: Arc<StdMutex<VecDeque<oneshot::Sender<String>>>>,
+    key: Option<String>,
+    model: String,
+}
+
+struct ApiServer {
+    addr: SocketAddr,
+    state: AppState,
+}
+
+#[derive(Deserialize)]
+struct ChatCompletionRequest {
+    messages: Vec<Value>,
+    #[serde(default)]
+    stream: bool,
+}
+
+// Bind the server and start serving, once per bind address
+async fn start(settings: &ApiSettings) -> Result<&'static ApiServer> {
+    SERVERS
+        .get_or_try_init(&settings.bind, || async {
+            let state = AppState {
+                inbound: Arc::new(StdMutex::new(None)),
+                pending: Arc::new(StdMutex::new(VecDeque::new())),
+                key: settings.key.clone(),
+                model: settings.model.clone(),
+            };
+
+            let mut app = Router::new()
+                .route("/v1/chat/completions", post(chat_completions))
+                .route("/v1/models", get(list_models))
+                .with_state(state.clone());
+            let http = HttpSettings {
+                cors_origins: settings.cors_origins.clone(),
+                ..Default::default()
+            };
+            if let Some(cors) = http.cors_layer()? {
+                app = app.layer(cors);
+            }
+
+            let listener = TcpListener::bind(&settings.bind)
+                .await
+                .context(format!("Failed to bind API server to {}", settings.bind))?;
+            let addr = listener.local_addr()?;
+            info!("OpenAI-compatible API listening on http://{}", addr);
+
+            tokio::spawn(async move {
+                if let Err(e) = axum::serve(listener, app).await {
+                    error!("API server error: {}", e);
+                }
+            });
+
+            Ok(ApiServer { addr, state })
+        })
+        .await
+}
+
+fn api_error(status: StatusCode, message: &str) -> Response {
+    let body = json!({
+        "error": {
+            "message": message,
+            "type": "invalid_request_error",
+        }
+    });
+    (status, Json(body)).into_response()
+}
+
+fn authorized(state: &AppState, headers: &HeaderMap) -> bool {
+    let Some(key) = &state.key else {
+        return true;
+    };
+    headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| super::webhook::constant_time_eq(token.as_bytes(), key.as_bytes()))
+}
+
+// The agent keeps its own history, so only the newest user message is used
+fn last_user_message(messages: &[Value]) -> Option<String> {
+    let message = messages.iter().rev().find(|m| m["role"] == "user")?;
+    match &message["content"] {
+        Value::String(text) => Some(text.clone()),
+        // Content parts, e.g. [{"type": "text", "text": "..."}]
+        Value::Array(parts) => {
+            let text: Vec<&str> = parts.iter().filter_map(|p| p["text"].as_str()).collect();
+            Some(text.join("\n"))
+        }
+        _ => None,
+    }
+}
+
+async fn list_models(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers) {
+        return api_error(StatusCode::UNAUTHORIZED, "Invalid API key");
+    }
+
+    Json(json!({
+        "object": "list",
+        "data": [{
+            "id": state.model,
+            "object": "model",
+            "created": unix_now(),
+            "owned_by": "agent",
+        }]
+    }))
+    .into_response()
+}
+
+async fn chat_completions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<ChatCompletionRequest>,
+) -> Response {
+    if !authorized(&state, &headers) {
+        return api_error(StatusCode::UNAUTHORIZED, "Invalid API key");
+    }
+
+    let Some(prompt) = last_user_message(&request.messages) else {
+        return api_error(StatusCode::BAD_REQUEST, "No user message in request");
+    };
+
+    let sender = state.inbound.lock().unwrap().clone();
+    let Some(sender) = sender else {
+        return api_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "This server does not accept input",
+        );
+    };
+
+    let (answer_tx, answer_rx) = oneshot::channel();
+    state.pending.lock().unwrap().push_back(answer_tx);
+    if sender.send(prompt).await.is_err() {
+        return api_error(StatusCode::SERVICE_UNAVAILABLE, "Failed to process message");
+    }
+
+    let Ok(content) = answer_rx.await else {
+        return api_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Agent stopped before answering",
+        );
+    };
+
+    let id = format!("chatcmpl-{}", uuid::Uuid::new_v4().simple());
+    let created = unix_now();
+
+    if request.stream {
+        // The whole answer is sent as one chunk, followed by the finish chunk
+        let chunk = |delta: Value, finish_reason: Value| {
+            let data = json!({
+                "id": id,
+                "object": "chat.completion.chunk",
+                "created": created,
+                "model": state.model,
+                "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}],
+            });
+            Ok::<_, Infallible>(Event::default().data(data.to_string()))
+        };
+        let events = vec![
+            chunk(
+                json!({"role": "assistant", "content": content}),
+                Value::Null,
+            ),
+            chunk(json!({}), json!("stop")),
+            Ok(Event::default().data("[DONE]")),
+        ];
+        return Sse::new(futures::stream::iter(events)).into_response();
+    }
+
+    Json(json!({
+        "id": id,
+        "object": "chat.completion",
+        "created": created,
+        "model": state.model,
+        "choices": [{
+            "index": 0,
+            "message": {"role": "assistant", "content": content},
+            "finish_reason": "stop",
+        }],
+        "usage": {"prompt_tokens": 0, "completion_tokens": 0, "total_tokens": 0},
+    }))
+    .into_response()
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// API input source implementation
+pub struct ApiSource {
+    message_rx: mpsc::Receiver<String>,
+    addr: SocketAddr,
+}
+
+impl ApiSource {
+    pub async fn new(settings: &ApiSettings) -> Result<Self> {
+        let server = start(settings).await?;
+        let (message_tx, message_rx) = mpsc::channel(100);
+        *server.state.inbound.lock().unwrap() = Some(message_tx);
+
+        Ok(Self {
+            message_rx,
+            addr: server.addr,
+        })
+    }
+
+    // Get the address the server is listening on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+#[async_trait]
+impl InputSource for ApiSource {
+    fn name(&self) -> &str {
+        "api"
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        match self.message_rx.recv().await {
+            Some(message) => Ok(Some(InboundMessage::new(message))),
+            None => Ok(None),
+        }
+    }
+}
+
+// API output destination implementation
+pub struct ApiDestination {
+    server: &'static ApiServer,
+}
+
+impl ApiDestination {
+    pub async fn new(settings: &ApiSettings) -> Result<Self> {
+        let server = start(settings).await?;
+        Ok(Self { server })
+    }
+}
+
+#[async_trait]
+impl OutputDestination for ApiDestination {
+    fn name(&self) -> &str {
+        "api"
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        &["assistant"]
+    }
+
+    async fn write_message(&self, role: &str, content: &str) -> Result<()> {
+        if role != "assistant" {
+            return Ok(());
+        }
+
+        // Answer the oldest request whose client is still waiting
+        let mut pending = self.server.state.pending.lock().unwrap();
+        let mut answer = content.to_string();
+        while let Some(waiting) = pending.pop_front() {
+            match waiting.send(answer) {
+                Ok(()) => break,
+                Err(unsent) => answer = unsent,
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use tracing::info;
+
+use super::OutputDestination;
+
+// Payload presets for incoming webhooks; placeholders are replaced with
+// JSON-escaped text, so they go inside string literals
+const MATTERMOST_TEMPLATE: &str = r#"{"text": "{{content}}", "username": "{{agent}}"}"#;
+const ROCKETCHAT_TEMPLATE: &str = r#"{"text": "{{content}}", "alias": "{{agent}}"}"#;
+const SLACK_TEMPLATE: &str = r#"{"text": "{{content}}", "username": "{{agent}}"}"#;
+const GOOGLE_CHAT_TEMPLATE: &str = r#"{"text": "{{content}}"}"#;
+// An Adaptive Card, accepted by both Teams workflows and legacy connectors
+const TEAMS_TEMPLATE: &str = r#"{
+    "type": "message",
+    "attachments": [{
+        "contentType": "application/vnd.microsoft.card.adaptive",
+        "content": {
+            "type": "AdaptiveCard",
+            "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+            "version": "1.4",
+            "body": [{"type": "TextBlock", "text": "{{content}}", "wrap": true}]
+        }
+    }]
+}"#;
+
+/// Pick the payload template for a platform, or the user's own for `custom`
+pub fn payload_template(platform: &str, custom: Option<&str>) -> Result<String> {
+    let template = match platform.to_lowercase().as_str() {
+        "mattermost" => MATTERMOST_TEMPLATE,
+        "rocketchat" | "rocket.chat" => ROCKETCHAT_TEMPLATE,
+        "slack" => SLACK_TEMPLATE,
+        "googlechat" | "google_chat" => GOOGLE_CHAT_TEMPLATE,
+        "teams" => TEAMS_TEMPLATE,
+        "custom" => {
+            custom.context("The custom chat webhook platform requires chat_webhook_template")?
+        }
+        _ => anyhow::bail!("Unsupported chat webhook platform: {}", platform),
+    };
+    Ok(template.to_string())
+}
+
+// Escape text for use inside a JSON string literal
+pub(crate) fn json_escape(text: &str) -> String {
+    let quoted = serde_json::Value::String(text.to_string()).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+fn render(template: &str, agent: &str, content: &str) -> Result<serde_json::Value> {
+    let payload = template
+        .replace("{{agent}}", &json_escape(agent))
+        .replace("{{content}}", &json_escape(content));
+    serde_json::from_str(&payload).context("Chat webhook template is not valid JSON")
+}
+
+// Chat webhook output destination implementation
+pub struct ChatWebhookDestination {
+    client: reqwest::Client,
+    url: String,
+    template: String,
+    agent: String,
+}
+
+impl ChatWebhookDestination {
+    /// Create a destination posting to `url`, checking the template renders to JSON
+    pub fn new(url: &str, template: String, agent: &str) -> Result<Self> {
+        render(&template, agent, "test \"message\"\nwith lines")?;
+        Ok(Self {
+            client: reqwest::Client::new(),
+            url: url.to_string(),
+            template,
+            agent: agent.to_string(),
+        })
+    }
+
+    // Get the webhook URL
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+#[async_trait]
+impl OutputDestination for ChatWebhookDestination {
+    fn name(&self) -> &str {
+        "chat_webhook"
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        &["assistant"]
+    }
+
+    async fn write_message(&self, _role: &str, content: &str) -> Result<()> {
+        let payload = render(&self.template, &self.agent, content)?;
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&payload)
+            .send()
+            .await
+            .context("Failed to send chat webhook")?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            anyhow::bail!("Chat webhook returned HTTP {}: {}", status, error_text);
+        }
+
+        info!("Chat webhook sent successfully");
+        Ok(())
+    }
+}
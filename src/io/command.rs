@@ -0,0 +1,115 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+use super::exec::shell_command;
+use super::{InboundMessage, InputSource};
+
+/// Settings for running a command on a schedule
+#[derive(Debug, Clone)]
+pub struct CommandSettings {
+    pub command: String,
+    pub interval: Duration,
+    pub template: Option<String>,
+}
+
+impl CommandSettings {
+    pub fn from_config(config: &crate::config::Config) -> Result<Self> {
+        let command = config
+            .command_input
+            .clone()
+            .context("Command input requires command_input to be set")?;
+        let interval = Duration::from_secs(config.command_interval.unwrap_or(600).max(1));
+
+        Ok(Self {
+            command,
+            interval,
+            template: config.command_template.clone(),
+        })
+    }
+}
+
+// Run the command once, giving up when the next run is due
+async fn run_command(settings: &CommandSettings) -> Result<String> {
+    let output = shell_command(&settings.command)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(settings.interval, output)
+        .await
+        .map_err(|_| anyhow::anyhow!("Command did not finish before its next run"))?
+        .context(format!("Failed to run command: {}", settings.command))?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Command failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// Command input source implementation
+pub struct CommandSource {
+    message_rx: mpsc::Receiver<String>,
+}
+
+impl CommandSource {
+    /// Run the command now and then on every interval, submitting its output
+    pub fn new(settings: CommandSettings) -> Self {
+        let (message_tx, message_rx) = mpsc::channel(10);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(settings.interval);
+            // A slow agent shouldn't cause a burst of catch-up runs
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+
+                let output = match run_command(&settings).await {
+                    Ok(output) => output,
+                    Err(e) => {
+                        error!("{:#}", e);
+                        continue;
+                    }
+                };
+                if output.is_empty() {
+                    info!("Command produced no output: {}", settings.command);
+                    continue;
+                }
+
+                let prompt = match &settings.template {
+                    Some(template) => template.replace("{{output}}", &output),
+                    None => output,
+                };
+                if message_tx.send(prompt).await.is_err() {
+                    error!("Failed to send message to channel");
+                    break;
+                }
+            }
+
+            tracing::info!("Command input task completed");
+        });
+
+        Self { message_rx }
+    }
+}
+
+#[async_trait]
+impl InputSource for CommandSource {
+    fn name(&self) -> &str {
+        "command"
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        match self.message_rx.recv().await {
+            Some(message) => Ok(Some(InboundMessage::new(message))),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use lettre::{
+    message::header::ContentType, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use mail_parser::MessageParser;
+use std::collections::BTreeMap;
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+use super::{InboundMessage, InputSource, OutputDestination};
+
+// The latest email from each correspondent, by lowercased address, so the reply to
+// a message is threaded under that correspondent's email
+static THREADS: StdMutex<BTreeMap<String, EmailThread>> = StdMutex::new(BTreeMap::new());
+
+fn remember_thread(thread: EmailThread) {
+    THREADS
+        .lock()
+        .unwrap()
+        .insert(thread.from.to_lowercase(), thread);
+}
+
+fn thread_for(sender: &str) -> Option<EmailThread> {
+    THREADS.lock().unwrap().get(&sender.to_lowercase()).cloned()
+}
+
+/// Settings for the IMAP mailbox and SMTP server
+#[derive(Debug, Clone)]
+pub struct EmailSettings {
+    pub imap_server: Option<String>,
+    pub imap_port: u16,
+    pub smtp_server: Option<String>,
+    pub smtp_port: u16,
+    pub username: String,
+    pub password: String,
+    pub address: String,
+    pub mailbox: String,
+    pub poll_interval: Duration,
+}
+
+impl EmailSettings {
+    /// Build settings from the config, reading the password from `EMAIL_PASSWORD` if unset
+    pub fn from_config(config: &crate::config::Config) -> Result<Self> {
+        let username = config
+            .email_username
+            .clone()
+            .context("Email IO requires email_username to be set")?;
+        let password = match &config.email_password {
+            Some(password) => password.clone(),
+            None => std::env::var("EMAIL_PASSWORD")
+                .context("EMAIL_PASSWORD environment variable not set")?,
+        };
+
+        Ok(Self {
+            imap_server: config.email_imap_server.clone(),
+            imap_port: config.email_imap_port.unwrap_or(993),
+            smtp_server: config.email_smtp_server.clone(),
+            smtp_port: config.email_smtp_port.unwrap_or(587),
+            address: config
+                .email_address
+                .clone()
+                .unwrap_or_else(|| username.clone()),
+            username,
+            password,
+            mailbox: config
+                .email_mailbox
+                .clone()
+                .unwrap_or_else(|| "INBOX".to_string()),
+            poll_interval: Duration::from_secs(config.email_poll_interval.unwrap_or(60)),
+        })
+    }
+}
+
+// Headers needed to reply within an email thread
+#[derive(Debug, Clone)]
+struct EmailThread {
+    from: String,
+    subject: String,
+    message_id: Option<String>,
+    references: Vec<String>,
+}
+
+// Parse a raw email into its thread headers and the new (unquoted) text
+fn parse_email(raw: &[u8]) -> Option<(EmailThread, String)> {
+    let message = MessageParser::default().parse(raw)?;
+    let from = message.from()?.first()?.address()?.to_string();
+    let subject = message.subject().unwrap_or_default().to_string();
+    let message_id = message.message_id().map(|id| format!("<{}>", id));
+
+    // References lists the whole thread; the reply adds this message to the end
+    let references = message
+        .references()
+        .as_text_list()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|id| format!("<{}>", id))
+        .collect();
+
+    // Drop quoted lines from earlier messages in the thread
+    let body = message.body_text(0).unwrap_or_default();
+    let text = body
+        .lines()
+        .take_while(|line| !(line.starts_with("On ") && line.trim_end().ends_with("wrote:")))
+        .filter(|line| !line.starts_with('>'))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string();
+
+    Some((
+        EmailThread {
+            from,
+            subject,
+            message_id,
+            references,
+        },
+        text,
+    ))
+}
+
+// Fetch unseen emails from the mailbox; fetching the full message marks it as seen
+async fn fetch_unseen(settings: &EmailSettings) -> Result<Vec<(EmailThread, String)>> {
+    let server = settings
+        .imap_server
+        .as_deref()
+        .context("Email input requires email_imap_server to be set")?;
+
+    let tcp = TcpStream::connect((server, settings.imap_port))
+        .await
+        .context(format!("Failed to connect to IMAP server {}", server))?;
+    let tls = async_native_tls::TlsConnector::new()
+        .connect(server, tcp)
+        .await
+        .context("IMAP TLS handshake failed")?;
+
+    let mut session = async_imap::Client::new(tls)
+        .login(&settings.username, &settings.password)
+        .await
+        .map_err(|(e, _)| e)
+        .context("IMAP login failed")?;
+    session.select(&settings.mailbox).await?;
+
+    let uids = session.uid_search("UNSEEN").await?;
+    let mut emails = Vec::new();
+    if !uids.is_empty() {
+        let uid_set = uids
+            .iter()
+            .map(|uid| uid.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let fetches: Vec<_> = session
+            .uid_fetch(&uid_set, "RFC822")
+            .await?
+            .try_collect()
+            .await?;
+
+        for fetch in &fetches {
+            match fetch.body().and_then(parse_email) {
+                // Skip our own replies if they land in the same mailbox
+                Some((thread, _)) if thread.from.eq_ignore_ascii_case(&settings.address) => {}
+                Some((_, text)) if text.is_empty() => {}
+                Some(email) => emails.push(email),
+                None => error!("Failed to parse email UID {:?}", fetch.uid),
+            }
+        }
+    }
+
+    session.logout().await?;
+    Ok(emails)
+}
+
+// Email input source implementation
+pub struct EmailSource {
+    message_rx: mpsc::Receiver<(String, Option<String>)>,
+}
+
+impl EmailSource {
+    pub fn new(settings: EmailSettings) -> Self {
+        let (message_tx, message_rx) = mpsc::channel(100);
+
+        // Poll the mailbox in a separate task
+        tokio::spawn(async move {
+            loop {
+                match fetch_unseen(&settings).await {
+                    Ok(emails) => {
+                        for (thread, text) in emails {
+                            info!("Received email from {}: {}", thread.from, thread.subject);
+                            // Each correspondent is a conversation of their own
+                            let from = thread.from.clone();
+                            remember_thread(thread);
+                            if message_tx.send((text, Some(from))).await.is_err() {
+                                tracing::info!("Email source task completed");
+                                return;
+                            }
+                        }
+                    }
+                    Err(e) => error!("Failed to poll mailbox: {:#}", e),
+                }
+
+                tokio::time::sleep(settings.poll_interval).await;
+            }
+        });
+
+        Self { message_rx }
+    }
+}
+
+#[async_trait]
+impl InputSource for EmailSource {
+    fn name(&self) -> &str {
+        "email"
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        match self.message_rx.recv().await {
+            Some((message, sender)) => Ok(Some(InboundMessage::new(message).with_sender(sender))),
+            None => Ok(None),
+        }
+    }
+}
+
+// Email output destination implementation
+pub struct EmailDestination {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    address: String,
+    // Sender of the message being answered
+    sender: StdMutex<Option<String>>,
+}
+
+impl EmailDestination {
+    pub fn new(settings: &EmailSettings) -> Result<Self> {
+        let server = settings
+            .smtp_server
+            .as_deref()
+            .context("Email output requires email_smtp_server to be set")?;
+
+        // Port 465 uses implicit TLS, anything else upgrades with STARTTLS
+        let builder = if settings.smtp_port == 465 {
+            AsyncSmtpTransport::<Tokio1Executor>::relay(server)?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(server)?
+        };
+        let transport = builder
+            .port(settings.smtp_port)
+            .credentials(Credentials::new(
+                settings.username.clone(),
+                settings.password.clone(),
+            ))
+            .build();
+
+        Ok(Self {
+            transport,
+            address: settings.address.clone(),
+            sender: StdMutex::new(None),
+        })
+    }
+}
+
+#[async_trait]
+impl OutputDestination for EmailDestination {
+    fn name(&self) -> &str {
+        "email"
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        &["assistant"]
+    }
+
+    fn answering(&self, message: &InboundMessage) {
+        *self.sender.lock().unwrap() = message.sender.clone();
+    }
+
+    async fn write_message(&self, _role: &str, content: &str) -> Result<()> {
+        // Only replies are sent; messages that didn't come by email have no one to write to
+        let sender = self.sender.lock().unwrap().clone();
+        let Some(thread) = sender.as_deref().and_then(thread_for) else {
+            return Ok(());
+        };
+
+        let subject = if thread.subject.to_lowercase().starts_with("re:") {
+            thread.subject.clone()
+        } else {
+            format!("Re: {}", thread.subject)
+        };
+
+        let mut builder = Message::builder()
+            .from(self.address.parse()?)
+            .to(thread.from.parse()?)
+            .subject(subject);
+        if let Some(message_id) = &thread.message_id {
+            let mut references = thread.references.clone();
+            references.push(message_id.clone());
+            builder = builder
+                .in_reply_to(message_id.clone())
+                .references(references.join(" "));
+        }
+        let email = builder
+            .header(ContentType::TEXT_PLAIN)
+            .body(content.to_string())?;
+
+        self.transport
+            .send(email)
+            .await
+            .context("Failed to send email")?;
+        info!("Sent email reply to {}", thread.from);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thread(from: &str, subject: &str) -> EmailThread {
+        EmailThread {
+            from: from.to_string(),
+            subject: subject.to_string(),
+            message_id: Some(format!("<{}@mail.test>", subject)),
+            references: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn each_correspondent_keeps_their_own_thread() {
+        remember_thread(thread("alice@thread.test", "first"));
+        remember_thread(thread("bob@thread.test", "second"));
+
+        let alice = thread_for("Alice@Thread.test").unwrap();
+        assert_eq!(alice.from, "alice@thread.test");
+        assert_eq!(alice.subject, "first");
+        assert_eq!(thread_for("bob@thread.test").unwrap().subject, "second");
+        assert!(thread_for("carol@thread.test").is_none());
+
+        // A later email from the same correspondent becomes the one to reply under
+        remember_thread(thread("alice@thread.test", "third"));
+        assert_eq!(thread_for("alice@thread.test").unwrap().subject, "third");
+        assert_eq!(thread_for("bob@thread.test").unwrap().subject, "second");
+    }
+
+    #[test]
+    fn parses_the_thread_and_new_text() {
+        let raw = b"From: Alice <alice@thread.test>\r\n\
+Subject: Re: Help\r\n\
+Message-ID: <2@mail.test>\r\n\
+References: <1@mail.test>\r\n\
+\r\n\
+Thanks, one more question\r\n\
+\r\n\
+On Mon, 1 Jan 2024, Agent wrote:\r\n\
+> Earlier answer\r\n";
+        let (thread, text) = parse_email(raw).unwrap();
+        assert_eq!(thread.from, "alice@thread.test");
+        assert_eq!(thread.subject, "Re: Help");
+        assert_eq!(thread.message_id.as_deref(), Some("<2@mail.test>"));
+        assert_eq!(thread.references, ["<1@mail.test>"]);
+        assert_eq!(text, "Thanks, one more question");
+    }
+}
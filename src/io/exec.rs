@@ -0,0 +1,120 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{debug, info};
+
+use super::OutputDestination;
+
+#[derive(Serialize)]
+struct ExecMessage<'a> {
+    agent: &'a str,
+    role: &'a str,
+    content: &'a str,
+    timestamp: u64,
+}
+
+// Build a command that runs `command` through the platform shell
+pub(crate) fn shell_command(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    }
+}
+
+// Exec output destination implementation
+pub struct ExecDestination {
+    command: String,
+    timeout: Duration,
+    agent: String,
+}
+
+impl ExecDestination {
+    /// Run `command` once per response, with the message as JSON on its stdin
+    pub fn new(command: &str, timeout: Duration, agent: &str) -> Self {
+        Self {
+            command: command.to_string(),
+            timeout,
+            agent: agent.to_string(),
+        }
+    }
+
+    // Get the command
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+}
+
+#[async_trait]
+impl OutputDestination for ExecDestination {
+    fn name(&self) -> &str {
+        "exec"
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        &["assistant", "image"]
+    }
+
+    async fn write_message(&self, role: &str, content: &str) -> Result<()> {
+        let message = ExecMessage {
+            agent: &self.agent,
+            role,
+            content,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        let mut json = serde_json::to_string(&message)?;
+        json.push('\n');
+
+        let mut child = shell_command(&self.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .context(format!("Failed to run exec command: {}", self.command))?;
+
+        // Close stdin after writing so the command sees end of input; a command
+        // that ignores its input may exit before reading it, which is fine
+        if let Some(mut stdin) = child.stdin.take() {
+            if let Err(e) = stdin.write_all(json.as_bytes()).await {
+                debug!("Exec command did not read its input: {}", e);
+            }
+        }
+
+        let output = tokio::time::timeout(self.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "Exec command timed out after {}s: {}",
+                    self.timeout.as_secs(),
+                    self.command
+                )
+            })??;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "Exec command failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !stdout.trim().is_empty() {
+            debug!("Exec command output: {}", stdout.trim());
+        }
+        info!("Exec command completed: {}", self.command);
+        Ok(())
+    }
+}
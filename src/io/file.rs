@@ -0,0 +1,218 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+use tracing::info;
+
+use super::OutputDestination;
+
+/// Transcript file formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscriptFormat {
+    Jsonl,
+    Markdown,
+}
+
+impl TranscriptFormat {
+    /// Parse a format name, falling back to the file extension when unset
+    pub fn from_config(format: Option<&str>, path: &Path) -> Result<Self> {
+        let format = match format {
+            Some(format) => format.to_lowercase(),
+            None => path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .unwrap_or("jsonl")
+                .to_lowercase(),
+        };
+
+        match format.as_str() {
+            "jsonl" | "json" => Ok(TranscriptFormat::Jsonl),
+            "markdown" | "md" => Ok(TranscriptFormat::Markdown),
+            _ => anyhow::bail!("Unsupported transcript format: {}", format),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TranscriptEntry<'a> {
+    timestamp: String,
+    session_id: &'a str,
+    role: &'a str,
+    content: &'a str,
+}
+
+// The open transcript file and the day it was opened on
+struct TranscriptFile {
+    file: File,
+    size: u64,
+    opened_on: NaiveDate,
+}
+
+// Transcript file output destination implementation
+pub struct FileDestination {
+    path: PathBuf,
+    format: TranscriptFormat,
+    max_bytes: Option<u64>,
+    rotate_daily: bool,
+    session_id: String,
+    current: Mutex<Option<TranscriptFile>>,
+}
+
+impl FileDestination {
+    /// Create a destination appending to `path`, with one session ID for this run
+    pub fn new(
+        path: &str,
+        format: TranscriptFormat,
+        max_bytes: Option<u64>,
+        rotate_daily: bool,
+    ) -> Result<Self> {
+        let path = PathBuf::from(path);
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).context(format!(
+                "Failed to create transcript directory: {}",
+                parent.display()
+            ))?;
+        }
+
+        Ok(Self {
+            path,
+            format,
+            max_bytes,
+            rotate_daily,
+            session_id: uuid::Uuid::new_v4().to_string(),
+            current: Mutex::new(None),
+        })
+    }
+
+    // Get the transcript path
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn format_entry(&self, now: DateTime<Utc>, role: &str, content: &str) -> Result<String> {
+        match self.format {
+            TranscriptFormat::Jsonl => {
+                let entry = TranscriptEntry {
+                    timestamp: now.to_rfc3339(),
+                    session_id: &self.session_id,
+                    role,
+                    content,
+                };
+                Ok(format!("{}\n", serde_json::to_string(&entry)?))
+            }
+            TranscriptFormat::Markdown => {
+                let content = if role == "image" {
+                    format!("<{} bytes base64-encoded>", content.len())
+                } else {
+                    content.to_string()
+                };
+                Ok(format!(
+                    "### {} ({}, session {})\n\n{}\n\n",
+                    role,
+                    now.format("%Y-%m-%d %H:%M:%S UTC"),
+                    self.session_id,
+                    content
+                ))
+            }
+        }
+    }
+
+    // Move the current file aside as <stem>.<timestamp>.<ext>
+    fn rotate(&self, now: DateTime<Utc>) -> Result<()> {
+        let stem = self
+            .path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("transcript");
+        let mut rotated_name = format!("{}.{}", stem, now.format("%Y%m%dT%H%M%S"));
+        if let Some(ext) = self.path.extension().and_then(|e| e.to_str()) {
+            rotated_name = format!("{}.{}", rotated_name, ext);
+        }
+        let rotated = self.path.with_file_name(rotated_name);
+
+        std::fs::rename(&self.path, &rotated).context(format!(
+            "Failed to rotate transcript to {}",
+            rotated.display()
+        ))?;
+        info!("Rotated transcript to {}", rotated.display());
+        Ok(())
+    }
+
+    fn open(&self, now: DateTime<Utc>) -> Result<TranscriptFile> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context(format!(
+                "Failed to open transcript: {}",
+                self.path.display()
+            ))?;
+        // An existing file counts as opened on the day it was last written
+        let metadata = file.metadata()?;
+        let opened_on = metadata
+            .modified()
+            .map(|modified| DateTime::<Utc>::from(modified).date_naive())
+            .unwrap_or_else(|_| now.date_naive());
+        Ok(TranscriptFile {
+            file,
+            size: metadata.len(),
+            opened_on,
+        })
+    }
+}
+
+#[async_trait]
+impl OutputDestination for FileDestination {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn keeps_transcript(&self) -> bool {
+        true
+    }
+
+    async fn write_message(&self, role: &str, content: &str) -> Result<()> {
+        let now = Utc::now();
+        let entry = self.format_entry(now, role, content)?;
+
+        let mut current = self.current.lock().await;
+
+        if current.is_none() {
+            *current = Some(self.open(now)?);
+        }
+
+        // Rotate before writing if the file is from an earlier day or would grow too large
+        if let Some(transcript) = current.as_ref() {
+            let new_day = self.rotate_daily && transcript.opened_on != now.date_naive();
+            let too_large = self.max_bytes.is_some_and(|max| {
+                transcript.size > 0 && transcript.size + entry.len() as u64 > max
+            });
+            if new_day || too_large {
+                *current = None;
+                self.rotate(now)?;
+                *current = Some(self.open(now)?);
+            }
+        }
+
+        let transcript = current.as_mut().unwrap();
+        transcript.file.write_all(entry.as_bytes())?;
+        transcript.file.flush()?;
+        transcript.size += entry.len() as u64;
+
+        Ok(())
+    }
+
+    async fn close(&self) -> Result<()> {
+        if let Some(transcript) = self.current.lock().await.take() {
+            transcript.file.sync_all().context(format!(
+                "Failed to sync transcript: {}",
+                self.path.display()
+            ))?;
+        }
+        Ok(())
+    }
+}
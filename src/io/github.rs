@@ -0,0 +1,357 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use axum::http::{HeaderMap, StatusCode};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::net::IpAddr;
+use std::sync::Mutex as StdMutex;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+use super::{InboundMessage, OutputDestination};
+
+/// Settings for the webhook server's `/github` route
+#[derive(Debug, Clone)]
+pub struct GithubSettings {
+    pub login: String,
+    pub secret: String,
+}
+
+impl GithubSettings {
+    /// Build settings from the config, reading the secret from `GITHUB_WEBHOOK_SECRET` if unset
+    pub fn from_config(config: &crate::config::Config) -> Result<Self> {
+        let login = config
+            .github_bot_login
+            .clone()
+            .context("GitHub events require github_bot_login to be set")?;
+        let secret = match &config.github_webhook_secret {
+            Some(secret) => secret.clone(),
+            None => std::env::var("GITHUB_WEBHOOK_SECRET")
+                .context("GITHUB_WEBHOOK_SECRET environment variable not set")?,
+        };
+
+        Ok(Self {
+            login: login.trim_start_matches('@').to_string(),
+            secret,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct IssueCommentEvent {
+    action: String,
+    issue: Issue,
+    comment: Comment,
+    repository: Repository,
+}
+
+#[derive(Deserialize)]
+struct Issue {
+    number: u64,
+    title: String,
+    comments_url: String,
+    // Present when the issue is a pull request
+    pull_request: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct Comment {
+    body: String,
+    user: User,
+}
+
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct Repository {
+    full_name: String,
+}
+
+/// Check a `sha256=<hex>` signature header, an HMAC-SHA256 of the raw body keyed
+/// with the secret
+pub(super) fn verify_signature(
+    secret: &str,
+    header: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> bool {
+    let Some(signature) = headers
+        .get(header)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("sha256="))
+        .and_then(|v| hex::decode(v).ok())
+    else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Handle one delivery to `/github`, sending comments that mention the bot as prompts
+pub(super) async fn handle_event(
+    settings: &GithubSettings,
+    sender: &mpsc::Sender<InboundMessage>,
+    client_ip: IpAddr,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> StatusCode {
+    if !verify_signature(&settings.secret, "x-hub-signature-256", headers, body) {
+        error!("Rejected GitHub delivery with an invalid signature");
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    // Other events, including the ping sent when the hook is created, are acknowledged
+    // and ignored
+    let event = headers
+        .get("x-github-event")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if event != "issue_comment" {
+        return StatusCode::OK;
+    }
+
+    let event: IssueCommentEvent = match serde_json::from_slice(body) {
+        Ok(event) => event,
+        Err(e) => {
+            error!("Invalid GitHub issue_comment payload: {}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if event.action != "created" {
+        return StatusCode::OK;
+    }
+
+    // Skip the agent's own replies, and comments that don't ask for it
+    let author = &event.comment.user.login;
+    if author.eq_ignore_ascii_case(&settings.login)
+        || !mentions(&event.comment.body, &settings.login)
+    {
+        return StatusCode::OK;
+    }
+
+    let kind = if event.issue.pull_request.is_some() {
+        "pull request"
+    } else {
+        "issue"
+    };
+    let prompt = format!(
+        "@{} commented on {} {}#{} \"{}\":\n\n{}",
+        author,
+        kind,
+        event.repository.full_name,
+        event.issue.number,
+        event.issue.title,
+        event.comment.body
+    );
+    info!(
+        "GitHub mention from @{} on {}#{}",
+        author, event.repository.full_name, event.issue.number
+    );
+
+    // Each issue and pull request is a conversation of its own
+    let thread = format!("{}#{}", event.repository.full_name, event.issue.number);
+    let mut message = InboundMessage::new(prompt)
+        .with_sender(Some(thread))
+        .with_remote_addr(client_ip);
+    message.comments_url = Some(event.issue.comments_url);
+    match sender.send(message).await {
+        Ok(_) => StatusCode::OK,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+// Whether `body` mentions `@login` as a whole login, not as the start of a longer
+// one such as `@login-bot`, nor inside an address such as `me@login.dev`
+fn mentions(body: &str, login: &str) -> bool {
+    let body = body.to_lowercase();
+    let mention = format!("@{}", login.to_lowercase());
+    let part_of_login = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+    body.match_indices(&mention).any(|(start, _)| {
+        let before = body[..start].chars().next_back();
+        let after = body[start + mention.len()..].chars().next();
+        // A sentence may end right after it, as in "thanks @login."
+        let ends = match after {
+            Some('.') => body[start + mention.len() + 1..]
+                .chars()
+                .next()
+                .is_none_or(|c| !part_of_login(c)),
+            Some(c) => !part_of_login(c),
+            None => true,
+        };
+        !before.is_some_and(|c| part_of_login(c) || c == '.') && ends
+    })
+}
+
+// GitHub output destination implementation
+pub struct GithubDestination {
+    client: reqwest::Client,
+    token: String,
+    // Comments URL of the mention being answered, None while answering anything else
+    comments_url: StdMutex<Option<String>>,
+}
+
+impl GithubDestination {
+    /// Create a destination posting replies with `token`, or `GITHUB_TOKEN` if unset
+    pub fn new(token: Option<String>) -> Result<Self> {
+        let token = match token {
+            Some(token) => token,
+            None => std::env::var("GITHUB_TOKEN")
+                .context("GITHUB_TOKEN environment variable not set")?,
+        };
+        Ok(Self {
+            client: reqwest::Client::new(),
+            token,
+            comments_url: StdMutex::new(None),
+        })
+    }
+}
+
+#[async_trait]
+impl OutputDestination for GithubDestination {
+    fn name(&self) -> &str {
+        "github"
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        &["assistant"]
+    }
+
+    fn answering(&self, message: &InboundMessage) {
+        *self.comments_url.lock().unwrap() = message.comments_url.clone();
+    }
+
+    async fn write_message(&self, role: &str, content: &str) -> Result<()> {
+        if role != "assistant" {
+            return Ok(());
+        }
+
+        // Only prompts from GitHub get a comment; others have nowhere to go
+        let Some(comments_url) = self.comments_url.lock().unwrap().clone() else {
+            return Ok(());
+        };
+
+        let response = self
+            .client
+            .post(&comments_url)
+            .bearer_auth(&self.token)
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "agent")
+            .json(&serde_json::json!({ "body": content }))
+            .send()
+            .await
+            .context("Failed to post GitHub comment")?;
+        if !response.status().is_success() {
+            anyhow::bail!(
+                "Failed to post GitHub comment: HTTP {}: {}",
+                response.status(),
+                response.text().await.unwrap_or_default()
+            );
+        }
+
+        info!("Posted reply to {}", comments_url);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn mentions_need_the_whole_login() {
+        assert!(mentions("@helper can you look?", "helper"));
+        assert!(mentions("Thanks @Helper.", "helper"));
+        assert!(mentions("cc @helper, @someone", "helper"));
+        assert!(!mentions("@helper-other can you look?", "helper"));
+        assert!(!mentions("@helperfoo", "helper"));
+        assert!(!mentions("@helper_2", "helper"));
+        assert!(!mentions("mail me@helper.dev", "helper"));
+        assert!(!mentions("@helper.dev", "helper"));
+        assert!(!mentions("no mention here", "helper"));
+    }
+
+    // A delivery of a comment on issue `number`, signed with `secret`
+    fn delivery(secret: &str, number: u64, body: &str) -> (HeaderMap, Vec<u8>) {
+        let payload = serde_json::json!({
+            "action": "created",
+            "issue": {
+                "number": number,
+                "title": "Broken",
+                "comments_url": format!("http://github.test/issues/{}/comments", number),
+            },
+            "comment": { "body": body, "user": { "login": "alice" } },
+            "repository": { "full_name": "acme/app" },
+        })
+        .to_string()
+        .into_bytes();
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(&payload);
+        let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-github-event", "issue_comment".parse().unwrap());
+        headers.insert("x-hub-signature-256", signature.parse().unwrap());
+        (headers, payload)
+    }
+
+    #[tokio::test]
+    async fn each_reply_goes_to_its_own_issue() -> Result<()> {
+        let settings = GithubSettings {
+            login: "helper".to_string(),
+            secret: "shh".to_string(),
+        };
+        let (sender, mut received) = mpsc::channel(10);
+        let ip = IpAddr::from([127, 0, 0, 1]);
+
+        // Two mentions arrive before either is answered
+        for number in [1, 2] {
+            let (headers, body) = delivery("shh", number, "@helper please look");
+            let status = handle_event(&settings, &sender, ip, &headers, &body).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let (headers, body) = delivery("wrong", 3, "@helper please look");
+        let status = handle_event(&settings, &sender, ip, &headers, &body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let first = received.recv().await.unwrap();
+        let second = received.recv().await.unwrap();
+        assert!(received.try_recv().is_err());
+
+        // A stand-in for GitHub's API, recording where comments were posted
+        let posted = Arc::new(Mutex::new(Vec::new()));
+        let seen = posted.clone();
+        let app = axum::Router::new().fallback(move |uri: axum::http::Uri| {
+            let seen = seen.clone();
+            async move {
+                seen.lock().unwrap().push(uri.path().to_string());
+                StatusCode::CREATED
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let output = GithubDestination::new(Some("token".to_string()))?;
+        for mut message in [first, second] {
+            let url = message.comments_url.take().unwrap();
+            message.comments_url = Some(url.replace("github.test", &addr.to_string()));
+            output.answering(&message);
+            output.write_message("assistant", "On it").await?;
+        }
+        // Messages from elsewhere have no issue to comment on
+        output.answering(&InboundMessage::new("hi"));
+        output.write_message("assistant", "Hello").await?;
+
+        assert_eq!(
+            *posted.lock().unwrap(),
+            ["/issues/1/comments", "/issues/2/comments"]
+        );
+        Ok(())
+    }
+}
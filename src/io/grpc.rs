@@ -0,0 +1,241 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{error, info};
+
+use super::{InboundMessage, InputSource, OutputDestination};
+
+/// Types and service traits generated from `proto/agent.proto`
+pub mod proto {
+    tonic::include_proto!("agent.v1");
+}
+
+use proto::agent_server::{Agent, AgentServer};
+use proto::{AskRequest, AskResponse, ChatRequest, ChatResponse};
+
+// The source and destination on a bind address share one server, so Chat streams and Ask calls
+// get their replies from the destination
+static SERVERS: super::shared::Shared<GrpcServer> = super::shared::Shared::new();
+
+#[derive(Clone)]
+struct Shared {
+    // Set once a gRPC input exists; without one, requests are rejected
+    inbound: Arc<StdMutex<Option<mpsc::Sender<String>>>>,
+    outbound: broadcast::Sender<ChatResponse>,
+    // Ask calls waiting for an answer, in the order their prompts were queued
+    pending_asks: Arc<StdMutex<VecDeque<oneshot::Sender<String>>>>,
+}
+
+impl Shared {
+    async fn submit(&self, message: String) -> Result<(), Status> {
+        let sender = self.inbound.lock().unwrap().clone();
+        match sender {
+            Some(sender) => sender
+                .send(message)
+                .await
+                .map_err(|_| Status::unavailable("Failed to process message")),
+            None => Err(Status::failed_precondition(
+                "This server does not accept input",
+            )),
+        }
+    }
+}
+
+struct GrpcServer {
+    addr: SocketAddr,
+    shared: Shared,
+}
+
+type ChatStream = Pin<Box<dyn Stream<Item = Result<ChatResponse, Status>> + Send>>;
+
+struct AgentService {
+    shared: Shared,
+}
+
+#[tonic::async_trait]
+impl Agent for AgentService {
+    type ChatStream = ChatStream;
+
+    async fn chat(
+        &self,
+        request: Request<Streaming<ChatRequest>>,
+    ) -> Result<Response<Self::ChatStream>, Status> {
+        // Subscribe before reading prompts so no reply is missed
+        let responses = BroadcastStream::new(self.shared.outbound.subscribe())
+            .filter_map(|response| response.ok().map(Ok));
+
+        let shared = self.shared.clone();
+        let mut requests = request.into_inner();
+        tokio::spawn(async move {
+            while let Some(request) = requests.next().await {
+                match request {
+                    Ok(request) => {
+                        if let Err(status) = shared.submit(request.message).await {
+                            error!("Failed to submit gRPC chat message: {}", status);
+                        }
+                    }
+                    Err(status) => {
+                        error!("gRPC chat stream error: {}", status);
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(responses)))
+    }
+
+    async fn ask(&self, request: Request<AskRequest>) -> Result<Response<AskResponse>, Status> {
+        let (answer_tx, answer_rx) = oneshot::channel();
+        self.shared
+            .pending_asks
+            .lock()
+            .unwrap()
+            .push_back(answer_tx);
+        self.shared.submit(request.into_inner().message).await?;
+
+        let content = answer_rx
+            .await
+            .map_err(|_| Status::unavailable("Agent stopped before answering"))?;
+        Ok(Response::new(AskResponse { content }))
+    }
+}
+
+// Bind the server and start serving, once per bind address
+async fn start(bind: &str) -> Result<&'static GrpcServer> {
+    SERVERS
+        .get_or_try_init(bind, || async {
+            let listener = tokio::net::TcpListener::bind(bind)
+                .await
+                .context(format!("Failed to bind gRPC server to {}", bind))?;
+            let addr = listener.local_addr()?;
+
+            let (outbound, _) = broadcast::channel(100);
+            let shared = Shared {
+                inbound: Arc::new(StdMutex::new(None)),
+                outbound,
+                pending_asks: Arc::new(StdMutex::new(VecDeque::new())),
+            };
+
+            let service = AgentServer::new(AgentService {
+                shared: shared.clone(),
+            });
+            tokio::spawn(async move {
+                let incoming = tokio_stream::wrappers::TcpListenerStream::new(listener);
+                if let Err(e) = tonic::transport::Server::builder()
+                    .add_service(service)
+                    .serve_with_incoming(incoming)
+                    .await
+                {
+                    error!("gRPC server error: {}", e);
+                }
+            });
+            info!("gRPC server listening on {}", addr);
+
+            Ok(GrpcServer { addr, shared })
+        })
+        .await
+}
+
+// gRPC input source implementation
+pub struct GrpcSource {
+    message_rx: mpsc::Receiver<String>,
+    addr: SocketAddr,
+}
+
+impl GrpcSource {
+    pub async fn new(bind: &str) -> Result<Self> {
+        let server = start(bind).await?;
+        let (message_tx, message_rx) = mpsc::channel(100);
+        *server.shared.inbound.lock().unwrap() = Some(message_tx);
+
+        Ok(Self {
+            message_rx,
+            addr: server.addr,
+        })
+    }
+
+    // Get the address the server is listening on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+#[async_trait]
+impl InputSource for GrpcSource {
+    fn name(&self) -> &str {
+        "grpc"
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        match self.message_rx.recv().await {
+            Some(message) => Ok(Some(InboundMessage::new(message))),
+            None => Ok(None),
+        }
+    }
+}
+
+// gRPC output destination implementation
+pub struct GrpcDestination {
+    server: &'static GrpcServer,
+}
+
+impl GrpcDestination {
+    pub async fn new(bind: &str) -> Result<Self> {
+        let server = start(bind).await?;
+        Ok(Self { server })
+    }
+
+    // Get the address the server is listening on
+    pub fn addr(&self) -> SocketAddr {
+        self.server.addr
+    }
+}
+
+#[async_trait]
+impl OutputDestination for GrpcDestination {
+    fn name(&self) -> &str {
+        "grpc"
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        &["assistant", "image"]
+    }
+
+    async fn write_message(&self, role: &str, content: &str) -> Result<()> {
+        if role != "assistant" && role != "image" {
+            return Ok(());
+        }
+
+        // Answer the oldest Ask call whose client is still waiting
+        if role == "assistant" {
+            let mut pending = self.server.shared.pending_asks.lock().unwrap();
+            let mut answer = content.to_string();
+            while let Some(waiting) = pending.pop_front() {
+                match waiting.send(answer) {
+                    Ok(()) => break,
+                    Err(unsent) => answer = unsent,
+                }
+            }
+        }
+
+        let response = ChatResponse {
+            role: role.to_string(),
+            content: content.to_string(),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        // No connected Chat streams is not an error
+        let _ = self.server.shared.outbound.send(response);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, error};
+
+use super::{InboundMessage, InputSource};
+
+/// Settings for polling a URL
+#[derive(Debug, Clone)]
+pub struct HttpPollSettings {
+    pub url: String,
+    pub interval: Duration,
+    pub template: Option<String>,
+    // Put fetched content in an untrusted block, for injection_guard
+    pub guard: bool,
+}
+
+impl HttpPollSettings {
+    pub fn from_config(config: &crate::config::Config) -> Result<Self> {
+        let url = config
+            .http_poll_url
+            .clone()
+            .context("HTTP polling input requires http_poll_url to be set")?;
+        let interval = Duration::from_secs(config.http_poll_interval.unwrap_or(300).max(1));
+
+        Ok(Self {
+            url,
+            interval,
+            template: config.http_poll_template.clone(),
+            guard: config.injection_guard,
+        })
+    }
+}
+
+// Validators from the last response, sent back so unchanged content costs a 304
+#[derive(Default)]
+struct Poller {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    last_body: Option<String>,
+}
+
+impl Poller {
+    // Fetch the URL, returning the body only if it changed since the last fetch
+    async fn poll(&mut self, client: &reqwest::Client, url: &str) -> Result<Option<String>> {
+        let mut request = client.get(url);
+        if let Some(etag) = &self.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+
+        let response = request
+            .send()
+            .await
+            .context(format!("Failed to fetch {}", url))?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        self.etag = header(ETAG);
+        self.last_modified = header(LAST_MODIFIED);
+
+        // Not every server honours the validators, so compare the body too
+        let body = response.text().await?.trim().to_string();
+        if body.is_empty() || self.last_body.as_ref() == Some(&body) {
+            return Ok(None);
+        }
+        self.last_body = Some(body.clone());
+        Ok(Some(body))
+    }
+}
+
+// HTTP polling input source implementation
+pub struct HttpPollSource {
+    message_rx: mpsc::Receiver<String>,
+}
+
+impl HttpPollSource {
+    /// Fetch the URL now and then on every interval, submitting content that changed
+    pub fn new(settings: HttpPollSettings) -> Self {
+        let (message_tx, message_rx) = mpsc::channel(10);
+
+        tokio::spawn(async move {
+            let client = reqwest::Client::builder()
+                .timeout(settings.interval)
+                .build()
+                .unwrap_or_default();
+            let mut poller = Poller::default();
+            let mut interval = tokio::time::interval(settings.interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+
+                let content = match poller.poll(&client, &settings.url).await {
+                    Ok(Some(content)) => content,
+                    Ok(None) => {
+                        debug!("No new content at {}", settings.url);
+                        continue;
+                    }
+                    Err(e) => {
+                        error!("{:#}", e);
+                        continue;
+                    }
+                };
+
+                let content = if settings.guard {
+                    crate::injection::wrap(&format!("url {}", settings.url), &content)
+                } else {
+                    content
+                };
+                let prompt = match &settings.template {
+                    Some(template) => template.replace("{{content}}", &content),
+                    None => content,
+                };
+                if message_tx.send(prompt).await.is_err() {
+                    error!("Failed to send message to channel");
+                    break;
+                }
+            }
+
+            tracing::info!("HTTP polling input task completed");
+        });
+
+        Self { message_rx }
+    }
+}
+
+#[async_trait]
+impl InputSource for HttpPollSource {
+    fn name(&self) -> &str {
+        "http_poll"
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        match self.message_rx.recv().await {
+            Some(message) => Ok(Some(InboundMessage::new(message))),
+            None => Ok(None),
+        }
+    }
+}
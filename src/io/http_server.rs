@@ -0,0 +1,137 @@
+use anyhow::{Context, Result};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method};
+use std::net::IpAddr;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Browser and reverse proxy settings shared by the webhook and API servers
+#[derive(Debug, Clone, Default)]
+pub struct HttpSettings {
+    // Origins allowed to call the servers from a browser; "*" allows any
+    pub cors_origins: Vec<String>,
+    // Proxies whose X-Forwarded-For header is believed
+    pub trusted_proxies: Vec<IpRange>,
+}
+
+impl HttpSettings {
+    pub fn from_config(config: &crate::config::Config) -> Result<Self> {
+        let mut trusted_proxies = Vec::new();
+        for proxy in &config.trusted_proxies {
+            trusted_proxies.push(IpRange::parse(proxy).context("trusted_proxies")?);
+        }
+        Ok(Self {
+            cors_origins: config.cors_origins.clone(),
+            trusted_proxies,
+        })
+    }
+
+    /// A CORS layer for the configured origins, or None to allow same-origin requests only
+    pub fn cors_layer(&self) -> Result<Option<CorsLayer>> {
+        if self.cors_origins.is_empty() {
+            return Ok(None);
+        }
+
+        let origins = if self.cors_origins.iter().any(|origin| origin == "*") {
+            AllowOrigin::any()
+        } else {
+            let mut origins = Vec::new();
+            for origin in &self.cors_origins {
+                origins.push(
+                    HeaderValue::from_str(origin.trim_end_matches('/'))
+                        .context(format!("Invalid CORS origin: {}", origin))?,
+                );
+            }
+            AllowOrigin::list(origins)
+        };
+
+        Ok(Some(
+            CorsLayer::new()
+                .allow_origin(origins)
+                .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+                .allow_headers([
+                    header::AUTHORIZATION,
+                    header::CONTENT_TYPE,
+                    HeaderName::from_static("x-signature-256"),
+                ]),
+        ))
+    }
+
+    /// The address of the client behind any trusted proxies
+    ///
+    /// X-Forwarded-For is read from the right, skipping trusted proxies, so a client
+    /// can't pick its own address by sending the header itself.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        let forwarded = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+            .collect::<Vec<_>>();
+        let mut client = peer;
+        for ip in forwarded.into_iter().rev() {
+            client = ip;
+            if !self.is_trusted(ip) {
+                break;
+            }
+        }
+        client
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|range| range.contains(ip))
+    }
+}
+
+/// A single address, or a CIDR range such as `10.0.0.0/8`
+#[derive(Debug, Clone, Copy)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    pub fn parse(range: &str) -> Result<Self> {
+        let (addr, prefix) = match range.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (range, None),
+        };
+        let addr: IpAddr = addr
+            .trim()
+            .parse()
+            .context(format!("Invalid IP address: {}", range))?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= bits)
+                .context(format!("Invalid IP range: {}", range))?,
+            None => bits,
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual-stack listener show up as mapped IPv6 addresses
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            ip => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(range) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(range) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
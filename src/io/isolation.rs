@@ -0,0 +1,208 @@
+//! Keeps one slow or failing output from holding up the others: every write gets a
+//! time limit, and an output that keeps failing is left out for a while, with what
+//! it missed kept for a retry when `retry_dir` is set
+
+use anyhow::Result;
+use async_trait::async_trait;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+use super::retry::{is_retryable, RetryQueue};
+use super::{InboundMessage, OutputDestination};
+use crate::config::Config;
+
+/// Seconds a write may take unless `output_timeout_secs` is set
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// Failures in a row that quarantine an output unless `output_max_failures` is set
+pub const DEFAULT_MAX_FAILURES: u32 = 5;
+
+/// Seconds a quarantined output is left out unless `output_quarantine_secs` is set
+pub const DEFAULT_QUARANTINE_SECS: u64 = 60;
+
+// How often the retry queue is looked at; its own backoff decides when to try
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+// Failures since the last write that worked, and when a quarantine ends
+#[derive(Default)]
+struct Failures {
+    count: u32,
+    quarantined_until: Option<Instant>,
+}
+
+/// An output whose writes are cut off after a time limit, and skipped for a while
+/// once too many fail in a row. With `retry_dir` set, messages it fails to deliver
+/// are kept and tried again
+pub struct Isolated {
+    inner: Arc<Inner>,
+    // Delivers the retry queue, when there is one
+    worker: Option<JoinHandle<()>>,
+}
+
+struct Inner {
+    output: Box<dyn OutputDestination>,
+    // None for no limit
+    timeout: Option<Duration>,
+    // 0 never quarantines
+    max_failures: u32,
+    quarantine: Duration,
+    failures: Mutex<Failures>,
+    retries: Option<RetryQueue>,
+}
+
+impl Isolated {
+    /// Wrap `output`, built from the entry of type `kind`, which names its retry queue
+    pub fn new(config: &Config, kind: &str, output: Box<dyn OutputDestination>) -> Result<Self> {
+        let timeout = config.output_timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS);
+        let inner = Arc::new(Inner {
+            output,
+            timeout: (timeout > 0).then(|| Duration::from_secs(timeout)),
+            max_failures: config.output_max_failures.unwrap_or(DEFAULT_MAX_FAILURES),
+            quarantine: Duration::from_secs(
+                config
+                    .output_quarantine_secs
+                    .unwrap_or(DEFAULT_QUARANTINE_SECS),
+            ),
+            failures: Mutex::new(Failures::default()),
+            retries: RetryQueue::open(config, kind)?,
+        });
+        let worker = inner.retries.is_some().then(|| {
+            let inner = inner.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(RETRY_INTERVAL).await;
+                    if let Some(retries) = &inner.retries {
+                        if retries.deliver(inner.output.as_ref(), inner.timeout).await {
+                            inner.working();
+                        }
+                    }
+                }
+            })
+        });
+        Ok(Self { inner, worker })
+    }
+}
+
+impl Inner {
+    // Run one write unless the output is quarantined, and count how it went; None when
+    // it was skipped. Only a whole message delivered shows the output works, since
+    // outputs that don't stream accept chunks without sending anything. Once a
+    // quarantine is over the next write is a trial: one more failure starts another
+    async fn guard(
+        &self,
+        write: impl Future<Output = Result<()>>,
+        whole: bool,
+    ) -> Option<Result<()>> {
+        let quarantined_until = self.failures.lock().unwrap().quarantined_until;
+        if quarantined_until.is_some_and(|until| Instant::now() < until) {
+            tracing::debug!("Skipping quarantined output {}", self.output.name());
+            return None;
+        }
+
+        let result = match self.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, write).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow::anyhow!("Timed out after {}s", timeout.as_secs())),
+            },
+            None => write.await,
+        };
+
+        match &result {
+            Ok(()) if !whole => {}
+            Ok(()) => self.working(),
+            Err(e) => {
+                let mut failures = self.failures.lock().unwrap();
+                failures.count += 1;
+                if self.max_failures > 0 && failures.count >= self.max_failures {
+                    tracing::warn!(
+                        "Output {} failed {} times in a row, leaving it out for {}s: {:#}",
+                        self.output.name(),
+                        failures.count,
+                        self.quarantine.as_secs(),
+                        e
+                    );
+                    failures.quarantined_until = Some(Instant::now() + self.quarantine);
+                }
+            }
+        }
+        Some(result)
+    }
+
+    // A message went through, so the output is back if it was quarantined
+    fn working(&self) {
+        let mut failures = self.failures.lock().unwrap();
+        if failures.quarantined_until.take().is_some() {
+            tracing::info!("Output {} is working again", self.output.name());
+        }
+        failures.count = 0;
+    }
+}
+
+#[async_trait]
+impl OutputDestination for Isolated {
+    fn name(&self) -> &str {
+        self.inner.output.name()
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        self.inner.output.default_roles()
+    }
+
+    fn answering(&self, message: &InboundMessage) {
+        self.inner.output.answering(message)
+    }
+
+    // Processing notices are only worth anything on time, and messages the output
+    // turned away would only be turned away again, so neither is retried
+    async fn write_message(&self, role: &str, content: &str) -> Result<()> {
+        let result = self
+            .inner
+            .guard(self.inner.output.write_message(role, content), true)
+            .await;
+        if let Some(retries) = &self.inner.retries {
+            let retry = match &result {
+                Some(Ok(())) => false,
+                Some(Err(e)) => is_retryable(e),
+                None => true,
+            };
+            if role != "processing" && retry {
+                retries.push(role, content);
+            }
+        }
+        result.unwrap_or(Ok(()))
+    }
+
+    async fn typing(&self, notice: &str) -> Result<()> {
+        let write = self.inner.output.typing(notice);
+        self.inner.guard(write, false).await.unwrap_or(Ok(()))
+    }
+
+    async fn write_chunk(&self, role: &str, delta: &str) -> Result<()> {
+        let write = self.inner.output.write_chunk(role, delta);
+        self.inner.guard(write, false).await.unwrap_or(Ok(()))
+    }
+
+    async fn end_message(&self) -> Result<()> {
+        let write = self.inner.output.end_message();
+        self.inner.guard(write, false).await.unwrap_or(Ok(()))
+    }
+
+    fn keeps_transcript(&self) -> bool {
+        self.inner.output.keeps_transcript()
+    }
+
+    // The agent limits how long closing takes as a whole
+    async fn close(&self) -> Result<()> {
+        self.inner.output.close().await
+    }
+}
+
+impl Drop for Isolated {
+    fn drop(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            worker.abort();
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use matrix_sdk::{
+    config::SyncSettings,
+    ruma::{
+        events::room::message::{
+            MessageType, OriginalSyncRoomMessageEvent, RoomMessageEventContent,
+        },
+        OwnedRoomId, RoomOrAliasId,
+    },
+    Client, Room, RoomState,
+};
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+use super::shared::Shared;
+use super::{InboundMessage, InputSource, OutputDestination};
+
+// One login per account is shared by the source and destination so they use the same device,
+// which keeps the end-to-end encryption keys in a single store
+static CONNECTIONS: Shared<MatrixConnection> = Shared::new();
+
+/// Settings for connecting to a Matrix homeserver
+#[derive(Debug, Clone)]
+pub struct MatrixSettings {
+    pub homeserver: String,
+    pub user: String,
+    pub password: String,
+    pub rooms: Vec<String>,
+    pub store_path: String,
+    pub device_name: String,
+}
+
+impl MatrixSettings {
+    /// Build settings from the config, reading the password from `MATRIX_PASSWORD` if unset
+    pub fn from_config(config: &crate::config::Config) -> Result<Self> {
+        let homeserver = config
+            .matrix_homeserver
+            .clone()
+            .context("Matrix IO requires matrix_homeserver to be set")?;
+        let user = config
+            .matrix_user
+            .clone()
+            .context("Matrix IO requires matrix_user to be set")?;
+        let password = match &config.matrix_password {
+            Some(password) => password.clone(),
+            None => std::env::var("MATRIX_PASSWORD")
+                .context("MATRIX_PASSWORD environment variable not set")?,
+        };
+
+        Ok(Self {
+            homeserver,
+            user,
+            password,
+            rooms: config.matrix_rooms.clone(),
+            store_path: config
+                .matrix_store_path
+                .clone()
+                .unwrap_or_else(|| format!("matrix-store-{}", config.agent_name)),
+            device_name: config.agent_name.clone(),
+        })
+    }
+}
+
+// Logged-in client plus the room the most recent message came from
+struct MatrixConnection {
+    client: Client,
+    rooms: Vec<OwnedRoomId>,
+    last_room: Arc<StdMutex<Option<OwnedRoomId>>>,
+}
+
+// Log in, join the configured rooms and start syncing, once per account
+async fn connect(settings: &MatrixSettings) -> Result<&'static MatrixConnection> {
+    let account = format!("{}@{}", settings.user, settings.homeserver);
+    CONNECTIONS
+        .get_or_try_init(&account, || async {
+            // The SQLite store keeps the session and the encryption keys for E2EE rooms
+            let client = Client::builder()
+                .homeserver_url(&settings.homeserver)
+                .sqlite_store(&settings.store_path, None)
+                .build()
+                .await
+                .context("Failed to create Matrix client")?;
+
+            client
+                .matrix_auth()
+                .login_username(&settings.user, &settings.password)
+                .initial_device_display_name(&settings.device_name)
+                .await
+                .context("Failed to log in to Matrix")?;
+            info!("Logged in to Matrix as {}", settings.user);
+
+            let mut rooms = Vec::new();
+            for room in &settings.rooms {
+                let id = <&RoomOrAliasId>::try_from(room.as_str())
+                    .map_err(|e| anyhow::anyhow!("Invalid Matrix room {}: {}", room, e))?;
+                match client.join_room_by_id_or_alias(id, &[]).await {
+                    Ok(joined) => {
+                        info!("Joined Matrix room {}", room);
+                        rooms.push(joined.room_id().to_owned());
+                    }
+                    Err(e) => error!("Failed to join Matrix room {}: {}", room, e),
+                }
+            }
+
+            // Skip the backlog so only messages sent after startup are answered
+            let response = client
+                .sync_once(SyncSettings::default())
+                .await
+                .context("Initial Matrix sync failed")?;
+
+            let sync_client = client.clone();
+            tokio::spawn(async move {
+                let settings = SyncSettings::default().token(response.next_batch);
+                if let Err(e) = sync_client.sync(settings).await {
+                    error!("Matrix sync stopped: {}", e);
+                }
+            });
+
+            Ok(MatrixConnection {
+                client,
+                rooms,
+                last_room: Arc::new(StdMutex::new(None)),
+            })
+        })
+        .await
+}
+
+// Matrix input source implementation
+pub struct MatrixSource {
+    message_rx: mpsc::Receiver<(String, Option<String>)>,
+}
+
+impl MatrixSource {
+    pub async fn new(settings: &MatrixSettings) -> Result<Self> {
+        let connection = connect(settings).await?;
+        let (message_tx, message_rx) = mpsc::channel(100);
+
+        let rooms = connection.rooms.clone();
+        let last_room = connection.last_room.clone();
+        connection.client.add_event_handler(
+            move |event: OriginalSyncRoomMessageEvent, room: Room, client: Client| {
+                let message_tx = message_tx.clone();
+                let rooms = rooms.clone();
+                let last_room = last_room.clone();
+                async move {
+                    if room.state() != RoomState::Joined
+                        || !rooms.contains(&room.room_id().to_owned())
+                    {
+                        return;
+                    }
+                    // Ignore the agent's own replies
+                    if client.user_id() == Some(&*event.sender) {
+                        return;
+                    }
+                    let MessageType::Text(text) = event.content.msgtype else {
+                        return;
+                    };
+
+                    // Each room is a conversation of its own
+                    *last_room.lock().unwrap() = Some(room.room_id().to_owned());
+                    let chat = room.room_id().to_string();
+                    if message_tx.send((text.body, Some(chat))).await.is_err() {
+                        error!("Failed to send message to channel");
+                    }
+                }
+            },
+        );
+
+        Ok(Self { message_rx })
+    }
+}
+
+#[async_trait]
+impl InputSource for MatrixSource {
+    fn name(&self) -> &str {
+        "matrix"
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        match self.message_rx.recv().await {
+            Some((message, sender)) => Ok(Some(InboundMessage::new(message).with_sender(sender))),
+            None => Ok(None),
+        }
+    }
+}
+
+// Matrix output destination implementation
+pub struct MatrixDestination {
+    connection: &'static MatrixConnection,
+}
+
+impl MatrixDestination {
+    pub async fn new(settings: &MatrixSettings) -> Result<Self> {
+        let connection = connect(settings).await?;
+        Ok(Self { connection })
+    }
+
+    // The room the last message came from, or every configured room
+    fn targets(&self) -> Vec<OwnedRoomId> {
+        let last_room = self.connection.last_room.lock().unwrap().clone();
+        match last_room {
+            Some(room) => vec![room],
+            None => self.connection.rooms.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl OutputDestination for MatrixDestination {
+    fn name(&self) -> &str {
+        "matrix"
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        &["assistant", "processing"]
+    }
+
+    async fn write_message(&self, _role: &str, content: &str) -> Result<()> {
+        for room_id in self.targets() {
+            let room = self
+                .connection
+                .client
+                .get_room(&room_id)
+                .context(format!("Not a member of Matrix room {}", room_id))?;
+            room.send(RoomMessageEventContent::text_plain(content))
+                .await
+                .context(format!("Failed to send to Matrix room {}", room_id))?;
+        }
+
+        Ok(())
+    }
+
+    // Matrix's own typing notice, which clients clear when the reply arrives
+    async fn typing(&self, _notice: &str) -> Result<()> {
+        for room_id in self.targets() {
+            let room = self
+                .connection
+                .client
+                .get_room(&room_id)
+                .context(format!("Not a member of Matrix room {}", room_id))?;
+            room.typing_notice(true).await.context(format!(
+                "Failed to send typing notice to Matrix room {}",
+                room_id
+            ))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! Input and output over in-process channels, for tests and programs that embed the
+//! agent and hand it messages themselves
+//!
+//! Each is named after the type it stands in for, such as `stdin` or `mqtt`, so the
+//! config's routes and `output_roles` apply to it as they would to the real one.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+
+use super::{InboundMessage, InputSource, OutputDestination};
+
+/// A message the agent wrote to a [`MemorySink`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Written {
+    /// Name of the sink it was written to
+    pub output: String,
+    pub role: String,
+    pub content: String,
+    /// Correlation ID of the message the agent was answering when it wrote this
+    pub in_reply_to: Option<String>,
+}
+
+/// An input whose messages are sent on a channel
+pub struct MemorySource {
+    name: String,
+    messages: mpsc::UnboundedReceiver<InboundMessage>,
+}
+
+impl MemorySource {
+    /// An input named `name`, and the sender to give it messages with
+    pub fn new(name: impl Into<String>) -> (Self, mpsc::UnboundedSender<InboundMessage>) {
+        let (sender, messages) = mpsc::unbounded_channel();
+        let source = Self {
+            name: name.into(),
+            messages,
+        };
+        (source, sender)
+    }
+}
+
+#[async_trait]
+impl InputSource for MemorySource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        match self.messages.recv().await {
+            Some(message) => Ok(Some(message)),
+            // With every sender gone nothing more can come, so there's nothing to poll
+            None => std::future::pending().await,
+        }
+    }
+}
+
+/// An output that sends what the agent writes on a channel
+pub struct MemorySink {
+    name: String,
+    written: mpsc::UnboundedSender<Written>,
+    answering: Mutex<Option<String>>,
+}
+
+impl MemorySink {
+    /// An output named `name`, and the receiver of what is written to it
+    pub fn new(name: impl Into<String>) -> (Self, mpsc::UnboundedReceiver<Written>) {
+        let (written, receiver) = mpsc::unbounded_channel();
+        (Self::sharing(name, written), receiver)
+    }
+
+    /// An output named `name` sending on an existing channel, so the messages of
+    /// several outputs arrive in the order they were written
+    pub fn sharing(name: impl Into<String>, written: mpsc::UnboundedSender<Written>) -> Self {
+        Self {
+            name: name.into(),
+            written,
+            answering: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl OutputDestination for MemorySink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn answering(&self, message: &InboundMessage) {
+        *self.answering.lock().unwrap() = Some(message.correlation_id.clone());
+    }
+
+    async fn write_message(&self, role: &str, content: &str) -> Result<()> {
+        self.written
+            .send(Written {
+                output: self.name.clone(),
+                role: role.to_string(),
+                content: content.to_string(),
+                in_reply_to: self.answering.lock().unwrap().clone(),
+            })
+            .map_err(|_| anyhow::anyhow!("Nothing is reading output {}", self.name))
+    }
+}
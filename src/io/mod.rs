@@ -1,14 +1,163 @@
 use anyhow::Result;
 use async_trait::async_trait;
 
+pub mod admin;
+pub mod allowlist;
+pub mod api;
+pub mod chat_webhook;
+pub mod command;
+#[cfg(feature = "email")]
+pub mod email;
+pub mod exec;
+pub mod file;
+pub mod github;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod http_poll;
+pub mod http_server;
+pub mod isolation;
+#[cfg(feature = "matrix")]
+pub mod matrix;
+pub mod memory;
 pub mod mqtt;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(feature = "nostr")]
+pub mod nostr;
+#[cfg(feature = "pubsub")]
+pub mod pubsub;
+pub mod registry;
+pub mod retry;
+pub mod route;
+pub mod schedule;
+pub(crate) mod shared;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod sse;
 pub mod stdin;
 pub mod stdout;
+pub mod tcp;
+pub mod tui;
+pub mod twitch;
+#[cfg(feature = "voice")]
+pub mod voice;
+pub mod web_ui;
+pub mod webhook;
+#[cfg(feature = "xmpp")]
+pub mod xmpp;
 
 // Re-export the source and destination types
-pub use mqtt::{MqttDestination, MqttSource};
+pub use admin::AdminCommand;
+pub use api::{ApiDestination, ApiSettings, ApiSource};
+pub use chat_webhook::ChatWebhookDestination;
+pub use command::{CommandSettings, CommandSource};
+#[cfg(feature = "email")]
+pub use email::{EmailDestination, EmailSettings, EmailSource};
+pub use exec::ExecDestination;
+pub use file::{FileDestination, TranscriptFormat};
+pub use github::{GithubDestination, GithubSettings};
+#[cfg(feature = "grpc")]
+pub use grpc::{GrpcDestination, GrpcSource};
+pub use http_poll::{HttpPollSettings, HttpPollSource};
+pub use http_server::HttpSettings;
+#[cfg(feature = "matrix")]
+pub use matrix::{MatrixDestination, MatrixSettings, MatrixSource};
+pub use mqtt::{ControlCommand, MqttControl, MqttDestination, MqttSettings, MqttSource};
+#[cfg(feature = "nats")]
+pub use nats::{NatsDestination, NatsSettings, NatsSource};
+#[cfg(feature = "nostr")]
+pub use nostr::{NostrDestination, NostrSettings, NostrSource};
+#[cfg(feature = "pubsub")]
+pub use pubsub::{PubsubDestination, PubsubSettings, PubsubSource};
+pub use registry::{register_input, register_output, IoContext};
+pub use schedule::{ScheduleSettings, ScheduleSource};
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteDestination;
+pub use sse::SseDestination;
 pub use stdin::StdinSource;
 pub use stdout::StdoutDestination;
+pub use tcp::{TcpDestination, TcpSettings, TcpSource};
+pub use tui::{TuiDestination, TuiSettings, TuiSource};
+pub use twitch::{TwitchDestination, TwitchSettings, TwitchSource};
+#[cfg(feature = "voice")]
+pub use voice::{VoiceSettings, VoiceSource};
+pub use webhook::{WebhookAuth, WebhookDestination, WebhookLimits, WebhookSource};
+#[cfg(feature = "xmpp")]
+pub use xmpp::{XmppDestination, XmppSettings, XmppSource};
+
+/// A message from an input, with where it came from
+#[derive(Debug, Clone)]
+pub struct InboundMessage {
+    /// Input type, e.g. mqtt; set by the agent
+    pub source: String,
+    /// Which of the agent's inputs it came from, by index; set by the agent
+    pub instance: usize,
+    pub content: String,
+    /// Who sent it, if the input knows. Each sender gets a conversation history of
+    /// their own on the input
+    pub sender: Option<String>,
+    /// The client's address, for inputs served over the network
+    pub remote_addr: Option<std::net::IpAddr>,
+    /// Name of the webhook token the message came with, when `webhook_tokens` are set
+    pub credential: Option<String>,
+    pub received_at: chrono::DateTime<chrono::Utc>,
+    /// Follows the message through the logs; a new UUID unless the input has an ID
+    /// of its own
+    pub correlation_id: String,
+    pub attachments: Vec<Attachment>,
+    /// Agents the message passed through on its way here, when another agent asked it;
+    /// 0 for a message from a person
+    pub hops: u32,
+    /// Output types that answer it, unless routes or `broadcast_replies` say otherwise.
+    /// When none of them is running, every output does. Left empty, the input's
+    /// `reply_to` is used
+    pub reply_to: Vec<String>,
+    /// The MQTT v5 response topic and correlation data the answer is to go back
+    /// with, when the request came with them
+    pub response_topic: Option<mqtt::ResponseTopic>,
+    /// Comments URL of the GitHub issue or pull request the answer is posted to, for
+    /// mentions from GitHub
+    pub comments_url: Option<String>,
+}
+
+/// A file sent along with a message
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub name: Option<String>,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+impl InboundMessage {
+    /// A message received now, with nothing known about its sender
+    pub fn new(content: impl Into<String>) -> Self {
+        Self {
+            source: String::new(),
+            instance: 0,
+            content: content.into(),
+            sender: None,
+            remote_addr: None,
+            credential: None,
+            received_at: chrono::Utc::now(),
+            correlation_id: uuid::Uuid::new_v4().to_string(),
+            attachments: Vec::new(),
+            hops: 0,
+            reply_to: Vec::new(),
+            response_topic: None,
+            comments_url: None,
+        }
+    }
+
+    pub fn with_sender(mut self, sender: Option<String>) -> Self {
+        self.sender = sender;
+        self
+    }
+
+    pub fn with_remote_addr(mut self, remote_addr: std::net::IpAddr) -> Self {
+        self.remote_addr = Some(remote_addr);
+        self
+    }
+}
 
 #[async_trait]
 pub trait InputSource: Send + Sync {
@@ -16,7 +165,18 @@ pub trait InputSource: Send + Sync {
     fn name(&self) -> &str;
 
     /// Read a message from the input source
-    async fn read_message(&mut self) -> Result<Option<String>>;
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>>;
+
+    /// Output types that answer this input's messages when they don't name their own
+    fn reply_to(&self) -> Vec<String> {
+        vec![self.name().to_string()]
+    }
+
+    /// Tell the source of `message` it was turned away because the agent is too busy,
+    /// for sources that can answer; the agent logs it either way
+    async fn reject(&mut self, _message: &InboundMessage, _reason: &str) -> Result<()> {
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -24,37 +184,58 @@ pub trait OutputDestination: Send + Sync {
     /// Get the name of the output destination
     fn name(&self) -> &str;
 
+    /// Roles of the messages the destination writes unless `output_roles` says
+    /// otherwise; the rest never reach it
+    fn default_roles(&self) -> &[&str] {
+        route::ROLES
+    }
+
+    /// Start on the answer to `message`: what is written from now until the next
+    /// call belongs to it, for destinations that address each answer to its request
+    fn answering(&self, _message: &InboundMessage) {}
+
     /// Write a message to the output destination
     async fn write_message(&self, role: &str, content: &str) -> Result<()>;
+
+    /// Show that a reply is on its way: `notice` as a `processing` message, unless the
+    /// destination has a typing indicator of its own
+    async fn typing(&self, notice: &str) -> Result<()> {
+        self.write_message("processing", notice).await
+    }
+
+    /// Write part of a message as it is generated. `write_message` still follows with
+    /// the whole message, so destinations that can't show partial messages ignore this
+    async fn write_chunk(&self, _role: &str, _delta: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Mark the end of a message sent with `write_chunk`
+    async fn end_message(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether the destination keeps a record of every conversation, so it gets
+    /// messages answered on other outputs too
+    fn keeps_transcript(&self) -> bool {
+        false
+    }
+
+    /// Deliver anything still buffered and close connections, once the agent has
+    /// stopped; nothing is written after this
+    async fn close(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// Factory function to create input sources
 pub async fn create_input_sources(config: &crate::config::Config) -> Vec<Box<dyn InputSource>> {
     let mut sources = Vec::new();
-
-    for source in &config.inputs_vec {
-        match source.as_str() {
-            "mqtt" => {
-                let mqtt_source = MqttSource::new(
-                    config.mqtt_input_topic.clone(),
-                    config.mqtt_broker.clone(),
-                    config.mqtt_port,
-                    Some(config.agent_name.clone()),
-                )
-                .await
-                .expect("Failed to create MQTT source");
-                sources.push(Box::new(mqtt_source) as Box<dyn InputSource>);
-            }
-            "stdin" => {
-                let stdin_source = StdinSource::new();
-                sources.push(Box::new(stdin_source) as Box<dyn InputSource>);
-            }
-            _ => {
-                tracing::error!("Unknown input source: {}", source);
-            }
+    for entry in config.input_entries() {
+        match registry::build_input(config, &entry).await {
+            Ok(source) => sources.push(source),
+            Err(e) => tracing::error!("Failed to create {} input: {:#}", entry.kind, e),
         }
     }
-
     sources
 }
 
@@ -63,29 +244,11 @@ pub async fn create_output_destinations(
     config: &crate::config::Config,
 ) -> Vec<Box<dyn OutputDestination>> {
     let mut destinations = Vec::new();
-
-    for dest in &config.outputs_vec {
-        match dest.as_str() {
-            "mqtt" => {
-                let mqtt_dest = MqttDestination::new(
-                    config.mqtt_output_topic.clone(),
-                    config.mqtt_broker.clone(),
-                    config.mqtt_port,
-                    Some(config.agent_name.clone()),
-                )
-                .await
-                .expect("Failed to create MQTT destination");
-                destinations.push(Box::new(mqtt_dest) as Box<dyn OutputDestination>);
-            }
-            "stdout" => {
-                let stdout_dest = StdoutDestination::new();
-                destinations.push(Box::new(stdout_dest) as Box<dyn OutputDestination>);
-            }
-            _ => {
-                tracing::error!("Unknown output destination: {}", dest);
-            }
+    for entry in config.output_entries() {
+        match registry::build_output(config, &entry).await {
+            Ok(destination) => destinations.push(destination),
+            Err(e) => tracing::error!("Failed to create {} output: {:#}", entry.kind, e),
         }
     }
-
     destinations
 }
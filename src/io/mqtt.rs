@@ -1,12 +1,337 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
-use rand::Rng;
-use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use rumqttc::v5::mqttbytes::v5::{ConnectProperties, Packet, PublishProperties};
+use rumqttc::v5::mqttbytes::{matches, QoS};
+use rumqttc::v5::{AsyncClient, Event, MqttOptions};
+use rumqttc::Outgoing as OutgoingEvent;
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
-use tracing::error;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex, Weak};
+use tokio::sync::{broadcast, mpsc, watch, Notify};
+use tracing::{error, warn};
 
-use super::{InputSource, OutputDestination};
+use super::{InboundMessage, InputSource, OutputDestination};
+
+// User property with the number of agents a question from another agent passed through
+const HOPS_PROPERTY: &str = "hops";
+
+/// Where an MQTT v5 request wants its answer, instead of the output topic
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseTopic {
+    /// The named broker the request came in on, or None for the default one
+    pub broker: Option<String>,
+    pub topic: String,
+    pub correlation_data: Option<Vec<u8>>,
+}
+
+/// Connection, topic and delivery settings for MQTT
+#[derive(Debug, Clone)]
+pub struct MqttSettings {
+    // Name of the connection in mqtt_brokers, or None for the global settings
+    pub name: Option<String>,
+    pub broker: String,
+    pub port: u16,
+    pub agent_name: String,
+    pub input_topic: String,
+    pub output_topic: String,
+    pub input_qos: QoS,
+    pub output_qos: QoS,
+    pub retain: bool,
+    // Sent as user properties on published messages
+    pub model: String,
+    pub client_id: Option<String>,
+    // With a persistent session the broker queues messages while the agent is offline
+    pub clean_session: bool,
+    pub session_expiry: u32,
+    pub input_format: InputFormat,
+    pub output_format: OutputFormat,
+    pub control_topic: String,
+    pub status_topic: String,
+    // Where streamed chunks go, if streaming is enabled
+    pub stream_topic: Option<String>,
+    // Outbound messages held while the broker link is down
+    pub queue_size: usize,
+    pub queue_policy: QueuePolicy,
+}
+
+impl MqttSettings {
+    pub fn from_config(config: &crate::config::Config) -> Result<Self> {
+        let agent_name = config.agent_name.clone();
+        let output_topic = config
+            .mqtt_output_topic
+            .clone()
+            .unwrap_or_else(|| format!("agent/{}/output", agent_name));
+        Ok(Self {
+            name: None,
+            broker: config
+                .mqtt_broker
+                .clone()
+                .unwrap_or_else(|| "localhost".to_string()),
+            port: config.mqtt_port.unwrap_or(1883),
+            input_topic: config
+                .mqtt_input_topic
+                .clone()
+                .unwrap_or_else(|| format!("agent/{}/input", agent_name)),
+            stream_topic: config
+                .mqtt_stream
+                .then(|| format!("{}/stream", output_topic)),
+            output_topic,
+            input_qos: qos(config.mqtt_input_qos)?,
+            output_qos: qos(config.mqtt_output_qos)?,
+            retain: config.mqtt_retain,
+            model: config.model.clone(),
+            client_id: config.mqtt_client_id.clone(),
+            clean_session: config.mqtt_clean_session.unwrap_or(true),
+            session_expiry: config.mqtt_session_expiry.unwrap_or(86400),
+            input_format: InputFormat::new(config.mqtt_input_format.as_deref())?,
+            output_format: OutputFormat::new(config.mqtt_output_format.as_deref()),
+            queue_size: config.mqtt_queue_size.unwrap_or(100).max(1),
+            queue_policy: QueuePolicy::new(config.mqtt_queue_policy.as_deref())?,
+            control_topic: config
+                .mqtt_control_topic
+                .clone()
+                .unwrap_or_else(|| format!("agent/{}/control", agent_name)),
+            status_topic: config
+                .mqtt_status_topic
+                .clone()
+                .unwrap_or_else(|| format!("agent/{}/status", agent_name)),
+            agent_name,
+        })
+    }
+
+    /// Build settings for a connection named in `mqtt_brokers`, which overrides the
+    /// broker address, topics and client ID of the global settings
+    pub fn for_broker(config: &crate::config::Config, name: &str) -> Result<Self> {
+        let broker = config
+            .mqtt_brokers
+            .iter()
+            .find(|broker| broker.name == name)
+            .context(format!("No MQTT broker named {} in mqtt_brokers", name))?;
+
+        let mut settings = Self::from_config(config)?;
+        settings.name = Some(name.to_string());
+        settings.broker = broker.broker.clone();
+        settings.port = broker.port.unwrap_or(1883);
+        if let Some(topic) = &broker.input_topic {
+            settings.input_topic = topic.clone();
+        }
+        if let Some(topic) = &broker.output_topic {
+            settings.output_topic = topic.clone();
+            if settings.stream_topic.is_some() {
+                settings.stream_topic = Some(format!("{}/stream", topic));
+            }
+        }
+        if broker.client_id.is_some() {
+            settings.client_id = broker.client_id.clone();
+        }
+        Ok(settings)
+    }
+
+    // Name of the input or output: mqtt, or mqtt:<name> for a broker from mqtt_brokers
+    fn io_name(&self) -> String {
+        match &self.name {
+            Some(name) => format!("mqtt:{}", name),
+            None => "mqtt".to_string(),
+        }
+    }
+
+    // Name of a client's connection check on /readyz
+    fn health_check(&self, direction: &str) -> String {
+        match &self.name {
+            Some(name) => format!("mqtt_{}:{}", direction, name),
+            None => format!("mqtt_{}", direction),
+        }
+    }
+
+    // Settings that tell connections apart; sources or destinations with the same
+    // key share one client
+    fn connection_key(&self, direction: &str) -> String {
+        // A persistent session without a client ID is named after the agent
+        let client_id = match (&self.client_id, self.clean_session) {
+            (Some(client_id), _) => client_id.as_str(),
+            (None, false) => self.agent_name.as_str(),
+            (None, true) => "",
+        };
+        let mut key = format!(
+            "{}|{:?}|{}:{}|{}|{}|{}",
+            direction,
+            self.name,
+            self.broker,
+            self.port,
+            client_id,
+            self.clean_session,
+            self.session_expiry
+        );
+        if direction == "output" {
+            key.push_str(&format!("|{}|{:?}", self.queue_size, self.queue_policy));
+        }
+        key
+    }
+
+    // Connection options for the input or output client
+    fn options(&self, direction: &str) -> MqttOptions {
+        // Named connections get their own client IDs, in case two share a broker
+        let direction = match &self.name {
+            Some(name) => format!("{}-{}", name, direction),
+            None => direction.to_string(),
+        };
+
+        // The broker finds a persistent session by client ID, so it must survive restarts;
+        // otherwise a random suffix keeps several agents from kicking each other off
+        let client_id = match (&self.client_id, self.clean_session) {
+            (Some(client_id), _) => format!("{}-{}", client_id, direction),
+            (None, false) => format!("{}-mqtt-{}", self.agent_name, direction),
+            (None, true) => format!(
+                "{}-mqtt-{}-{}",
+                self.agent_name,
+                direction,
+                rand::random::<u16>()
+            ),
+        };
+
+        let mut options = MqttOptions::new(client_id, &self.broker, self.port);
+        options.set_keep_alive(std::time::Duration::from_secs(30));
+        options.set_clean_start(self.clean_session);
+        if !self.clean_session {
+            // MQTT v5 ends the session on disconnect unless it has an expiry interval
+            let mut properties = ConnectProperties::new();
+            properties.session_expiry_interval = Some(self.session_expiry);
+            options.set_connect_properties(properties);
+        }
+        options
+    }
+}
+
+// Map a configured QoS level, defaulting to at-least-once delivery
+fn qos(level: Option<u8>) -> Result<QoS> {
+    match level {
+        None | Some(1) => Ok(QoS::AtLeastOnce),
+        Some(0) => Ok(QoS::AtMostOnce),
+        Some(2) => Ok(QoS::ExactlyOnce),
+        Some(other) => anyhow::bail!("Invalid MQTT QoS level {}, expected 0, 1 or 2", other),
+    }
+}
+
+/// What to do with a new outbound message when the queue is full
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueuePolicy {
+    // Wait for room, holding up the agent until the broker is back
+    Block,
+    DropOldest,
+    DropNewest,
+}
+
+impl QueuePolicy {
+    fn new(policy: Option<&str>) -> Result<Self> {
+        match policy {
+            None | Some("block") => Ok(Self::Block),
+            Some("drop_oldest") => Ok(Self::DropOldest),
+            Some("drop_newest") => Ok(Self::DropNewest),
+            Some(other) => anyhow::bail!(
+                "Invalid MQTT queue policy {}, expected block, drop_oldest or drop_newest",
+                other
+            ),
+        }
+    }
+}
+
+// A message waiting to be published
+struct Outgoing {
+    topic: String,
+    qos: QoS,
+    retain: bool,
+    payload: Vec<u8>,
+    properties: PublishProperties,
+}
+
+// Published messages wait here until the broker link is up, so they survive a
+// reconnect instead of being lost
+struct OutboundQueue {
+    items: StdMutex<VecDeque<Outgoing>>,
+    capacity: usize,
+    policy: QueuePolicy,
+    // Messages taken for publishing that the broker hasn't acknowledged yet (QoS 1
+    // and 2) or that haven't been sent yet (QoS 0)
+    unacked: AtomicUsize,
+    // Signalled whenever a message is added, removed or delivered
+    changed: Notify,
+}
+
+impl OutboundQueue {
+    async fn push(&self, message: Outgoing) {
+        loop {
+            // Created before checking, so a change in between isn't missed
+            let changed = self.changed.notified();
+            {
+                let mut items = self.items.lock().unwrap();
+                if items.len() < self.capacity {
+                    items.push_back(message);
+                    break;
+                }
+                match self.policy {
+                    QueuePolicy::Block => {}
+                    QueuePolicy::DropOldest => {
+                        warn!("MQTT output queue is full, dropping the oldest message");
+                        items.pop_front();
+                        items.push_back(message);
+                        break;
+                    }
+                    QueuePolicy::DropNewest => {
+                        warn!("MQTT output queue is full, dropping the newest message");
+                        return;
+                    }
+                }
+            }
+            changed.await;
+        }
+        self.changed.notify_waiters();
+    }
+
+    async fn pop(&self) -> Outgoing {
+        loop {
+            let changed = self.changed.notified();
+            let message = {
+                let mut items = self.items.lock().unwrap();
+                let message = items.pop_front();
+                if message.is_some() {
+                    // Counted under the lock, so the message is never in neither place
+                    self.unacked.fetch_add(1, Ordering::SeqCst);
+                }
+                message
+            };
+            if let Some(message) = message {
+                self.changed.notify_waiters();
+                return message;
+            }
+            changed.await;
+        }
+    }
+
+    // A message taken by `pop` was delivered, or will never be
+    fn delivered(&self) {
+        // A resumed session can acknowledge messages from before this run
+        let _ = self
+            .unacked
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+        self.changed.notify_waiters();
+    }
+
+    // Wait until every message has been delivered
+    async fn drained(&self) {
+        loop {
+            let changed = self.changed.notified();
+            let idle = {
+                let items = self.items.lock().unwrap();
+                items.is_empty() && self.unacked.load(Ordering::SeqCst) == 0
+            };
+            if idle {
+                return;
+            }
+            changed.await;
+        }
+    }
+}
 
 // MQTT message format
 #[derive(Serialize, Deserialize)]
@@ -14,60 +339,191 @@ struct MqttMessage {
     role: String,
     content: String,
     timestamp: u64,
+    // Identifies the conversation an input message belongs to; session_id wins if
+    // both are set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sender: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    session_id: Option<String>,
 }
 
-// MQTT input source implementation
-pub struct MqttSource {
-    message_rx: mpsc::Receiver<String>,
-    _shutdown_tx: tokio::sync::broadcast::Sender<()>, // Keep sender alive
+/// How prompts are read from incoming payloads
+#[derive(Debug, Clone)]
+pub enum InputFormat {
+    // The MQTT message format, falling back to plain text for anything that isn't JSON
+    Json,
+    // The whole payload is the prompt
+    Plain,
+    // A JSON pointer such as `/data/text` to a string in any JSON payload
+    Pointer(String),
 }
 
-impl MqttSource {
-    pub async fn new(
-        topic: Option<String>,
-        broker: Option<String>,
-        port: Option<u16>,
-        agent_name: Option<String>,
-    ) -> Result<Self> {
-        let agent_name = agent_name.unwrap_or_else(|| "agent".to_string());
-        let default_topic = format!("agent/{}/input", agent_name);
-        let topic = topic.unwrap_or_else(|| default_topic);
-        let broker = broker.unwrap_or_else(|| "localhost".to_string());
-        let port = port.unwrap_or(1883);
-
-        // Generate a random client ID outside the async block
-        let random_suffix: u16 = {
-            let mut rng = rand::thread_rng();
-            rng.gen()
+impl InputFormat {
+    fn new(format: Option<&str>) -> Result<Self> {
+        match format {
+            None | Some("json") => Ok(Self::Json),
+            Some("plain") => Ok(Self::Plain),
+            Some(pointer) if pointer.starts_with('/') => Ok(Self::Pointer(pointer.to_string())),
+            Some(other) => anyhow::bail!(
+                "Invalid MQTT input format {}, expected json, plain or a JSON pointer",
+                other
+            ),
+        }
+    }
+
+    // Extract the prompt and its sender, or None for payloads that aren't prompts
+    fn parse(&self, payload: &[u8]) -> Result<Option<(String, Option<String>)>> {
+        let text = std::str::from_utf8(payload)?;
+        let (prompt, sender) = match self {
+            Self::Json => match serde_json::from_str::<MqttMessage>(text) {
+                Ok(message) if message.role == "user" => {
+                    (message.content, message.session_id.or(message.sender))
+                }
+                Ok(_) => return Ok(None),
+                Err(_) => (text.to_string(), None),
+            },
+            Self::Plain => (text.to_string(), None),
+            Self::Pointer(pointer) => {
+                let value: serde_json::Value = serde_json::from_str(text)?;
+                match value.pointer(pointer) {
+                    Some(serde_json::Value::String(prompt)) => (prompt.clone(), None),
+                    Some(_) => anyhow::bail!("{} in MQTT payload is not a string", pointer),
+                    None => return Ok(None),
+                }
+            }
         };
-        let client_id = format!("{}-mqtt-input-{}", agent_name, random_suffix);
+        let prompt = prompt.trim().to_string();
+        if prompt.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some((prompt, sender.filter(|sender| !sender.is_empty()))))
+    }
+}
 
-        // Create MQTT options with reconnection settings
-        let mut mqtt_options = MqttOptions::new(&client_id, &broker, port);
-        mqtt_options.set_keep_alive(std::time::Duration::from_secs(30));
-        mqtt_options.set_clean_session(true);
+/// How responses are published
+#[derive(Debug, Clone)]
+pub enum OutputFormat {
+    // The MQTT message format
+    Json,
+    // Just the response text
+    Plain,
+    // A template with {{content}}, {{role}}, {{agent}} and {{timestamp}} placeholders
+    Template(String),
+}
+
+impl OutputFormat {
+    fn new(format: Option<&str>) -> Self {
+        match format {
+            None | Some("json") => Self::Json,
+            Some("plain") => Self::Plain,
+            Some(template) => Self::Template(template.to_string()),
+        }
+    }
 
-        // Set manual reconnection parameters - we'll handle reconnection in the event loop
+    fn render(&self, agent: &str, role: &str, content: &str) -> Result<String> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        match self {
+            Self::Json => Ok(serde_json::to_string(&MqttMessage {
+                role: role.to_string(),
+                content: content.to_string(),
+                timestamp,
+                sender: None,
+                session_id: None,
+            })?),
+            Self::Plain => Ok(content.to_string()),
+            Self::Template(template) => {
+                // Values are escaped when the template is JSON, so they go inside strings
+                let json = template.trim_start().starts_with('{');
+                let escape = |text: &str| {
+                    if json {
+                        super::chat_webhook::json_escape(text)
+                    } else {
+                        text.to_string()
+                    }
+                };
+                Ok(template
+                    .replace("{{content}}", &escape(content))
+                    .replace("{{role}}", &escape(role))
+                    .replace("{{agent}}", &escape(agent))
+                    .replace("{{timestamp}}", &timestamp.to_string()))
+            }
+        }
+    }
+}
+
+// Connections shared by every source, or every destination, with the same settings,
+// so agents in one process use one client per broker; each ends with its last user
+static INPUT_CONNECTIONS: StdMutex<Vec<(String, Weak<InputConnection>)>> =
+    StdMutex::new(Vec::new());
+static OUTPUT_CONNECTIONS: StdMutex<Vec<(String, Weak<OutputConnection>)>> =
+    StdMutex::new(Vec::new());
+
+// Find the live connection registered under `key`, or make and register a new one
+fn shared<T>(
+    registry: &StdMutex<Vec<(String, Weak<T>)>>,
+    key: String,
+    connect: impl FnOnce() -> T,
+) -> Arc<T> {
+    let mut connections = registry.lock().unwrap();
+    connections.retain(|(_, connection)| connection.strong_count() > 0);
+    if let Some(connection) = connections
+        .iter()
+        .find(|(existing, _)| *existing == key)
+        .and_then(|(_, connection)| connection.upgrade())
+    {
+        return connection;
+    }
+    let connection = Arc::new(connect());
+    connections.push((key, Arc::downgrade(&connection)));
+    connection
+}
+
+// A source reading from a shared input connection
+struct Subscriber {
+    topic: String,
+    qos: QoS,
+    format: InputFormat,
+    messages: mpsc::Sender<InboundMessage>,
+}
+
+// Whether a message on `topic` belongs to a subscription to `filter`
+fn topic_matches(topic: &str, filter: &str) -> bool {
+    // Shared subscriptions ($share/<group>/<filter>) match on the filter part
+    let filter = match filter.strip_prefix("$share/") {
+        Some(shared) => shared.split_once('/').map_or(shared, |(_, filter)| filter),
+        None => filter,
+    };
+    matches(topic, filter)
+}
+
+struct InputConnection {
+    client: AsyncClient,
+    subscribers: Arc<StdMutex<Vec<Subscriber>>>,
+    _shutdown_tx: broadcast::Sender<()>, // Ends the event loop when dropped
+}
+
+impl InputConnection {
+    fn connect(settings: &MqttSettings) -> Self {
+        let broker_name = settings.name.clone();
+
+        // Create MQTT options with reconnection settings
+        let mqtt_options = settings.options("input");
+        let health_check = settings.health_check("input");
+        crate::health::report(&health_check, false, "connecting");
 
         // Create the MQTT client
         let (client, mut eventloop) = AsyncClient::new(mqtt_options, 10);
 
-        // Create a channel for message passing
-        let (message_tx, message_rx) = mpsc::channel(100);
-
         // Create a shutdown channel that is Send
-        let (shutdown_tx, mut shutdown_rx) = tokio::sync::broadcast::channel::<()>(1);
-        let shutdown_tx_clone = shutdown_tx.clone();
-
-        // Subscribe to the input topic
-        match client.subscribe(&topic, QoS::AtLeastOnce).await {
-            Ok(_) => tracing::info!("Successfully subscribed to topic: {}", topic),
-            Err(e) => tracing::error!("Failed to subscribe to topic {}: {}", topic, e),
-        }
+        let (shutdown_tx, mut shutdown_rx) = broadcast::channel::<()>(1);
+        let subscribers: Arc<StdMutex<Vec<Subscriber>>> = Arc::default();
 
         // Start the event loop in a separate task
-        let topic_clone = topic.clone();
         let client_clone = client.clone();
+        let subscribers_clone = subscribers.clone();
         tokio::spawn(async move {
             let mut consecutive_errors = 0;
 
@@ -85,15 +541,42 @@ impl MqttSource {
                                 // Reset error counter on successful message
                                 consecutive_errors = 0;
 
-                                if let Ok(message_str) = std::str::from_utf8(&publish.payload) {
-                                    match serde_json::from_str::<MqttMessage>(message_str) {
-                                        Ok(mqtt_message) => {
-                                            if mqtt_message.role == "user" {
-                                                if message_tx.send(mqtt_message.content).await.is_err() {
-                                                    error!("Failed to send message to channel");
-                                                }
+                                // Hand the message to every source subscribed to its topic,
+                                // forgetting the ones that have gone away
+                                let topic = String::from_utf8_lossy(&publish.topic).to_string();
+                                let targets: Vec<_> = {
+                                    let mut subscribers = subscribers_clone.lock().unwrap();
+                                    subscribers.retain(|subscriber| !subscriber.messages.is_closed());
+                                    subscribers
+                                        .iter()
+                                        .filter(|subscriber| topic_matches(&topic, &subscriber.topic))
+                                        .map(|subscriber| (subscriber.format.clone(), subscriber.messages.clone()))
+                                        .collect()
+                                };
+                                for (format, messages) in targets {
+                                    match format.parse(&publish.payload) {
+                                        Ok(Some((prompt, sender))) => {
+                                            let mut message = InboundMessage::new(prompt).with_sender(sender);
+                                            // Questions from other agents count the agents they passed through
+                                            message.hops = publish
+                                                .properties
+                                                .as_ref()
+                                                .and_then(|p| p.user_properties.iter().find(|(key, _)| key == HOPS_PROPERTY))
+                                                .and_then(|(_, hops)| hops.parse().ok())
+                                                .unwrap_or(0);
+                                            // Requests with a response topic are answered there
+                                            message.response_topic = publish.properties.as_ref().and_then(|p| {
+                                                Some(ResponseTopic {
+                                                    broker: broker_name.clone(),
+                                                    topic: p.response_topic.clone()?,
+                                                    correlation_data: p.correlation_data.as_ref().map(|d| d.to_vec()),
+                                                })
+                                            });
+                                            if messages.send(message).await.is_err() {
+                                                error!("Failed to send message to channel");
                                             }
                                         },
+                                        Ok(None) => {},
                                         Err(e) => {
                                             error!("Failed to parse MQTT message: {}", e);
                                         }
@@ -101,15 +584,25 @@ impl MqttSource {
                                 }
                             },
                             Ok(Event::Incoming(Packet::ConnAck(_))) => {
-                                tracing::info!("MQTT connection established, subscribing to topic: {}", topic_clone);
+                                crate::health::report(&health_check, true, "connected");
                                 // Resubscribe after reconnection
-                                if let Err(e) = client_clone.subscribe(&topic_clone, QoS::AtLeastOnce).await {
-                                    error!("Failed to resubscribe to topic {}: {}", topic_clone, e);
+                                let topics: Vec<_> = subscribers_clone
+                                    .lock()
+                                    .unwrap()
+                                    .iter()
+                                    .map(|subscriber| (subscriber.topic.clone(), subscriber.qos))
+                                    .collect();
+                                for (topic, qos) in topics {
+                                    tracing::info!("MQTT connection established, subscribing to topic: {}", topic);
+                                    if let Err(e) = client_clone.subscribe(&topic, qos).await {
+                                        error!("Failed to resubscribe to topic {}: {}", topic, e);
+                                    }
                                 }
                             },
                             Ok(_) => {},
                             Err(e) => {
                                 consecutive_errors += 1;
+                                crate::health::report(&health_check, false, e.to_string());
                                 error!("MQTT input error (attempt {}): {}", consecutive_errors, e);
 
                                 // Exponential backoff with maximum delay
@@ -133,9 +626,58 @@ impl MqttSource {
             tracing::info!("MQTT input client task completed");
         });
 
+        Self {
+            client,
+            subscribers,
+            _shutdown_tx: shutdown_tx,
+        }
+    }
+}
+
+// MQTT input source implementation
+pub struct MqttSource {
+    name: String,
+    message_rx: mpsc::Receiver<InboundMessage>,
+    connection: Arc<InputConnection>,
+    // Where rejections go, as the agent's responses would
+    output_topic: String,
+    output_qos: QoS,
+    output_format: OutputFormat,
+    agent_name: String,
+}
+
+impl MqttSource {
+    pub async fn new(settings: &MqttSettings) -> Result<Self> {
+        let topic = settings.input_topic.clone();
+        let qos = settings.input_qos;
+
+        // Create a channel for message passing
+        let (message_tx, message_rx) = mpsc::channel(100);
+
+        let connection = shared(&INPUT_CONNECTIONS, settings.connection_key("input"), || {
+            InputConnection::connect(settings)
+        });
+        connection.subscribers.lock().unwrap().push(Subscriber {
+            topic: topic.clone(),
+            qos,
+            format: settings.input_format.clone(),
+            messages: message_tx,
+        });
+
+        // Subscribe to the input topic
+        match connection.client.subscribe(&topic, qos).await {
+            Ok(_) => tracing::info!("Successfully subscribed to topic: {}", topic),
+            Err(e) => tracing::error!("Failed to subscribe to topic {}: {}", topic, e),
+        }
+
         Ok(Self {
+            name: settings.io_name(),
             message_rx,
-            _shutdown_tx: shutdown_tx_clone, // Store sender to keep it alive
+            connection,
+            output_topic: settings.output_topic.clone(),
+            output_qos: settings.output_qos,
+            output_format: settings.output_format.clone(),
+            agent_name: settings.agent_name.clone(),
         })
     }
 }
@@ -143,60 +685,121 @@ impl MqttSource {
 #[async_trait]
 impl InputSource for MqttSource {
     fn name(&self) -> &str {
-        "mqtt"
+        &self.name
     }
 
-    async fn read_message(&mut self) -> Result<Option<String>> {
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
         match self.message_rx.recv().await {
             Some(message) => Ok(Some(message)),
             None => Ok(None),
         }
     }
+
+    async fn reject(&mut self, _message: &InboundMessage, reason: &str) -> Result<()> {
+        let payload = self
+            .output_format
+            .render(&self.agent_name, "error", reason)?;
+        self.connection
+            .client
+            .publish(&self.output_topic, self.output_qos, false, payload)
+            .await?;
+        Ok(())
+    }
+}
+
+// A streamed chunk, or the end marker with `done` set and no delta
+#[derive(Serialize)]
+struct MqttChunk<'a> {
+    message_id: &'a str,
+    seq: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta: Option<&'a str>,
+    done: bool,
+    timestamp: u64,
+}
+
+// The message currently being streamed
+struct StreamState {
+    message_id: String,
+    next_seq: u64,
 }
 
 // MQTT output destination implementation
 pub struct MqttDestination {
-    client: AsyncClient,
+    name: String,
+    connection: Arc<OutputConnection>,
     topic: String,
-    _shutdown_tx: tokio::sync::broadcast::Sender<()>, // Keep sender alive
+    qos: QoS,
+    retain: bool,
+    output_format: OutputFormat,
+    agent_name: String,
+    broker_name: Option<String>,
+    stream_topic: Option<String>,
+    stream: StdMutex<Option<StreamState>>,
+    user_properties: Vec<(String, String)>,
+    // The response topic of the request being answered, until its answer is sent
+    response_topic: StdMutex<Option<ResponseTopic>>,
 }
 
-impl MqttDestination {
-    pub async fn new(
-        topic: Option<String>,
-        broker: Option<String>,
-        port: Option<u16>,
-        agent_name: Option<String>,
-    ) -> Result<Self> {
-        let agent_name = agent_name.unwrap_or_else(|| "agent".to_string());
-        let default_topic = format!("agent/{}/output", agent_name);
-        let topic = topic.unwrap_or_else(|| default_topic);
-        let broker = broker.unwrap_or_else(|| "localhost".to_string());
-        let port = port.unwrap_or(1883);
-
-        // Generate a random client ID outside the async block
-        let random_suffix: u16 = {
-            let mut rng = rand::thread_rng();
-            rng.gen()
-        };
-        let client_id = format!("{}-mqtt-output-{}", agent_name, random_suffix);
+struct OutputConnection {
+    queue: Arc<OutboundQueue>,
+    client: AsyncClient,
+    // The event loop task, until the connection is closed
+    event_loop: tokio::sync::Mutex<Option<tokio::task::JoinHandle<()>>>,
+    _shutdown_tx: broadcast::Sender<()>, // Ends the event loop when dropped
+}
 
+impl OutputConnection {
+    fn connect(settings: &MqttSettings) -> Self {
         // Create MQTT options with reconnection settings
-        let mut mqtt_options = MqttOptions::new(&client_id, &broker, port);
-        mqtt_options.set_keep_alive(std::time::Duration::from_secs(30));
-        mqtt_options.set_clean_session(true);
-
-        // Set manual reconnection parameters - we'll handle reconnection in the event loop
+        let mqtt_options = settings.options("output");
+        let health_check = settings.health_check("output");
+        crate::health::report(&health_check, false, "connecting");
 
         // Create the MQTT client
         let (client, mut eventloop) = AsyncClient::new(mqtt_options, 10);
 
         // Create a shutdown channel that is Send
-        let (shutdown_tx, mut shutdown_rx) = tokio::sync::broadcast::channel::<()>(1);
-        let shutdown_tx_clone = shutdown_tx.clone();
+        let (shutdown_tx, mut shutdown_rx) = broadcast::channel::<()>(1);
 
-        // Start the event loop in a separate task
+        let queue = Arc::new(OutboundQueue {
+            items: StdMutex::new(VecDeque::new()),
+            capacity: settings.queue_size,
+            policy: settings.queue_policy,
+            unacked: AtomicUsize::new(0),
+            changed: Notify::new(),
+        });
+        let (connected_tx, mut connected_rx) = watch::channel(false);
+
+        // Hand queued messages to the client only while connected, so they wait in the
+        // queue through an outage; the task ends with the event loop task
+        let publisher_queue = queue.clone();
+        let publisher = client.clone();
         tokio::spawn(async move {
+            while connected_rx.wait_for(|connected| *connected).await.is_ok() {
+                let message = publisher_queue.pop().await;
+                if let Err(e) = publisher
+                    .publish_with_properties(
+                        message.topic,
+                        message.qos,
+                        message.retain,
+                        message.payload,
+                        message.properties,
+                    )
+                    .await
+                {
+                    error!("Failed to publish MQTT message: {}", e);
+                    publisher_queue.delivered();
+                    break;
+                }
+            }
+        });
+
+        // Start the event loop in a separate task
+        let delivery_queue = queue.clone();
+        let event_loop = tokio::spawn(async move {
             let mut consecutive_errors = 0;
 
             loop {
@@ -211,11 +814,24 @@ impl MqttDestination {
                         match event {
                             Ok(Event::Incoming(Packet::ConnAck(_))) => {
                                 tracing::info!("MQTT output connection established");
+                                crate::health::report(&health_check, true, "connected");
                                 consecutive_errors = 0;
+                                let _ = connected_tx.send(true);
+                            },
+                            // QoS 0 messages are done once sent, others once acknowledged
+                            Ok(Event::Outgoing(OutgoingEvent::Publish(0)))
+                            | Ok(Event::Incoming(Packet::PubAck(_)))
+                            | Ok(Event::Incoming(Packet::PubComp(_))) => delivery_queue.delivered(),
+                            // Sent by `close` once everything is delivered
+                            Ok(Event::Outgoing(OutgoingEvent::Disconnect)) => {
+                                tracing::info!("MQTT output client disconnected");
+                                break;
                             },
                             Ok(_) => {},
                             Err(e) => {
                                 consecutive_errors += 1;
+                                let _ = connected_tx.send(false);
+                                crate::health::report(&health_check, false, e.to_string());
                                 error!("MQTT output error (attempt {}): {}", consecutive_errors, e);
 
                                 // Exponential backoff with maximum delay
@@ -239,10 +855,53 @@ impl MqttDestination {
             tracing::info!("MQTT output client task completed");
         });
 
-        Ok(Self {
+        Self {
+            queue,
             client,
-            topic,
-            _shutdown_tx: shutdown_tx_clone, // Store sender to keep it alive
+            event_loop: tokio::sync::Mutex::new(Some(event_loop)),
+            _shutdown_tx: shutdown_tx,
+        }
+    }
+
+    // Wait for queued messages to be delivered, then disconnect from the broker.
+    // Destinations sharing the connection all close it; the first one disconnects.
+    async fn close(&self) -> Result<()> {
+        self.queue.drained().await;
+        let mut event_loop = self.event_loop.lock().await;
+        if let Some(task) = event_loop.take() {
+            self.client
+                .disconnect()
+                .await
+                .context("Failed to disconnect from MQTT broker")?;
+            let _ = task.await;
+        }
+        Ok(())
+    }
+}
+
+impl MqttDestination {
+    pub async fn new(settings: &MqttSettings) -> Result<Self> {
+        let connection = shared(
+            &OUTPUT_CONNECTIONS,
+            settings.connection_key("output"),
+            || OutputConnection::connect(settings),
+        );
+        Ok(Self {
+            name: settings.io_name(),
+            connection,
+            topic: settings.output_topic.clone(),
+            qos: settings.output_qos,
+            retain: settings.retain,
+            output_format: settings.output_format.clone(),
+            agent_name: settings.agent_name.clone(),
+            broker_name: settings.name.clone(),
+            stream_topic: settings.stream_topic.clone(),
+            stream: StdMutex::new(None),
+            response_topic: StdMutex::new(None),
+            user_properties: vec![
+                ("session_id".to_string(), uuid::Uuid::new_v4().to_string()),
+                ("model".to_string(), settings.model.clone()),
+            ],
         })
     }
 }
@@ -250,25 +909,423 @@ impl MqttDestination {
 #[async_trait]
 impl OutputDestination for MqttDestination {
     fn name(&self) -> &str {
-        "mqtt"
+        &self.name
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        &["assistant", "image"]
+    }
+
+    fn answering(&self, message: &InboundMessage) {
+        *self.response_topic.lock().unwrap() = message
+            .response_topic
+            .clone()
+            .filter(|response_topic| response_topic.broker == self.broker_name);
     }
 
     async fn write_message(&self, role: &str, content: &str) -> Result<()> {
-        if role == "assistant" {
-            let message = MqttMessage {
-                role: role.to_string(),
-                content: content.to_string(),
-                timestamp: std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_secs(),
-            };
+        let payload = self.output_format.render(&self.agent_name, role, content)?;
+        let mut properties = PublishProperties {
+            user_properties: self.user_properties.clone(),
+            ..Default::default()
+        };
 
-            let json = serde_json::to_string(&message)?;
-            self.client
-                .publish(&self.topic, QoS::AtLeastOnce, false, json)
-                .await?;
+        // Answer the request from this broker on its response topic; images go out
+        // with it but only the assistant message completes the request, and other
+        // roles go to the output topic
+        let reply_to = {
+            let mut response_topic = self.response_topic.lock().unwrap();
+            match role {
+                "assistant" => response_topic.take(),
+                "image" => response_topic.clone(),
+                _ => None,
+            }
+        };
+        // Replies are meant for one requester, so they are never retained
+        let (topic, retain) = match reply_to {
+            Some(reply_to) => {
+                properties.correlation_data = reply_to.correlation_data.map(Into::into);
+                (reply_to.topic, false)
+            }
+            None => (self.topic.clone(), self.retain),
+        };
+
+        self.connection
+            .queue
+            .push(Outgoing {
+                topic,
+                qos: self.qos,
+                retain,
+                payload: payload.into_bytes(),
+                properties,
+            })
+            .await;
+        Ok(())
+    }
+
+    async fn write_chunk(&self, role: &str, delta: &str) -> Result<()> {
+        let Some(topic) = &self.stream_topic else {
+            return Ok(());
+        };
+        if role != "assistant" {
+            return Ok(());
         }
+
+        // The first chunk starts a new message; the rest carry on its sequence
+        let (message_id, seq) = {
+            let mut stream = self.stream.lock().unwrap();
+            let state = stream.get_or_insert_with(|| StreamState {
+                message_id: uuid::Uuid::new_v4().to_string(),
+                next_seq: 0,
+            });
+            state.next_seq += 1;
+            (state.message_id.clone(), state.next_seq - 1)
+        };
+        self.publish_chunk(topic, &message_id, seq, Some(role), Some(delta))
+            .await
+    }
+
+    async fn end_message(&self) -> Result<()> {
+        let Some(topic) = &self.stream_topic else {
+            return Ok(());
+        };
+        let Some(state) = self.stream.lock().unwrap().take() else {
+            return Ok(());
+        };
+        self.publish_chunk(topic, &state.message_id, state.next_seq, None, None)
+            .await
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.connection.close().await
+    }
+}
+
+impl MqttDestination {
+    // Publish a chunk, or the end marker when there is no delta
+    async fn publish_chunk(
+        &self,
+        topic: &str,
+        message_id: &str,
+        seq: u64,
+        role: Option<&str>,
+        delta: Option<&str>,
+    ) -> Result<()> {
+        let chunk = MqttChunk {
+            message_id,
+            seq,
+            role,
+            delta,
+            done: delta.is_none(),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        let properties = PublishProperties {
+            user_properties: self.user_properties.clone(),
+            ..Default::default()
+        };
+
+        // Chunks only make sense while the message is being written, so they are
+        // never retained
+        self.connection
+            .queue
+            .push(Outgoing {
+                topic: topic.to_string(),
+                qos: self.qos,
+                retain: false,
+                payload: serde_json::to_vec(&chunk)?,
+                properties,
+            })
+            .await;
         Ok(())
     }
 }
+
+/// A runtime administration command received on the MQTT control topic
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlCommand {
+    ClearHistory,
+    SetModel(String),
+    SetProvider {
+        provider: String,
+        model: Option<String>,
+    },
+    ReloadConfig,
+    Pause,
+    Resume,
+    Status,
+}
+
+impl ControlCommand {
+    /// The command's name as it is written on the control topic
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ClearHistory => "clear_history",
+            Self::SetModel(_) => "set_model",
+            Self::SetProvider { .. } => "set_provider",
+            Self::ReloadConfig => "reload_config",
+            Self::Pause => "pause",
+            Self::Resume => "resume",
+            Self::Status => "status",
+        }
+    }
+
+    // Parse `{"command": "set_model", "model": "gpt-4o"}` or plain `set_model gpt-4o`,
+    // and `set_provider anthropic [model]` or its JSON with "provider" and "model"
+    fn parse(payload: &[u8]) -> Result<Self> {
+        let text = std::str::from_utf8(payload)?.trim();
+        let (name, provider, model) = match serde_json::from_str::<serde_json::Value>(text) {
+            Ok(value) => (
+                value["command"]
+                    .as_str()
+                    .context("Control message has no command")?
+                    .to_string(),
+                value["provider"].as_str().map(|s| s.to_string()),
+                value["model"].as_str().map(|s| s.to_string()),
+            ),
+            Err(_) => {
+                let mut words = text.split_whitespace().map(|s| s.to_string());
+                let name = words.next().unwrap_or_default();
+                let first = words.next();
+                if name == "set_provider" {
+                    (name, first, words.next())
+                } else {
+                    (name, None, first)
+                }
+            }
+        };
+        let model = model.filter(|model| !model.is_empty());
+
+        match name.as_str() {
+            "clear_history" => Ok(Self::ClearHistory),
+            "set_model" => Ok(Self::SetModel(model.context("set_model requires a model")?)),
+            "set_provider" => Ok(Self::SetProvider {
+                provider: provider
+                    .filter(|provider| !provider.is_empty())
+                    .context("set_provider requires a provider")?,
+                model,
+            }),
+            "reload_config" => Ok(Self::ReloadConfig),
+            "pause" => Ok(Self::Pause),
+            "resume" => Ok(Self::Resume),
+            "status" => Ok(Self::Status),
+            other => anyhow::bail!("Unknown control command: {}", other),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ControlStatus<'a> {
+    command: &'a str,
+    ok: bool,
+    message: &'a str,
+    timestamp: u64,
+}
+
+/// MQTT client for the control topic, acknowledging commands on the status topic
+pub struct MqttControl {
+    client: AsyncClient,
+    status_topic: String,
+    _shutdown_tx: tokio::sync::broadcast::Sender<()>, // Keep sender alive
+}
+
+impl MqttControl {
+    /// Subscribe to the control topic, returning the client and the commands it receives
+    pub async fn new(settings: &MqttSettings) -> Result<(Self, mpsc::Receiver<ControlCommand>)> {
+        let (client, mut eventloop) = AsyncClient::new(settings.options("control"), 10);
+        let health_check = settings.health_check("control");
+        crate::health::report(&health_check, false, "connecting");
+        let (command_tx, command_rx) = mpsc::channel(10);
+        let (shutdown_tx, mut shutdown_rx) = tokio::sync::broadcast::channel::<()>(1);
+
+        let topic = settings.control_topic.clone();
+        let status_topic = settings.status_topic.clone();
+        let client_clone = client.clone();
+        let status_topic_clone = status_topic.clone();
+        tokio::spawn(async move {
+            let mut consecutive_errors = 0;
+
+            loop {
+                tokio::select! {
+                    _ = shutdown_rx.recv() => break,
+                    event = eventloop.poll() => {
+                        match event {
+                            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                                consecutive_errors = 0;
+                                match ControlCommand::parse(&publish.payload) {
+                                    Ok(command) => {
+                                        tracing::info!("Received control command: {}", command.name());
+                                        if command_tx.send(command).await.is_err() {
+                                            break;
+                                        }
+                                    }
+                                    // Tell the sender, since nothing else will answer
+                                    Err(e) => {
+                                        error!("Invalid control command: {:#}", e);
+                                        let status = control_status("unknown", false, &format!("{:#}", e));
+                                        let _ = client_clone
+                                            .publish(&status_topic_clone, QoS::AtLeastOnce, false, status)
+                                            .await;
+                                    }
+                                }
+                            }
+                            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                                tracing::info!("MQTT control connection established, subscribing to topic: {}", topic);
+                                crate::health::report(&health_check, true, "connected");
+                                consecutive_errors = 0;
+                                if let Err(e) = client_clone.subscribe(&topic, QoS::AtLeastOnce).await {
+                                    error!("Failed to subscribe to control topic {}: {}", topic, e);
+                                }
+                            }
+                            Ok(_) => {}
+                            Err(e) => {
+                                consecutive_errors += 1;
+                                crate::health::report(&health_check, false, e.to_string());
+                                error!("MQTT control error (attempt {}): {}", consecutive_errors, e);
+
+                                // Exponential backoff with maximum delay
+                                let delay = std::cmp::min(
+                                    std::time::Duration::from_millis(100 * 2u64.pow(consecutive_errors.min(10))),
+                                    std::time::Duration::from_secs(30)
+                                );
+                                tokio::time::sleep(delay).await;
+                            }
+                        }
+                    }
+                }
+            }
+
+            tracing::info!("MQTT control client task completed");
+        });
+
+        Ok((
+            Self {
+                client,
+                status_topic,
+                _shutdown_tx: shutdown_tx,
+            },
+            command_rx,
+        ))
+    }
+
+    /// Publish the outcome of a command to the status topic
+    pub async fn acknowledge(&self, command: &ControlCommand, result: &Result<String>) {
+        let status = match result {
+            Ok(message) => control_status(command.name(), true, message),
+            Err(e) => control_status(command.name(), false, &format!("{:#}", e)),
+        };
+        if let Err(e) = self
+            .client
+            .publish(&self.status_topic, QoS::AtLeastOnce, false, status)
+            .await
+        {
+            error!("Failed to publish control status: {}", e);
+        }
+    }
+}
+
+fn control_status(command: &str, ok: bool, message: &str) -> String {
+    serde_json::to_string(&ControlStatus {
+        command,
+        ok,
+        message,
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    })
+    .unwrap_or_default()
+}
+
+/// Put `question` to the agent named `peer` on its input topic, `agent/<peer>/input`,
+/// and wait up to `timeout` for the answer on a response topic of this agent's.
+/// `session` names the conversation the peer keeps with this one, and `hops` counts
+/// the agents the question has passed through, this one included
+pub async fn ask_agent(
+    settings: &MqttSettings,
+    peer: &str,
+    question: &str,
+    session: &str,
+    hops: u32,
+    timeout: std::time::Duration,
+) -> Result<String> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let response_topic = format!("agent/{}/replies/{}", settings.agent_name, id);
+
+    // A client of its own, so the answer isn't taken for a prompt by the input
+    let mut options = MqttOptions::new(
+        format!("{}-ask-{}", settings.agent_name, &id[..8]),
+        &settings.broker,
+        settings.port,
+    );
+    options.set_keep_alive(std::time::Duration::from_secs(30));
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
+    client.subscribe(&response_topic, QoS::AtLeastOnce).await?;
+
+    let payload = serde_json::to_string(&MqttMessage {
+        role: "user".to_string(),
+        content: question.to_string(),
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        sender: None,
+        session_id: Some(session.to_string()),
+    })?;
+    let properties = PublishProperties {
+        response_topic: Some(response_topic.clone()),
+        correlation_data: Some(id.clone().into()),
+        user_properties: vec![(HOPS_PROPERTY.to_string(), hops.to_string())],
+        ..Default::default()
+    };
+
+    let exchange = async {
+        loop {
+            match eventloop.poll().await? {
+                // Ask once the answer can't be missed
+                Event::Incoming(Packet::SubAck(_)) => {
+                    client
+                        .publish_with_properties(
+                            format!("agent/{}/input", peer),
+                            QoS::AtLeastOnce,
+                            false,
+                            payload.clone(),
+                            properties.clone(),
+                        )
+                        .await?;
+                }
+                Event::Incoming(Packet::Publish(publish))
+                    if String::from_utf8_lossy(&publish.topic) == response_topic =>
+                {
+                    let text = String::from_utf8_lossy(&publish.payload).to_string();
+                    // Answers in the MQTT message format are unwrapped
+                    return Ok(match serde_json::from_str::<MqttMessage>(&text) {
+                        Ok(message) => message.content,
+                        Err(_) => text,
+                    });
+                }
+                _ => {}
+            }
+        }
+    };
+    let answer = tokio::time::timeout(timeout, exchange).await;
+
+    // Let the disconnect go out before dropping the client
+    if client.disconnect().await.is_ok() {
+        let disconnected = async {
+            while let Ok(event) = eventloop.poll().await {
+                if matches!(event, Event::Outgoing(OutgoingEvent::Disconnect)) {
+                    break;
+                }
+            }
+        };
+        let _ = tokio::time::timeout(std::time::Duration::from_secs(1), disconnected).await;
+    }
+
+    match answer {
+        Ok(answer) => answer,
+        Err(_) => anyhow::bail!("{} didn't answer within {}s", peer, timeout.as_secs()),
+    }
+}
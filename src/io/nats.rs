@@ -0,0 +1,220 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex as StdMutex;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+use super::shared::Shared;
+use super::{InboundMessage, InputSource, OutputDestination};
+
+// One connection per server URL and input subject, so per agent by default, is
+// shared by the source and destination so replies can be routed to the subject the
+// last request asked for
+static CONNECTIONS: Shared<NatsConnection> = Shared::new();
+
+/// Settings for connecting to a NATS server
+#[derive(Debug, Clone)]
+pub struct NatsSettings {
+    pub url: String,
+    pub credentials_file: Option<String>,
+    pub input_subject: String,
+    pub output_subject: String,
+    pub queue_group: Option<String>,
+}
+
+impl NatsSettings {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self {
+            url: config
+                .nats_url
+                .clone()
+                .unwrap_or_else(|| "nats://localhost:4222".to_string()),
+            credentials_file: config.nats_credentials_file.clone(),
+            input_subject: config
+                .nats_input_subject
+                .clone()
+                .unwrap_or_else(|| format!("agent.{}.input", config.agent_name)),
+            output_subject: config
+                .nats_output_subject
+                .clone()
+                .unwrap_or_else(|| format!("agent.{}.output", config.agent_name)),
+            queue_group: config.nats_queue_group.clone(),
+        }
+    }
+}
+
+// NATS message format, the same as MQTT
+#[derive(Serialize, Deserialize)]
+struct NatsMessage {
+    role: String,
+    content: String,
+    timestamp: u64,
+}
+
+struct NatsConnection {
+    client: async_nats::Client,
+    // Reply subject of the most recent request, answered by the next assistant message
+    pending_reply: StdMutex<Option<async_nats::Subject>>,
+}
+
+async fn connect(settings: &NatsSettings) -> Result<&'static NatsConnection> {
+    CONNECTIONS
+        .get_or_try_init(
+            &format!("{} {}", settings.url, settings.input_subject),
+            || async {
+                let mut options = async_nats::ConnectOptions::new();
+                if let Some(path) = &settings.credentials_file {
+                    options = options
+                        .credentials_file(path)
+                        .await
+                        .context(format!("Failed to read NATS credentials: {}", path))?;
+                }
+
+                let client = options
+                    .connect(settings.url.as_str())
+                    .await
+                    .context(format!("Failed to connect to NATS server {}", settings.url))?;
+                info!("Connected to NATS server {}", settings.url);
+
+                Ok(NatsConnection {
+                    client,
+                    pending_reply: StdMutex::new(None),
+                })
+            },
+        )
+        .await
+}
+
+// Accept both the JSON message format and plain text
+fn parse_payload(payload: &[u8]) -> Option<String> {
+    let text = std::str::from_utf8(payload).ok()?;
+    match serde_json::from_str::<NatsMessage>(text) {
+        Ok(message) if message.role == "user" => Some(message.content),
+        Ok(_) => None,
+        Err(_) => Some(text.trim().to_string()).filter(|text| !text.is_empty()),
+    }
+}
+
+// NATS input source implementation
+pub struct NatsSource {
+    message_rx: mpsc::Receiver<String>,
+}
+
+impl NatsSource {
+    pub async fn new(settings: &NatsSettings) -> Result<Self> {
+        let connection = connect(settings).await?;
+
+        // A queue group spreads requests across several agents instead of fanning out
+        let subject = settings.input_subject.clone();
+        let mut subscriber = match &settings.queue_group {
+            Some(group) => {
+                connection
+                    .client
+                    .queue_subscribe(subject.clone(), group.clone())
+                    .await
+            }
+            None => connection.client.subscribe(subject.clone()).await,
+        }
+        .context(format!("Failed to subscribe to NATS subject {}", subject))?;
+        info!("Subscribed to NATS subject: {}", subject);
+
+        let (message_tx, message_rx) = mpsc::channel(100);
+        tokio::spawn(async move {
+            while let Some(message) = subscriber.next().await {
+                let Some(content) = parse_payload(&message.payload) else {
+                    error!("Ignoring NATS message on {}", message.subject);
+                    continue;
+                };
+
+                if let Some(reply) = message.reply {
+                    *connection.pending_reply.lock().unwrap() = Some(reply);
+                }
+                if message_tx.send(content).await.is_err() {
+                    error!("Failed to send message to channel");
+                    break;
+                }
+            }
+
+            tracing::info!("NATS input task completed");
+        });
+
+        Ok(Self { message_rx })
+    }
+}
+
+#[async_trait]
+impl InputSource for NatsSource {
+    fn name(&self) -> &str {
+        "nats"
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        match self.message_rx.recv().await {
+            Some(message) => Ok(Some(InboundMessage::new(message))),
+            None => Ok(None),
+        }
+    }
+}
+
+// NATS output destination implementation
+pub struct NatsDestination {
+    connection: &'static NatsConnection,
+    subject: String,
+}
+
+impl NatsDestination {
+    pub async fn new(settings: &NatsSettings) -> Result<Self> {
+        let connection = connect(settings).await?;
+        Ok(Self {
+            connection,
+            subject: settings.output_subject.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl OutputDestination for NatsDestination {
+    fn name(&self) -> &str {
+        "nats"
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        &["assistant", "image"]
+    }
+
+    async fn write_message(&self, role: &str, content: &str) -> Result<()> {
+        let message = NatsMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        let json = serde_json::to_string(&message)?;
+
+        // Answer a pending request on its reply subject instead of broadcasting
+        let reply = if role == "assistant" {
+            self.connection.pending_reply.lock().unwrap().take()
+        } else {
+            None
+        };
+        match reply {
+            Some(reply) => self.connection.client.publish(reply, json.into()).await?,
+            None => {
+                self.connection
+                    .client
+                    .publish(self.subject.clone(), json.into())
+                    .await?
+            }
+        }
+        Ok(())
+    }
+    // Publishes are buffered by the client until it flushes them
+    async fn close(&self) -> Result<()> {
+        self.connection.client.flush().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use nostr_sdk::prelude::*;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{debug, error, info};
+
+use super::shared::Shared;
+use super::{InboundMessage, InputSource, OutputDestination};
+
+// One relay pool per key is shared by the source and destination, so replies go out with
+// the same keys and relays the messages came in on
+static CONNECTIONS: Shared<NostrConnection> = Shared::new();
+
+/// Settings for connecting to Nostr relays
+#[derive(Debug, Clone)]
+pub struct NostrSettings {
+    pub secret_key: String,
+    pub relays: Vec<String>,
+    pub allowed_pubkeys: Vec<String>,
+}
+
+impl NostrSettings {
+    /// Build settings from the config, reading the key from `NOSTR_SECRET_KEY` if unset
+    pub fn from_config(config: &crate::config::Config) -> Result<Self> {
+        let secret_key = match &config.nostr_secret_key {
+            Some(key) => key.clone(),
+            None => std::env::var("NOSTR_SECRET_KEY")
+                .context("NOSTR_SECRET_KEY environment variable not set")?,
+        };
+        if config.nostr_relays.is_empty() {
+            anyhow::bail!("Nostr IO requires at least one entry in nostr_relays");
+        }
+
+        Ok(Self {
+            secret_key,
+            relays: config.nostr_relays.clone(),
+            allowed_pubkeys: config.nostr_allowed_pubkeys.clone(),
+        })
+    }
+}
+
+// Which DM scheme a message used, so the reply uses the same one
+#[derive(Debug, Clone, Copy)]
+enum Protocol {
+    // Kind 4 messages encrypted with NIP-04
+    Nip04,
+    // Gift-wrapped kind 14 messages from NIP-17
+    Nip17,
+}
+
+#[derive(Debug, Clone)]
+struct ReplyTarget {
+    pubkey: PublicKey,
+    protocol: Protocol,
+}
+
+// The input's channel, with each message's sender, once there is an input
+type Inbound = Arc<StdMutex<Option<mpsc::Sender<(String, Option<String>)>>>>;
+
+struct NostrConnection {
+    client: Client,
+    keys: Keys,
+    // Set once a Nostr input exists; until then messages are dropped
+    inbound: Inbound,
+    last_target: Arc<StdMutex<Option<ReplyTarget>>>,
+}
+
+// Connect to the relays and subscribe to DMs, once per key
+async fn connect(settings: &NostrSettings) -> Result<&'static NostrConnection> {
+    CONNECTIONS
+        .get_or_try_init(&settings.secret_key, || async {
+            let keys = Keys::parse(&settings.secret_key).context("Invalid Nostr secret key")?;
+            let mut allowed = Vec::new();
+            for pubkey in &settings.allowed_pubkeys {
+                allowed.push(
+                    PublicKey::parse(pubkey)
+                        .context(format!("Invalid Nostr public key: {}", pubkey))?,
+                );
+            }
+
+            let client = Client::new(keys.clone());
+            for relay in &settings.relays {
+                client
+                    .add_relay(relay)
+                    .await
+                    .context(format!("Invalid Nostr relay: {}", relay))?;
+            }
+            client.connect().await;
+            info!(
+                "Connected to Nostr relays as {}",
+                keys.public_key().to_bech32()?
+            );
+
+            // Gift wraps carry a randomized timestamp up to two days in the past, so
+            // they are filtered by the real time of the message inside instead
+            let started = Timestamp::now();
+            let nip04 = Filter::new()
+                .kind(Kind::EncryptedDirectMessage)
+                .pubkey(keys.public_key())
+                .since(started);
+            let nip17 = Filter::new()
+                .kind(Kind::GiftWrap)
+                .pubkey(keys.public_key())
+                .since(started - Duration::from_secs(2 * 24 * 60 * 60));
+            let notifications = client.notifications();
+            client.subscribe(vec![nip04, nip17], None).await?;
+
+            let connection = NostrConnection {
+                client,
+                keys,
+                inbound: Arc::new(StdMutex::new(None)),
+                last_target: Arc::new(StdMutex::new(None)),
+            };
+
+            tokio::spawn(receive(
+                connection.client.clone(),
+                connection.keys.clone(),
+                notifications,
+                allowed,
+                started,
+                connection.inbound.clone(),
+                connection.last_target.clone(),
+            ));
+
+            Ok(connection)
+        })
+        .await
+}
+
+// Decrypt incoming DMs and pass them to the input
+async fn receive(
+    client: Client,
+    keys: Keys,
+    mut notifications: broadcast::Receiver<RelayPoolNotification>,
+    allowed: Vec<PublicKey>,
+    started: Timestamp,
+    inbound: Inbound,
+    last_target: Arc<StdMutex<Option<ReplyTarget>>>,
+) {
+    loop {
+        let event = match notifications.recv().await {
+            Ok(RelayPoolNotification::Event { event, .. }) => event,
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                error!("Missed {} Nostr notifications", skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+
+        let message = match event.kind {
+            Kind::EncryptedDirectMessage => {
+                nip04::decrypt(keys.secret_key(), &event.pubkey, &event.content)
+                    .map(|content| (event.pubkey, content, Protocol::Nip04))
+                    .map_err(anyhow::Error::from)
+            }
+            Kind::GiftWrap => match client.unwrap_gift_wrap(&event).await {
+                Ok(UnwrappedGift { sender, rumor })
+                    if rumor.kind == Kind::PrivateDirectMessage && rumor.created_at >= started =>
+                {
+                    Ok((sender, rumor.content, Protocol::Nip17))
+                }
+                Ok(_) => continue,
+                Err(e) => Err(e.into()),
+            },
+            _ => continue,
+        };
+        let (sender, content, protocol) = match message {
+            Ok(message) => message,
+            Err(e) => {
+                error!("Failed to decrypt Nostr message: {}", e);
+                continue;
+            }
+        };
+
+        if !allowed.is_empty() && !allowed.contains(&sender) {
+            debug!("Ignoring Nostr message from {}", sender);
+            continue;
+        }
+        let content = content.trim().to_string();
+        if content.is_empty() {
+            continue;
+        }
+
+        let tx = inbound.lock().unwrap().clone();
+        if let Some(tx) = tx {
+            let user = sender.to_string();
+            *last_target.lock().unwrap() = Some(ReplyTarget {
+                pubkey: sender,
+                protocol,
+            });
+            if tx.send((content, Some(user))).await.is_err() {
+                error!("Failed to send message to channel");
+            }
+        }
+    }
+
+    info!("Nostr notifications ended");
+}
+
+// Nostr input source implementation
+pub struct NostrSource {
+    message_rx: mpsc::Receiver<(String, Option<String>)>,
+}
+
+impl NostrSource {
+    pub async fn new(settings: &NostrSettings) -> Result<Self> {
+        let connection = connect(settings).await?;
+        let (message_tx, message_rx) = mpsc::channel(100);
+        *connection.inbound.lock().unwrap() = Some(message_tx);
+        Ok(Self { message_rx })
+    }
+}
+
+#[async_trait]
+impl InputSource for NostrSource {
+    fn name(&self) -> &str {
+        "nostr"
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        match self.message_rx.recv().await {
+            Some((message, sender)) => Ok(Some(InboundMessage::new(message).with_sender(sender))),
+            None => Ok(None),
+        }
+    }
+}
+
+// Nostr output destination implementation
+pub struct NostrDestination {
+    connection: &'static NostrConnection,
+}
+
+impl NostrDestination {
+    pub async fn new(settings: &NostrSettings) -> Result<Self> {
+        let connection = connect(settings).await?;
+        Ok(Self { connection })
+    }
+}
+
+#[async_trait]
+impl OutputDestination for NostrDestination {
+    fn name(&self) -> &str {
+        "nostr"
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        &["assistant"]
+    }
+
+    async fn write_message(&self, _role: &str, content: &str) -> Result<()> {
+        // DMs need a recipient, so only messages that came from Nostr are answered
+        let Some(target) = self.connection.last_target.lock().unwrap().clone() else {
+            return Ok(());
+        };
+
+        let client = &self.connection.client;
+        match target.protocol {
+            Protocol::Nip04 => {
+                let encrypted =
+                    nip04::encrypt(self.connection.keys.secret_key(), &target.pubkey, content)?;
+                let builder = EventBuilder::new(Kind::EncryptedDirectMessage, encrypted)
+                    .tag(Tag::public_key(target.pubkey));
+                client
+                    .send_event_builder(builder)
+                    .await
+                    .context("Failed to send Nostr DM")?;
+            }
+            Protocol::Nip17 => {
+                client
+                    .send_private_msg(target.pubkey, content, [])
+                    .await
+                    .context("Failed to send Nostr DM")?;
+            }
+        }
+
+        Ok(())
+    }
+}
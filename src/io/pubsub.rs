@@ -0,0 +1,381 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{mpsc, Mutex};
+use tracing::{error, info};
+
+use super::{InboundMessage, InputSource, OutputDestination};
+
+const PUBSUB_SCOPE: &str = "https://www.googleapis.com/auth/pubsub";
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+/// Settings for Google Cloud Pub/Sub
+#[derive(Debug, Clone)]
+pub struct PubsubSettings {
+    pub project: Option<String>,
+    pub subscription: Option<String>,
+    pub topic: Option<String>,
+    // Service account key file; without one, the GCE/GKE/Cloud Run metadata server is used
+    pub credentials_file: Option<String>,
+}
+
+impl PubsubSettings {
+    /// Build settings from the config, falling back to `GOOGLE_APPLICATION_CREDENTIALS` for the key file
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self {
+            project: config.pubsub_project.clone(),
+            subscription: config.pubsub_subscription.clone(),
+            topic: config.pubsub_topic.clone(),
+            credentials_file: config
+                .pubsub_credentials
+                .clone()
+                .or_else(|| std::env::var("GOOGLE_APPLICATION_CREDENTIALS").ok()),
+        }
+    }
+}
+
+// The fields of a service account key file needed to sign token requests
+#[derive(Deserialize)]
+struct ServiceAccountKey {
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+    project_id: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Claims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+enum Credentials {
+    ServiceAccount(ServiceAccountKey),
+    Metadata,
+    // The Pub/Sub emulator accepts requests without a token
+    Emulator,
+}
+
+// REST client for the Pub/Sub API, caching its access token until shortly before expiry
+struct PubsubClient {
+    http: reqwest::Client,
+    base_url: String,
+    project: String,
+    credentials: Credentials,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl PubsubClient {
+    async fn new(settings: &PubsubSettings) -> Result<Self> {
+        let (base_url, credentials) = match std::env::var("PUBSUB_EMULATOR_HOST") {
+            Ok(host) => (format!("http://{}", host), Credentials::Emulator),
+            Err(_) => {
+                let credentials = match &settings.credentials_file {
+                    Some(path) => {
+                        let key = std::fs::read_to_string(path)
+                            .context(format!("Failed to read Pub/Sub credentials: {}", path))?;
+                        Credentials::ServiceAccount(
+                            serde_json::from_str(&key)
+                                .context(format!("Invalid service account key: {}", path))?,
+                        )
+                    }
+                    None => Credentials::Metadata,
+                };
+                ("https://pubsub.googleapis.com".to_string(), credentials)
+            }
+        };
+
+        let project = match (&settings.project, &credentials) {
+            (Some(project), _) => project.clone(),
+            (
+                None,
+                Credentials::ServiceAccount(ServiceAccountKey {
+                    project_id: Some(project),
+                    ..
+                }),
+            ) => project.clone(),
+            _ => anyhow::bail!("Pub/Sub IO requires pubsub_project to be set"),
+        };
+
+        Ok(Self {
+            // Pulls wait on the server for messages, so leave room for that
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(90))
+                .build()?,
+            base_url,
+            project,
+            credentials,
+            token: Mutex::new(None),
+        })
+    }
+
+    // Get a cached access token, or fetch a new one
+    async fn token(&self) -> Result<Option<String>> {
+        let mut token = self.token.lock().await;
+        if let Some((value, expires)) = token.as_ref() {
+            if Instant::now() < *expires {
+                return Ok(Some(value.clone()));
+            }
+        }
+
+        let response: TokenResponse = match &self.credentials {
+            Credentials::Emulator => return Ok(None),
+            Credentials::ServiceAccount(key) => {
+                // Exchange a self-signed JWT for an access token
+                let iat = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)?
+                    .as_secs();
+                let claims = Claims {
+                    iss: &key.client_email,
+                    scope: PUBSUB_SCOPE,
+                    aud: &key.token_uri,
+                    iat,
+                    exp: iat + 3600,
+                };
+                let assertion = jsonwebtoken::encode(
+                    &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256),
+                    &claims,
+                    &jsonwebtoken::EncodingKey::from_rsa_pem(key.private_key.as_bytes())?,
+                )?;
+                self.http
+                    .post(&key.token_uri)
+                    .form(&[
+                        ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                        ("assertion", assertion.as_str()),
+                    ])
+                    .send()
+                    .await?
+                    .error_for_status()
+                    .context("Failed to get a Pub/Sub access token")?
+                    .json()
+                    .await?
+            }
+            Credentials::Metadata => {
+                self.http
+                    .get(METADATA_TOKEN_URL)
+                    .header("Metadata-Flavor", "Google")
+                    .send()
+                    .await
+                    .context("No Pub/Sub credentials configured and no metadata server found")?
+                    .error_for_status()?
+                    .json()
+                    .await?
+            }
+        };
+
+        let expires = Instant::now() + Duration::from_secs(response.expires_in.saturating_sub(60));
+        *token = Some((response.access_token.clone(), expires));
+        Ok(Some(response.access_token))
+    }
+
+    // POST to a Pub/Sub resource method, e.g. `subscriptions/agent:pull`
+    async fn call<T: for<'de> Deserialize<'de>>(
+        &self,
+        path: &str,
+        body: &serde_json::Value,
+    ) -> Result<T> {
+        let url = format!("{}/v1/projects/{}/{}", self.base_url, self.project, path);
+        let mut request = self.http.post(&url).json(body);
+        if let Some(token) = self.token().await? {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            anyhow::bail!(
+                "Pub/Sub request to {} failed: HTTP {}: {}",
+                path,
+                response.status(),
+                response.text().await.unwrap_or_default()
+            );
+        }
+        Ok(response.json().await?)
+    }
+}
+
+// Pub/Sub message format, the same as MQTT
+#[derive(Serialize, Deserialize)]
+struct PubsubMessage {
+    role: String,
+    content: String,
+    timestamp: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PullResponse {
+    #[serde(default)]
+    received_messages: Vec<ReceivedMessage>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReceivedMessage {
+    ack_id: String,
+    message: RawMessage,
+}
+
+#[derive(Deserialize)]
+struct RawMessage {
+    #[serde(default)]
+    data: String,
+}
+
+// Accept both the JSON message format and plain text
+fn parse_payload(data: &str) -> Option<String> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .ok()?;
+    let text = std::str::from_utf8(&bytes).ok()?;
+    match serde_json::from_str::<PubsubMessage>(text) {
+        Ok(message) if message.role == "user" => Some(message.content),
+        Ok(_) => None,
+        Err(_) => Some(text.trim().to_string()).filter(|text| !text.is_empty()),
+    }
+}
+
+// Pub/Sub input source implementation
+pub struct PubsubSource {
+    message_rx: mpsc::Receiver<String>,
+}
+
+impl PubsubSource {
+    /// Pull from the configured subscription, acknowledging messages once queued
+    pub async fn new(settings: &PubsubSettings) -> Result<Self> {
+        let subscription = settings
+            .subscription
+            .clone()
+            .context("Pub/Sub input requires pubsub_subscription to be set")?;
+        let client = PubsubClient::new(settings).await?;
+        info!(
+            "Pulling from Pub/Sub subscription projects/{}/subscriptions/{}",
+            client.project, subscription
+        );
+
+        let (message_tx, message_rx) = mpsc::channel(100);
+        tokio::spawn(async move {
+            let pull = format!("subscriptions/{}:pull", subscription);
+            let acknowledge = format!("subscriptions/{}:acknowledge", subscription);
+
+            loop {
+                let response: PullResponse = match client
+                    .call(&pull, &serde_json::json!({ "maxMessages": 10 }))
+                    .await
+                {
+                    Ok(response) => response,
+                    Err(e) => {
+                        error!("{:#}", e);
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        continue;
+                    }
+                };
+
+                let mut ack_ids = Vec::new();
+                for received in response.received_messages {
+                    if let Some(content) = parse_payload(&received.message.data) {
+                        if message_tx.send(content).await.is_err() {
+                            error!("Failed to send message to channel");
+                            return;
+                        }
+                    }
+                    ack_ids.push(received.ack_id);
+                }
+
+                if !ack_ids.is_empty() {
+                    let result: Result<serde_json::Value> = client
+                        .call(&acknowledge, &serde_json::json!({ "ackIds": ack_ids }))
+                        .await;
+                    if let Err(e) = result {
+                        error!("Failed to acknowledge Pub/Sub messages: {:#}", e);
+                    }
+                }
+            }
+        });
+
+        Ok(Self { message_rx })
+    }
+}
+
+#[async_trait]
+impl InputSource for PubsubSource {
+    fn name(&self) -> &str {
+        "pubsub"
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        match self.message_rx.recv().await {
+            Some(message) => Ok(Some(InboundMessage::new(message))),
+            None => Ok(None),
+        }
+    }
+}
+
+// Pub/Sub output destination implementation
+pub struct PubsubDestination {
+    client: PubsubClient,
+    publish: String,
+}
+
+impl PubsubDestination {
+    pub async fn new(settings: &PubsubSettings) -> Result<Self> {
+        let topic = settings
+            .topic
+            .clone()
+            .context("Pub/Sub output requires pubsub_topic to be set")?;
+        let client = PubsubClient::new(settings).await?;
+        info!(
+            "Publishing to Pub/Sub topic projects/{}/topics/{}",
+            client.project, topic
+        );
+
+        Ok(Self {
+            client,
+            publish: format!("topics/{}:publish", topic),
+        })
+    }
+}
+
+#[async_trait]
+impl OutputDestination for PubsubDestination {
+    fn name(&self) -> &str {
+        "pubsub"
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        &["assistant", "image"]
+    }
+
+    async fn write_message(&self, role: &str, content: &str) -> Result<()> {
+        let message = PubsubMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+
+        let data =
+            base64::engine::general_purpose::STANDARD.encode(serde_json::to_string(&message)?);
+        let _: serde_json::Value = self
+            .client
+            .call(
+                &self.publish,
+                &serde_json::json!({ "messages": [{ "data": data, "attributes": { "role": role } }] }),
+            )
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,808 @@
+//! Input and output types by name. Each type registers a constructor that builds it
+//! from the config and its entry's settings, so feature-gated modules and other crates
+//! can add transports without touching the factories.
+
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use std::future::Future;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use super::*;
+use crate::config::{Config, IoEntry};
+
+/// What a constructor gets to build one input or output
+pub struct IoContext {
+    /// The entry's type, e.g. `mqtt` or `mqtt:backup`
+    pub kind: String,
+    /// The agent's config; built-in types get it with the entry's settings applied
+    pub config: Config,
+    /// The entry's own settings, as written next to its `type`
+    pub settings: serde_json::Map<String, serde_json::Value>,
+}
+
+type Build<T> = Arc<dyn Fn(IoContext) -> BoxFuture<'static, Result<T>> + Send + Sync>;
+type Check = Arc<dyn Fn(&IoContext) -> Result<()> + Send + Sync>;
+
+struct Registration<T> {
+    kind: String,
+    build: Build<T>,
+    check: Check,
+    // Whether the entry's settings name config keys, applied to IoContext::config,
+    // rather than belonging to the type alone
+    config_settings: bool,
+}
+
+// Derived Clone would want T: Clone
+impl<T> Clone for Registration<T> {
+    fn clone(&self) -> Self {
+        Self {
+            kind: self.kind.clone(),
+            build: self.build.clone(),
+            check: self.check.clone(),
+            config_settings: self.config_settings,
+        }
+    }
+}
+
+struct Registry {
+    inputs: Vec<Registration<Box<dyn InputSource>>>,
+    outputs: Vec<Registration<Box<dyn OutputDestination>>>,
+}
+
+// The built-in types, then any registered by the embedding program
+static REGISTRY: OnceLock<RwLock<Registry>> = OnceLock::new();
+
+fn registry() -> &'static RwLock<Registry> {
+    REGISTRY.get_or_init(|| {
+        let mut registry = Registry {
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        };
+        builtin_inputs(&mut registry.inputs);
+        builtin_outputs(&mut registry.outputs);
+        RwLock::new(registry)
+    })
+}
+
+/// Make an input type available under `kind`, in place of any type of that name.
+/// `build` gets the agent's config and the entry's settings as written, which the
+/// config doesn't check
+pub fn register_input<B, F>(kind: &str, build: B)
+where
+    B: Fn(IoContext) -> F + Send + Sync + 'static,
+    F: Future<Output = Result<Box<dyn InputSource>>> + Send + 'static,
+{
+    let registration = Registration {
+        kind: kind.to_string(),
+        build: Arc::new(move |io| Box::pin(build(io))),
+        check: Arc::new(|_| Ok(())),
+        config_settings: false,
+    };
+    add(&mut registry().write().unwrap().inputs, registration);
+}
+
+/// Make an output type available under `kind`, like `register_input`
+pub fn register_output<B, F>(kind: &str, build: B)
+where
+    B: Fn(IoContext) -> F + Send + Sync + 'static,
+    F: Future<Output = Result<Box<dyn OutputDestination>>> + Send + 'static,
+{
+    let registration = Registration {
+        kind: kind.to_string(),
+        build: Arc::new(move |io| Box::pin(build(io))),
+        check: Arc::new(|_| Ok(())),
+        config_settings: false,
+    };
+    add(&mut registry().write().unwrap().outputs, registration);
+}
+
+/// Names of the registered types for `direction`, "input" or "output", built-in first
+pub fn kinds(direction: &str) -> Vec<String> {
+    let registry = registry().read().unwrap();
+    match direction {
+        "input" => registry.inputs.iter().map(|r| r.kind.clone()).collect(),
+        _ => registry.outputs.iter().map(|r| r.kind.clone()).collect(),
+    }
+}
+
+/// The context an entry's type is built with; fails on an unknown type or, for
+/// built-in types, settings the config doesn't have
+pub fn context(config: &Config, entry: &IoEntry, direction: &str) -> Result<IoContext> {
+    let registry = registry().read().unwrap();
+    let config_settings = match direction {
+        "input" => find(&registry.inputs, &entry.kind).map(|r| r.config_settings),
+        _ => find(&registry.outputs, &entry.kind).map(|r| r.config_settings),
+    }
+    .with_context(|| format!("unknown {} {}", direction, entry.kind))?;
+    drop(registry);
+    prepare(config, entry, direction, config_settings)
+}
+
+/// Check an entry's settings without connecting or binding anything, for
+/// `agent validate`
+pub fn check(config: &Config, entry: &IoEntry, direction: &str) -> Result<()> {
+    let io = context(config, entry, direction)?;
+    let registry = registry().read().unwrap();
+    let check = match direction {
+        "input" => find(&registry.inputs, &entry.kind).map(|r| r.check.clone()),
+        _ => find(&registry.outputs, &entry.kind).map(|r| r.check.clone()),
+    };
+    drop(registry);
+    if direction == "input" {
+        allowlist::Allowlist::new(&io.config)?;
+    }
+    check.map_or(Ok(()), |check| check(&io))
+}
+
+/// Build the input for an entry
+pub async fn build_input(config: &Config, entry: &IoEntry) -> Result<Box<dyn InputSource>> {
+    let registration = find(&registry().read().unwrap().inputs, &entry.kind)
+        .with_context(|| format!("Unknown input source: {}", entry.kind))?;
+    let io = prepare(config, entry, "input", registration.config_settings)?;
+    let allowlist = allowlist::Allowlist::new(&io.config)?;
+    let input = (registration.build)(io).await?;
+    Ok(match allowlist {
+        Some(allowlist) => Box::new(allowlist::Allowlisted::new(input, allowlist)),
+        None => input,
+    })
+}
+
+/// Build the output for an entry
+pub async fn build_output(config: &Config, entry: &IoEntry) -> Result<Box<dyn OutputDestination>> {
+    let registration = find(&registry().read().unwrap().outputs, &entry.kind)
+        .with_context(|| format!("Unknown output destination: {}", entry.kind))?;
+    let io = prepare(config, entry, "output", registration.config_settings)?;
+    let config = io.config.clone();
+    let output = (registration.build)(io).await?;
+    Ok(Box::new(isolation::Isolated::new(
+        &config,
+        &entry.kind,
+        output,
+    )?))
+}
+
+// The registration for a type, by its full name or the part before a colon, so
+// mqtt:backup is an mqtt entry
+fn find<T>(list: &[Registration<T>], kind: &str) -> Option<Registration<T>> {
+    let base = kind.split(':').next().unwrap_or_default();
+    list.iter()
+        .find(|r| r.kind == kind)
+        .or_else(|| list.iter().find(|r| r.kind == base))
+        .cloned()
+}
+
+fn add<T>(list: &mut Vec<Registration<T>>, registration: Registration<T>) {
+    match list.iter_mut().find(|r| r.kind == registration.kind) {
+        Some(existing) => *existing = registration,
+        None => list.push(registration),
+    }
+}
+
+fn prepare(
+    config: &Config,
+    entry: &IoEntry,
+    direction: &str,
+    config_settings: bool,
+) -> Result<IoContext> {
+    let config = if config_settings {
+        config.with_settings(entry, direction)?
+    } else {
+        config.clone()
+    };
+    Ok(IoContext {
+        kind: entry.kind.clone(),
+        config,
+        settings: entry.settings.clone(),
+    })
+}
+
+// A built-in type, whose settings are config keys
+fn builtin<T, B, F, C>(list: &mut Vec<Registration<T>>, kind: &str, build: B, check: C)
+where
+    B: Fn(IoContext) -> F + Send + Sync + 'static,
+    F: Future<Output = Result<T>> + Send + 'static,
+    C: Fn(&IoContext) -> Result<()> + Send + Sync + 'static,
+{
+    list.push(Registration {
+        kind: kind.to_string(),
+        build: Arc::new(move |io| Box::pin(build(io))),
+        check: Arc::new(check),
+        config_settings: true,
+    });
+}
+
+// A built-in type left out of this build, kept so using it says which feature it needs
+#[allow(dead_code)]
+fn needs_feature<T: 'static>(
+    list: &mut Vec<Registration<T>>,
+    kind: &'static str,
+    direction: &'static str,
+    feature: &'static str,
+) {
+    let missing = move || {
+        anyhow::anyhow!(
+            "{} {} requires building with --features {}",
+            kind,
+            direction,
+            feature
+        )
+    };
+    builtin(
+        list,
+        kind,
+        move |_| async move { Err(missing()) },
+        move |_| Err(missing()),
+    );
+}
+
+fn no_check(_: &IoContext) -> Result<()> {
+    Ok(())
+}
+
+// mqtt, or mqtt:<name> for a broker from mqtt_brokers
+fn mqtt_settings(io: &IoContext) -> Result<MqttSettings> {
+    match io.kind.strip_prefix("mqtt:") {
+        Some(broker) => MqttSettings::for_broker(&io.config, broker),
+        None => MqttSettings::from_config(&io.config),
+    }
+}
+
+#[cfg(feature = "grpc")]
+fn grpc_bind(config: &Config) -> &str {
+    config.grpc_bind.as_deref().unwrap_or("127.0.0.1:50051")
+}
+
+fn builtin_inputs(inputs: &mut Vec<Registration<Box<dyn InputSource>>>) {
+    builtin(
+        inputs,
+        "stdin",
+        |io| async move {
+            let stdin_source = StdinSource::new(io.config.stdin_history_file.clone());
+            Ok(Box::new(stdin_source) as Box<dyn InputSource>)
+        },
+        no_check,
+    );
+    builtin(
+        inputs,
+        "tui",
+        |io| async move {
+            let tui_source = TuiSource::new(&TuiSettings::from_config(&io.config))?;
+            Ok(Box::new(tui_source) as Box<dyn InputSource>)
+        },
+        no_check,
+    );
+    builtin(
+        inputs,
+        "command",
+        |io| async move {
+            let settings = CommandSettings::from_config(&io.config)?;
+            tracing::info!(
+                "Command input running every {}s: {}",
+                settings.interval.as_secs(),
+                settings.command
+            );
+            Ok(Box::new(CommandSource::new(settings)) as Box<dyn InputSource>)
+        },
+        |io| CommandSettings::from_config(&io.config).map(drop),
+    );
+    builtin(
+        inputs,
+        "http_poll",
+        |io| async move {
+            let settings = HttpPollSettings::from_config(&io.config)?;
+            tracing::info!(
+                "HTTP polling input fetching {} every {}s",
+                settings.url,
+                settings.interval.as_secs()
+            );
+            Ok(Box::new(HttpPollSource::new(settings)) as Box<dyn InputSource>)
+        },
+        |io| HttpPollSettings::from_config(&io.config).map(drop),
+    );
+    builtin(
+        inputs,
+        "schedule",
+        |io| async move {
+            let settings = ScheduleSettings::from_config(&io.config)?;
+            tracing::info!("Schedule input running at {}", settings.expression);
+            Ok(Box::new(ScheduleSource::new(settings)) as Box<dyn InputSource>)
+        },
+        |io| ScheduleSettings::from_config(&io.config).map(drop),
+    );
+    builtin(
+        inputs,
+        "mqtt",
+        |io| async move {
+            let mqtt_source = MqttSource::new(&mqtt_settings(&io)?).await?;
+            if let Some(broker) = io.kind.strip_prefix("mqtt:") {
+                tracing::info!("MQTT input connected to broker {}", broker);
+            }
+            Ok(Box::new(mqtt_source) as Box<dyn InputSource>)
+        },
+        |io| mqtt_settings(io).map(drop),
+    );
+    builtin(
+        inputs,
+        "webhook",
+        |io| async move {
+            let config = &io.config;
+            // GitHub events are only served once a bot login is configured
+            let github = match config.github_bot_login {
+                Some(_) => match GithubSettings::from_config(config) {
+                    Ok(settings) => Some(settings),
+                    Err(e) => {
+                        tracing::error!("GitHub events disabled: {}", e);
+                        None
+                    }
+                },
+                None => None,
+            };
+            let bind = config.webhook_bind.as_deref().unwrap_or("0.0.0.0");
+            let auth = WebhookAuth::from_config(config);
+            if !auth.is_enabled() {
+                tracing::warn!(
+                    "Webhook input has no webhook_token or webhook_secret; anyone who can reach it can send prompts"
+                );
+            }
+            let limits = WebhookLimits::from_config(config);
+            let http = HttpSettings::from_config(config)?;
+            let webhook_source =
+                WebhookSource::new(bind, config.webhook_port, auth, limits, http, github).await?;
+            tracing::info!(
+                "Webhook input listening on http://{}",
+                webhook_source.addr()
+            );
+            if config.webhook_port.is_none() {
+                tracing::warn!(
+                    "Webhook port {} was picked at random and will change on restart; set webhook_port to fix it",
+                    webhook_source.addr().port()
+                );
+            }
+            Ok(Box::new(webhook_source) as Box<dyn InputSource>)
+        },
+        |io| {
+            HttpSettings::from_config(&io.config)?.cors_layer()?;
+            if io.config.github_bot_login.is_some() {
+                GithubSettings::from_config(&io.config)?;
+            }
+            Ok(())
+        },
+    );
+    builtin(
+        inputs,
+        "api",
+        |io| async move {
+            let api_source = ApiSource::new(&ApiSettings::from_config(&io.config)).await?;
+            tracing::info!("API input listening on {}", api_source.addr());
+            Ok(Box::new(api_source) as Box<dyn InputSource>)
+        },
+        |io| {
+            HttpSettings::from_config(&io.config)?
+                .cors_layer()
+                .map(drop)
+        },
+    );
+    builtin(
+        inputs,
+        "tcp",
+        |io| async move {
+            let tcp_source = TcpSource::new(&TcpSettings::from_config(&io.config)).await?;
+            tracing::info!("TCP input listening on {}", tcp_source.addr());
+            Ok(Box::new(tcp_source) as Box<dyn InputSource>)
+        },
+        no_check,
+    );
+    #[cfg(feature = "grpc")]
+    builtin(
+        inputs,
+        "grpc",
+        |io| async move {
+            let grpc_source = GrpcSource::new(grpc_bind(&io.config)).await?;
+            tracing::info!("gRPC input listening on {}", grpc_source.addr());
+            Ok(Box::new(grpc_source) as Box<dyn InputSource>)
+        },
+        no_check,
+    );
+    #[cfg(not(feature = "grpc"))]
+    needs_feature(inputs, "grpc", "input", "grpc");
+    #[cfg(feature = "nats")]
+    builtin(
+        inputs,
+        "nats",
+        |io| async move {
+            let nats_source = NatsSource::new(&NatsSettings::from_config(&io.config)).await?;
+            Ok(Box::new(nats_source) as Box<dyn InputSource>)
+        },
+        no_check,
+    );
+    #[cfg(not(feature = "nats"))]
+    needs_feature(inputs, "nats", "input", "nats");
+    #[cfg(feature = "pubsub")]
+    builtin(
+        inputs,
+        "pubsub",
+        |io| async move {
+            let pubsub_source = PubsubSource::new(&PubsubSettings::from_config(&io.config)).await?;
+            Ok(Box::new(pubsub_source) as Box<dyn InputSource>)
+        },
+        no_check,
+    );
+    #[cfg(not(feature = "pubsub"))]
+    needs_feature(inputs, "pubsub", "input", "pubsub");
+    #[cfg(feature = "matrix")]
+    builtin(
+        inputs,
+        "matrix",
+        |io| async move {
+            let matrix_source =
+                MatrixSource::new(&MatrixSettings::from_config(&io.config)?).await?;
+            Ok(Box::new(matrix_source) as Box<dyn InputSource>)
+        },
+        |io| MatrixSettings::from_config(&io.config).map(drop),
+    );
+    #[cfg(not(feature = "matrix"))]
+    needs_feature(inputs, "matrix", "input", "matrix");
+    #[cfg(feature = "xmpp")]
+    builtin(
+        inputs,
+        "xmpp",
+        |io| async move {
+            let xmpp_source = XmppSource::new(&XmppSettings::from_config(&io.config)?).await?;
+            Ok(Box::new(xmpp_source) as Box<dyn InputSource>)
+        },
+        |io| XmppSettings::from_config(&io.config).map(drop),
+    );
+    #[cfg(not(feature = "xmpp"))]
+    needs_feature(inputs, "xmpp", "input", "xmpp");
+    builtin(
+        inputs,
+        "twitch",
+        |io| async move {
+            let twitch_source =
+                TwitchSource::new(&TwitchSettings::from_config(&io.config)?).await?;
+            Ok(Box::new(twitch_source) as Box<dyn InputSource>)
+        },
+        |io| TwitchSettings::from_config(&io.config).map(drop),
+    );
+    #[cfg(feature = "nostr")]
+    builtin(
+        inputs,
+        "nostr",
+        |io| async move {
+            let nostr_source = NostrSource::new(&NostrSettings::from_config(&io.config)?).await?;
+            Ok(Box::new(nostr_source) as Box<dyn InputSource>)
+        },
+        |io| NostrSettings::from_config(&io.config).map(drop),
+    );
+    #[cfg(not(feature = "nostr"))]
+    needs_feature(inputs, "nostr", "input", "nostr");
+    #[cfg(feature = "email")]
+    builtin(
+        inputs,
+        "email",
+        |io| async move {
+            let email_source = EmailSource::new(EmailSettings::from_config(&io.config)?);
+            Ok(Box::new(email_source) as Box<dyn InputSource>)
+        },
+        |io| EmailSettings::from_config(&io.config).map(drop),
+    );
+    #[cfg(not(feature = "email"))]
+    needs_feature(inputs, "email", "input", "email");
+    #[cfg(feature = "voice")]
+    builtin(
+        inputs,
+        "voice",
+        |io| async move {
+            let voice_source = VoiceSource::new(VoiceSettings::from_config(&io.config)?).await?;
+            Ok(Box::new(voice_source) as Box<dyn InputSource>)
+        },
+        |io| VoiceSettings::from_config(&io.config).map(drop),
+    );
+    #[cfg(not(feature = "voice"))]
+    needs_feature(inputs, "voice", "input", "voice");
+}
+
+fn builtin_outputs(outputs: &mut Vec<Registration<Box<dyn OutputDestination>>>) {
+    builtin(
+        outputs,
+        "stdout",
+        |_| async move { Ok(Box::new(StdoutDestination::new()) as Box<dyn OutputDestination>) },
+        no_check,
+    );
+    builtin(
+        outputs,
+        "tui",
+        |io| async move {
+            let tui_dest = TuiDestination::new(&TuiSettings::from_config(&io.config));
+            Ok(Box::new(tui_dest) as Box<dyn OutputDestination>)
+        },
+        no_check,
+    );
+    builtin(
+        outputs,
+        "mqtt",
+        |io| async move {
+            let mqtt_dest = MqttDestination::new(&mqtt_settings(&io)?).await?;
+            if let Some(broker) = io.kind.strip_prefix("mqtt:") {
+                tracing::info!("MQTT output connected to broker {}", broker);
+            }
+            Ok(Box::new(mqtt_dest) as Box<dyn OutputDestination>)
+        },
+        |io| mqtt_settings(io).map(drop),
+    );
+    builtin(
+        outputs,
+        "webhook",
+        |io| async move {
+            let url = io
+                .config
+                .webhook_url
+                .as_deref()
+                .context("Webhook output requires webhook_url to be set")?;
+            let webhook_dest = WebhookDestination::new(url)?;
+            tracing::info!("Webhook output sending to {}", webhook_dest.url());
+            Ok(Box::new(webhook_dest) as Box<dyn OutputDestination>)
+        },
+        |io| match &io.config.webhook_url {
+            Some(url) => WebhookDestination::new(url).map(drop),
+            None => anyhow::bail!("webhook output requires webhook_url to be set"),
+        },
+    );
+    builtin(
+        outputs,
+        "chat_webhook",
+        |io| async move {
+            let config = &io.config;
+            let url = config
+                .chat_webhook_url
+                .as_deref()
+                .context("Chat webhook output requires chat_webhook_url to be set")?;
+            let chat_dest = ChatWebhookDestination::new(
+                url,
+                chat_webhook_template(config)?,
+                &config.agent_name,
+            )?;
+            tracing::info!("Chat webhook output sending to {}", chat_dest.url());
+            Ok(Box::new(chat_dest) as Box<dyn OutputDestination>)
+        },
+        |io| match &io.config.chat_webhook_url {
+            Some(_) => chat_webhook_template(&io.config).map(drop),
+            None => anyhow::bail!("chat_webhook output requires chat_webhook_url to be set"),
+        },
+    );
+    builtin(
+        outputs,
+        "sse",
+        |io| async move {
+            if !io.config.has_input("webhook") {
+                tracing::warn!("SSE output is served by the webhook input; add webhook to inputs");
+            }
+            Ok(Box::new(SseDestination::new()) as Box<dyn OutputDestination>)
+        },
+        |io| served_by_webhook(io, "sse"),
+    );
+    builtin(
+        outputs,
+        "github",
+        |io| async move {
+            if !io.config.has_input("webhook") {
+                tracing::warn!(
+                    "GitHub events are received by the webhook input; add webhook to inputs"
+                );
+            }
+            let github_dest = GithubDestination::new(io.config.github_token.clone())?;
+            Ok(Box::new(github_dest) as Box<dyn OutputDestination>)
+        },
+        |io| {
+            served_by_webhook(io, "github")?;
+            GithubDestination::new(io.config.github_token.clone()).map(drop)
+        },
+    );
+    builtin(
+        outputs,
+        "api",
+        |io| async move {
+            let api_dest = ApiDestination::new(&ApiSettings::from_config(&io.config)).await?;
+            Ok(Box::new(api_dest) as Box<dyn OutputDestination>)
+        },
+        |io| {
+            HttpSettings::from_config(&io.config)?
+                .cors_layer()
+                .map(drop)
+        },
+    );
+    builtin(
+        outputs,
+        "tcp",
+        |io| async move {
+            let tcp_dest = TcpDestination::new(&TcpSettings::from_config(&io.config)).await?;
+            tracing::info!("TCP output serving clients on {}", tcp_dest.addr());
+            Ok(Box::new(tcp_dest) as Box<dyn OutputDestination>)
+        },
+        no_check,
+    );
+    #[cfg(feature = "grpc")]
+    builtin(
+        outputs,
+        "grpc",
+        |io| async move {
+            let grpc_dest = GrpcDestination::new(grpc_bind(&io.config)).await?;
+            tracing::info!("gRPC output serving clients on {}", grpc_dest.addr());
+            Ok(Box::new(grpc_dest) as Box<dyn OutputDestination>)
+        },
+        no_check,
+    );
+    #[cfg(not(feature = "grpc"))]
+    needs_feature(outputs, "grpc", "output", "grpc");
+    #[cfg(feature = "nats")]
+    builtin(
+        outputs,
+        "nats",
+        |io| async move {
+            let nats_dest = NatsDestination::new(&NatsSettings::from_config(&io.config)).await?;
+            Ok(Box::new(nats_dest) as Box<dyn OutputDestination>)
+        },
+        no_check,
+    );
+    #[cfg(not(feature = "nats"))]
+    needs_feature(outputs, "nats", "output", "nats");
+    #[cfg(feature = "pubsub")]
+    builtin(
+        outputs,
+        "pubsub",
+        |io| async move {
+            let pubsub_dest =
+                PubsubDestination::new(&PubsubSettings::from_config(&io.config)).await?;
+            Ok(Box::new(pubsub_dest) as Box<dyn OutputDestination>)
+        },
+        no_check,
+    );
+    #[cfg(not(feature = "pubsub"))]
+    needs_feature(outputs, "pubsub", "output", "pubsub");
+    builtin(
+        outputs,
+        "exec",
+        |io| async move {
+            let config = &io.config;
+            let command = config
+                .exec_command
+                .as_deref()
+                .context("Exec output requires exec_command to be set")?;
+            let exec_dest = ExecDestination::new(
+                command,
+                std::time::Duration::from_secs(config.exec_timeout.unwrap_or(30)),
+                &config.agent_name,
+            );
+            tracing::info!("Exec output running: {}", exec_dest.command());
+            Ok(Box::new(exec_dest) as Box<dyn OutputDestination>)
+        },
+        |io| match &io.config.exec_command {
+            Some(_) => Ok(()),
+            None => anyhow::bail!("exec output requires exec_command to be set"),
+        },
+    );
+    builtin(
+        outputs,
+        "file",
+        |io| async move {
+            let config = &io.config;
+            let path = transcript_path(config);
+            let file_dest = FileDestination::new(
+                path,
+                transcript_format(config)?,
+                config.transcript_max_bytes,
+                config.transcript_rotate_daily,
+            )?;
+            tracing::info!("Writing transcript to {}", file_dest.path().display());
+            Ok(Box::new(file_dest) as Box<dyn OutputDestination>)
+        },
+        |io| transcript_format(&io.config).map(drop),
+    );
+    #[cfg(feature = "sqlite")]
+    builtin(
+        outputs,
+        "sqlite",
+        |io| async move {
+            let path = io.config.sqlite_path.as_deref().unwrap_or("agent.db");
+            Ok(Box::new(SqliteDestination::new(path)?) as Box<dyn OutputDestination>)
+        },
+        no_check,
+    );
+    #[cfg(not(feature = "sqlite"))]
+    needs_feature(outputs, "sqlite", "output", "sqlite");
+    #[cfg(feature = "matrix")]
+    builtin(
+        outputs,
+        "matrix",
+        |io| async move {
+            let matrix_dest =
+                MatrixDestination::new(&MatrixSettings::from_config(&io.config)?).await?;
+            Ok(Box::new(matrix_dest) as Box<dyn OutputDestination>)
+        },
+        |io| MatrixSettings::from_config(&io.config).map(drop),
+    );
+    #[cfg(not(feature = "matrix"))]
+    needs_feature(outputs, "matrix", "output", "matrix");
+    #[cfg(feature = "xmpp")]
+    builtin(
+        outputs,
+        "xmpp",
+        |io| async move {
+            let xmpp_dest = XmppDestination::new(&XmppSettings::from_config(&io.config)?).await?;
+            Ok(Box::new(xmpp_dest) as Box<dyn OutputDestination>)
+        },
+        |io| XmppSettings::from_config(&io.config).map(drop),
+    );
+    #[cfg(not(feature = "xmpp"))]
+    needs_feature(outputs, "xmpp", "output", "xmpp");
+    builtin(
+        outputs,
+        "twitch",
+        |io| async move {
+            let twitch_dest =
+                TwitchDestination::new(&TwitchSettings::from_config(&io.config)?).await?;
+            Ok(Box::new(twitch_dest) as Box<dyn OutputDestination>)
+        },
+        |io| TwitchSettings::from_config(&io.config).map(drop),
+    );
+    #[cfg(feature = "nostr")]
+    builtin(
+        outputs,
+        "nostr",
+        |io| async move {
+            let nostr_dest =
+                NostrDestination::new(&NostrSettings::from_config(&io.config)?).await?;
+            Ok(Box::new(nostr_dest) as Box<dyn OutputDestination>)
+        },
+        |io| NostrSettings::from_config(&io.config).map(drop),
+    );
+    #[cfg(not(feature = "nostr"))]
+    needs_feature(outputs, "nostr", "output", "nostr");
+    #[cfg(feature = "email")]
+    builtin(
+        outputs,
+        "email",
+        |io| async move {
+            let email_dest = EmailDestination::new(&EmailSettings::from_config(&io.config)?)?;
+            Ok(Box::new(email_dest) as Box<dyn OutputDestination>)
+        },
+        |io| EmailSettings::from_config(&io.config).map(drop),
+    );
+    #[cfg(not(feature = "email"))]
+    needs_feature(outputs, "email", "output", "email");
+}
+
+// The sse and github outputs are served by the webhook input's server
+fn served_by_webhook(io: &IoContext, kind: &str) -> Result<()> {
+    if !io.config.has_input("webhook") {
+        anyhow::bail!(
+            "{} output is served by the webhook input; add webhook to inputs",
+            kind
+        );
+    }
+    Ok(())
+}
+
+fn chat_webhook_template(config: &Config) -> Result<String> {
+    chat_webhook::payload_template(
+        config
+            .chat_webhook_platform
+            .as_deref()
+            .unwrap_or("mattermost"),
+        config.chat_webhook_template.as_deref(),
+    )
+}
+
+fn transcript_path(config: &Config) -> &str {
+    config
+        .transcript_path
+        .as_deref()
+        .unwrap_or("transcript.jsonl")
+}
+
+fn transcript_format(config: &Config) -> Result<TranscriptFormat> {
+    TranscriptFormat::from_config(
+        config.transcript_format.as_deref(),
+        std::path::Path::new(transcript_path(config)),
+    )
+}
@@ -0,0 +1,217 @@
+//! Messages an output failed to deliver, kept on disk and tried again in order with
+//! growing delays until they go through or grow too old
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::OutputDestination;
+use crate::config::Config;
+
+/// Seconds a message is retried for unless `retry_max_age_secs` is set
+pub const DEFAULT_MAX_AGE_SECS: u64 = 24 * 60 * 60;
+
+// Wait after the first failed retry, doubling with each one after it up to the most
+const FIRST_DELAY: Duration = Duration::from_secs(5);
+const MAX_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// An output's error that trying again won't fix, such as a receiver turning the
+/// message itself away; messages that fail with it aren't kept for a retry
+#[derive(Debug)]
+pub struct Undeliverable(pub String);
+
+impl std::fmt::Display for Undeliverable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Undeliverable {}
+
+/// Whether a write that failed with `error` is worth trying again
+pub fn is_retryable(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<Undeliverable>().is_none()
+}
+
+// One message waiting, a line of the queue's file
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+struct Delivery {
+    role: String,
+    content: String,
+    // Unix seconds
+    queued_at: i64,
+}
+
+struct State {
+    deliveries: VecDeque<Delivery>,
+    // When the front message is tried next, and how long to wait if that fails
+    next_try: Instant,
+    delay: Duration,
+}
+
+/// One output's failed deliveries
+pub struct RetryQueue {
+    path: PathBuf,
+    // Seconds
+    max_age: i64,
+    state: Mutex<State>,
+}
+
+impl RetryQueue {
+    /// The queue for the output from entry `kind`, with what it held before a
+    /// restart, or `None` when `retry_dir` isn't set
+    pub fn open(config: &Config, kind: &str) -> Result<Option<Self>> {
+        let Some(dir) = &config.retry_dir else {
+            return Ok(None);
+        };
+        std::fs::create_dir_all(dir)
+            .context(format!("Failed to create retry directory: {}", dir))?;
+        let name = format!("{}-{}.jsonl", config.agent_name, kind).replace([':', '/'], "_");
+        let path = PathBuf::from(dir).join(name);
+
+        let mut deliveries = VecDeque::new();
+        match std::fs::File::open(&path) {
+            Ok(file) => {
+                for line in std::io::BufReader::new(file).lines() {
+                    let line = line.context(format!("Failed to read {}", path.display()))?;
+                    match serde_json::from_str(&line) {
+                        Ok(delivery) => deliveries.push_back(delivery),
+                        Err(_) => tracing::warn!(
+                            "Skipping an unreadable line in retry queue {}",
+                            path.display()
+                        ),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context(format!("Failed to open {}", path.display())),
+        }
+        if !deliveries.is_empty() {
+            tracing::info!(
+                "{} message(s) waiting to be retried for the {} output",
+                deliveries.len(),
+                kind
+            );
+        }
+
+        let max_age = config.retry_max_age_secs.unwrap_or(DEFAULT_MAX_AGE_SECS);
+        Ok(Some(Self {
+            path,
+            max_age: max_age.min(i64::MAX as u64) as i64,
+            state: Mutex::new(State {
+                deliveries,
+                next_try: Instant::now(),
+                delay: FIRST_DELAY,
+            }),
+        }))
+    }
+
+    /// Keep a message the output failed to deliver
+    pub fn push(&self, role: &str, content: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.deliveries.push_back(Delivery {
+            role: role.to_string(),
+            content: content.to_string(),
+            queued_at: Utc::now().timestamp(),
+        });
+        self.save(&state.deliveries);
+    }
+
+    /// Deliver the waiting messages in order, if it is time to, until one fails or
+    /// none are left; returns whether any went through
+    pub async fn deliver(&self, output: &dyn OutputDestination, timeout: Option<Duration>) -> bool {
+        let mut delivered = false;
+        loop {
+            let delivery = {
+                let mut state = self.state.lock().unwrap();
+                let oldest = Utc::now().timestamp() - self.max_age;
+                let before = state.deliveries.len();
+                state
+                    .deliveries
+                    .retain(|delivery| delivery.queued_at > oldest);
+                if state.deliveries.len() < before {
+                    tracing::warn!(
+                        "Gave up on {} message(s) for output {} after {}s",
+                        before - state.deliveries.len(),
+                        output.name(),
+                        self.max_age
+                    );
+                    self.save(&state.deliveries);
+                }
+                if Instant::now() < state.next_try {
+                    return delivered;
+                }
+                match state.deliveries.front() {
+                    Some(delivery) => delivery.clone(),
+                    None => return delivered,
+                }
+            };
+
+            let write = output.write_message(&delivery.role, &delivery.content);
+            let result = match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, write)
+                    .await
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("Timed out"))),
+                None => write.await,
+            };
+
+            let mut state = self.state.lock().unwrap();
+            match result {
+                Ok(()) => {
+                    if state.deliveries.front() == Some(&delivery) {
+                        state.deliveries.pop_front();
+                    }
+                    state.delay = FIRST_DELAY;
+                    self.save(&state.deliveries);
+                    delivered = true;
+                }
+                Err(e) if !is_retryable(&e) => {
+                    tracing::warn!("Gave up on a message for output {}: {:#}", output.name(), e);
+                    if state.deliveries.front() == Some(&delivery) {
+                        state.deliveries.pop_front();
+                    }
+                    self.save(&state.deliveries);
+                }
+                Err(e) => {
+                    tracing::debug!(
+                        "Retry to output {} failed, next in {}s: {:#}",
+                        output.name(),
+                        state.delay.as_secs(),
+                        e
+                    );
+                    state.next_try = Instant::now() + state.delay;
+                    state.delay = (state.delay * 2).min(MAX_DELAY);
+                    return delivered;
+                }
+            }
+        }
+    }
+
+    // Write the queue out whole, through a temporary file so a crash part way leaves
+    // the last complete copy
+    fn save(&self, deliveries: &VecDeque<Delivery>) {
+        let temporary = self.path.with_extension("jsonl.tmp");
+        let written = std::fs::File::create(&temporary)
+            .map_err(anyhow::Error::from)
+            .and_then(|mut file| {
+                for delivery in deliveries {
+                    writeln!(file, "{}", serde_json::to_string(delivery)?)?;
+                }
+                file.sync_all()?;
+                Ok(())
+            })
+            .and_then(|()| Ok(std::fs::rename(&temporary, &self.path)?));
+        if let Err(e) = written {
+            tracing::warn!(
+                "Failed to save retry queue {}: {:#}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
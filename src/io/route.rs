@@ -0,0 +1,139 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::{InboundMessage, OutputDestination};
+use crate::config::Config;
+
+/// Roles of the messages the agent writes to its outputs
+pub const ROLES: &[&str] = &[
+    "user",
+    "assistant",
+    "system",
+    "processing",
+    "tool",
+    "image",
+    "export",
+];
+
+/// Whether `name` is the input or output `pattern` names: itself, or one of its
+/// type's, as `mqtt` names `mqtt:cloud` too
+pub fn matches(pattern: &str, name: &str) -> bool {
+    name == pattern
+        || name
+            .strip_prefix(pattern)
+            .is_some_and(|rest| rest.starts_with(':'))
+}
+
+/// An output as one input's routes see it, leaving out messages of other roles
+pub struct Routed<'a> {
+    output: &'a dyn OutputDestination,
+    roles: Vec<String>,
+}
+
+impl Routed<'_> {
+    fn allows(&self, role: &str) -> bool {
+        self.roles.iter().any(|allowed| allowed == role)
+    }
+}
+
+#[async_trait]
+impl OutputDestination for Routed<'_> {
+    fn name(&self) -> &str {
+        self.output.name()
+    }
+
+    fn answering(&self, message: &InboundMessage) {
+        self.output.answering(message)
+    }
+
+    async fn write_message(&self, role: &str, content: &str) -> Result<()> {
+        if !self.allows(role) {
+            return Ok(());
+        }
+        self.output.write_message(role, content).await
+    }
+
+    async fn typing(&self, notice: &str) -> Result<()> {
+        if !self.allows("processing") {
+            return Ok(());
+        }
+        self.output.typing(notice).await
+    }
+
+    async fn write_chunk(&self, role: &str, delta: &str) -> Result<()> {
+        if !self.allows(role) {
+            return Ok(());
+        }
+        self.output.write_chunk(role, delta).await
+    }
+
+    // Only assistant messages are streamed
+    async fn end_message(&self) -> Result<()> {
+        if !self.allows("assistant") {
+            return Ok(());
+        }
+        self.output.end_message().await
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.output.close().await
+    }
+}
+
+/// The outputs that get the messages of `input`: the ones its routes name, or else
+/// the ones in `reply_to` along with transcripts, or every output when none of those
+/// is running or replies are broadcast. Each lets through the roles both the routes
+/// and the output allow
+pub fn outputs_for<'a>(
+    config: &Config,
+    input: &str,
+    reply_to: &[String],
+    outputs: &'a [Box<dyn OutputDestination>],
+) -> Vec<Box<dyn OutputDestination + 'a>> {
+    let routes: Vec<_> = config
+        .routes
+        .iter()
+        .filter(|route| matches(&route.from, input))
+        .collect();
+    let replying =
+        |output: &dyn OutputDestination| reply_to.iter().any(|name| name == output.name());
+    let broadcast =
+        config.broadcast_replies || !outputs.iter().any(|output| replying(output.as_ref()));
+    outputs
+        .iter()
+        .filter_map(|output| {
+            // A broker's own entry comes before its type's
+            let configured = config.output_roles.get(output.name()).or_else(|| {
+                let (kind, _) = output.name().split_once(':')?;
+                config.output_roles.get(kind)
+            });
+            let mut roles: Vec<String> = match configured {
+                Some(roles) => roles.clone(),
+                None => output
+                    .default_roles()
+                    .iter()
+                    .map(|role| role.to_string())
+                    .collect(),
+            };
+            if !routes.is_empty() {
+                let matching: Vec<_> = routes
+                    .iter()
+                    .filter(|route| route.to.iter().any(|to| matches(to, output.name())))
+                    .collect();
+                if matching.is_empty() {
+                    return None;
+                }
+                // A route without roles sends every role
+                if !matching.iter().any(|route| route.roles.is_empty()) {
+                    roles.retain(|role| matching.iter().any(|route| route.roles.contains(role)));
+                }
+            } else if !broadcast && !replying(output.as_ref()) && !output.keeps_transcript() {
+                return None;
+            }
+            Some(Box::new(Routed {
+                output: output.as_ref(),
+                roles,
+            }) as Box<dyn OutputDestination + 'a>)
+        })
+        .collect()
+}
@@ -0,0 +1,401 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Duration, Local, LocalResult, NaiveDate, TimeZone, Timelike};
+use minijinja::{context, Environment};
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+use super::{InboundMessage, InputSource};
+
+const MONTHS: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A cron expression: minute, hour, day of month, month and day of week, in local time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    // One bit per allowed value
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // When both days and weekdays are restricted, a day matching either one runs
+    days_any: bool,
+    weekdays_any: bool,
+}
+
+impl Cron {
+    /// Parse the five fields, each `*`, a value, a range, a list or a step like `*/15`,
+    /// or one of @hourly, @daily, @weekly, @monthly and @yearly. Months and weekdays
+    /// can be names (`jan`, `mon`), and Sunday is 0 or 7.
+    pub fn parse(expression: &str) -> Result<Self> {
+        let expression = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            anyhow::bail!(
+                "Invalid cron expression `{}`: expected minute, hour, day, month and weekday",
+                expression
+            );
+        };
+
+        let weekday_bits = field(weekdays, 0, 7, WEEKDAYS, "weekday")?;
+        Ok(Self {
+            minutes: field(minutes, 0, 59, &[], "minute")?,
+            hours: field(hours, 0, 23, &[], "hour")?,
+            days: field(days, 1, 31, &[], "day")?,
+            months: field(months, 1, 12, MONTHS, "month")?,
+            // 7 is Sunday as well as 0
+            weekdays: (weekday_bits | weekday_bits >> 7) & 0x7f,
+            days_any: days.starts_with('*'),
+            weekdays_any: weekdays.starts_with('*'),
+        })
+    }
+
+    /// The first time after `after` the expression matches, if it ever does
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = after.naive_local().with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let mut date = start.date();
+        // A schedule for February 29 can be eight years from its next run
+        for _ in 0..366 * 8 {
+            if self.runs_on(date) {
+                for hour in (0..24).filter(|hour| self.hours & 1 << hour != 0) {
+                    for minute in (0..60).filter(|minute| self.minutes & 1 << minute != 0) {
+                        let time = date.and_hms_opt(hour, minute, 0)?;
+                        if time < start {
+                            continue;
+                        }
+                        // Times skipped by a daylight saving change don't run, and
+                        // times repeated by one run the first time round. chrono
+                        // doesn't always list the earlier one first
+                        match Local.from_local_datetime(&time) {
+                            LocalResult::Single(time) => return Some(time),
+                            LocalResult::Ambiguous(a, b) => return Some(a.min(b)),
+                            LocalResult::None => {}
+                        }
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    fn runs_on(&self, date: NaiveDate) -> bool {
+        let day = self.days & 1 << date.day() != 0;
+        let weekday = self.weekdays & 1 << date.weekday().num_days_from_sunday() != 0;
+        let matches = if self.days_any || self.weekdays_any {
+            day && weekday
+        } else {
+            day || weekday
+        };
+        matches && self.months & 1 << date.month() != 0
+    }
+}
+
+// The values a cron field allows, as bits
+fn field(text: &str, min: u32, max: u32, names: &[&str], what: &str) -> Result<u64> {
+    let value = |text: &str| -> Result<u32> {
+        let value = match names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(text))
+        {
+            Some(index) => index as u32 + min,
+            None => text
+                .parse()
+                .with_context(|| format!("Invalid cron {}: {}", what, text))?,
+        };
+        if !(min..=max).contains(&value) {
+            anyhow::bail!("Cron {} {} is outside {}-{}", what, value, min, max);
+        }
+        Ok(value)
+    };
+
+    let mut bits = 0;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .with_context(|| format!("Invalid cron {} step: {}", what, part))?;
+                (range, Some(step))
+            }
+            None => (part, None),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (value(start)?, value(end)?),
+            // A value with a step runs from there to the end, like 5/15
+            None if step.is_some() => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if start > end {
+            anyhow::bail!("Invalid cron {} range: {}", what, range);
+        }
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+/// Settings for sending a prompt on a cron schedule
+#[derive(Debug, Clone)]
+pub struct ScheduleSettings {
+    pub cron: Cron,
+    pub expression: String,
+    pub prompt: String,
+    pub name: Option<String>,
+    pub agent_name: String,
+}
+
+impl ScheduleSettings {
+    pub fn from_config(config: &crate::config::Config) -> Result<Self> {
+        let expression = config
+            .schedule_cron
+            .clone()
+            .context("Schedule input requires schedule_cron to be set")?;
+        let cron = Cron::parse(&expression)?;
+        if cron.next_after(Local::now()).is_none() {
+            anyhow::bail!("Cron expression `{}` never runs", expression);
+        }
+        let prompt = config
+            .schedule_prompt
+            .clone()
+            .context("Schedule input requires schedule_prompt to be set")?;
+        Environment::new()
+            .template_from_str(&prompt)
+            .context("Failed to parse schedule_prompt")?;
+
+        Ok(Self {
+            cron,
+            expression,
+            prompt,
+            name: config.schedule_name.clone(),
+            agent_name: config.agent_name.clone(),
+        })
+    }
+
+    // The prompt for a run now, after one at `last_run` if this isn't the first
+    fn render(&self, now: DateTime<Local>, last_run: Option<DateTime<Local>>) -> Result<String> {
+        let format = |time: DateTime<Local>| time.format("%Y-%m-%d %H:%M %:z").to_string();
+        let prompt = Environment::new().render_str(
+            &self.prompt,
+            context! {
+                agent_name => self.agent_name,
+                date => now.format("%Y-%m-%d").to_string(),
+                time => now.format("%H:%M").to_string(),
+                datetime => format(now),
+                weekday => now.format("%A").to_string(),
+                last_run => last_run.map(format),
+            },
+        )?;
+        Ok(prompt.trim().to_string())
+    }
+}
+
+// Scheduled prompt input source implementation
+pub struct ScheduleSource {
+    message_rx: mpsc::Receiver<String>,
+    name: Option<String>,
+}
+
+impl ScheduleSource {
+    /// Send the prompt every time the cron expression matches, from now on
+    pub fn new(settings: ScheduleSettings) -> Self {
+        let (message_tx, message_rx) = mpsc::channel(10);
+        let name = settings.name.clone();
+
+        tokio::spawn(async move {
+            let mut last_run = None;
+            let mut after = Local::now();
+            while let Some(next) = settings.cron.next_after(after) {
+                info!(
+                    "Next scheduled prompt at {}",
+                    next.format("%Y-%m-%d %H:%M %:z")
+                );
+                // Wake up at least every minute, so a changed clock or a suspended
+                // machine doesn't throw the schedule off
+                loop {
+                    let now = Local::now();
+                    if now >= next {
+                        break;
+                    }
+                    let wait = (next - now).to_std().unwrap_or_default();
+                    tokio::time::sleep(wait.min(std::time::Duration::from_secs(60))).await;
+                }
+
+                let now = Local::now();
+                match settings.render(now, last_run) {
+                    Ok(prompt) if prompt.is_empty() => info!("Scheduled prompt is empty, skipping"),
+                    Ok(prompt) => {
+                        if message_tx.send(prompt).await.is_err() {
+                            error!("Failed to send message to channel");
+                            break;
+                        }
+                    }
+                    Err(e) => error!("Failed to render scheduled prompt: {:#}", e),
+                }
+                last_run = Some(now);
+                // A run that was late doesn't make the missed ones run too
+                after = now.max(next);
+            }
+
+            tracing::info!("Schedule input task completed");
+        });
+
+        Self { message_rx, name }
+    }
+}
+
+#[async_trait]
+impl InputSource for ScheduleSource {
+    fn name(&self) -> &str {
+        "schedule"
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        match self.message_rx.recv().await {
+            Some(message) => Ok(Some(
+                InboundMessage::new(message).with_sender(self.name.clone()),
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every test runs in a zone with daylight saving, set the same way by each so
+    // they can run in parallel
+    fn new_york() {
+        std::env::set_var("TZ", "EST5EDT,M3.2.0,M11.1.0");
+    }
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        new_york();
+        Local
+            .with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .single()
+            .expect("an unambiguous local time")
+    }
+
+    fn values(bits: u64) -> Vec<u32> {
+        (0..64).filter(|value| bits & 1 << value != 0).collect()
+    }
+
+    #[test]
+    fn steps_start_from_the_range() {
+        let every_quarter = Cron::parse("*/15 * * * *").unwrap();
+        assert_eq!(values(every_quarter.minutes), [0, 15, 30, 45]);
+        let from_five = Cron::parse("5/15 * * * *").unwrap();
+        assert_eq!(values(from_five.minutes), [5, 20, 35, 50]);
+        let in_range = Cron::parse("0 8-17/3 * * *").unwrap();
+        assert_eq!(values(in_range.hours), [8, 11, 14, 17]);
+    }
+
+    #[test]
+    fn weekdays_take_names_and_either_sunday() {
+        let weekdays = Cron::parse("0 9 * * mon-fri").unwrap();
+        assert_eq!(values(weekdays.weekdays), [1, 2, 3, 4, 5]);
+        assert_eq!(weekdays, Cron::parse("0 9 * * 1-5").unwrap());
+
+        let sunday = Cron::parse("0 0 * * 0").unwrap();
+        assert_eq!(values(sunday.weekdays), [0]);
+        assert_eq!(sunday, Cron::parse("0 0 * * 7").unwrap());
+        assert_eq!(sunday, Cron::parse("0 0 * * sun").unwrap());
+        assert_eq!(sunday, Cron::parse("@weekly").unwrap());
+    }
+
+    #[test]
+    fn day_of_month_or_weekday() {
+        // Both restricted: the 13th, and every Friday (2026-03-06 is a Friday)
+        let either = Cron::parse("0 9 13 * fri").unwrap();
+        assert_eq!(
+            either.next_after(at(2026, 3, 1, 0, 0)),
+            Some(at(2026, 3, 6, 9, 0))
+        );
+        assert_eq!(
+            either.next_after(at(2026, 3, 6, 9, 0)),
+            Some(at(2026, 3, 13, 9, 0))
+        );
+        assert_eq!(
+            either.next_after(at(2026, 3, 13, 9, 0)),
+            Some(at(2026, 3, 20, 9, 0))
+        );
+
+        // Only one restricted: only the 13th
+        let day = Cron::parse("0 9 13 * *").unwrap();
+        assert_eq!(
+            day.next_after(at(2026, 3, 1, 0, 0)),
+            Some(at(2026, 3, 13, 9, 0))
+        );
+        // A day and a weekday with a step both restrict, so 1/2 * fri still ORs
+        let odd_days = Cron::parse("0 9 1/2 * fri").unwrap();
+        assert_eq!(
+            odd_days.next_after(at(2026, 3, 1, 9, 0)),
+            Some(at(2026, 3, 3, 9, 0))
+        );
+    }
+
+    #[test]
+    fn february_29_waits_for_a_leap_year() {
+        let leap_day = Cron::parse("0 0 29 2 *").unwrap();
+        assert_eq!(
+            leap_day.next_after(at(2025, 3, 1, 0, 0)),
+            Some(at(2028, 2, 29, 0, 0))
+        );
+
+        // February 30 never comes
+        let never = Cron::parse("0 0 30 2 *").unwrap();
+        assert_eq!(never.next_after(at(2025, 3, 1, 0, 0)), None);
+    }
+
+    #[test]
+    fn invalid_fields_are_rejected() {
+        for expression in [
+            "5-1 * * * *",
+            "*/0 * * * *",
+            "60 * * * *",
+            "0 24 * * *",
+            "0 0 0 * *",
+            "0 0 * 13 *",
+            "0 0 * * 8",
+            "0 0 * * funday",
+            "0 0 * *",
+            "0 0 * * * *",
+            "@fortnightly",
+        ] {
+            assert!(Cron::parse(expression).is_err(), "{} parsed", expression);
+        }
+    }
+
+    #[test]
+    fn times_skipped_by_daylight_saving_dont_run() {
+        // Clocks go from 02:00 to 03:00 on 2026-03-08, so 02:30 doesn't happen that day
+        let half_two = Cron::parse("30 2 * * *").unwrap();
+        assert_eq!(
+            half_two.next_after(at(2026, 3, 7, 3, 0)),
+            Some(at(2026, 3, 9, 2, 30))
+        );
+
+        // Clocks go back from 02:00 to 01:00 on 2026-11-01; 01:30 runs once, the first
+        // time round
+        let half_one = Cron::parse("30 1 * * *").unwrap();
+        let first = half_one.next_after(at(2026, 10, 31, 2, 0)).unwrap();
+        assert_eq!(first.to_rfc3339(), "2026-11-01T01:30:00-04:00");
+        assert_eq!(half_one.next_after(first), Some(at(2026, 11, 2, 1, 30)));
+    }
+}
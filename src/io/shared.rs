@@ -0,0 +1,39 @@
+//! Connections shared by the inputs and outputs of one type, such as a server the
+//! source reads prompts from and the destination answers on
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Mutex as StdMutex;
+use tokio::sync::OnceCell;
+
+/// One connection per key, such as a bind address or an account, made by the first
+/// input or output that asks for it. Entries with different keys get connections of
+/// their own; `Config::validate` checks that entries sharing a key agree on the rest
+pub(crate) struct Shared<T: 'static> {
+    cells: StdMutex<BTreeMap<String, &'static OnceCell<T>>>,
+}
+
+impl<T: Send + Sync> Shared<T> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            cells: StdMutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The connection for `key`, made with `init` if there isn't one yet
+    pub(crate) async fn get_or_try_init<F, Fut>(&self, key: &str, init: F) -> Result<&'static T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        // Cells live for the whole process, like the connections they hold
+        let cell = *self
+            .cells
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_insert_with(|| Box::leak(Box::new(OnceCell::new())));
+        cell.get_or_try_init(init).await
+    }
+}
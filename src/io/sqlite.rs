@@ -0,0 +1,158 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+use std::sync::Mutex as StdMutex;
+use std::time::Instant;
+use tracing::info;
+
+use super::OutputDestination;
+use crate::providers::{total_usage, Usage};
+
+// Every message, plus one row per answered prompt with its cost and timing
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id TEXT NOT NULL,
+    timestamp TEXT NOT NULL,
+    role TEXT NOT NULL,
+    content TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS exchanges (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id TEXT NOT NULL,
+    started_at TEXT NOT NULL,
+    prompt TEXT NOT NULL,
+    response TEXT NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    latency_ms INTEGER NOT NULL,
+    tool_calls TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS messages_session ON messages (session_id);
+CREATE INDEX IF NOT EXISTS exchanges_session ON exchanges (session_id);
+";
+
+// A prompt waiting for its answer
+struct Exchange {
+    started_at: DateTime<Utc>,
+    started: Instant,
+    prompt: String,
+    usage_before: Usage,
+    tool_calls: Vec<String>,
+}
+
+struct Recorder {
+    connection: Connection,
+    current: Option<Exchange>,
+}
+
+// SQLite output destination implementation
+pub struct SqliteDestination {
+    session_id: String,
+    recorder: StdMutex<Recorder>,
+}
+
+impl SqliteDestination {
+    /// Open (or create) the database at `path`, with one session ID for this run
+    pub fn new(path: &str) -> Result<Self> {
+        let connection =
+            Connection::open(path).context(format!("Failed to open SQLite database: {}", path))?;
+        connection
+            .execute_batch(SCHEMA)
+            .context("Failed to create SQLite tables")?;
+        info!("Recording exchanges to SQLite database {}", path);
+
+        Ok(Self {
+            session_id: uuid::Uuid::new_v4().to_string(),
+            recorder: StdMutex::new(Recorder {
+                connection,
+                current: None,
+            }),
+        })
+    }
+
+    // Store the answered prompt with the tokens used since it arrived
+    fn finish_exchange(
+        &self,
+        connection: &Connection,
+        exchange: Exchange,
+        response: &str,
+    ) -> Result<()> {
+        let usage = total_usage();
+        let prompt_tokens = usage
+            .prompt_tokens
+            .saturating_sub(exchange.usage_before.prompt_tokens);
+        let completion_tokens = usage
+            .completion_tokens
+            .saturating_sub(exchange.usage_before.completion_tokens);
+
+        connection.execute(
+            "INSERT INTO exchanges (session_id, started_at, prompt, response, prompt_tokens, completion_tokens, latency_ms, tool_calls)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                self.session_id,
+                exchange.started_at.to_rfc3339(),
+                exchange.prompt,
+                response,
+                prompt_tokens as i64,
+                completion_tokens as i64,
+                exchange.started.elapsed().as_millis() as i64,
+                serde_json::to_string(&exchange.tool_calls)?,
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OutputDestination for SqliteDestination {
+    fn name(&self) -> &str {
+        "sqlite"
+    }
+
+    fn keeps_transcript(&self) -> bool {
+        true
+    }
+
+    async fn write_message(&self, role: &str, content: &str) -> Result<()> {
+        let now = Utc::now();
+        let mut recorder = self.recorder.lock().unwrap();
+
+        // Images would bloat the database, so only their size is kept
+        let stored = if role == "image" {
+            format!("<{} bytes base64-encoded>", content.len())
+        } else {
+            content.to_string()
+        };
+        recorder.connection.execute(
+            "INSERT INTO messages (session_id, timestamp, role, content) VALUES (?1, ?2, ?3, ?4)",
+            params![self.session_id, now.to_rfc3339(), role, stored],
+        )?;
+
+        match role {
+            "user" => {
+                recorder.current = Some(Exchange {
+                    started_at: now,
+                    started: Instant::now(),
+                    prompt: content.to_string(),
+                    usage_before: total_usage(),
+                    tool_calls: Vec::new(),
+                });
+            }
+            "tool" => {
+                if let Some(exchange) = recorder.current.as_mut() {
+                    exchange.tool_calls.push(content.to_string());
+                }
+            }
+            "assistant" => {
+                if let Some(exchange) = recorder.current.take() {
+                    self.finish_exchange(&recorder.connection, exchange, content)?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
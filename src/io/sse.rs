@@ -0,0 +1,140 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures::Stream;
+use serde::Serialize;
+use std::convert::Infallible;
+use std::sync::{Mutex, OnceLock};
+use tokio::sync::broadcast;
+
+use super::{InboundMessage, OutputDestination};
+
+// Messages for connected SSE clients, served by the webhook server at /events
+static EVENTS: OnceLock<broadcast::Sender<SseMessage>> = OnceLock::new();
+
+fn events() -> &'static broadcast::Sender<SseMessage> {
+    // Room for the chunks of a long streamed response
+    EVENTS.get_or_init(|| broadcast::channel(1024).0)
+}
+
+#[derive(Clone, Serialize)]
+struct SseMessage {
+    // The event name: the role for whole messages, or chunk and end while streaming
+    #[serde(skip)]
+    event: String,
+    role: String,
+    content: String,
+    timestamp: u64,
+    // The session of the webhook request being answered, so each chat page can pick
+    // out its own answers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_id: Option<String>,
+}
+
+/// Handler for `GET /events`, streaming every published message as an event named after
+/// its role, preceded by `chunk` events and an `end` event when it was streamed
+pub async fn handle_events() -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let receiver = events().subscribe();
+    let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(message) => {
+                    let event = Event::default()
+                        .event(message.event.clone())
+                        .json_data(&message)
+                        .unwrap_or_default();
+                    return Some((Ok(event), receiver));
+                }
+                // A slow client misses messages rather than holding up the agent
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("SSE client lagging, skipped {} messages", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+// SSE output destination implementation
+pub struct SseDestination {
+    session_id: Mutex<Option<String>>,
+}
+
+impl SseDestination {
+    pub fn new() -> Self {
+        events();
+        SseDestination {
+            session_id: Mutex::new(None),
+        }
+    }
+
+    fn publish(&self, event: &str, role: &str, content: &str) {
+        let session_id = self.session_id.lock().unwrap().clone();
+        publish(event, role, content, session_id);
+    }
+}
+
+impl Default for SseDestination {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl OutputDestination for SseDestination {
+    fn name(&self) -> &str {
+        "sse"
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        &["assistant", "image", "processing"]
+    }
+
+    // Webhook requests name their session in their sender; other inputs' senders
+    // aren't the server's to hand out
+    fn answering(&self, message: &InboundMessage) {
+        *self.session_id.lock().unwrap() = if message.source == "webhook" {
+            message.sender.clone()
+        } else {
+            None
+        };
+    }
+
+    async fn write_message(&self, role: &str, content: &str) -> Result<()> {
+        self.publish(role, role, content);
+        Ok(())
+    }
+
+    // Pages show a typing indicator until the reply arrives
+    async fn typing(&self, notice: &str) -> Result<()> {
+        self.publish("typing", "processing", notice);
+        Ok(())
+    }
+
+    async fn write_chunk(&self, role: &str, delta: &str) -> Result<()> {
+        self.publish("chunk", role, delta);
+        Ok(())
+    }
+
+    async fn end_message(&self) -> Result<()> {
+        self.publish("end", "assistant", "");
+        Ok(())
+    }
+}
+
+fn publish(event: &str, role: &str, content: &str, session_id: Option<String>) {
+    let message = SseMessage {
+        event: event.to_string(),
+        role: role.to_string(),
+        content: content.to_string(),
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        session_id,
+    };
+    // No connected clients is not an error
+    let _ = events().send(message);
+}
@@ -1,73 +1,204 @@
 use anyhow::Result;
 use async_trait::async_trait;
+use rustyline::error::ReadlineError;
+use rustyline::{Config, DefaultEditor};
 use std::io::{self, BufRead};
 use tokio::sync::mpsc;
-use tokio::task;
 use tracing::error;
 
-use super::InputSource;
+use super::{InboundMessage, InputSource};
+
+// Opens and closes a multi-line block
+const QUOTES: &str = "\"\"\"";
 
 pub struct StdinSource {
     message_rx: mpsc::Receiver<String>,
-    _shutdown_tx: tokio::sync::broadcast::Sender<()>, // Keep sender alive
 }
 
 impl StdinSource {
-    pub fn new() -> Self {
+    /// Read lines from stdin, with line editing and history when it is a terminal.
+    /// Each line is a message, except in a `"""` block or a `<<WORD` heredoc
+    pub fn new(history_file: Option<String>) -> Self {
         let (message_tx, message_rx) = mpsc::channel(100);
 
-        // Create a shutdown channel that is Send
-        let (shutdown_tx, mut shutdown_rx) = tokio::sync::broadcast::channel::<()>(1);
-        let shutdown_tx_clone = shutdown_tx.clone();
-
-        // Spawn a task to read from stdin
-        tokio::spawn(async move {
-            loop {
-                tokio::select! {
-                    // Check for shutdown signal
-                    _ = shutdown_rx.recv() => {
-                        tracing::info!("Stdin source shutting down");
-                        break;
+        if atty::is(atty::Stream::Stdin) {
+            // A paste is inserted into the line whole, newlines and all, instead of
+            // each of its lines being entered as a message
+            let config = Config::builder().bracketed_paste(true).build();
+            match DefaultEditor::with_config(config) {
+                Ok(editor) => {
+                    // readline blocks, so it gets its own thread
+                    std::thread::spawn(move || read_interactive(editor, history_file, message_tx));
+                }
+                Err(e) => {
+                    error!("Line editing unavailable, reading plain stdin: {}", e);
+                    std::thread::spawn(move || read_plain(message_tx));
+                }
+            }
+        } else {
+            std::thread::spawn(move || read_plain(message_tx));
+        }
+
+        Self { message_rx }
+    }
+}
+
+// Read lines with editing and history until the user ends the session
+fn read_interactive(
+    mut editor: DefaultEditor,
+    history_file: Option<String>,
+    message_tx: mpsc::Sender<String>,
+) {
+    if let Some(path) = &history_file {
+        // A missing file just means there is no history yet
+        if let Err(e) = editor.load_history(path) {
+            tracing::debug!("No stdin history loaded from {}: {}", path, e);
+        }
+    }
+
+    let mut block = None;
+    loop {
+        let prompt = if block.is_some() { "... " } else { "> " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                let Some(line) = collect(&mut block, &line) else {
+                    continue;
+                };
+
+                let _ = editor.add_history_entry(line.as_str());
+                if let Some(path) = &history_file {
+                    if let Err(e) = editor.save_history(path) {
+                        error!("Failed to save stdin history to {}: {}", path, e);
                     }
-                    // Read from stdin
-                    line_result = task::spawn_blocking(|| {
-                        let mut line = String::new();
-                        io::stdin().lock().read_line(&mut line).map(|_| line)
-                    }) => {
-                        let line = match line_result {
-                            Ok(result) => result,
-                            Err(e) => {
-                                error!("Failed to spawn blocking task: {}", e);
-                                break;
-                            }
-                        };
-
-                        match line {
-                            Ok(line) => {
-                                let line = line.trim().to_string();
-                                if !line.is_empty() {
-                                    if message_tx.send(line).await.is_err() {
-                                        error!("Failed to send message to channel");
-                                        break;
-                                    }
-                                }
-                            },
-                            Err(e) => {
-                                error!("Failed to read from stdin: {}", e);
-                                break;
-                            }
-                        }
+                }
+
+                let exiting = line.eq_ignore_ascii_case("exit");
+                if message_tx.blocking_send(line).is_err() {
+                    error!("Failed to send message to channel");
+                    break;
+                }
+                // Don't put the terminal back into raw mode while the agent shuts down
+                if exiting {
+                    break;
+                }
+            }
+            // Ctrl-C cancels the line being typed, or the block, not the agent
+            Err(ReadlineError::Interrupted) => block = None,
+            // Ctrl-D ends the session like typing "exit", sending a block left open
+            Err(ReadlineError::Eof) => {
+                if let Some(message) = block.take().and_then(Block::finish) {
+                    let _ = message_tx.blocking_send(message);
+                }
+                let _ = message_tx.blocking_send("exit".to_string());
+                break;
+            }
+            Err(e) => {
+                error!("Failed to read from stdin: {}", e);
+                break;
+            }
+        }
+    }
+
+    tracing::info!("Stdin source task completed");
+}
+
+// Read raw lines, for piped or redirected stdin. A blocked read would hold up the
+// runtime's shutdown, so it gets its own thread like readline does.
+fn read_plain(message_tx: mpsc::Sender<String>) {
+    let mut block = None;
+    for line in io::stdin().lock().lines() {
+        match line {
+            Ok(line) => {
+                if let Some(message) = collect(&mut block, &line) {
+                    if message_tx.blocking_send(message).is_err() {
+                        // The agent has stopped reading
+                        return;
                     }
                 }
             }
+            Err(e) => {
+                error!("Failed to read from stdin: {}", e);
+                break;
+            }
+        }
+    }
+    // Input that ends inside a block still sends what the block has
+    if let Some(message) = block.and_then(Block::finish) {
+        let _ = message_tx.blocking_send(message);
+    }
 
-            tracing::info!("Stdin source task completed");
-        });
+    tracing::info!("Stdin source task completed");
+}
 
-        Self {
-            message_rx,
-            _shutdown_tx: shutdown_tx_clone, // Store sender to keep it alive
+// A message written over several lines, from a line of """ to the next one or from
+// <<WORD to a line of WORD alone
+struct Block {
+    terminator: String,
+    lines: Vec<String>,
+}
+
+impl Block {
+    fn finish(self) -> Option<String> {
+        let message = self.lines.join("\n");
+        (!message.trim().is_empty()).then_some(message)
+    }
+}
+
+// Take one line of input, giving back the message it completes, if any. Lines
+// inside a block keep their indentation, since they are often code
+fn collect(block: &mut Option<Block>, line: &str) -> Option<String> {
+    if let Some(open) = block {
+        let trimmed = line.trim_end();
+        if open.terminator == QUOTES {
+            if let Some(last) = trimmed.strip_suffix(QUOTES) {
+                if !last.trim().is_empty() {
+                    open.lines.push(last.to_string());
+                }
+                return block.take().and_then(Block::finish);
+            }
+        } else if trimmed.trim_start() == open.terminator {
+            return block.take().and_then(Block::finish);
         }
+        open.lines.push(line.to_string());
+        return None;
+    }
+
+    let trimmed = line.trim();
+    // A pasted line is already whole
+    if trimmed.contains('\n') {
+        return Some(trimmed.to_string());
+    }
+    if let Some(first) = trimmed.strip_prefix(QUOTES) {
+        // """on one line""" is an ordinary message
+        if let Some(message) = first.strip_suffix(QUOTES) {
+            let message = message.trim();
+            return (!message.is_empty()).then(|| message.to_string());
+        }
+        *block = Some(Block {
+            terminator: QUOTES.to_string(),
+            lines: (!first.trim().is_empty())
+                .then(|| first.to_string())
+                .into_iter()
+                .collect(),
+        });
+        return None;
+    }
+    if let Some(word) = trimmed.strip_prefix("<<") {
+        let word = word.trim();
+        if !word.is_empty() && word.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            *block = Some(Block {
+                terminator: word.to_string(),
+                lines: Vec::new(),
+            });
+            return None;
+        }
+    }
+    (!trimmed.is_empty()).then(|| trimmed.to_string())
+}
+
+impl Default for StdinSource {
+    fn default() -> Self {
+        Self::new(None)
     }
 }
 
@@ -77,10 +208,14 @@ impl InputSource for StdinSource {
         "stdin"
     }
 
-    async fn read_message(&mut self) -> Result<Option<String>> {
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
         match self.message_rx.recv().await {
-            Some(message) => Ok(Some(message)),
+            Some(message) => Ok(Some(InboundMessage::new(message))),
             None => Ok(None),
         }
     }
+
+    fn reply_to(&self) -> Vec<String> {
+        vec!["stdout".to_string()]
+    }
 }
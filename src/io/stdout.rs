@@ -1,17 +1,36 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use std::io::{self, Write};
+use std::sync::Mutex;
 use tracing::info;
 
 use super::OutputDestination;
 
-pub struct StdoutDestination;
+pub struct StdoutDestination {
+    // The message being streamed, then the last one streamed, so write_message
+    // doesn't print it a second time
+    streamed: Mutex<Streamed>,
+}
+
+#[derive(Default)]
+struct Streamed {
+    text: String,
+    open: bool,
+}
 
 impl StdoutDestination {
     pub fn new() -> Self {
         // Make sure stdout is not buffered
         io::stdout().flush().ok();
-        StdoutDestination
+        StdoutDestination {
+            streamed: Mutex::new(Streamed::default()),
+        }
+    }
+}
+
+impl Default for StdoutDestination {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -22,12 +41,18 @@ impl OutputDestination for StdoutDestination {
     }
 
     async fn write_message(&self, role: &str, content: &str) -> Result<()> {
+        let streamed = std::mem::take(&mut *self.streamed.lock().unwrap());
+        if role == "assistant" && !content.is_empty() && streamed.text == content {
+            return Ok(());
+        }
+
         // Format the message based on the role
         let formatted_message = match role {
             "assistant" => format!("\nAssistant: {}\n", content),
             "user" => format!("\nUser: {}\n", content),
-            "system" => format!("\nSystem: {}\n", content),
+            "system" | "processing" => format!("\nSystem: {}\n", content),
             "tool" => format!("\nTool: {}\n", content),
+            "image" => format!("\nImage: <{} bytes base64-encoded>\n", content.len()),
             _ => format!("\n{}: {}\n", role, content),
         };
 
@@ -43,4 +68,32 @@ impl OutputDestination for StdoutDestination {
 
         Ok(())
     }
+
+    async fn write_chunk(&self, role: &str, delta: &str) -> Result<()> {
+        {
+            let mut streamed = self.streamed.lock().unwrap();
+            if !streamed.open {
+                let label = match role {
+                    "assistant" => "Assistant",
+                    other => other,
+                };
+                print!("\n{}: ", label);
+                *streamed = Streamed {
+                    text: String::new(),
+                    open: true,
+                };
+            }
+            streamed.text.push_str(delta);
+        }
+        print!("{}", delta);
+        io::stdout().flush()?;
+        Ok(())
+    }
+
+    async fn end_message(&self) -> Result<()> {
+        self.streamed.lock().unwrap().open = false;
+        println!();
+        io::stdout().flush()?;
+        Ok(())
+    }
 }
@@ -0,0 +1,306 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc};
+use tokio_native_tls::native_tls;
+use tracing::{error, info};
+
+use super::shared::Shared;
+use super::{InboundMessage, InputSource, OutputDestination};
+
+// The source and destination on a bind address share one listener, so clients read replies on the
+// same connection they send prompts on
+static SERVERS: Shared<TcpServer> = Shared::new();
+
+/// Settings for the TCP line-protocol server
+#[derive(Debug, Clone)]
+pub struct TcpSettings {
+    pub bind: String,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+}
+
+impl TcpSettings {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self {
+            bind: config
+                .tcp_bind
+                .clone()
+                .unwrap_or_else(|| "127.0.0.1:7878".to_string()),
+            tls_cert: config.tcp_tls_cert.clone(),
+            tls_key: config.tcp_tls_key.clone(),
+        }
+    }
+}
+
+// Request and response lines
+#[derive(Deserialize)]
+struct TcpRequest {
+    message: String,
+}
+
+#[derive(Serialize)]
+struct TcpStatus {
+    status: String,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct TcpOutgoingMessage {
+    role: String,
+    content: String,
+    timestamp: u64,
+}
+
+// Set once a TCP input exists; without one, incoming lines are rejected
+type Inbound = Arc<StdMutex<Option<mpsc::Sender<InboundMessage>>>>;
+
+struct TcpServer {
+    addr: SocketAddr,
+    inbound: Inbound,
+    outbound: broadcast::Sender<String>,
+}
+
+// Bind the listener and accept connections, once per bind address
+async fn start(settings: &TcpSettings) -> Result<&'static TcpServer> {
+    SERVERS
+        .get_or_try_init(&settings.bind, || async {
+            let tls = match (&settings.tls_cert, &settings.tls_key) {
+                (Some(cert), Some(key)) => Some(load_tls(cert, key)?),
+                (None, None) => None,
+                _ => anyhow::bail!("TCP TLS requires both tcp_tls_cert and tcp_tls_key"),
+            };
+
+            let listener = TcpListener::bind(&settings.bind)
+                .await
+                .context(format!("Failed to bind TCP server to {}", settings.bind))?;
+            let addr = listener.local_addr()?;
+            info!(
+                "TCP server listening on {}{}",
+                addr,
+                if tls.is_some() { " (TLS)" } else { "" }
+            );
+
+            let inbound: Inbound = Arc::new(StdMutex::new(None));
+            let (outbound, _) = broadcast::channel(100);
+            tokio::spawn(accept_loop(
+                listener,
+                tls,
+                inbound.clone(),
+                outbound.clone(),
+            ));
+
+            Ok(TcpServer {
+                addr,
+                inbound,
+                outbound,
+            })
+        })
+        .await
+}
+
+fn load_tls(cert: &str, key: &str) -> Result<tokio_native_tls::TlsAcceptor> {
+    let cert = std::fs::read(cert).context(format!("Failed to read TLS certificate: {}", cert))?;
+    let key = std::fs::read(key).context(format!("Failed to read TLS key: {}", key))?;
+    let identity = native_tls::Identity::from_pkcs8(&cert, &key)
+        .context("Invalid TLS certificate or key (expected PEM, PKCS#8 key)")?;
+    Ok(native_tls::TlsAcceptor::new(identity)?.into())
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    tls: Option<tokio_native_tls::TlsAcceptor>,
+    inbound: Inbound,
+    outbound: broadcast::Sender<String>,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("Failed to accept TCP connection: {}", e);
+                continue;
+            }
+        };
+        info!("TCP client connected: {}", peer);
+
+        let tls = tls.clone();
+        let inbound = inbound.clone();
+        let outbound = outbound.subscribe();
+        tokio::spawn(async move {
+            let result = match tls {
+                Some(tls) => match tls.accept(stream).await {
+                    Ok(stream) => handle_connection(stream, peer, inbound, outbound).await,
+                    Err(e) => Err(e.into()),
+                },
+                None => handle_connection(stream, peer, inbound, outbound).await,
+            };
+            match result {
+                Ok(()) => info!("TCP client disconnected: {}", peer),
+                Err(e) => error!("TCP client {} error: {}", peer, e),
+            }
+        });
+    }
+}
+
+// Read request lines and write status and broadcast lines until the client disconnects
+async fn handle_connection<S>(
+    stream: S,
+    peer: SocketAddr,
+    inbound: Inbound,
+    mut outbound: broadcast::Receiver<String>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let status = submit(&inbound, peer, &line).await;
+                let mut reply = serde_json::to_string(&status)?;
+                reply.push('\n');
+                writer.write_all(reply.as_bytes()).await?;
+            }
+            message = outbound.recv() => {
+                match message {
+                    Ok(message) => writer.write_all(message.as_bytes()).await?,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("TCP client lagging, skipped {} messages", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                }
+            }
+        }
+    }
+}
+
+// Parse a request line and queue its message for the input source
+async fn submit(inbound: &Inbound, peer: SocketAddr, line: &str) -> TcpStatus {
+    let request = match serde_json::from_str::<TcpRequest>(line) {
+        Ok(request) => request,
+        Err(e) => return TcpStatus::error(format!("Invalid request: {}", e)),
+    };
+
+    let sender = inbound.lock().unwrap().clone();
+    match sender {
+        Some(sender) => match sender
+            .send(InboundMessage::new(request.message).with_remote_addr(peer.ip()))
+            .await
+        {
+            Ok(()) => TcpStatus::success("Message received"),
+            Err(_) => TcpStatus::error("Failed to process message".to_string()),
+        },
+        None => TcpStatus::error("This server does not accept input".to_string()),
+    }
+}
+
+impl TcpStatus {
+    fn success(message: &str) -> Self {
+        Self {
+            status: "success".to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn error(message: String) -> Self {
+        Self {
+            status: "error".to_string(),
+            message,
+        }
+    }
+}
+
+// TCP input source implementation
+pub struct TcpSource {
+    message_rx: mpsc::Receiver<InboundMessage>,
+    addr: SocketAddr,
+}
+
+impl TcpSource {
+    pub async fn new(settings: &TcpSettings) -> Result<Self> {
+        let server = start(settings).await?;
+        let (message_tx, message_rx) = mpsc::channel(100);
+        *server.inbound.lock().unwrap() = Some(message_tx);
+
+        Ok(Self {
+            message_rx,
+            addr: server.addr,
+        })
+    }
+
+    // Get the address the server is listening on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+#[async_trait]
+impl InputSource for TcpSource {
+    fn name(&self) -> &str {
+        "tcp"
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        match self.message_rx.recv().await {
+            Some(message) => Ok(Some(message)),
+            None => Ok(None),
+        }
+    }
+}
+
+// TCP output destination implementation
+pub struct TcpDestination {
+    server: &'static TcpServer,
+}
+
+impl TcpDestination {
+    pub async fn new(settings: &TcpSettings) -> Result<Self> {
+        let server = start(settings).await?;
+        Ok(Self { server })
+    }
+
+    // Get the address the server is listening on
+    pub fn addr(&self) -> SocketAddr {
+        self.server.addr
+    }
+}
+
+#[async_trait]
+impl OutputDestination for TcpDestination {
+    fn name(&self) -> &str {
+        "tcp"
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        &["assistant", "image"]
+    }
+
+    async fn write_message(&self, role: &str, content: &str) -> Result<()> {
+        let message = TcpOutgoingMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+
+        let mut line = serde_json::to_string(&message)?;
+        line.push('\n');
+        // No connected clients is not an error
+        let _ = self.server.outbound.send(line);
+        Ok(())
+    }
+}
@@ -0,0 +1,405 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Position};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::sync::mpsc as std_mpsc;
+use std::sync::{Mutex as StdMutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use super::{InboundMessage, InputSource, OutputDestination};
+
+// The source and destination share one terminal, drawn from a dedicated thread
+static TUI: OnceLock<Tui> = OnceLock::new();
+
+/// What the status bar shows besides token usage
+#[derive(Debug, Clone)]
+pub struct TuiSettings {
+    pub agent_name: String,
+    pub provider: String,
+    pub model: String,
+}
+
+impl TuiSettings {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self {
+            agent_name: config.agent_name.clone(),
+            provider: config.provider.clone(),
+            model: config.model.clone(),
+        }
+    }
+}
+
+// What the UI thread is asked to show
+enum Display {
+    Message(String, String),
+    // Part of a streamed message, by role
+    Chunk(String, String),
+    End,
+}
+
+struct Tui {
+    // Messages for the scrollback, drained by the UI thread
+    display_tx: StdMutex<std_mpsc::Sender<Display>>,
+    // Lines typed by the user, taken by the input source
+    input_rx: StdMutex<Option<mpsc::Receiver<String>>>,
+}
+
+// Take over the terminal and start the UI thread, once per process
+fn start(settings: &TuiSettings) -> &'static Tui {
+    TUI.get_or_init(|| {
+        let (display_tx, display_rx) = std_mpsc::channel();
+        let (input_tx, input_rx) = mpsc::channel(100);
+        let app = App::new(settings.clone(), display_rx, input_tx);
+
+        std::thread::spawn(move || {
+            let mut terminal = ratatui::init();
+            let result = app.run(&mut terminal);
+            ratatui::restore();
+            if let Err(e) = result {
+                tracing::error!("Terminal UI error: {}", e);
+            }
+        });
+
+        Tui {
+            display_tx: StdMutex::new(display_tx),
+            input_rx: StdMutex::new(Some(input_rx)),
+        }
+    })
+}
+
+/// Give the terminal back to the shell; call before exiting the process
+pub fn restore_terminal() {
+    if TUI.get().is_some() {
+        ratatui::restore();
+    }
+}
+
+struct Entry {
+    role: String,
+    content: String,
+}
+
+struct App {
+    settings: TuiSettings,
+    display_rx: std_mpsc::Receiver<Display>,
+    input_tx: mpsc::Sender<String>,
+    entries: Vec<Entry>,
+    // Whether the last entry is a streamed message, and whether it is still arriving
+    streamed: bool,
+    streaming: bool,
+    // The input line as characters, so the cursor can move by character
+    input: Vec<char>,
+    cursor: usize,
+    // Rows scrolled up from the bottom of the scrollback
+    scroll_back: usize,
+    busy: bool,
+    quit: bool,
+}
+
+impl App {
+    fn new(
+        settings: TuiSettings,
+        display_rx: std_mpsc::Receiver<Display>,
+        input_tx: mpsc::Sender<String>,
+    ) -> Self {
+        Self {
+            settings,
+            display_rx,
+            input_tx,
+            entries: Vec::new(),
+            streamed: false,
+            streaming: false,
+            input: Vec::new(),
+            cursor: 0,
+            scroll_back: 0,
+            busy: false,
+            quit: false,
+        }
+    }
+
+    fn run(mut self, terminal: &mut DefaultTerminal) -> std::io::Result<()> {
+        while !self.quit {
+            while let Ok(display) = self.display_rx.try_recv() {
+                match display {
+                    Display::Message(role, content) => self.push(role, content),
+                    Display::Chunk(role, delta) => self.push_chunk(role, &delta),
+                    Display::End => self.streaming = false,
+                }
+            }
+
+            terminal.draw(|frame| self.draw(frame))?;
+
+            // Poll so new messages are drawn even while the user is idle
+            if event::poll(Duration::from_millis(100))? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.handle_key(key);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, role: String, content: String) {
+        match role.as_str() {
+            "user" => self.busy = true,
+            "assistant" => self.busy = false,
+            _ => {}
+        }
+        // A streamed message is shown already
+        let streamed = std::mem::take(&mut self.streamed);
+        if streamed
+            && self
+                .entries
+                .last()
+                .is_some_and(|entry| entry.role == role && entry.content == content)
+        {
+            return;
+        }
+        self.entries.push(Entry { role, content });
+    }
+
+    fn push_chunk(&mut self, role: String, delta: &str) {
+        match self.entries.last_mut() {
+            Some(entry) if self.streaming => entry.content.push_str(delta),
+            _ => self.entries.push(Entry {
+                role,
+                content: delta.to_string(),
+            }),
+        }
+        self.streamed = true;
+        self.streaming = true;
+    }
+
+    fn submit(&mut self) {
+        let line: String = self.input.drain(..).collect();
+        self.cursor = 0;
+        let line = line.trim().to_string();
+        if line.is_empty() {
+            return;
+        }
+        // Jump back to the newest messages to follow the reply
+        self.scroll_back = 0;
+        if self.input_tx.blocking_send(line).is_err() {
+            self.quit = true;
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('c') | KeyCode::Char('d') if ctrl => self.exit(),
+            KeyCode::Esc => self.exit(),
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.cursor = self.input.len(),
+            KeyCode::Char('u') if ctrl => {
+                self.input.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            KeyCode::Char(c) => {
+                self.input.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.input.remove(self.cursor);
+            }
+            KeyCode::Delete if self.cursor < self.input.len() => {
+                self.input.remove(self.cursor);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.input.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.input.len(),
+            KeyCode::Up => self.scroll_back += 1,
+            KeyCode::Down => self.scroll_back = self.scroll_back.saturating_sub(1),
+            KeyCode::PageUp => self.scroll_back += 10,
+            KeyCode::PageDown => self.scroll_back = self.scroll_back.saturating_sub(10),
+            KeyCode::Enter => self.submit(),
+            _ => {}
+        }
+    }
+
+    // Ask the agent to shut down the same way typing "exit" does
+    fn exit(&mut self) {
+        let _ = self.input_tx.blocking_send("exit".to_string());
+        self.quit = true;
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [history_area, input_area, status_area] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let lines: Vec<Line> = self.entries.iter().flat_map(entry_lines).collect();
+        let history = Paragraph::new(lines).wrap(Wrap { trim: false });
+
+        // Scroll so the newest message sits at the bottom, minus what the user scrolled up
+        let rows = history.line_count(history_area.width.saturating_sub(2)) + 2;
+        let max_scroll = rows.saturating_sub(history_area.height as usize);
+        self.scroll_back = self.scroll_back.min(max_scroll);
+        let scroll = (max_scroll - self.scroll_back) as u16;
+
+        let title = if self.scroll_back > 0 {
+            format!(
+                " {} (scrolled up, PageDown to follow) ",
+                self.settings.agent_name
+            )
+        } else {
+            format!(" {} ", self.settings.agent_name)
+        };
+        frame.render_widget(
+            history
+                .block(Block::bordered().title(title))
+                .scroll((scroll, 0)),
+            history_area,
+        );
+
+        // Keep the cursor visible on long input lines
+        let inner_width = input_area.width.saturating_sub(2).max(1) as usize;
+        let offset = (self.cursor + 1).saturating_sub(inner_width);
+        let visible: String = self.input.iter().skip(offset).collect();
+        frame.render_widget(
+            Paragraph::new(visible).block(Block::bordered().title(" Message ")),
+            input_area,
+        );
+        frame.set_cursor_position(Position::new(
+            input_area.x + 1 + (self.cursor - offset) as u16,
+            input_area.y + 1,
+        ));
+
+        let usage = crate::providers::total_usage();
+        let status = format!(
+            " {}/{} | tokens: {} in, {} out | {} | Enter send, PgUp/PgDn scroll, Esc quit",
+            self.settings.provider,
+            self.settings.model,
+            usage.prompt_tokens,
+            usage.completion_tokens,
+            if self.busy { "Thinking..." } else { "Ready" },
+        );
+        frame.render_widget(
+            Paragraph::new(status).style(Style::default().bg(Color::DarkGray).fg(Color::White)),
+            status_area,
+        );
+    }
+}
+
+// Render one message as a role label followed by its content lines
+fn entry_lines(entry: &Entry) -> Vec<Line<'_>> {
+    let (label, color) = match entry.role.as_str() {
+        "user" => ("You", Color::Green),
+        "assistant" => ("Assistant", Color::Cyan),
+        "tool" => ("Tool", Color::Yellow),
+        "image" => ("Image", Color::Magenta),
+        "system" | "processing" => ("System", Color::DarkGray),
+        other => (other, Color::White),
+    };
+    let label = Span::styled(
+        format!("{}: ", label),
+        Style::default().fg(color).add_modifier(Modifier::BOLD),
+    );
+
+    if entry.role == "image" {
+        let summary = format!("<{} bytes base64-encoded>", entry.content.len());
+        return vec![Line::from(vec![label, Span::raw(summary)]), Line::default()];
+    }
+
+    let content_style = match entry.role.as_str() {
+        "tool" | "system" | "processing" => Style::default().fg(Color::DarkGray),
+        _ => Style::default(),
+    };
+    let mut lines: Vec<Line> = Vec::new();
+    for (i, text) in entry.content.lines().enumerate() {
+        let span = Span::styled(text, content_style);
+        if i == 0 {
+            lines.push(Line::from(vec![label.clone(), span]));
+        } else {
+            lines.push(Line::from(span));
+        }
+    }
+    if lines.is_empty() {
+        lines.push(Line::from(label));
+    }
+    lines.push(Line::default());
+    lines
+}
+
+// Terminal UI input source implementation
+pub struct TuiSource {
+    input_rx: mpsc::Receiver<String>,
+}
+
+impl TuiSource {
+    pub fn new(settings: &TuiSettings) -> Result<Self> {
+        let input_rx = start(settings)
+            .input_rx
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Only one TUI input can be configured"))?;
+        Ok(Self { input_rx })
+    }
+}
+
+#[async_trait]
+impl InputSource for TuiSource {
+    fn name(&self) -> &str {
+        "tui"
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        match self.input_rx.recv().await {
+            Some(message) => Ok(Some(InboundMessage::new(message))),
+            None => Ok(None),
+        }
+    }
+}
+
+// Terminal UI output destination implementation
+pub struct TuiDestination {
+    tui: &'static Tui,
+}
+
+impl TuiDestination {
+    pub fn new(settings: &TuiSettings) -> Self {
+        Self {
+            tui: start(settings),
+        }
+    }
+
+    fn display(&self, display: Display) {
+        // The UI may already be closed while the agent shuts down
+        let _ = self.tui.display_tx.lock().unwrap().send(display);
+    }
+}
+
+#[async_trait]
+impl OutputDestination for TuiDestination {
+    fn name(&self) -> &str {
+        "tui"
+    }
+
+    async fn write_message(&self, role: &str, content: &str) -> Result<()> {
+        self.display(Display::Message(role.to_string(), content.to_string()));
+        Ok(())
+    }
+
+    async fn write_chunk(&self, role: &str, delta: &str) -> Result<()> {
+        self.display(Display::Chunk(role.to_string(), delta.to_string()));
+        Ok(())
+    }
+
+    async fn end_message(&self) -> Result<()> {
+        self.display(Display::End);
+        Ok(())
+    }
+}
@@ -0,0 +1,359 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_native_tls::native_tls;
+use tracing::{debug, error, info};
+
+use super::shared::Shared;
+use super::{InboundMessage, InputSource, OutputDestination};
+
+const TWITCH_HOST: &str = "irc.chat.twitch.tv";
+const TWITCH_PORT: u16 = 6697;
+// Twitch rejects chat messages longer than 500 characters
+const MAX_MESSAGE_CHARS: usize = 450;
+
+// One chat connection per account is shared by the source and destination, so replies go to
+// the channel the command came from
+static CONNECTIONS: Shared<TwitchConnection> = Shared::new();
+
+/// Settings for connecting to Twitch chat
+#[derive(Debug, Clone)]
+pub struct TwitchSettings {
+    pub username: String,
+    pub oauth_token: String,
+    pub channels: Vec<String>,
+    pub prefix: String,
+    pub user_cooldown: Duration,
+}
+
+impl TwitchSettings {
+    /// Build settings from the config, reading the token from `TWITCH_OAUTH_TOKEN` if unset
+    pub fn from_config(config: &crate::config::Config) -> Result<Self> {
+        let username = config
+            .twitch_username
+            .clone()
+            .context("Twitch IO requires twitch_username to be set")?;
+        let oauth_token = match &config.twitch_oauth_token {
+            Some(token) => token.clone(),
+            None => std::env::var("TWITCH_OAUTH_TOKEN")
+                .context("TWITCH_OAUTH_TOKEN environment variable not set")?,
+        };
+        if config.twitch_channels.is_empty() {
+            anyhow::bail!("Twitch IO requires at least one entry in twitch_channels");
+        }
+
+        Ok(Self {
+            username: username.to_lowercase(),
+            oauth_token,
+            // IRC channel names are the lowercase login with a leading #
+            channels: config
+                .twitch_channels
+                .iter()
+                .map(|c| format!("#{}", c.trim_start_matches('#').to_lowercase()))
+                .collect(),
+            prefix: config
+                .twitch_prefix
+                .clone()
+                .unwrap_or_else(|| "!ask".to_string()),
+            user_cooldown: Duration::from_secs(config.twitch_user_cooldown.unwrap_or(30)),
+        })
+    }
+}
+
+// The channel and viewer the next reply is for
+#[derive(Debug, Clone)]
+struct ReplyTarget {
+    channel: String,
+    user: String,
+}
+
+// The input's channel, with each message's sender, once there is an input
+type Inbound = Arc<StdMutex<Option<mpsc::Sender<(String, Option<String>)>>>>;
+
+struct TwitchConnection {
+    outgoing: mpsc::Sender<String>,
+    // Set once a Twitch input exists; until then commands are ignored
+    inbound: Inbound,
+    channels: Vec<String>,
+    last_target: Arc<StdMutex<Option<ReplyTarget>>>,
+}
+
+// Start the chat connection task, once per account
+async fn connect(settings: &TwitchSettings) -> Result<&'static TwitchConnection> {
+    CONNECTIONS
+        .get_or_try_init(&settings.username.to_lowercase(), || async {
+            let (outgoing, outgoing_rx) = mpsc::channel(100);
+            let connection = TwitchConnection {
+                outgoing,
+                inbound: Arc::new(StdMutex::new(None)),
+                channels: settings.channels.clone(),
+                last_target: Arc::new(StdMutex::new(None)),
+            };
+
+            tokio::spawn(run(
+                settings.clone(),
+                outgoing_rx,
+                connection.inbound.clone(),
+                connection.last_target.clone(),
+            ));
+
+            Ok(connection)
+        })
+        .await
+}
+
+// Keep a chat connection open, reconnecting with exponential backoff
+async fn run(
+    settings: TwitchSettings,
+    mut outgoing_rx: mpsc::Receiver<String>,
+    inbound: Inbound,
+    last_target: Arc<StdMutex<Option<ReplyTarget>>>,
+) {
+    let mut backoff = Duration::from_secs(1);
+    let mut last_command: HashMap<String, Instant> = HashMap::new();
+
+    loop {
+        let mut session = Session {
+            settings: &settings,
+            inbound: &inbound,
+            last_target: &last_target,
+            last_command: &mut last_command,
+            backoff: &mut backoff,
+        };
+        match session.run(&mut outgoing_rx).await {
+            Ok(()) => info!("Twitch chat connection closed"),
+            Err(e) => error!("Twitch chat connection error: {:#}", e),
+        }
+
+        tokio::time::sleep(backoff).await;
+        backoff = std::cmp::min(backoff * 2, Duration::from_secs(60));
+    }
+}
+
+struct Session<'a> {
+    settings: &'a TwitchSettings,
+    inbound: &'a Inbound,
+    last_target: &'a Arc<StdMutex<Option<ReplyTarget>>>,
+    // When each viewer's last accepted command arrived, for the cooldown
+    last_command: &'a mut HashMap<String, Instant>,
+    backoff: &'a mut Duration,
+}
+
+impl Session<'_> {
+    async fn run(&mut self, outgoing_rx: &mut mpsc::Receiver<String>) -> Result<()> {
+        let tcp = TcpStream::connect((TWITCH_HOST, TWITCH_PORT))
+            .await
+            .context("Failed to connect to Twitch chat")?;
+        let connector = tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+        let stream = connector.connect(TWITCH_HOST, tcp).await?;
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut lines = BufReader::new(reader).lines();
+
+        let token = self.settings.oauth_token.trim_start_matches("oauth:");
+        send_line(&mut writer, &format!("PASS oauth:{}", token)).await?;
+        send_line(&mut writer, &format!("NICK {}", self.settings.username)).await?;
+        for channel in &self.settings.channels {
+            send_line(&mut writer, &format!("JOIN {}", channel)).await?;
+        }
+
+        loop {
+            tokio::select! {
+                line = lines.next_line() => {
+                    let Some(line) = line? else {
+                        return Ok(());
+                    };
+                    if let Some(reply) = self.handle_line(&line).await? {
+                        send_line(&mut writer, &reply).await?;
+                    }
+                }
+                Some(line) = outgoing_rx.recv() => {
+                    send_line(&mut writer, &line).await?;
+                }
+            }
+        }
+    }
+
+    // Handle one line from the server, returning a line to send back if needed
+    async fn handle_line(&mut self, line: &str) -> Result<Option<String>> {
+        if let Some(server) = line.strip_prefix("PING ") {
+            return Ok(Some(format!("PONG {}", server)));
+        }
+        if line.contains(" NOTICE * :Login authentication failed")
+            || line.contains(" NOTICE * :Improperly formatted auth")
+        {
+            anyhow::bail!("Twitch login failed, check twitch_username and the OAuth token");
+        }
+        // The welcome numeric confirms the login worked
+        if line.split(' ').nth(1) == Some("001") {
+            info!("Connected to Twitch chat as {}", self.settings.username);
+            *self.backoff = Duration::from_secs(1);
+            return Ok(None);
+        }
+
+        let Some(message) = parse_privmsg(line) else {
+            return Ok(None);
+        };
+        if message.user == self.settings.username {
+            return Ok(None);
+        }
+        let Some(prompt) = message.text.strip_prefix(&self.settings.prefix) else {
+            return Ok(None);
+        };
+        let prompt = prompt.trim();
+        if prompt.is_empty() {
+            return Ok(None);
+        }
+
+        // One command per viewer per cooldown keeps chat from flooding the agent
+        let now = Instant::now();
+        if let Some(last) = self.last_command.get(&message.user) {
+            if now.duration_since(*last) < self.settings.user_cooldown {
+                debug!(
+                    "Ignoring Twitch command from {} during cooldown",
+                    message.user
+                );
+                return Ok(None);
+            }
+        }
+
+        let sender = self.inbound.lock().unwrap().clone();
+        if let Some(sender) = sender {
+            // Each viewer is a conversation of their own
+            let user = message.user.clone();
+            self.last_command.insert(message.user.clone(), now);
+            *self.last_target.lock().unwrap() = Some(ReplyTarget {
+                channel: message.channel,
+                user: message.user,
+            });
+            if sender.send((prompt.to_string(), Some(user))).await.is_err() {
+                error!("Failed to send message to channel");
+            }
+        }
+        Ok(None)
+    }
+}
+
+async fn send_line<W: AsyncWrite + Unpin>(writer: &mut W, line: &str) -> Result<()> {
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\r\n").await?;
+    Ok(())
+}
+
+struct PrivMsg {
+    user: String,
+    channel: String,
+    text: String,
+}
+
+// Parse `:nick!nick@nick.tmi.twitch.tv PRIVMSG #channel :text`
+fn parse_privmsg(line: &str) -> Option<PrivMsg> {
+    let (prefix, rest) = line.strip_prefix(':')?.split_once(' ')?;
+    let (channel, text) = rest.strip_prefix("PRIVMSG ")?.split_once(" :")?;
+    let user = prefix.split('!').next()?;
+    Some(PrivMsg {
+        user: user.to_lowercase(),
+        channel: channel.to_string(),
+        text: text.to_string(),
+    })
+}
+
+// Split a reply into single-line chat messages short enough for Twitch
+fn chat_messages(content: &str) -> Vec<String> {
+    let text = content.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut messages = Vec::new();
+    let mut current = String::new();
+    for word in text.split(' ') {
+        if !current.is_empty()
+            && current.chars().count() + 1 + word.chars().count() > MAX_MESSAGE_CHARS
+        {
+            messages.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        // A single overlong word is cut rather than rejected
+        current.extend(word.chars().take(MAX_MESSAGE_CHARS));
+    }
+    if !current.is_empty() {
+        messages.push(current);
+    }
+    messages
+}
+
+// Twitch input source implementation
+pub struct TwitchSource {
+    message_rx: mpsc::Receiver<(String, Option<String>)>,
+}
+
+impl TwitchSource {
+    pub async fn new(settings: &TwitchSettings) -> Result<Self> {
+        let connection = connect(settings).await?;
+        let (message_tx, message_rx) = mpsc::channel(100);
+        *connection.inbound.lock().unwrap() = Some(message_tx);
+        Ok(Self { message_rx })
+    }
+}
+
+#[async_trait]
+impl InputSource for TwitchSource {
+    fn name(&self) -> &str {
+        "twitch"
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        match self.message_rx.recv().await {
+            Some((message, sender)) => Ok(Some(InboundMessage::new(message).with_sender(sender))),
+            None => Ok(None),
+        }
+    }
+}
+
+// Twitch output destination implementation
+pub struct TwitchDestination {
+    connection: &'static TwitchConnection,
+}
+
+impl TwitchDestination {
+    pub async fn new(settings: &TwitchSettings) -> Result<Self> {
+        let connection = connect(settings).await?;
+        Ok(Self { connection })
+    }
+}
+
+#[async_trait]
+impl OutputDestination for TwitchDestination {
+    fn name(&self) -> &str {
+        "twitch"
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        &["assistant"]
+    }
+
+    async fn write_message(&self, _role: &str, content: &str) -> Result<()> {
+        // Answer the viewer who asked, or post in every channel if nobody did
+        let last_target = self.connection.last_target.lock().unwrap().clone();
+        let (channels, mention) = match last_target {
+            Some(target) => (vec![target.channel], format!("@{} ", target.user)),
+            None => (self.connection.channels.clone(), String::new()),
+        };
+
+        for channel in channels {
+            for (i, message) in chat_messages(content).into_iter().enumerate() {
+                let mention = if i == 0 { mention.as_str() } else { "" };
+                self.connection
+                    .outgoing
+                    .send(format!("PRIVMSG {} :{}{}", channel, mention, message))
+                    .await
+                    .context("Twitch chat connection has ended")?;
+            }
+        }
+
+        Ok(())
+    }
+}
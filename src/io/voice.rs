@@ -0,0 +1,448 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, Sample, SampleFormat, SizedSample};
+use reqwest::{header, multipart, Client};
+use std::collections::VecDeque;
+use std::io::Cursor;
+use std::sync::mpsc as std_mpsc;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info};
+
+use super::{InboundMessage, InputSource};
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+const DEFAULT_MODEL: &str = "whisper-1";
+const DEFAULT_WHISPER_BINARY: &str = "whisper-cli";
+
+// Whisper works on 16 kHz mono audio
+const WHISPER_SAMPLE_RATE: u32 = 16_000;
+// Speech is detected per frame of this length
+const FRAME_MS: u32 = 30;
+// Audio kept from just before speech starts, so the first syllable isn't clipped
+const PRE_ROLL_MS: u32 = 300;
+// Segments with less speech than this are treated as noise
+const MIN_SPEECH_MS: u32 = 250;
+
+/// Speech-to-text backends
+#[derive(Debug, Clone)]
+pub enum Transcriber {
+    /// An OpenAI-compatible `/v1/audio/transcriptions` endpoint, with a client
+    /// sending the API key and any extra headers
+    Api {
+        url: String,
+        model: String,
+        client: Client,
+    },
+    /// A local whisper.cpp command line
+    Local { binary: String, model: String },
+}
+
+/// Settings for capturing and transcribing microphone audio
+#[derive(Debug, Clone)]
+pub struct VoiceSettings {
+    pub device: Option<String>,
+    pub transcriber: Transcriber,
+    pub language: Option<String>,
+    pub threshold: f32,
+    pub silence_ms: u32,
+    pub max_segment_secs: u32,
+}
+
+impl VoiceSettings {
+    /// The API backend uses the openai provider's API key, base URL and headers,
+    /// whichever provider answers messages
+    pub fn from_config(config: &crate::config::Config) -> Result<Self> {
+        let transcriber = match config.voice_transcriber.as_deref().unwrap_or("api") {
+            "api" => {
+                let api_key = crate::credentials::load_api_key_for(config, "openai")
+                    .context("Voice transcription with the Whisper API needs an OpenAI API key")?;
+                let settings = config.provider_settings("openai");
+                let mut headers = header::HeaderMap::new();
+                headers.insert(
+                    "Authorization",
+                    header::HeaderValue::from_str(&format!("Bearer {}", api_key))?,
+                );
+                Transcriber::Api {
+                    url: match &config.voice_api_url {
+                        Some(url) => url.clone(),
+                        None => format!(
+                            "{}/audio/transcriptions",
+                            settings.base_url(DEFAULT_BASE_URL)
+                        ),
+                    },
+                    model: config
+                        .voice_model
+                        .clone()
+                        .unwrap_or_else(|| DEFAULT_MODEL.to_string()),
+                    client: settings.client(headers)?,
+                }
+            }
+            "local" => Transcriber::Local {
+                binary: config
+                    .voice_whisper_binary
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_WHISPER_BINARY.to_string()),
+                model: config
+                    .voice_whisper_model
+                    .clone()
+                    .context("Local voice transcription requires voice_whisper_model")?,
+            },
+            other => anyhow::bail!(
+                "Unknown voice transcriber: {} (expected api or local)",
+                other
+            ),
+        };
+
+        Ok(Self {
+            device: config.voice_device.clone(),
+            transcriber,
+            language: config.voice_language.clone(),
+            threshold: config.voice_threshold.unwrap_or(0.02) as f32,
+            silence_ms: config.voice_silence_ms.unwrap_or(800) as u32,
+            max_segment_secs: config.voice_max_segment_secs.unwrap_or(30) as u32,
+        })
+    }
+}
+
+// Energy-based voice activity detection, cutting audio into utterances
+struct Segmenter {
+    frame_len: usize,
+    threshold: f32,
+    silence_frames: usize,
+    pre_roll_frames: usize,
+    min_speech_frames: usize,
+    max_frames: usize,
+    // Samples that don't fill a frame yet
+    pending: Vec<f32>,
+    pre_roll: VecDeque<Vec<f32>>,
+    segment: Vec<f32>,
+    segment_frames: usize,
+    speech_frames: usize,
+    silent_run: usize,
+    in_speech: bool,
+}
+
+impl Segmenter {
+    fn new(settings: &VoiceSettings, sample_rate: u32) -> Self {
+        let frames = |ms: u32| (ms / FRAME_MS).max(1) as usize;
+        Self {
+            frame_len: (sample_rate * FRAME_MS / 1000) as usize,
+            threshold: settings.threshold,
+            silence_frames: frames(settings.silence_ms),
+            pre_roll_frames: frames(PRE_ROLL_MS),
+            min_speech_frames: frames(MIN_SPEECH_MS),
+            max_frames: frames(settings.max_segment_secs * 1000),
+            pending: Vec::new(),
+            pre_roll: VecDeque::new(),
+            segment: Vec::new(),
+            segment_frames: 0,
+            speech_frames: 0,
+            silent_run: 0,
+            in_speech: false,
+        }
+    }
+
+    // Feed captured samples, returning any utterances that ended
+    fn push(&mut self, samples: &[f32]) -> Vec<Vec<f32>> {
+        self.pending.extend_from_slice(samples);
+
+        let mut utterances = Vec::new();
+        while self.pending.len() >= self.frame_len {
+            let frame: Vec<f32> = self.pending.drain(..self.frame_len).collect();
+            if let Some(utterance) = self.push_frame(frame) {
+                utterances.push(utterance);
+            }
+        }
+        utterances
+    }
+
+    fn push_frame(&mut self, frame: Vec<f32>) -> Option<Vec<f32>> {
+        let rms = (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt();
+        let loud = rms >= self.threshold;
+
+        if !self.in_speech {
+            if !loud {
+                self.pre_roll.push_back(frame);
+                if self.pre_roll.len() > self.pre_roll_frames {
+                    self.pre_roll.pop_front();
+                }
+                return None;
+            }
+            self.in_speech = true;
+            self.segment_frames = self.pre_roll.len();
+            self.segment = self.pre_roll.drain(..).flatten().collect();
+            self.speech_frames = 0;
+            self.silent_run = 0;
+        }
+
+        self.segment.extend_from_slice(&frame);
+        self.segment_frames += 1;
+        if loud {
+            self.speech_frames += 1;
+            self.silent_run = 0;
+        } else {
+            self.silent_run += 1;
+        }
+
+        if self.silent_run >= self.silence_frames || self.segment_frames >= self.max_frames {
+            self.in_speech = false;
+            let segment = std::mem::take(&mut self.segment);
+            if self.speech_frames >= self.min_speech_frames {
+                return Some(segment);
+            }
+        }
+        None
+    }
+}
+
+// Linear resampling, good enough for speech recognition
+fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to {
+        return samples.to_vec();
+    }
+    let ratio = from as f64 / to as f64;
+    let len = (samples.len() as f64 / ratio) as usize;
+    (0..len)
+        .map(|i| {
+            let position = i as f64 * ratio;
+            let index = position as usize;
+            let fraction = (position - index as f64) as f32;
+            let current = samples[index];
+            let next = samples.get(index + 1).copied().unwrap_or(current);
+            current + (next - current) * fraction
+        })
+        .collect()
+}
+
+// Encode 16 kHz mono samples as a 16-bit PCM WAV file
+fn encode_wav(samples: &[f32]) -> Result<Vec<u8>> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: WHISPER_SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut wav = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut wav, spec)?;
+    for sample in samples {
+        writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+    }
+    writer.finalize()?;
+    Ok(wav.into_inner())
+}
+
+impl Transcriber {
+    async fn transcribe(&self, wav: Vec<u8>, language: Option<&str>) -> Result<String> {
+        match self {
+            Transcriber::Api { url, model, client } => {
+                let file = multipart::Part::bytes(wav)
+                    .file_name("speech.wav")
+                    .mime_str("audio/wav")?;
+                let mut form = multipart::Form::new()
+                    .text("model", model.clone())
+                    .part("file", file);
+                if let Some(language) = language {
+                    form = form.text("language", language.to_string());
+                }
+
+                let response = client.post(url).multipart(form).send().await?;
+                if !response.status().is_success() {
+                    let error_text = response.text().await?;
+                    anyhow::bail!("Transcription API error: {}", error_text);
+                }
+                let body: serde_json::Value = response.json().await?;
+                Ok(body["text"].as_str().unwrap_or_default().trim().to_string())
+            }
+            Transcriber::Local { binary, model } => {
+                // whisper.cpp reads its audio from a file
+                let path = std::env::temp_dir()
+                    .join(format!("agent-voice-{}.wav", uuid::Uuid::new_v4().simple()));
+                tokio::fs::write(&path, wav).await?;
+
+                let mut command = tokio::process::Command::new(binary);
+                command
+                    .arg("-m")
+                    .arg(model)
+                    .arg("-f")
+                    .arg(&path)
+                    .arg("--no-timestamps")
+                    .arg("--no-prints");
+                if let Some(language) = language {
+                    command.arg("-l").arg(language);
+                }
+                let output = command.output().await;
+                let _ = tokio::fs::remove_file(&path).await;
+
+                let output = output.context(format!("Failed to run {}", binary))?;
+                if !output.status.success() {
+                    anyhow::bail!(
+                        "{} failed: {}",
+                        binary,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                }
+                let text = String::from_utf8_lossy(&output.stdout);
+                Ok(text.split_whitespace().collect::<Vec<_>>().join(" "))
+            }
+        }
+    }
+}
+
+// Open the input device and start streaming mono samples to `sample_tx`
+fn open_stream(
+    device_name: Option<&str>,
+    sample_tx: std_mpsc::Sender<Vec<f32>>,
+) -> Result<(cpal::Stream, u32)> {
+    let host = cpal::default_host();
+    let device = match device_name {
+        Some(name) => host
+            .input_devices()?
+            .find(|device| device.name().is_ok_and(|n| n == name))
+            .context(format!("Audio input device not found: {}", name))?,
+        None => host
+            .default_input_device()
+            .context("No default audio input device")?,
+    };
+
+    let supported = device.default_input_config()?;
+    let sample_format = supported.sample_format();
+    let config: cpal::StreamConfig = supported.into();
+    info!(
+        "Capturing audio from {} ({} Hz, {} channels)",
+        device.name().unwrap_or_default(),
+        config.sample_rate.0,
+        config.channels
+    );
+
+    let stream = match sample_format {
+        SampleFormat::F32 => build_stream::<f32>(&device, &config, sample_tx)?,
+        SampleFormat::I16 => build_stream::<i16>(&device, &config, sample_tx)?,
+        SampleFormat::U16 => build_stream::<u16>(&device, &config, sample_tx)?,
+        other => anyhow::bail!("Unsupported audio sample format: {:?}", other),
+    };
+    stream.play()?;
+
+    Ok((stream, config.sample_rate.0))
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    sample_tx: std_mpsc::Sender<Vec<f32>>,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let channels = config.channels as usize;
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            // Mix down to mono
+            let mono = data
+                .chunks(channels)
+                .map(|frame| {
+                    frame.iter().map(|&s| f32::from_sample(s)).sum::<f32>() / channels as f32
+                })
+                .collect();
+            let _ = sample_tx.send(mono);
+        },
+        |e| error!("Audio input error: {}", e),
+        None,
+    )?;
+    Ok(stream)
+}
+
+// cpal streams can't move between threads, so capture and segmentation run on
+// a dedicated thread that hands 16 kHz utterances to the transcription task
+fn capture(
+    settings: VoiceSettings,
+    utterance_tx: mpsc::Sender<Vec<f32>>,
+    ready: oneshot::Sender<Result<()>>,
+) {
+    let (sample_tx, sample_rx) = std_mpsc::channel();
+    let (_stream, sample_rate) = match open_stream(settings.device.as_deref(), sample_tx) {
+        Ok(opened) => opened,
+        Err(e) => {
+            let _ = ready.send(Err(e));
+            return;
+        }
+    };
+    let _ = ready.send(Ok(()));
+
+    let mut segmenter = Segmenter::new(&settings, sample_rate);
+    while let Ok(samples) = sample_rx.recv() {
+        for utterance in segmenter.push(&samples) {
+            let utterance = resample(&utterance, sample_rate, WHISPER_SAMPLE_RATE);
+            if utterance_tx.blocking_send(utterance).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Voice input source implementation
+pub struct VoiceSource {
+    message_rx: mpsc::Receiver<String>,
+}
+
+impl VoiceSource {
+    pub async fn new(settings: VoiceSettings) -> Result<Self> {
+        let (utterance_tx, mut utterance_rx) = mpsc::channel::<Vec<f32>>(10);
+        let (ready_tx, ready_rx) = oneshot::channel();
+
+        let capture_settings = settings.clone();
+        std::thread::spawn(move || capture(capture_settings, utterance_tx, ready_tx));
+        ready_rx
+            .await
+            .context("Audio capture thread exited during startup")??;
+
+        let (message_tx, message_rx) = mpsc::channel(100);
+        tokio::spawn(async move {
+            while let Some(utterance) = utterance_rx.recv().await {
+                let text = match encode_wav(&utterance) {
+                    Ok(wav) => {
+                        settings
+                            .transcriber
+                            .transcribe(wav, settings.language.as_deref())
+                            .await
+                    }
+                    Err(e) => Err(e),
+                };
+
+                match text {
+                    // whisper.cpp marks silence and noise as e.g. [BLANK_AUDIO]
+                    Ok(text)
+                        if text.is_empty() || (text.starts_with('[') && text.ends_with(']')) => {}
+                    Ok(text) => {
+                        info!("Heard: {}", text);
+                        if message_tx.send(text).await.is_err() {
+                            error!("Failed to send message to channel");
+                            break;
+                        }
+                    }
+                    Err(e) => error!("Failed to transcribe speech: {:#}", e),
+                }
+            }
+
+            tracing::info!("Voice input task completed");
+        });
+
+        Ok(Self { message_rx })
+    }
+}
+
+#[async_trait]
+impl InputSource for VoiceSource {
+    fn name(&self) -> &str {
+        "voice"
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        match self.message_rx.recv().await {
+            Some(message) => Ok(Some(InboundMessage::new(message))),
+            None => Ok(None),
+        }
+    }
+}
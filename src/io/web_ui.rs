@@ -0,0 +1,37 @@
+use axum::extract::Path;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use rust_embed::RustEmbed;
+
+// The chat page, compiled into the binary so it needs no files at runtime
+#[derive(RustEmbed)]
+#[folder = "web/"]
+struct Assets;
+
+/// Handler for `GET /chat`, the chat page
+pub(super) async fn handle_index() -> Response {
+    serve("index.html")
+}
+
+/// Handler for `GET /chat/*path`, the page's scripts and styles
+pub(super) async fn handle_asset(Path(path): Path<String>) -> Response {
+    serve(&path)
+}
+
+fn serve(path: &str) -> Response {
+    match Assets::get(path) {
+        Some(file) => ([(header::CONTENT_TYPE, content_type(path))], file.data).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+fn content_type(path: &str) -> &'static str {
+    match path.rsplit('.').next() {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        _ => "application/octet-stream",
+    }
+}
@@ -1,41 +1,249 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
 use axum::{
-    routing::{post, get},
-    Router,
-    extract::State,
-    response::IntoResponse,
-    http::StatusCode,
-    Json,
+    body::Bytes,
+    extract::{ConnectInfo, DefaultBodyLimit, Query, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
 };
+use reqwest;
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Instant, SystemTime};
+use tokio::net::TcpListener;
 use tokio::sync::{mpsc, Mutex};
 use tokio::task::JoinHandle;
-use tracing::{info, error};
-use portpicker::pick_unused_port;
-use tokio::net::TcpListener;
-use reqwest;
-use serde_json::json;
-use std::time::SystemTime;
+use tracing::{error, info, warn};
 
-use super::{InputSource, OutputDestination};
+use super::github::GithubSettings;
+use super::http_server::HttpSettings;
+use super::retry::Undeliverable;
+use super::{InboundMessage, InputSource, OutputDestination};
 
 // Message queue for webhook input
-type MessageSender = mpsc::Sender<String>;
-type MessageReceiver = Mutex<mpsc::Receiver<String>>;
+type MessageSender = mpsc::Sender<InboundMessage>;
+type MessageReceiver = Mutex<mpsc::Receiver<InboundMessage>>;
+
+/// Credentials required by the webhook server's routes
+#[derive(Debug, Clone, Default)]
+pub struct WebhookAuth {
+    // Expected in an `Authorization: Bearer` header on the root route
+    pub token: Option<String>,
+    // Also accepted there, by name, so messages say whose token they came with
+    pub tokens: BTreeMap<String, String>,
+    // Key for the HMAC-SHA256 of the body in an `X-Signature-256: sha256=<hex>` header
+    pub secret: Option<String>,
+    // Bearer token for the /admin API, which is only served when this is set
+    pub admin_token: Option<String>,
+}
+
+impl WebhookAuth {
+    /// Build settings from the config, falling back to `WEBHOOK_TOKEN`, `WEBHOOK_SECRET`
+    /// and `ADMIN_TOKEN`. Empty values count as unset, since an empty token would let
+    /// every request without one through
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        let setting = |value: &Option<String>, var: &str| {
+            value
+                .clone()
+                .filter(|value| !value.is_empty())
+                .or_else(|| std::env::var(var).ok().filter(|value| !value.is_empty()))
+        };
+        Self {
+            token: setting(&config.webhook_token, "WEBHOOK_TOKEN"),
+            tokens: config
+                .webhook_tokens
+                .iter()
+                .filter(|(_, token)| !token.is_empty())
+                .map(|(name, token)| (name.clone(), token.clone()))
+                .collect(),
+            secret: setting(&config.webhook_secret, "WEBHOOK_SECRET"),
+            admin_token: setting(&config.admin_token, "ADMIN_TOKEN"),
+        }
+    }
+
+    /// Whether the root route requires credentials
+    pub fn is_enabled(&self) -> bool {
+        self.has_tokens() || self.secret.is_some()
+    }
+
+    // Every configured check must pass; with both a token and named tokens, any of
+    // them will do
+    fn verify(&self, headers: &HeaderMap, body: &[u8]) -> bool {
+        if !self.token_matches(bearer(headers)) {
+            return false;
+        }
+        match &self.secret {
+            Some(secret) => {
+                super::github::verify_signature(secret, "x-signature-256", headers, body)
+            }
+            None => true,
+        }
+    }
+
+    // The event stream has no body to sign, and browsers can't sign requests, so it
+    // takes a token alone. With only a secret set there is no token to give, so the
+    // stream stays closed rather than open to anyone
+    fn verify_stream(&self, given: &str) -> bool {
+        if !self.has_tokens() {
+            return self.secret.is_none();
+        }
+        self.token_matches(given)
+    }
+
+    fn has_tokens(&self) -> bool {
+        self.token.is_some() || !self.tokens.is_empty()
+    }
+
+    // Whether `given` is the token or one of the named tokens, if any are set
+    fn token_matches(&self, given: &str) -> bool {
+        if !self.has_tokens() {
+            return true;
+        }
+        let matches = self
+            .token
+            .as_ref()
+            .is_some_and(|token| constant_time_eq(given.as_bytes(), token.as_bytes()));
+        matches || self.named(given).is_some()
+    }
+
+    // The name of the named token the request carries, if it carries one
+    fn token_name(&self, headers: &HeaderMap) -> Option<String> {
+        self.named(bearer(headers))
+    }
+
+    fn named(&self, given: &str) -> Option<String> {
+        self.tokens
+            .iter()
+            .find(|(_, token)| constant_time_eq(given.as_bytes(), token.as_bytes()))
+            .map(|(name, _)| name.clone())
+    }
+}
+
+fn bearer(headers: &HeaderMap) -> &str {
+    headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default()
+}
+
+/// Limits protecting the webhook server from floods and oversized requests
+#[derive(Debug, Clone)]
+pub struct WebhookLimits {
+    // Requests a minute each client address may make to the prompt, event and admin routes;
+    // 0 turns the limit off
+    pub rate_limit: u32,
+    pub max_body_bytes: usize,
+}
+
+impl WebhookLimits {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self {
+            rate_limit: config.webhook_rate_limit.unwrap_or(60),
+            max_body_bytes: config.webhook_max_body.unwrap_or(1024 * 1024),
+        }
+    }
+}
+
+// A token bucket per client address, refilling at the limit per minute and holding
+// at most a minute's worth
+struct RateLimiter {
+    limit: u32,
+    // Says which address a request came from when it passed through a proxy
+    http: HttpSettings,
+    buckets: StdMutex<HashMap<IpAddr, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    fn check(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let capacity = self.limit as f64;
+        let refill = |tokens: f64, since: Instant| {
+            (tokens + now.duration_since(since).as_secs_f64() * capacity / 60.0).min(capacity)
+        };
+
+        let mut buckets = self.buckets.lock().unwrap();
+        // Full buckets are the same as none, so drop them before the map grows large
+        if buckets.len() > 10_000 {
+            buckets.retain(|_, (tokens, since)| refill(*tokens, *since) < capacity);
+        }
+        let (tokens, since) = buckets.entry(ip).or_insert((capacity, now));
+        *tokens = refill(*tokens, *since);
+        *since = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Middleware answering 429 to clients over their rate limit
+async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let ip = limiter.http.client_ip(addr.ip(), request.headers());
+    if limiter.check(ip) {
+        return next.run(request).await;
+    }
+    warn!("Rate limited webhook request from {}", ip);
+    let response = WebhookResponse {
+        status: "error".to_string(),
+        message: "Too many requests".to_string(),
+    };
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(
+            "retry-after",
+            (60 / limiter.limit.max(1)).max(1).to_string(),
+        )],
+        Json(response),
+    )
+        .into_response()
+}
+
+// Compare without returning early, so timing doesn't reveal how much of a token matched
+pub(super) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
 
 // Shared state for the Axum server
 #[derive(Clone)]
 struct AppState {
     message_sender: MessageSender,
+    auth: Arc<WebhookAuth>,
+    // Says which address a message came from when it passed through a proxy
+    http: Arc<HttpSettings>,
+    // Set when GitHub events are configured
+    github: Option<Arc<GithubSettings>>,
 }
 
 // Request and response structures
 #[derive(Deserialize)]
 struct WebhookRequest {
     message: String,
+    // Who sent the message, for a conversation of their own; session_id wins
+    #[serde(default)]
+    sender: Option<String>,
+    #[serde(default)]
+    session_id: Option<String>,
+}
+
+// Query of `GET /events`; browsers' EventSource can't send an Authorization header,
+// so the token can come here instead
+#[derive(Deserialize)]
+struct EventsQuery {
+    #[serde(default)]
+    token: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -48,41 +256,45 @@ struct WebhookResponse {
 pub struct WebhookSource {
     receiver: MessageReceiver,
     server_handle: Arc<StdMutex<Option<JoinHandle<()>>>>,
-    port: u16,
+    addr: SocketAddr,
 }
 
 impl WebhookSource {
-    pub fn new() -> Self {
+    /// Create a new webhook source, listening on `bind` at `port`, or on a free port
+    /// picked by the OS if none is set
+    ///
+    /// Requests to the root route and `/events` must pass `auth`, and every request is held to
+    /// `limits`, with clients behind proxies and browser origins handled as `http` says.
+    /// With `github` settings, the server also accepts GitHub events at `/github`.
+    pub async fn new(
+        bind: &str,
+        port: Option<u16>,
+        auth: WebhookAuth,
+        limits: WebhookLimits,
+        http: HttpSettings,
+        github: Option<GithubSettings>,
+    ) -> Result<Self> {
         // Create a channel for message passing
         let (sender, receiver) = mpsc::channel(100);
         let receiver = Mutex::new(receiver);
-        
-        // Find an available port
-        let port = pick_unused_port().expect("No available ports");
-        
-        // Create the server handle
-        let server_handle = Arc::new(StdMutex::new(None));
-        let server_handle_clone = server_handle.clone();
-        
-        // Start the server in a separate task
-        let sender_clone = sender.clone();
-        tokio::spawn(async move {
-            // Start the HTTP server
-            if let Err(e) = start_webhook_server(port, sender_clone, server_handle_clone).await {
-                error!("Failed to start webhook server: {}", e);
-            }
-        });
-        
-        WebhookSource {
+
+        // Bind before returning, so a taken port is reported instead of leaving the
+        // input silently dead
+        let (addr, handle) =
+            start_webhook_server(bind, port.unwrap_or(0), sender, auth, limits, http, github)
+                .await?;
+        let server_handle = Arc::new(StdMutex::new(Some(handle)));
+
+        Ok(WebhookSource {
             receiver,
             server_handle,
-            port,
-        }
+            addr,
+        })
     }
-    
-    // Get the port the server is listening on
-    pub fn port(&self) -> u16 {
-        self.port
+
+    // Get the address the server is listening on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
     }
 }
 
@@ -91,8 +303,8 @@ impl InputSource for WebhookSource {
     fn name(&self) -> &str {
         "webhook"
     }
-    
-    async fn read_message(&mut self) -> Result<Option<String>> {
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
         // Try to receive a message from the channel
         let mut receiver = self.receiver.lock().await;
         match receiver.try_recv() {
@@ -101,20 +313,17 @@ impl InputSource for WebhookSource {
                 // No message available, wait a bit
                 tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
                 Ok(None)
-            },
+            }
             Err(mpsc::error::TryRecvError::Disconnected) => {
                 // Channel is closed, this shouldn't happen
                 Err(anyhow::anyhow!("Webhook message channel disconnected"))
             }
         }
     }
-    
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
-    }
-    
-    fn clone_box(&self) -> Box<dyn InputSource> {
-        Box::new(WebhookSource::new())
+
+    // The server also answers the chat page and GitHub
+    fn reply_to(&self) -> Vec<String> {
+        ["webhook", "sse", "github"].map(String::from).to_vec()
     }
 }
 
@@ -129,56 +338,125 @@ impl Drop for WebhookSource {
 
 // Start the webhook HTTP server
 async fn start_webhook_server(
-    port: u16, 
+    bind: &str,
+    port: u16,
     sender: MessageSender,
-    server_handle: Arc<StdMutex<Option<JoinHandle<()>>>>
-) -> Result<()> {
+    auth: WebhookAuth,
+    limits: WebhookLimits,
+    http: HttpSettings,
+    github: Option<GithubSettings>,
+) -> Result<(SocketAddr, JoinHandle<()>)> {
+    let admin_token = auth.admin_token.clone();
+    let cors = http.cors_layer()?;
+
     // Create the application state
-    let state = AppState { message_sender: sender };
-    
-    // Build the router
-    let app = Router::new()
+    let state = AppState {
+        message_sender: sender,
+        auth: Arc::new(auth),
+        http: Arc::new(http.clone()),
+        github: github.map(Arc::new),
+    };
+
+    // Build the router; prompts can run up the API bill, and admin requests and the
+    // event stream can guess tokens, so those routes are rate limited
+    let mut app = Router::new()
         .route("/", post(handle_webhook))
-        .route("/health", get(health_check))
-        .with_state(state);
-    
-    // Create the socket address
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
-    
-    info!("Webhook server listening on http://{}", addr);
-    
+        .route("/github", post(handle_github))
+        .route("/events", get(handle_events))
+        .with_state(state.clone());
+    if let Some(token) = admin_token {
+        app = app.nest("/admin", super::admin::router(token));
+    }
+    if limits.rate_limit > 0 {
+        let limiter = Arc::new(RateLimiter {
+            limit: limits.rate_limit,
+            http,
+            buckets: StdMutex::new(HashMap::new()),
+        });
+        app = app.route_layer(middleware::from_fn_with_state(limiter, rate_limit));
+    }
+    let mut app = app
+        .merge(
+            Router::new()
+                .route("/health", get(health_check))
+                .route("/healthz", get(health_check))
+                .route("/readyz", get(readiness_check))
+                .route("/metrics", get(metrics))
+                .route("/chat", get(super::web_ui::handle_index))
+                .route("/chat/*path", get(super::web_ui::handle_asset))
+                .with_state(state),
+        )
+        .layer(DefaultBodyLimit::max(limits.max_body_bytes));
+    // Outermost, so preflights are answered and rejections still carry CORS headers
+    if let Some(cors) = cors {
+        app = app.layer(cors);
+    }
+
     // Create a TCP listener
-    let listener = TcpListener::bind(addr).await?;
-    
+    let listener = TcpListener::bind((bind, port)).await.context(format!(
+        "Failed to bind webhook server to {}:{}",
+        bind, port
+    ))?;
+    let addr = listener.local_addr()?;
+    info!("Webhook server listening on http://{}", addr);
+
     // Start the server
-    let server = axum::serve(listener, app);
-    
-    // Store the server handle
+    // Client addresses are needed for rate limiting
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    );
+
+    // The source aborts the server when it's dropped
     let handle = tokio::spawn(async move {
         if let Err(e) = server.await {
             error!("Webhook server error: {}", e);
         }
     });
-    
-    *server_handle.lock().unwrap() = Some(handle);
-    
-    Ok(())
+
+    Ok((addr, handle))
 }
 
-// Handler for webhook POST requests
+// Handler for webhook POST requests, which need the raw body to check the signature
 async fn handle_webhook(
     State(state): State<AppState>,
-    Json(payload): Json<WebhookRequest>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
 ) -> impl IntoResponse {
+    if !state.auth.verify(&headers, &body) {
+        error!("Rejected webhook request with missing or invalid credentials");
+        let response = WebhookResponse {
+            status: "error".to_string(),
+            message: "Unauthorized".to_string(),
+        };
+        return (StatusCode::UNAUTHORIZED, Json(response));
+    }
+
+    let payload: WebhookRequest = match serde_json::from_slice(&body) {
+        Ok(payload) => payload,
+        Err(e) => {
+            let response = WebhookResponse {
+                status: "error".to_string(),
+                message: format!("Invalid request: {}", e),
+            };
+            return (StatusCode::BAD_REQUEST, Json(response));
+        }
+    };
+
     // Send the message to the channel
-    match state.message_sender.send(payload.message).await {
+    let mut message = InboundMessage::new(payload.message)
+        .with_sender(payload.session_id.or(payload.sender))
+        .with_remote_addr(state.http.client_ip(addr.ip(), &headers));
+    message.credential = state.auth.token_name(&headers);
+    match state.message_sender.send(message).await {
         Ok(_) => {
             let response = WebhookResponse {
                 status: "success".to_string(),
                 message: "Message received".to_string(),
             };
             (StatusCode::OK, Json(response))
-        },
+        }
         Err(_) => {
             let response = WebhookResponse {
                 status: "error".to_string(),
@@ -189,9 +467,62 @@ async fn handle_webhook(
     }
 }
 
-// Health check endpoint
+// Handler for the SSE stream, which carries the answers to every prompt, so it takes
+// the same token as the prompts do
+async fn handle_events(
+    State(state): State<AppState>,
+    Query(query): Query<EventsQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let given = query.token.as_deref().unwrap_or_else(|| bearer(&headers));
+    if !state.auth.verify_stream(given) {
+        error!("Rejected SSE client with missing or invalid credentials");
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    super::sse::handle_events().await.into_response()
+}
+
+// Handler for GitHub webhook deliveries, which need the raw body to check the signature
+async fn handle_github(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let ip = state.http.client_ip(addr.ip(), &headers);
+    match &state.github {
+        Some(settings) => {
+            super::github::handle_event(settings, &state.message_sender, ip, &headers, &body).await
+        }
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+// Liveness check: the process is up and serving requests
 async fn health_check() -> impl IntoResponse {
-    StatusCode::OK
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+// Readiness check: every connection, input and the provider is working
+async fn readiness_check() -> impl IntoResponse {
+    let (ready, checks) = crate::health::checks();
+    let (status, text) = if ready {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not_ready")
+    };
+    (
+        status,
+        Json(serde_json::json!({ "status": text, "checks": checks })),
+    )
+}
+
+// Input queue metrics, for Prometheus to scrape
+async fn metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        crate::queue::metrics(),
+    )
 }
 
 // Webhook output destination implementation
@@ -206,7 +537,7 @@ impl WebhookDestination {
             url: url.to_string(),
         })
     }
-    
+
     // Get the webhook URL
     pub fn url(&self) -> &str {
         &self.url
@@ -225,49 +556,134 @@ impl OutputDestination for WebhookDestination {
     fn name(&self) -> &str {
         "webhook"
     }
-    
+
+    fn default_roles(&self) -> &[&str] {
+        &["assistant", "image"]
+    }
+
     async fn write_message(&self, role: &str, content: &str) -> Result<()> {
-        // If we have a webhook URL and the role is "assistant", send the message
-        // You can modify this condition to include other roles if needed
-        if role == "assistant" {
-            info!("Sending webhook to URL: {}", self.url);
-            
-            // Create the JSON payload
-            let json = json!({
-                "role": role,
-                "content": content,
-                "timestamp": SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_secs(),
-            });
-            
-            // Send the webhook
-            let client = reqwest::Client::new();
-            match client.post(&self.url)
-                .header("Content-Type", "application/json")
-                .json(&json)
-                .send()
-                .await 
-            {
-                Ok(response) => {
-                    if response.status().is_success() {
-                        info!("Webhook sent successfully");
-                    } else {
-                        error!("Failed to send webhook: HTTP {}", response.status());
-                    }
-                },
-                Err(e) => {
-                    error!("Failed to send webhook: {}", e);
-                    return Err(anyhow::anyhow!("Failed to send webhook: {}", e));
-                }
-            }
+        info!("Sending webhook to URL: {}", self.url);
+
+        // Create the JSON payload
+        let json = WebhookOutgoingMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+
+        // Send the webhook
+        let client = reqwest::Client::new();
+        let response = client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .json(&json)
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to send webhook: {}", e))?;
+
+        // A busy or failing receiver may take it later; anything else it turned away
+        // would be turned away again
+        let status = response.status();
+        if status.is_success() {
+            info!("Webhook sent successfully");
+            Ok(())
+        } else if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            Err(anyhow::anyhow!("Failed to send webhook: HTTP {}", status))
+        } else {
+            Err(Undeliverable(format!("Webhook rejected: HTTP {}", status)).into())
         }
-        
-        Ok(())
     }
-    
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    fn headers(token: Option<&str>, signature: Option<String>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(token) = token {
+            let value = format!("Bearer {}", token);
+            headers.insert("authorization", value.parse().unwrap());
+        }
+        if let Some(signature) = signature {
+            headers.insert("x-signature-256", signature.parse().unwrap());
+        }
+        headers
+    }
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn signatures_cover_the_exact_body() {
+        let auth = WebhookAuth {
+            secret: Some("shh".to_string()),
+            ..Default::default()
+        };
+        let body = br#"{"message":"hi"}"#;
+        assert!(auth.verify(&headers(None, Some(sign("shh", body))), body));
+        assert!(!auth.verify(&headers(None, Some(sign("shh", body))), b"{}"));
+        assert!(!auth.verify(&headers(None, Some(sign("other", body))), body));
+        assert!(!auth.verify(&headers(None, Some("sha256=zz".to_string())), body));
+        let unprefixed = sign("shh", body).replace("sha256=", "");
+        assert!(!auth.verify(&headers(None, Some(unprefixed)), body));
+        assert!(!auth.verify(&headers(None, None), body));
+    }
+
+    #[test]
+    fn tokens_and_signatures_must_both_pass() {
+        let auth = WebhookAuth {
+            token: Some("let-me-in".to_string()),
+            tokens: BTreeMap::from([("ci".to_string(), "ci-token".to_string())]),
+            secret: Some("shh".to_string()),
+            ..Default::default()
+        };
+        let body = b"{}";
+        let signed = || Some(sign("shh", body));
+        assert!(auth.verify(&headers(Some("let-me-in"), signed()), body));
+        assert!(auth.verify(&headers(Some("ci-token"), signed()), body));
+        assert!(!auth.verify(&headers(Some("let-me-in"), None), body));
+        assert!(!auth.verify(&headers(Some("wrong"), signed()), body));
+        assert!(!auth.verify(&headers(None, signed()), body));
+
+        // Named tokens say whose they are
+        let named = headers(Some("ci-token"), None);
+        assert_eq!(auth.token_name(&named).as_deref(), Some("ci"));
+        assert_eq!(auth.token_name(&headers(Some("let-me-in"), None)), None);
+    }
+
+    #[test]
+    fn the_event_stream_takes_a_token_alone() {
+        let signed = WebhookAuth {
+            token: Some("let-me-in".to_string()),
+            secret: Some("shh".to_string()),
+            ..Default::default()
+        };
+        assert!(signed.verify_stream("let-me-in"));
+        assert!(!signed.verify_stream("wrong"));
+
+        // With only a secret there is no token a browser could give
+        let secret_only = WebhookAuth {
+            secret: Some("shh".to_string()),
+            ..Default::default()
+        };
+        assert!(!secret_only.verify_stream(""));
+        assert!(WebhookAuth::default().verify_stream(""));
+    }
+
+    #[test]
+    fn tokens_compare_whole() {
+        assert!(constant_time_eq(b"let-me-in", b"let-me-in"));
+        assert!(!constant_time_eq(b"let-me-in", b"let-me-io"));
+        assert!(!constant_time_eq(b"let-me", b"let-me-in"));
+        assert!(!constant_time_eq(b"", b"let-me-in"));
+    }
+}
@@ -0,0 +1,302 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::sync::mpsc;
+use tokio_xmpp::parsers::message::{Body, Message, MessageType};
+use tokio_xmpp::parsers::muc::Muc;
+use tokio_xmpp::parsers::presence::{Presence, Type as PresenceType};
+use tokio_xmpp::parsers::{BareJid, Element, Jid};
+use tokio_xmpp::{AsyncClient, Event};
+use tracing::{error, info, warn};
+
+use super::shared::Shared;
+use super::{InboundMessage, InputSource, OutputDestination};
+
+// One session per account is shared by the source and destination, so replies go out on the
+// same stream the messages came in on
+static CONNECTIONS: Shared<XmppConnection> = Shared::new();
+
+/// Settings for connecting to an XMPP server
+#[derive(Debug, Clone)]
+pub struct XmppSettings {
+    pub jid: String,
+    pub password: String,
+    pub rooms: Vec<String>,
+    pub nick: String,
+}
+
+impl XmppSettings {
+    /// Build settings from the config, reading the password from `XMPP_PASSWORD` if unset
+    pub fn from_config(config: &crate::config::Config) -> Result<Self> {
+        let jid = config
+            .xmpp_jid
+            .clone()
+            .context("XMPP IO requires xmpp_jid to be set")?;
+        let password = match &config.xmpp_password {
+            Some(password) => password.clone(),
+            None => std::env::var("XMPP_PASSWORD")
+                .context("XMPP_PASSWORD environment variable not set")?,
+        };
+
+        Ok(Self {
+            jid,
+            password,
+            rooms: config.xmpp_rooms.clone(),
+            nick: config
+                .xmpp_nick
+                .clone()
+                .unwrap_or_else(|| config.agent_name.clone()),
+        })
+    }
+}
+
+// Where the reply to a message goes
+#[derive(Debug, Clone)]
+enum ReplyTarget {
+    Chat(Jid),
+    Room(BareJid),
+}
+
+// The input's channel, with each message's sender, once there is an input
+type Inbound = Arc<StdMutex<Option<mpsc::Sender<(String, Option<String>)>>>>;
+
+// Outgoing stanzas for the session task, plus the conversation to answer next
+struct XmppConnection {
+    outgoing: mpsc::Sender<Element>,
+    // Set once an XMPP input exists; until then incoming messages are dropped
+    inbound: Inbound,
+    rooms: Vec<BareJid>,
+    last_target: Arc<StdMutex<Option<ReplyTarget>>>,
+}
+
+// Start the session task, once per account
+async fn connect(settings: &XmppSettings) -> Result<&'static XmppConnection> {
+    CONNECTIONS
+        .get_or_try_init(&settings.jid, || async {
+            let jid: BareJid = settings
+                .jid
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid XMPP JID {}: {}", settings.jid, e))?;
+            let mut rooms = Vec::new();
+            for room in &settings.rooms {
+                let room: BareJid = room
+                    .parse()
+                    .map_err(|e| anyhow::anyhow!("Invalid XMPP room {}: {}", room, e))?;
+                rooms.push(room);
+            }
+
+            let mut client = AsyncClient::new(jid, settings.password.clone());
+            client.set_reconnect(true);
+
+            let (outgoing, outgoing_rx) = mpsc::channel(100);
+            let connection = XmppConnection {
+                outgoing,
+                inbound: Arc::new(StdMutex::new(None)),
+                rooms,
+                last_target: Arc::new(StdMutex::new(None)),
+            };
+
+            tokio::spawn(run_session(
+                client,
+                outgoing_rx,
+                connection.inbound.clone(),
+                connection.rooms.clone(),
+                settings.nick.clone(),
+                connection.last_target.clone(),
+            ));
+
+            Ok(connection)
+        })
+        .await
+}
+
+// Drive the client: send queued stanzas and handle incoming events until the stream ends
+async fn run_session(
+    mut client: AsyncClient,
+    mut outgoing_rx: mpsc::Receiver<Element>,
+    inbound: Inbound,
+    rooms: Vec<BareJid>,
+    nick: String,
+    last_target: Arc<StdMutex<Option<ReplyTarget>>>,
+) {
+    loop {
+        tokio::select! {
+            event = client.next() => {
+                let Some(event) = event else {
+                    break;
+                };
+                match event {
+                    Event::Online { .. } => {
+                        info!("Connected to XMPP server");
+                        // Rejoin on every (re)connect, since room presence doesn't survive it
+                        for stanza in online_stanzas(&rooms, &nick) {
+                            if let Err(e) = client.send_stanza(stanza).await {
+                                error!("Failed to send XMPP presence: {}", e);
+                            }
+                        }
+                    }
+                    Event::Disconnected(e) => warn!("Disconnected from XMPP server: {}", e),
+                    Event::Stanza(stanza) => {
+                        let Some((content, target)) = parse_message(stanza, &rooms, &nick) else {
+                            continue;
+                        };
+                        // Each room, and each contact chatting directly, is a conversation
+                        let chat = match &target {
+                            ReplyTarget::Chat(jid) => jid.to_bare().to_string(),
+                            ReplyTarget::Room(room) => room.to_string(),
+                        };
+                        let sender = inbound.lock().unwrap().clone();
+                        if let Some(sender) = sender {
+                            *last_target.lock().unwrap() = Some(target);
+                            if sender.send((content, Some(chat))).await.is_err() {
+                                error!("Failed to send message to channel");
+                            }
+                        }
+                    }
+                }
+            }
+            Some(stanza) = outgoing_rx.recv() => {
+                if let Err(e) = client.send_stanza(stanza).await {
+                    error!("Failed to send XMPP message: {}", e);
+                }
+            }
+        }
+    }
+
+    info!("XMPP session ended");
+}
+
+// Initial presence, then a join presence to each room as room@service/nick
+fn online_stanzas(rooms: &[BareJid], nick: &str) -> Vec<Element> {
+    let mut stanzas = vec![Presence::new(PresenceType::None).into()];
+    for room in rooms {
+        match format!("{}/{}", room, nick).parse::<Jid>() {
+            Ok(occupant) => {
+                info!("Joining XMPP room {}", room);
+                let join = Presence::new(PresenceType::None)
+                    .with_to(occupant)
+                    .with_payloads(vec![Muc::new().into()]);
+                stanzas.push(join.into());
+            }
+            Err(e) => error!("Invalid XMPP nickname {} for room {}: {}", nick, room, e),
+        }
+    }
+    stanzas
+}
+
+// Pick out message bodies to answer, with where the answer should go
+fn parse_message(stanza: Element, rooms: &[BareJid], nick: &str) -> Option<(String, ReplyTarget)> {
+    // Room history replayed on join carries a delay; only new messages are answered
+    if stanza.has_child("delay", "urn:xmpp:delay") {
+        return None;
+    }
+
+    let message = Message::try_from(stanza).ok()?;
+    let from = message.from.clone()?;
+    let (_, body) = message.get_best_body(vec![])?;
+    let content = body.0.trim().to_string();
+    if content.is_empty() {
+        return None;
+    }
+
+    match message.type_ {
+        MessageType::Groupchat => {
+            let room = rooms.iter().find(|room| from.to_bare() == **room)?;
+            // Ignore the agent's own messages echoed back by the room
+            let own = format!("{}/{}", room, nick).parse::<Jid>().ok();
+            if own.as_ref() == Some(&from) {
+                return None;
+            }
+            Some((content, ReplyTarget::Room(room.clone())))
+        }
+        MessageType::Chat | MessageType::Normal => Some((content, ReplyTarget::Chat(from))),
+        _ => None,
+    }
+}
+
+// XMPP input source implementation
+pub struct XmppSource {
+    message_rx: mpsc::Receiver<(String, Option<String>)>,
+}
+
+impl XmppSource {
+    pub async fn new(settings: &XmppSettings) -> Result<Self> {
+        let connection = connect(settings).await?;
+        let (message_tx, message_rx) = mpsc::channel(100);
+        *connection.inbound.lock().unwrap() = Some(message_tx);
+        Ok(Self { message_rx })
+    }
+}
+
+#[async_trait]
+impl InputSource for XmppSource {
+    fn name(&self) -> &str {
+        "xmpp"
+    }
+
+    async fn read_message(&mut self) -> Result<Option<InboundMessage>> {
+        match self.message_rx.recv().await {
+            Some((message, sender)) => Ok(Some(InboundMessage::new(message).with_sender(sender))),
+            None => Ok(None),
+        }
+    }
+}
+
+// XMPP output destination implementation
+pub struct XmppDestination {
+    connection: &'static XmppConnection,
+}
+
+impl XmppDestination {
+    pub async fn new(settings: &XmppSettings) -> Result<Self> {
+        let connection = connect(settings).await?;
+        Ok(Self { connection })
+    }
+}
+
+#[async_trait]
+impl OutputDestination for XmppDestination {
+    fn name(&self) -> &str {
+        "xmpp"
+    }
+
+    fn default_roles(&self) -> &[&str] {
+        &["assistant"]
+    }
+
+    async fn write_message(&self, _role: &str, content: &str) -> Result<()> {
+        // Reply to whoever wrote last, or post in every configured room
+        let last_target = self.connection.last_target.lock().unwrap().clone();
+        let targets = match last_target {
+            Some(target) => vec![target],
+            None => self
+                .connection
+                .rooms
+                .iter()
+                .cloned()
+                .map(ReplyTarget::Room)
+                .collect(),
+        };
+
+        for target in targets {
+            let (to, type_) = match target {
+                ReplyTarget::Chat(jid) => (jid, MessageType::Chat),
+                ReplyTarget::Room(room) => (Jid::from(room), MessageType::Groupchat),
+            };
+            let mut message = Message::new(Some(to));
+            message.type_ = type_;
+            message
+                .bodies
+                .insert(String::new(), Body(content.to_string()));
+
+            self.connection
+                .outgoing
+                .send(message.into())
+                .await
+                .context("XMPP session has ended")?;
+        }
+
+        Ok(())
+    }
+}
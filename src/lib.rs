@@ -1,4 +1,35 @@
+pub mod agent;
+pub mod ask;
+pub mod budget;
 pub mod cli;
+pub mod commands;
+pub mod compare;
 pub mod config;
+pub mod credentials;
+pub mod daemon;
+pub mod export;
+pub mod guardrails;
+pub mod health;
+pub mod history;
+pub mod ingest;
+pub mod init;
+pub mod injection;
 pub mod io;
+pub mod logging;
+pub mod memory;
+pub mod models;
+pub mod moderation;
+pub mod prompt;
 pub mod providers;
+pub mod queue;
+pub mod redact;
+pub mod service;
+pub mod session;
+pub mod supervisor;
+pub mod testing;
+pub mod tools;
+pub mod validate;
+#[cfg(windows)]
+pub mod win_service;
+
+pub use agent::{Agent, AgentBuilder};
@@ -0,0 +1,150 @@
+//! Where the logs go and how much of them: the terminal or a rotated `log_file`,
+//! filtered per module by `--log-filter`, `--verbose` or `RUST_LOG`
+
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing_appender::rolling::{Builder, Rotation};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::Config;
+
+/// Accepted log_rotation values
+pub const ROTATIONS: &[&str] = &["daily", "hourly", "size", "never"];
+
+/// Megabytes a log file grows to with size rotation unless `log_max_size_mb` is set
+pub const DEFAULT_MAX_SIZE_MB: u64 = 10;
+
+/// Rotated log files kept unless `log_max_files` is set
+pub const DEFAULT_MAX_FILES: usize = 7;
+
+/// The filter to log with: `log_filter` if given, debug for everything with
+/// `verbose`, then `RUST_LOG`, and `default` otherwise
+pub fn filter(log_filter: Option<&str>, verbose: bool, default: &str) -> Result<EnvFilter> {
+    if let Some(directives) = log_filter {
+        return EnvFilter::try_new(directives)
+            .context(format!("Invalid log filter: {}", directives));
+    }
+    if verbose {
+        return Ok(EnvFilter::new("debug"));
+    }
+    match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(directives) if !directives.trim().is_empty() => EnvFilter::try_new(&directives).context(
+            format!("Invalid {}: {}", EnvFilter::DEFAULT_ENV, directives),
+        ),
+        _ => Ok(EnvFilter::new(default)),
+    }
+}
+
+/// A writer to the config's `log_file`, rotated as `log_rotation` says, or `None`
+/// when logs go to the terminal
+pub fn file_writer(config: &Config) -> Result<Option<BoxMakeWriter>> {
+    let Some(log_file) = &config.log_file else {
+        return Ok(None);
+    };
+    let path = Path::new(log_file);
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    std::fs::create_dir_all(directory).context(format!(
+        "Failed to create log directory: {}",
+        directory.display()
+    ))?;
+    let name = path
+        .file_name()
+        .context(format!("log_file is not a file: {}", log_file))?
+        .to_string_lossy()
+        .into_owned();
+    let max_files = config.log_max_files.unwrap_or(DEFAULT_MAX_FILES);
+
+    let rotation = match config.log_rotation.as_deref().unwrap_or("daily") {
+        "size" => {
+            let max_bytes = config.log_max_size_mb.unwrap_or(DEFAULT_MAX_SIZE_MB) * 1024 * 1024;
+            let file = SizeRotating::open(path.to_path_buf(), max_bytes, max_files)?;
+            return Ok(Some(BoxMakeWriter::new(Mutex::new(file))));
+        }
+        "hourly" => Rotation::HOURLY,
+        "never" => Rotation::NEVER,
+        _ => Rotation::DAILY,
+    };
+    let mut builder = Builder::new().rotation(rotation).filename_prefix(name);
+    if max_files > 0 {
+        builder = builder.max_log_files(max_files);
+    }
+    let appender = builder
+        .build(directory)
+        .context(format!("Failed to open log file: {}", log_file))?;
+    Ok(Some(BoxMakeWriter::new(appender)))
+}
+
+// A log file moved to <path>.1 once it reaches max_bytes, with older ones moving up
+// to <path>.<max_files> and the oldest dropped
+struct SizeRotating {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl SizeRotating {
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> Result<Self> {
+        let file = append(&path).context(format!("Failed to open log file: {}", path.display()))?;
+        let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            let _ = std::fs::remove_file(&self.path);
+        } else {
+            for index in (1..self.max_files).rev() {
+                let _ = std::fs::rename(self.rotated(index), self.rotated(index + 1));
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRotating {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // Each write is one whole log line, so lines aren't split between files
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn append(path: &Path) -> std::io::Result<File> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+}
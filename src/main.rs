@@ -1,215 +1,252 @@
-mod cli;
-mod config;
-mod io;
-mod providers;
-
 use anyhow::{Context, Result};
 use clap::Parser;
-use serde_json::json;
-
-use cli::Args;
-use config::Config;
-use providers::{Function, Message, Tool};
-
-// Function to evaluate mathematical expressions
-fn evaluate_expression(expression: &str) -> f64 {
-    // This is a simple implementation that handles basic operations
-    // In a real-world scenario, you might want to use a more robust expression evaluator
-
-    // Remove whitespace
-    let expr = expression.replace(" ", "");
-
-    // Try to parse as a simple number first
-    if let Ok(num) = expr.parse::<f64>() {
-        return num;
-    }
-
-    // Handle addition
-    if let Some(idx) = expr.find('+') {
-        let left = &expr[0..idx];
-        let right = &expr[idx + 1..];
-        return evaluate_expression(left) + evaluate_expression(right);
-    }
 
-    // Handle subtraction
-    if let Some(idx) = expr.rfind('-') {
-        // Make sure it's not a negative number
-        if idx > 0 {
-            let left = &expr[0..idx];
-            let right = &expr[idx + 1..];
-            return evaluate_expression(left) - evaluate_expression(right);
-        }
-    }
+use agent::cli::{Args, Command};
+use agent::config::{self, kinds, Config};
+use agent::{
+    ask, compare, credentials, daemon, export, ingest, init, io, logging, models, service,
+    supervisor, validate, Agent,
+};
 
-    // Handle multiplication
-    if let Some(idx) = expr.find('*') {
-        let left = &expr[0..idx];
-        let right = &expr[idx + 1..];
-        return evaluate_expression(left) * evaluate_expression(right);
-    }
-
-    // Handle division
-    if let Some(idx) = expr.find('/') {
-        let left = &expr[0..idx];
-        let right = &expr[idx + 1..];
-        let right_val = evaluate_expression(right);
-        if right_val != 0.0 {
-            return evaluate_expression(left) / right_val;
-        } else {
-            return f64::NAN; // Division by zero
-        }
-    }
-
-    // Handle square root
-    if expr.starts_with("sqrt(") && expr.ends_with(")") {
-        let inner = &expr[5..expr.len() - 1];
-        let inner_val = evaluate_expression(inner);
-        if inner_val >= 0.0 {
-            return inner_val.sqrt();
-        } else {
-            return f64::NAN; // Negative square root
-        }
-    }
-
-    // Handle power
-    if let Some(idx) = expr.find('^') {
-        let left = &expr[0..idx];
-        let right = &expr[idx + 1..];
-        return evaluate_expression(left).powf(evaluate_expression(right));
-    }
+fn main() -> Result<()> {
+    // Parse command line arguments
+    let args = Args::parse();
 
-    // Handle parentheses
-    if expr.starts_with("(") && expr.ends_with(")") {
-        let inner = &expr[1..expr.len() - 1];
-        return evaluate_expression(inner);
+    let config_path = args.config.clone().unwrap_or_else(config::default_path);
+
+    // Setup logging with the filter from the command line or RUST_LOG. The answer of
+    // ask is for scripts to read, so only warnings and errors show, on stderr
+    let asking = matches!(&args.command, Some(Command::Ask { .. })) || args.pipe.is_some();
+    let log_filter = logging::filter(
+        args.log_filter.as_deref(),
+        args.verbose,
+        if asking { "warn" } else { "info" },
+    )?;
+    let log_filter_text = log_filter.to_string();
+
+    // Running the agent reads log_file and the daemon settings from the config. A
+    // config that fails to load is reported once logging is up, in the foreground
+    let startup_config = match &args.command {
+        Some(Command::Run { .. }) | None => args.load_config(&config_path).ok(),
+        _ => None,
+    };
+    let supervising = matches!(
+        &args.command,
+        Some(Command::Run {
+            supervise: true,
+            ..
+        })
+    );
+    let daemon_config = startup_config
+        .as_ref()
+        .filter(|config| config.daemon && !asking);
+
+    // The supervisor leaves the log file to the agent process it starts
+    let log_writer = match &startup_config {
+        Some(config) if !supervising => logging::file_writer(config)?,
+        _ => None,
+    };
+    // Where the logs go when they can't go to the terminal and log_file isn't set
+    let agent_name = match &startup_config {
+        Some(config) => config.agent_name.clone(),
+        None => args
+            .overrides
+            .agent_name
+            .clone()
+            .unwrap_or_else(|| Config::default().agent_name),
+    };
+    let temporary_log = std::env::temp_dir().join(format!("agent-{}.log", agent_name));
+
+    if let Some(writer) = log_writer {
+        tracing_subscriber::fmt()
+            .with_env_filter(log_filter)
+            .with_ansi(false)
+            .with_writer(writer)
+            .init();
+    } else if args.overrides.tui || args.windows_service {
+        // The terminal UI owns the screen, and a Windows service has no console, so
+        // their logs go to a file instead
+        let log_file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&temporary_log)?;
+        tracing_subscriber::fmt()
+            .with_env_filter(log_filter)
+            .with_ansi(false)
+            .with_writer(std::sync::Mutex::new(log_file))
+            .init();
+    } else if asking {
+        tracing_subscriber::fmt()
+            .with_env_filter(log_filter)
+            .with_ansi(atty::is(atty::Stream::Stderr))
+            .with_writer(std::io::stderr)
+            .init();
+    } else {
+        // A daemon's stdout is a file, with no use for colors
+        tracing_subscriber::fmt()
+            .with_env_filter(log_filter)
+            .with_ansi(daemon_config.is_none())
+            .init();
     }
 
-    // If we can't parse the expression, return NaN
-    f64::NAN
-}
-
-// Add this function to manage message history
-fn manage_message_history(messages: &mut Vec<providers::Message>, max_messages: usize) {
-    // Always keep the system message (first message)
-    if messages.len() <= 1 || messages.len() <= max_messages {
-        return;
+    if let Some(config) = daemon_config {
+        daemon::start(config, &temporary_log)?;
     }
 
-    // Keep the system message and the most recent messages
-    let system_message = messages.remove(0);
-
-    // If we have too many messages, trim the oldest ones (after the system message)
-    while messages.len() > max_messages - 1 {
-        messages.remove(0);
+    // The service manager runs the agent on a thread of its own, with a shutdown
+    // channel it sends on when asked to stop
+    if args.windows_service {
+        #[cfg(windows)]
+        return agent::win_service::run(
+            &service::service_name(&agent_name),
+            Box::new(move |shutdown_tx| {
+                tokio::runtime::Runtime::new()?.block_on(run(
+                    args,
+                    &agent_name,
+                    &config_path,
+                    &log_filter_text,
+                    shutdown_tx,
+                ))
+            }),
+        );
+        #[cfg(not(windows))]
+        anyhow::bail!("--windows-service only works on Windows");
     }
 
-    // Put the system message back at the beginning
-    messages.insert(0, system_message);
+    // Stops the agent on a signal
+    let (shutdown_tx, _) = tokio::sync::broadcast::channel::<()>(1);
 
-    tracing::info!("Trimmed message history to {} messages", messages.len());
+    tokio::runtime::Runtime::new()?.block_on(run(
+        args,
+        &agent_name,
+        &config_path,
+        &log_filter_text,
+        shutdown_tx,
+    ))
 }
 
-// Add this function to handle retries for API calls
-async fn with_retries<F, Fut, T>(
-    operation: F,
-    max_retries: usize,
-    operation_name: &str,
-) -> Result<T>
-where
-    F: Fn() -> Fut,
-    Fut: std::future::Future<Output = Result<T>>,
-{
-    let mut retries = 0;
-    let mut backoff = tokio::time::Duration::from_millis(1000);
-
-    loop {
-        match operation().await {
-            Ok(result) => return Ok(result),
-            Err(e) => {
-                retries += 1;
-                if retries > max_retries {
-                    tracing::error!(
-                        "Operation '{}' failed after {} retries: {}",
-                        operation_name,
-                        max_retries,
-                        e
-                    );
-                    return Err(e);
-                }
-
-                tracing::warn!(
-                    "Operation '{}' failed (attempt {}/{}): {}",
-                    operation_name,
-                    retries,
-                    max_retries,
-                    e
-                );
-                tokio::time::sleep(backoff).await;
-                backoff = std::cmp::min(backoff * 2, tokio::time::Duration::from_secs(30));
+async fn run(
+    args: Args,
+    agent_name: &str,
+    config_path: &str,
+    log_filter: &str,
+    shutdown_tx: tokio::sync::broadcast::Sender<()>,
+) -> Result<()> {
+    // Handle subcommands before starting the agent
+    match &args.command {
+        Some(Command::Service { action }) => {
+            return service::run(action, agent_name, config_path, args.profile.as_deref());
+        }
+        Some(Command::Keyring { action }) => {
+            let config = args.load_config(config_path)?;
+            return credentials::run_keyring(action, &config);
+        }
+        Some(Command::Init {
+            name,
+            inputs,
+            outputs,
+            tools,
+            yes,
+            force,
+        }) => {
+            let list = |items: &str| items.split(',').map(|s| s.trim().to_string()).collect();
+            let options = init::InitOptions {
+                agent_name: name.clone(),
+                provider: args.overrides.provider.clone(),
+                model: args.overrides.model.clone(),
+                inputs: inputs.as_deref().map(list),
+                outputs: outputs.as_deref().map(list),
+                enable_tools: *tools,
+                yes: *yes,
+                force: *force,
+            };
+            return init::run(args.config.as_deref().unwrap_or("config.yaml"), options);
+        }
+        Some(Command::Validate) => {
+            return validate::run(config_path, &args);
+        }
+        Some(Command::Models) => {
+            let config = args.load_config(config_path)?;
+            return models::run(&config, args.json).await;
+        }
+        Some(Command::Ask {
+            question,
+            stream,
+            agent,
+        }) => {
+            let config = args.load_agent_config(config_path, agent.as_deref())?;
+            let status =
+                ask::run(config, ask::prompt(question, false)?, *stream, args.json).await?;
+            if status != 0 {
+                std::process::exit(status);
             }
+            return Ok(());
         }
-    }
-}
-
-// Add this function to send messages to all outputs
-async fn send_to_all_outputs(
-    outputs: &[Box<dyn io::OutputDestination>],
-    role: &str,
-    content: &str,
-    message_type: &str,
-) {
-    tracing::info!("Sending {} message to all outputs", message_type);
-
-    let mut futures = Vec::new();
-    for output in outputs {
-        let output_name = output.name().to_string();
-        let future = async move {
-            match output.write_message(role, content).await {
-                Ok(_) => tracing::info!(
-                    "Successfully sent {} message to output: {}",
-                    message_type,
-                    output_name
-                ),
-                Err(e) => tracing::error!(
-                    "Failed to send {} message to output {}: {}",
-                    message_type,
-                    output_name,
-                    e
-                ),
+        None if args.pipe.is_some() => {
+            let config = args.load_agent_config(config_path, None)?;
+            let question: Vec<String> = args.pipe.iter().cloned().collect();
+            let status = ask::run(config, ask::prompt(&question, true)?, false, args.json).await?;
+            if status != 0 {
+                std::process::exit(status);
             }
-        };
-        futures.push(future);
+            return Ok(());
+        }
+        Some(Command::Compare {
+            models,
+            prompt_file,
+            history_file,
+            format,
+            width,
+        }) => {
+            let config = args.load_config(config_path)?;
+            return compare::run(
+                &config,
+                models,
+                prompt_file,
+                history_file.as_deref(),
+                format,
+                *width,
+            )
+            .await;
+        }
+        Some(Command::Ingest { sources, agent }) => {
+            let config = args.load_agent_config(config_path, agent.as_deref())?;
+            return ingest::run(&config, sources).await;
+        }
+        Some(Command::Export {
+            session,
+            format,
+            output,
+            agent,
+        }) => {
+            let config = args.load_agent_config(config_path, agent.as_deref())?;
+            return export::run(&config, session.as_deref(), format, output.as_deref());
+        }
+        Some(Command::Run {
+            supervise: true,
+            max_restarts,
+            restart_window,
+        }) => {
+            return supervisor::run(
+                agent_name,
+                *max_restarts,
+                tokio::time::Duration::from_secs(*restart_window),
+            )
+            .await;
+        }
+        Some(Command::Run { .. }) | None => {}
     }
 
-    // Execute all futures concurrently
-    futures::future::join_all(futures).await;
-}
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Parse command line arguments
-    let args = Args::parse();
-
-    // Setup logging with appropriate level
-    let log_level = if args.verbose {
-        tracing::Level::DEBUG
-    } else {
-        tracing::Level::INFO
-    };
-
-    tracing_subscriber::fmt().with_max_level(log_level).init();
-
-    tracing::info!("Log level set to {}", log_level);
+    tracing::info!("Log filter set to {}", log_filter);
 
-    // Create a shutdown channel
-    let (shutdown_tx, _) = tokio::sync::broadcast::channel::<()>(1);
     let shutdown_tx_clone = shutdown_tx.clone();
 
     // Setup signal handlers for graceful shutdown
     tokio::spawn(async move {
-        match tokio::signal::ctrl_c().await {
+        match shutdown_signal().await {
             Ok(()) => {
                 tracing::info!("Received shutdown signal, initiating graceful shutdown...");
+                daemon::notify("STOPPING=1");
                 let _ = shutdown_tx_clone.send(());
             }
             Err(err) => {
@@ -218,474 +255,101 @@ async fn main() -> Result<()> {
         }
     });
 
-    // Load configuration
-    let config_path = args.config.as_deref().unwrap_or("config.yaml");
-    let mut config = Config::from_yaml(config_path)?;
-
-    // Update config with command line arguments
-    if !args.agent_name.is_empty() {
-        config.agent_name = args.agent_name;
-    }
-
-    if !args.provider.is_empty() {
-        config.provider = args.provider;
-    }
-
-    if !args.model.is_empty() {
-        config.model = args.model;
-    }
-
-    if !args.system_message.is_empty() {
-        config.system_message = args.system_message;
-    }
-
-    // Only update inputs if explicitly provided via command line
-    if let Some(inputs) = &args.inputs {
+    // Load configuration, with the command line's settings over the file's
+    if let Some(inputs) = &args.overrides.inputs {
         tracing::info!("Updating inputs from CLI arguments: {}", inputs);
-        config.inputs_vec = inputs.split(',').map(|s| s.trim().to_string()).collect();
-    } else {
-        tracing::info!("Keeping inputs from config file: {:?}", config.inputs_vec);
     }
-
-    // Only update outputs if explicitly provided via command line
-    if let Some(outputs) = &args.outputs {
+    if let Some(outputs) = &args.overrides.outputs {
         tracing::info!("Updating outputs from CLI arguments: {}", outputs);
-        config.outputs_vec = outputs.split(',').map(|s| s.trim().to_string()).collect();
-    } else {
-        tracing::info!("Keeping outputs from config file: {:?}", config.outputs_vec);
-    }
-
-    // Update other config values if provided via command line
-    if args.enable_tools {
-        config.enable_tools = true;
-    }
-
-    if args.daemon {
-        config.daemon = true;
-    }
-
-    if let Some(broker) = &args.mqtt_broker {
-        config.mqtt_broker = Some(broker.clone());
-    }
-
-    if let Some(port) = args.mqtt_port {
-        config.mqtt_port = Some(port);
     }
+    let config = args.load_config(config_path)?;
 
-    if let Some(input_topic) = &args.mqtt_input_topic {
-        config.mqtt_input_topic = Some(input_topic.clone());
-    }
-
-    if let Some(output_topic) = &args.mqtt_output_topic {
-        config.mqtt_output_topic = Some(output_topic.clone());
-    }
-
-    if let Some(max_history) = args.max_history_messages {
-        config.max_history_messages = Some(max_history);
-    }
+    // Command line values are held to the same rules as the config file
+    config.validate().context("Invalid configuration")?;
 
     // Print the final configuration
     tracing::info!("Final configuration:");
     tracing::info!("  Agent name: {}", config.agent_name);
-    tracing::info!("  Inputs: {:?}", config.inputs_vec);
-    tracing::info!("  Outputs: {:?}", config.outputs_vec);
-
-    // If daemon mode is requested, daemonize the process
-    if config.daemon {
-        tracing::info!("Starting in daemon mode...");
-        let daemon = daemonize::Daemonize::new()
-            .working_directory(".")
-            .user(
-                users::get_current_username()
-                    .unwrap()
-                    .to_string_lossy()
-                    .as_ref(),
-            )
-            .group(
-                users::get_current_groupname()
-                    .unwrap()
-                    .to_string_lossy()
-                    .as_ref(),
-            );
-
-        match daemon.start() {
-            Ok(_) => tracing::info!("Daemon started successfully"),
-            Err(e) => tracing::error!("Error starting daemon: {}", e),
-        }
+    tracing::info!("  Config file: {}", config_path);
+    if let Some(profile) = &config.profile {
+        tracing::info!("  Profile: {}", profile);
     }
-
-    // Initialize provider
-    let api_key_env_var = format!("{}_API_KEY", config.provider.to_uppercase());
-    let api_key = std::env::var(&api_key_env_var)
-        .context(format!("{} environment variable not set", api_key_env_var))?;
-
-    // Create the provider
-    let provider = providers::get_provider(&config.provider, &api_key)?;
-
-    // Print the selected provider and model
-    tracing::info!(
-        "Using provider: {} with model: {}",
-        provider.name(),
-        config.model
-    );
-
-    // Print the available models
-    tracing::info!(
-        "Available models for {}: {:?}",
-        provider.name(),
-        provider.available_models()
-    );
-
-    // Initialize tools if enabled
-    let tools = if config.enable_tools {
-        Some(vec![
-            Tool {
-                type_: "function".to_string(),
-                function: Function {
-                    name: "get_current_weather".to_string(),
-                    description: "Get the current weather".to_string(),
-                    parameters: json!({
-                        "type": "object",
-                        "properties": {
-                            "location": {
-                                "type": "string",
-                                "description": "The location to get weather for, e.g. 'San Francisco, CA'"
-                            }
-                        },
-                        "required": ["location"]
-                    }),
-                },
-            },
-            Tool {
-                type_: "function".to_string(),
-                function: Function {
-                    name: "calculate".to_string(),
-                    description: "Evaluate a mathematical expression".to_string(),
-                    parameters: json!({
-                        "type": "object",
-                        "properties": {
-                            "expression": {
-                                "type": "string",
-                                "description": "The mathematical expression to evaluate, e.g. '2 + 2'"
-                            }
-                        },
-                        "required": ["expression"]
-                    }),
-                },
-            },
-        ])
+    tracing::info!("  Inputs: {:?}", kinds(&config.input_entries()));
+    tracing::info!("  Outputs: {:?}", kinds(&config.output_entries()));
+
+    // Run every agent in this process, stopping when all have stopped
+    let agents = config.agent_configs()?;
+    let result = if agents.len() == 1 {
+        let config = agents.into_iter().next().expect("one agent");
+        Agent::builder()
+            .config(config)
+            .config_file(config_path)
+            .shutdown(shutdown_tx)
+            .run()
+            .await
     } else {
-        None
-    };
-
-    // Create input sources using the new function
-    tracing::info!("Creating input sources: {:?}", config.inputs_vec);
-    let inputs = io::create_input_sources(&config).await;
-    tracing::info!("Successfully created {} input sources", inputs.len());
-
-    // Create output destinations using the new function
-    tracing::info!("Configuring output destinations: {:?}", &config.outputs_vec);
-    let outputs = io::create_output_destinations(&config).await;
-    tracing::info!("Successfully created {} output destinations", outputs.len());
-
-    // Initialize conversation history
-    let mut messages = vec![Message {
-        role: "system".to_string(),
-        content: config.system_message.clone(),
-        tool_calls: None,
-        tool_call_id: None,
-    }];
-
-    // First, create proper channels for input sources
-    tracing::debug!("Setting up message channels...");
-    let (tx, mut rx) = tokio::sync::mpsc::channel::<(usize, String)>(10);
-
-    // Spawn tasks for each input source
-    let mut input_tasks = tokio::task::JoinSet::new();
-    for (i, input) in inputs.iter().enumerate() {
-        let input_tx = tx.clone();
-        let input_name = input.name().to_string();
-        let mut shutdown_rx = shutdown_tx.subscribe();
-
-        tracing::debug!("Starting listener for input source {}: {}", i, input_name);
-
-        // Clone the config values we need
-        let mqtt_input_topic = config.mqtt_input_topic.clone();
-        let mqtt_broker = config.mqtt_broker.clone();
-        let mqtt_port = config.mqtt_port;
-        let agent_name = config.agent_name.clone();
-
-        // Create a task to monitor this input
-        input_tasks.spawn(async move {
-            tracing::debug!("Starting listener task for input source {}: {}", i, input_name);
-
-            // Clone the input for this task - we'll create a new instance with the same type
-            let mut input_source = match input_name.as_str() {
-                "mqtt" => {
-                    // Import directly from the mqtt module
-                    let mqtt_source = crate::io::mqtt::MqttSource::new(
-                        mqtt_input_topic,
-                        mqtt_broker,
-                        mqtt_port,
-                        Some(agent_name),
-                    ).await.expect("Failed to create MQTT source");
-                    Box::new(mqtt_source) as Box<dyn crate::io::InputSource>
-                },
-                "stdin" => Box::new(crate::io::stdin::StdinSource::new()) as Box<dyn crate::io::InputSource>,
-                _ => panic!("Unknown input source: {}", input_name),
-            };
-
-            // Implement exponential backoff for error recovery
-            let mut backoff = tokio::time::Duration::from_millis(100);
-
-            loop {
-                tokio::select! {
-                    // Check for shutdown signal
-                    _ = shutdown_rx.recv() => {
-                        tracing::info!("Shutting down input source {}: {}", i, input_name);
-                        break;
-                    }
-                    // Try to read a message
-                    result = input_source.read_message() => {
-                        match result {
-                            Ok(Some(msg)) => {
-                                tracing::debug!("Input {}: Received message: {}", i, msg);
-                                // Send the message to the main loop
-                                if let Err(e) = input_tx.send((i, msg)).await {
-                                    tracing::error!("Failed to forward message from input {}: {}", i, e);
-                                    // If the channel is closed, we should exit
-                                    break;
-                                }
-                                // Reset backoff on success
-                                backoff = tokio::time::Duration::from_millis(100);
-                            },
-                            Ok(None) => {
-                                // No message, wait a bit before checking again
-                                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                            },
-                            Err(e) => {
-                                tracing::error!("Error reading from input {}: {}", i, e);
-                                // Use exponential backoff with a maximum delay
-                                tokio::time::sleep(backoff).await;
-                                backoff = std::cmp::min(backoff * 2, tokio::time::Duration::from_secs(30));
-                            }
-                        }
-                    }
-                }
-            }
-
-            tracing::info!("Input source task {} completed", i);
+        use tracing::Instrument;
+
+        let names: Vec<String> = agents
+            .iter()
+            .map(|agent| agent.agent_name.clone())
+            .collect();
+        tracing::info!("Running agents: {}", names.join(", "));
+        // Each agent answers the admin API under its own name
+        let runs = agents.into_iter().map(|config| {
+            let span = tracing::info_span!("agent", name = %config.agent_name);
+            Agent::builder()
+                .config(config)
+                .config_file(config_path)
+                .shutdown(shutdown_tx.clone())
+                .run()
+                .instrument(span)
         });
-    }
-
-    // Main event loop - truly event-driven
-    tracing::info!("Starting event-driven message processing...");
-    let mut shutdown_rx = shutdown_tx.subscribe();
-
-    loop {
-        tokio::select! {
-            // Check for shutdown signal
-            _ = shutdown_rx.recv() => {
-                tracing::info!("Main loop received shutdown signal, exiting...");
-                break;
-            }
-            // Wait for a message from any input source
-            msg = rx.recv() => {
-                match msg {
-                    Some((idx, content)) => {
-                        tracing::info!("\n\n=== MESSAGE RECEIVED ===");
-                        tracing::info!("From input source {}: {}", idx, content);
-                        tracing::info!("==========================\n\n");
-
-                        // Check for exit command
-                        if content.to_lowercase() == "exit" {
-                            tracing::info!("Received exit command, shutting down");
-                            for output in &outputs {
-                                let _ = output.write_message("system", "Goodbye!").await;
-                            }
-                            // Trigger shutdown
-                            let _ = shutdown_tx.send(());
-                            break;
-                        }
-
-                        // Process the message - dereference the provider to get &dyn Provider
-                        if let Err(e) = process_message(idx, content, provider.as_ref(), &config, &mut messages, &outputs, tools.as_deref()).await {
-                            tracing::error!("Error processing message: {}", e);
-                        }
-                    },
-                    None => {
-                        tracing::info!("All input channels closed, exiting");
-                        break;
-                    }
-                }
-            }
-        }
-    }
-
-    // Wait for all input tasks to complete
-    tracing::info!("Waiting for input tasks to complete...");
-    let shutdown_timeout = tokio::time::Duration::from_secs(5);
-    let wait_for_tasks = async {
-        while let Some(res) = input_tasks.join_next().await {
-            if let Err(e) = res {
-                tracing::error!("Error joining input task: {}", e);
+        for (name, result) in names.iter().zip(futures::future::join_all(runs).await) {
+            if let Err(e) = result {
+                tracing::error!("Agent {} stopped: {:#}", name, e);
             }
         }
+        Ok(())
     };
 
-    // Add a timeout to prevent hanging
-    match tokio::time::timeout(shutdown_timeout, wait_for_tasks).await {
-        Ok(_) => tracing::info!("All input tasks completed successfully"),
-        Err(_) => tracing::warn!("Timed out waiting for some input tasks to complete"),
-    }
+    io::tui::restore_terminal();
+    result?;
 
-    // Force exit after a short delay to ensure all logs are flushed
-    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    // Returning drops the runtime and everything left in it, closing connections
     tracing::info!("Agent shutdown complete");
-    std::process::exit(0);
+    Ok(())
 }
 
-// Update the process_message function to fix the tool_call structure and provider type
-async fn process_message(
-    _input_idx: usize,
-    content: String,
-    provider: &dyn providers::Provider,
-    config: &Config,
-    messages: &mut Vec<providers::Message>,
-    outputs: &[Box<dyn io::OutputDestination>],
-    tools: Option<&[providers::Tool]>,
-) -> Result<()> {
-    // Add user message to history
-    messages.push(providers::Message {
-        role: "user".to_string(),
-        content: content.clone(),
-        tool_calls: None,
-        tool_call_id: None,
-    });
-
-    // Send user message to all outputs
-    send_to_all_outputs(outputs, "user", &content, "user").await;
-
-    // Send processing message to all outputs
-    send_to_all_outputs(
-        outputs,
-        "system",
-        "Processing your request...",
-        "processing",
-    )
-    .await;
-
-    // Get chat completion with retries
-    tracing::info!("Getting chat completion from AI");
-    let response = with_retries(
-        || provider.chat_completion(&config.model, &messages, tools),
-        3,
-        "chat_completion",
-    )
-    .await?;
+// Wait for Ctrl-C, or SIGTERM from a service manager or the supervisor
+#[cfg(unix)]
+async fn shutdown_signal() -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
 
-    // Log the AI's response
-    if let Some(tool_calls) = &response.tool_calls {
-        tracing::info!("AI responded with tool calls: {:?}", tool_calls);
-    } else {
-        tracing::info!("AI responded: {}", response.message.content);
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
     }
+}
 
-    // Handle tool calls if present
-    if let Some(tool_calls) = &response.tool_calls {
-        // Add the assistant's response to the conversation
-        messages.push(providers::Message {
-            role: "assistant".to_string(),
-            content: response.message.content.clone(),
-            tool_calls: response.tool_calls.clone(),
-            tool_call_id: None,
-        });
-
-        // Process each tool call
-        for tool_call in tool_calls {
-            // Unwrap the function since it's an Option
-            if let Some(function) = &tool_call.function {
-                let function_name = &function.name;
-                let function_args = &function.arguments;
-
-                tracing::info!(
-                    "Processing tool call: {} with args: {}",
-                    function_name,
-                    function_args
-                );
-
-                // Parse the arguments
-                let args: serde_json::Value = serde_json::from_str(function_args)?;
-
-                // Execute the function
-                let result = match function_name.as_str() {
-                    "get_current_weather" => {
-                        let location = args["location"].as_str().unwrap_or("unknown");
-                        format!("Weather in {}: Sunny, 72°F", location)
-                    }
-                    "calculate" => {
-                        let expression = args["expression"].as_str().unwrap_or("0");
-                        let result = evaluate_expression(expression);
-                        format!("Result: {}", result)
-                    }
-                    _ => format!("Unknown function: {}", function_name),
-                };
-
-                // Add the tool result to the conversation
-                messages.push(providers::Message {
-                    role: "tool".to_string(),
-                    content: result,
-                    tool_calls: None,
-                    tool_call_id: tool_call.id.clone(),
-                });
-            }
-        }
-
-        // Get a follow-up response from the AI with retries
-        tracing::info!("Getting follow-up response from AI");
-        let follow_up = with_retries(
-            || provider.chat_completion(&config.model, &messages, None),
-            3,
-            "follow_up_chat_completion",
-        )
-        .await?;
-
-        // Add the follow-up response to the conversation
-        messages.push(providers::Message {
-            role: "assistant".to_string(),
-            content: follow_up.message.content.clone(),
-            tool_calls: None,
-            tool_call_id: None,
-        });
-
-        // Manage message history to prevent excessive memory usage
-        manage_message_history(messages, config.max_history_messages.unwrap_or(50));
-
-        // Send the assistant's response to all outputs
-        send_to_all_outputs(
-            outputs,
-            "assistant",
-            &follow_up.message.content,
-            "assistant",
-        )
-        .await;
-
-        tracing::info!("AI follow-up response: {}", follow_up.message.content);
-    } else {
-        // Add the assistant's response to the conversation
-        messages.push(providers::Message {
-            role: "assistant".to_string(),
-            content: response.message.content.clone(),
-            tool_calls: None,
-            tool_call_id: None,
-        });
-
-        // Manage message history to prevent excessive memory usage
-        manage_message_history(messages, config.max_history_messages.unwrap_or(50));
-
-        // Send the assistant's response to all outputs
-        send_to_all_outputs(outputs, "assistant", &response.message.content, "assistant").await;
+// Ctrl-C or Ctrl-Break, or the console closing or Windows shutting down
+#[cfg(windows)]
+async fn shutdown_signal() -> std::io::Result<()> {
+    use tokio::signal::windows;
+
+    let mut ctrl_break = windows::ctrl_break()?;
+    let mut close = windows::ctrl_close()?;
+    let mut shutdown = windows::ctrl_shutdown()?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = ctrl_break.recv() => Ok(()),
+        _ = close.recv() => Ok(()),
+        _ = shutdown.recv() => Ok(()),
     }
+}
 
-    Ok(())
+#[cfg(not(any(unix, windows)))]
+async fn shutdown_signal() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
 }
@@ -0,0 +1,159 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::config::Config;
+use crate::providers::embeddings::{cosine_similarity, Embedder};
+use crate::providers::{tokens, Message};
+
+const DEFAULT_TOP_K: usize = 3;
+const DEFAULT_MIN_SCORE: f32 = 0.3;
+
+// Each side of a recalled exchange is cut to this many tokens, so a few long answers
+// can't fill the context window
+const SNIPPET_TOKENS: usize = 150;
+
+// One remembered exchange, a line of the index file
+#[derive(Serialize, Deserialize)]
+struct MemoryEntry {
+    session: String,
+    timestamp: String,
+    user: String,
+    assistant: String,
+    embedding: Vec<f32>,
+}
+
+/// Long-term memory: every answered exchange is embedded and appended to a local
+/// index, and the ones most similar to a new message are recalled into its prompt
+///
+/// Memories are kept per conversation, so one user's exchanges are never recalled
+/// for another.
+pub struct Memory {
+    embedder: Embedder,
+    top_k: usize,
+    min_score: f32,
+    path: PathBuf,
+    entries: Vec<MemoryEntry>,
+}
+
+impl Memory {
+    /// Open the index at `memory_path`, creating it on the first exchange
+    pub fn new(config: &Config) -> Result<Self> {
+        let path = PathBuf::from(
+            config
+                .memory_path
+                .clone()
+                .unwrap_or_else(|| format!("{}-memory.jsonl", config.agent_name)),
+        );
+
+        let mut entries = Vec::new();
+        if path.exists() {
+            let contents = std::fs::read_to_string(&path)
+                .context(format!("Failed to read memory index: {}", path.display()))?;
+            for (number, line) in contents.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let entry = serde_json::from_str(line).context(format!(
+                    "Failed to parse memory index {} at line {}",
+                    path.display(),
+                    number + 1
+                ))?;
+                entries.push(entry);
+            }
+        }
+
+        Ok(Memory {
+            embedder: Embedder::new(config)?,
+            top_k: config.memory_top_k.unwrap_or(DEFAULT_TOP_K),
+            min_score: config.memory_min_score.unwrap_or(DEFAULT_MIN_SCORE),
+            path,
+            entries,
+        })
+    }
+
+    /// Number of remembered exchanges
+    pub fn count(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Past exchanges of `session` most like `query`, written out for the system
+    /// message, leaving out those still in `history`
+    pub async fn recall(
+        &self,
+        session: &str,
+        query: &str,
+        history: &[Message],
+    ) -> Result<Option<String>> {
+        let candidates: Vec<&MemoryEntry> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.session == session)
+            .filter(|entry| {
+                !history
+                    .iter()
+                    .any(|message| message.role == "user" && message.content == entry.user)
+            })
+            .collect();
+        // Nothing to find, so don't pay for an embedding
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+
+        let query = self.embedder.embed(query).await?;
+        let mut scored: Vec<(f32, &MemoryEntry)> = candidates
+            .into_iter()
+            .map(|entry| (cosine_similarity(&query, &entry.embedding), entry))
+            .filter(|(score, _)| *score >= self.min_score)
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.truncate(self.top_k);
+        if scored.is_empty() {
+            return Ok(None);
+        }
+
+        let mut text = "Earlier exchanges in this conversation that may be relevant:".to_string();
+        for (_, entry) in scored {
+            text.push_str(&format!(
+                "\n\n[{}]\nUser: {}\nAssistant: {}",
+                entry.timestamp,
+                tokens::truncate(&entry.user, SNIPPET_TOKENS),
+                tokens::truncate(&entry.assistant, SNIPPET_TOKENS)
+            ));
+        }
+        Ok(Some(text))
+    }
+
+    /// Embed an exchange and add it to the index
+    pub async fn remember(&mut self, session: &str, user: &str, assistant: &str) -> Result<()> {
+        let embedding = self
+            .embedder
+            .embed(&format!("User: {}\nAssistant: {}", user, assistant))
+            .await?;
+        let entry = MemoryEntry {
+            session: session.to_string(),
+            timestamp: Utc::now().format("%Y-%m-%d %H:%M UTC").to_string(),
+            user: user.to_string(),
+            assistant: assistant.to_string(),
+            embedding,
+        };
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context(format!(
+                "Failed to open memory index: {}",
+                self.path.display()
+            ))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+//! The `models` subcommand: the models a provider offers, from its own model list
+
+use anyhow::{Context, Result};
+
+use crate::config::Config;
+use crate::{credentials, providers};
+
+/// Print the models of the configured provider, as a table or as JSON
+pub async fn run(config: &Config, json: bool) -> Result<()> {
+    let name = config.provider.to_lowercase();
+    let key = credentials::load_api_key_for(config, &name)?;
+    let provider = providers::get_provider(&name, &key, &config.provider_settings(&name))?;
+    let mut models = provider
+        .list_models()
+        .await
+        .context(format!("Failed to list {}'s models", name))?;
+    models.sort_by(|a, b| a.id.cmp(&b.id));
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&models)?);
+        return Ok(());
+    }
+    if models.is_empty() {
+        println!("{} lists no models", name);
+        return Ok(());
+    }
+
+    let width = models.iter().map(|model| model.id.len()).max().unwrap_or(0);
+    println!(
+        "{:<width$}  {:>9}  {:<10}  NOTES",
+        "MODEL", "CONTEXT", "CREATED"
+    );
+    for model in &models {
+        let mut notes = Vec::new();
+        // The configured model is marked, so a retired one stands out by its absence
+        if name == config.provider && model.id == config.model {
+            notes.push("configured".to_string());
+        }
+        notes.extend(model.deprecation.clone());
+        let line = format!(
+            "{:<width$}  {:>9}  {:<10}  {}",
+            model.id,
+            model
+                .context_window
+                .map_or("-".to_string(), |tokens| tokens.to_string()),
+            model.created.as_deref().unwrap_or("-"),
+            notes.join(", ")
+        );
+        println!("{}", line.trim_end());
+    }
+    if name == config.provider && !models.iter().any(|model| model.id == config.model) {
+        println!(
+            "\nThe configured model, {}, is not in {}'s list",
+            config.model, name
+        );
+    }
+    Ok(())
+}
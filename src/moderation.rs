@@ -0,0 +1,232 @@
+//! Moderation of incoming messages and the agent's responses, by the OpenAI
+//! moderation endpoint or a local classifier command
+
+use anyhow::{Context, Result};
+use reqwest::{header, Client};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+use crate::config::Config;
+use crate::credentials;
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+const DEFAULT_MODEL: &str = "omni-moderation-latest";
+
+// How long the classifier command gets for one text
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What replaces flagged text with the redact action
+pub const PLACEHOLDER: &str = "[REDACTED:moderation]";
+
+/// Accepted moderation_input_action and moderation_output_action values
+pub const ACTIONS: &[&str] = &["block", "flag", "redact", "off"];
+
+#[derive(Deserialize)]
+struct ModerationResponse {
+    results: Vec<ModerationResult>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResult {
+    #[serde(default)]
+    categories: BTreeMap<String, bool>,
+}
+
+enum Classifier {
+    OpenAi {
+        client: Client,
+        api_url: String,
+        model: String,
+    },
+    Command(String),
+}
+
+/// What to do with a text after moderation
+pub enum Verdict {
+    /// Go on with this text, the original or a placeholder for it
+    Allow(String),
+    /// Drop the text; the reason names the flagged categories
+    Block(String),
+}
+
+/// Screens text with the configured classifier and applies the action for its direction
+pub struct Moderator {
+    classifier: Classifier,
+    categories: Vec<String>,
+    input_action: String,
+    output_action: String,
+}
+
+impl Moderator {
+    /// The moderator for the config's `moderation`, or `None` when it is off
+    pub fn new(config: &Config) -> Result<Option<Self>> {
+        let classifier = match config.moderation.as_deref() {
+            None => return Ok(None),
+            Some("openai") => {
+                let api_key = credentials::load_api_key_for(config, "openai")
+                    .context("Moderation needs an OpenAI API key")?;
+                let settings = config.provider_settings("openai");
+                let mut headers = header::HeaderMap::new();
+                headers.insert(
+                    "Authorization",
+                    header::HeaderValue::from_str(&format!("Bearer {}", api_key))?,
+                );
+                Classifier::OpenAi {
+                    client: settings.client(headers)?,
+                    api_url: match &config.moderation_url {
+                        Some(url) => url.clone(),
+                        None => format!("{}/moderations", settings.base_url(DEFAULT_BASE_URL)),
+                    },
+                    model: config
+                        .moderation_model
+                        .clone()
+                        .unwrap_or_else(|| DEFAULT_MODEL.to_string()),
+                }
+            }
+            Some("command") => Classifier::Command(
+                config
+                    .moderation_command
+                    .clone()
+                    .context("moderation: command requires moderation_command to be set")?,
+            ),
+            Some(other) => anyhow::bail!(
+                "Unknown moderation classifier {}, expected openai or command",
+                other
+            ),
+        };
+        Ok(Some(Self {
+            classifier,
+            categories: config.moderation_categories.clone(),
+            input_action: action(config.moderation_input_action.as_deref()),
+            output_action: action(config.moderation_output_action.as_deref()),
+        }))
+    }
+
+    /// Screen a message before the agent answers it
+    pub async fn check_input(&self, text: &str, session: &str) -> Verdict {
+        self.check(text, &self.input_action, "message", session)
+            .await
+    }
+
+    /// Screen a response before it is sent to the outputs
+    pub async fn check_output(&self, text: &str, session: &str) -> Verdict {
+        self.check(text, &self.output_action, "response", session)
+            .await
+    }
+
+    /// Whether responses are screened, so they can't be streamed before they are
+    pub fn checks_output(&self) -> bool {
+        self.output_action != "off"
+    }
+
+    async fn check(&self, text: &str, action: &str, what: &str, session: &str) -> Verdict {
+        if action == "off" || text.trim().is_empty() {
+            return Verdict::Allow(text.to_string());
+        }
+        // A classifier that can't be reached lets text through, so an outage of the
+        // moderation service doesn't take the agent down with it
+        let flagged = match self.classify(text).await {
+            Ok(flagged) => flagged,
+            Err(e) => {
+                tracing::warn!("Moderation failed, letting the {} through: {:#}", what, e);
+                return Verdict::Allow(text.to_string());
+            }
+        };
+        let flagged: Vec<String> = flagged
+            .into_iter()
+            .filter(|category| self.categories.is_empty() || self.categories.contains(category))
+            .collect();
+        if flagged.is_empty() {
+            return Verdict::Allow(text.to_string());
+        }
+
+        let categories = flagged.join(", ");
+        tracing::warn!(
+            "Moderation flagged a {} in session {} for {}; action: {}",
+            what,
+            session,
+            categories,
+            action
+        );
+        match action {
+            "flag" => Verdict::Allow(text.to_string()),
+            "redact" => Verdict::Allow(PLACEHOLDER.to_string()),
+            _ => Verdict::Block(format!(
+                "The {} was blocked by moderation ({})",
+                what, categories
+            )),
+        }
+    }
+
+    // The categories the classifier flags `text` for
+    async fn classify(&self, text: &str) -> Result<Vec<String>> {
+        match &self.classifier {
+            Classifier::OpenAi {
+                client,
+                api_url,
+                model,
+            } => {
+                let response = client
+                    .post(api_url)
+                    .json(&json!({ "model": model, "input": text }))
+                    .send()
+                    .await?;
+                if !response.status().is_success() {
+                    let error_text = response.text().await?;
+                    anyhow::bail!("Moderation API error: {}", error_text);
+                }
+                let response: ModerationResponse = response.json().await?;
+                Ok(response
+                    .results
+                    .into_iter()
+                    .flat_map(|result| result.categories)
+                    .filter(|(_, flagged)| *flagged)
+                    .map(|(category, _)| category)
+                    .collect())
+            }
+            Classifier::Command(command) => {
+                let mut child = crate::io::exec::shell_command(command)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .kill_on_drop(true)
+                    .spawn()
+                    .context(format!("Failed to run moderation command: {}", command))?;
+                if let Some(mut stdin) = child.stdin.take() {
+                    if let Err(e) = stdin.write_all(text.as_bytes()).await {
+                        tracing::debug!("Moderation command did not read its input: {}", e);
+                    }
+                }
+                let output = tokio::time::timeout(COMMAND_TIMEOUT, child.wait_with_output())
+                    .await
+                    .map_err(|_| {
+                        anyhow::anyhow!(
+                            "Moderation command timed out after {}s",
+                            COMMAND_TIMEOUT.as_secs()
+                        )
+                    })??;
+                if !output.status.success() {
+                    anyhow::bail!(
+                        "Moderation command failed ({}): {}",
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                }
+                // Categories separated by commas or whitespace
+                Ok(String::from_utf8_lossy(&output.stdout)
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|category| !category.is_empty())
+                    .map(str::to_string)
+                    .collect())
+            }
+        }
+    }
+}
+
+fn action(action: Option<&str>) -> String {
+    action.unwrap_or("block").to_string()
+}
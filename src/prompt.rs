@@ -0,0 +1,179 @@
+//! The system message as sent: the configured one rendered as a minijinja template for
+//! every request, with the contents of the context files and the config's variables
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use minijinja::{Environment, Value};
+use std::collections::{BTreeMap, HashSet};
+
+use crate::config::Config;
+use crate::providers::Tool;
+
+/// Variables every system message and context file can use
+pub const VARIABLES: &[&str] = &[
+    "agent_name",
+    "hostname",
+    "platform",
+    "arch",
+    "date",
+    "time",
+    "datetime",
+    "weekday",
+    "provider",
+    "model",
+    "tools",
+    "context",
+];
+
+/// The context files and variables a system message is rendered with
+pub struct SystemPrompt {
+    // Contents of the context files by path, read when the config is (re)loaded
+    context_files: Vec<(String, String)>,
+    variables: BTreeMap<String, serde_json::Value>,
+    hostname: String,
+}
+
+impl SystemPrompt {
+    /// Read the config's context files. Loading the config checks they exist, so one
+    /// that can't be read is only left out, with an error in the log.
+    pub fn load(config: &Config) -> Self {
+        let mut context_files = Vec::new();
+        for path in &config.context_files {
+            match std::fs::read_to_string(path) {
+                Ok(text) => context_files.push((path.clone(), text)),
+                Err(e) => tracing::error!("Failed to read context file {}: {}", path, e),
+            }
+        }
+        SystemPrompt {
+            context_files,
+            variables: config.prompt_variables.clone(),
+            hostname: hostname(),
+        }
+    }
+
+    /// Render `template` for a request now, with the tools offered to it.
+    ///
+    /// The context files are rendered first and joined where the template says
+    /// `{{ context }}`, or after it otherwise.
+    pub fn render(
+        &self,
+        template: &str,
+        config: &Config,
+        tools: Option<&[Tool]>,
+    ) -> Result<String> {
+        let env = Environment::new();
+        let now = Local::now();
+        let mut variables: BTreeMap<&str, Value> = self
+            .variables
+            .iter()
+            .map(|(name, value)| (name.as_str(), Value::from_serialize(value)))
+            .collect();
+        let tools: Vec<&str> = tools
+            .unwrap_or_default()
+            .iter()
+            .map(|tool| tool.function.name.as_str())
+            .collect();
+        for (name, value) in [
+            ("agent_name", Value::from(config.agent_name.as_str())),
+            ("hostname", Value::from(self.hostname.as_str())),
+            ("platform", Value::from(std::env::consts::OS)),
+            ("arch", Value::from(std::env::consts::ARCH)),
+            ("date", Value::from(now.format("%Y-%m-%d").to_string())),
+            ("time", Value::from(now.format("%H:%M").to_string())),
+            (
+                "datetime",
+                Value::from(now.format("%Y-%m-%d %H:%M %:z").to_string()),
+            ),
+            ("weekday", Value::from(now.format("%A").to_string())),
+            ("provider", Value::from(config.provider.as_str())),
+            ("model", Value::from(config.model.as_str())),
+            ("tools", Value::from_serialize(&tools)),
+        ] {
+            variables.insert(name, value);
+        }
+
+        let mut files = Vec::new();
+        for (path, text) in &self.context_files {
+            let text = env
+                .render_str(text, &variables)
+                .context(format!("Failed to render context file {}", path))?;
+            if !text.trim().is_empty() {
+                files.push(text.trim().to_string());
+            }
+        }
+        let context = files.join("\n\n");
+        variables.insert("context", Value::from(context.as_str()));
+
+        let template = env
+            .template_from_str(template)
+            .context("Failed to parse system message")?;
+        let message = template
+            .render(&variables)
+            .context("Failed to render system message")?;
+        if context.is_empty() || template.undeclared_variables(false).contains("context") {
+            Ok(message)
+        } else if message.trim().is_empty() {
+            Ok(context)
+        } else {
+            Ok(format!("{}\n\n{}", message.trim_end(), context))
+        }
+    }
+}
+
+/// Problems with the system message and context files as templates: syntax errors and
+/// variables that are neither built in nor in `prompt_variables`
+pub fn check(config: &Config) -> Vec<String> {
+    let mut known: HashSet<&str> = VARIABLES.iter().copied().collect();
+    known.extend(config.prompt_variables.keys().map(String::as_str));
+
+    let mut sources = vec![("system_message".to_string(), config.system_message.clone())];
+    for path in &config.context_files {
+        // Missing files are reported on their own
+        if let Ok(text) = std::fs::read_to_string(path) {
+            sources.push((format!("context file {}", path), text));
+        }
+    }
+
+    let env = Environment::new();
+    let mut problems = Vec::new();
+    for (source, text) in &sources {
+        match env.template_from_str(text) {
+            Ok(template) => {
+                let mut unknown: Vec<String> = template
+                    .undeclared_variables(false)
+                    .into_iter()
+                    .filter(|name| !known.contains(name.as_str()))
+                    .collect();
+                unknown.sort();
+                for name in unknown {
+                    problems.push(format!(
+                        "{}: unknown variable {}; define it under prompt_variables",
+                        source, name
+                    ));
+                }
+            }
+            Err(e) => problems.push(format!("{}: {}", source, e)),
+        }
+    }
+    for name in config.prompt_variables.keys() {
+        if VARIABLES.contains(&name.as_str()) {
+            problems.push(format!(
+                "prompt_variables: {} is a built-in variable and can't be redefined",
+                name
+            ));
+        }
+    }
+    problems
+}
+
+// The machine's name, from the kernel on Linux or the environment elsewhere
+fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
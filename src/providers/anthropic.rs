@@ -4,18 +4,24 @@ use reqwest::{header, Client};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use super::{ChatCompletionResponse, FunctionCall, Message, Provider, Tool, ToolCall};
+use super::{
+    ChatCompletionResponse, FunctionCall, Message, ModelInfo, Provider, ProviderSettings, Tool,
+    ToolCall, Usage,
+};
+
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com/v1";
 
 pub struct AnthropicProvider {
     client: Client,
     #[allow(dead_code)]
     api_key: String,
+    base_url: String,
 }
 
 impl AnthropicProvider {
-    pub fn new(api_key: &str) -> Self {
+    pub fn new(api_key: &str, settings: &ProviderSettings) -> Result<Self> {
         let mut headers = header::HeaderMap::new();
-        headers.insert("x-api-key", header::HeaderValue::from_str(api_key).unwrap());
+        headers.insert("x-api-key", header::HeaderValue::from_str(api_key)?);
         headers.insert(
             "Content-Type",
             header::HeaderValue::from_static("application/json"),
@@ -25,12 +31,11 @@ impl AnthropicProvider {
             header::HeaderValue::from_static("2023-06-01"),
         );
 
-        let client = Client::builder().default_headers(headers).build().unwrap();
-
-        AnthropicProvider {
-            client,
+        Ok(AnthropicProvider {
+            client: settings.client(headers)?,
             api_key: api_key.to_string(),
-        }
+            base_url: settings.base_url(DEFAULT_BASE_URL),
+        })
     }
 }
 
@@ -40,6 +45,40 @@ impl Provider for AnthropicProvider {
         "anthropic"
     }
 
+    // The list comes in pages, continued after the last ID of the one before
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        let mut models = Vec::new();
+        let mut after: Option<String> = None;
+        loop {
+            let mut request = self
+                .client
+                .get(format!("{}/models", self.base_url))
+                .query(&[("limit", "1000")]);
+            if let Some(after) = &after {
+                request = request.query(&[("after_id", after)]);
+            }
+            let response = request.send().await?;
+            if !response.status().is_success() {
+                let error_text = response.text().await?;
+                anyhow::bail!("Anthropic API error: {}", error_text);
+            }
+            let page: Value = response.json().await?;
+            models.extend(
+                page["data"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(ModelInfo::from_json),
+            );
+            match page["last_id"].as_str() {
+                Some(last) if page["has_more"].as_bool() == Some(true) => {
+                    after = Some(last.to_string())
+                }
+                _ => return Ok(models),
+            }
+        }
+    }
+
     fn available_models(&self) -> Vec<String> {
         vec![
             "claude-3.7-sonnet".to_string(),
@@ -88,7 +127,7 @@ impl Provider for AnthropicProvider {
 
         let response = self
             .client
-            .post("https://api.anthropic.com/v1/messages")
+            .post(format!("{}/messages", self.base_url))
             .json(&request)
             .send()
             .await?;
@@ -124,6 +163,10 @@ impl Provider for AnthropicProvider {
                 tool_call_id: None,
             },
             tool_calls,
+            usage: response_json.usage.map(|usage| Usage {
+                prompt_tokens: usage.input_tokens,
+                completion_tokens: usage.output_tokens,
+            }),
         })
     }
 }
@@ -160,6 +203,13 @@ struct AnthropicResponse {
     model: String,
     stop_reason: Option<String>,
     tool_use: Option<AnthropicToolUse>,
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicUsage {
+    input_tokens: u64,
+    output_tokens: u64,
 }
 
 #[allow(dead_code)]
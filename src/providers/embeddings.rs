@@ -0,0 +1,116 @@
+//! Text embeddings from an OpenAI-compatible `/embeddings` endpoint, for memory and
+//! document search
+
+use anyhow::{Context, Result};
+use reqwest::{header, Client};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::config::Config;
+use crate::credentials;
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Embedding model used unless the config names another
+pub const DEFAULT_MODEL: &str = "text-embedding-3-small";
+
+// Most texts sent in one request
+const BATCH_SIZE: usize = 64;
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// Client for the embeddings endpoint, using the openai provider's API key, base URL
+/// and headers whichever provider answers messages
+pub struct Embedder {
+    client: Client,
+    api_url: String,
+    model: String,
+}
+
+impl Embedder {
+    pub fn new(config: &Config) -> Result<Self> {
+        let api_key = credentials::load_api_key_for(config, "openai")
+            .context("Embeddings need an OpenAI API key")?;
+        let settings = config.provider_settings("openai");
+        let mut headers = header::HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            header::HeaderValue::from_str(&format!("Bearer {}", api_key))?,
+        );
+
+        Ok(Embedder {
+            client: settings.client(headers)?,
+            api_url: match &config.embedding_url {
+                Some(url) => url.clone(),
+                None => format!("{}/embeddings", settings.base_url(DEFAULT_BASE_URL)),
+            },
+            model: config.embedding_model().to_string(),
+        })
+    }
+
+    /// Embed one text
+    pub async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        self.embed_all(&[text.to_string()])
+            .await?
+            .pop()
+            .context("No embedding returned from embedding API")
+    }
+
+    /// Embed several texts, in as few requests as the batch size allows
+    pub async fn embed_all(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(BATCH_SIZE) {
+            let request = json!({
+                "model": self.model,
+                "input": batch,
+            });
+            let response = self
+                .client
+                .post(&self.api_url)
+                .json(&request)
+                .send()
+                .await?;
+
+            if !response.status().is_success() {
+                let error_text = response.text().await?;
+                anyhow::bail!("Embedding API error: {}", error_text);
+            }
+
+            let mut response: EmbeddingResponse = response.json().await?;
+            if response.data.len() != batch.len() {
+                anyhow::bail!(
+                    "Embedding API returned {} embeddings for {} texts",
+                    response.data.len(),
+                    batch.len()
+                );
+            }
+            response.data.sort_by_key(|data| data.index);
+            embeddings.extend(response.data.into_iter().map(|data| data.embedding));
+        }
+        Ok(embeddings)
+    }
+}
+
+/// Cosine similarity of two embeddings; 0 when their lengths differ
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
@@ -0,0 +1,141 @@
+//! A provider that answers from a script instead of a model, for tests and for trying
+//! out inputs and outputs without an API key
+
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use super::{
+    tokens, ChatCompletionResponse, FunctionCall, Message, Provider, Tool, ToolCall, Usage,
+};
+
+/// A request the provider was sent
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub model: String,
+    pub messages: Vec<Message>,
+    /// Names of the tools offered with it
+    pub tools: Vec<String>,
+}
+
+// An answer waiting its turn
+enum Scripted {
+    Reply(String),
+    CallTool { name: String, arguments: String },
+    Fail(String),
+}
+
+#[derive(Default)]
+struct Script {
+    answers: VecDeque<Scripted>,
+    requests: Vec<MockRequest>,
+}
+
+/// Answers requests with the replies, tool calls and errors queued on it, in order,
+/// and with `echo: <the last user message>` once they run out. Clones share the
+/// script, so one can be kept to look at the requests after another is handed to
+/// the agent
+#[derive(Clone, Default)]
+pub struct MockProvider {
+    script: Arc<Mutex<Script>>,
+}
+
+impl MockProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer a request with `text`
+    pub fn reply(self, text: impl Into<String>) -> Self {
+        self.push(Scripted::Reply(text.into()))
+    }
+
+    /// Answer a request by calling the tool `name` with `arguments`
+    pub fn call_tool(self, name: impl Into<String>, arguments: serde_json::Value) -> Self {
+        self.push(Scripted::CallTool {
+            name: name.into(),
+            arguments: arguments.to_string(),
+        })
+    }
+
+    /// Fail a request with `error`, as an unreachable provider would
+    pub fn fail(self, error: impl Into<String>) -> Self {
+        self.push(Scripted::Fail(error.into()))
+    }
+
+    /// Every request sent so far, oldest first
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.script.lock().unwrap().requests.clone()
+    }
+
+    fn push(self, answer: Scripted) -> Self {
+        self.script.lock().unwrap().answers.push_back(answer);
+        self
+    }
+}
+
+#[async_trait]
+impl Provider for MockProvider {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn available_models(&self) -> Vec<String> {
+        vec!["mock".to_string()]
+    }
+
+    async fn chat_completion(
+        &self,
+        model: &str,
+        messages: &[Message],
+        tools: Option<&[Tool]>,
+    ) -> Result<ChatCompletionResponse> {
+        let answer = {
+            let mut script = self.script.lock().unwrap();
+            script.requests.push(MockRequest {
+                model: model.to_string(),
+                messages: messages.to_vec(),
+                tools: tools
+                    .into_iter()
+                    .flatten()
+                    .map(|tool| tool.function.name.clone())
+                    .collect(),
+            });
+            script.answers.pop_front()
+        };
+
+        let (content, tool_calls) = match answer {
+            Some(Scripted::Reply(text)) => (text, None),
+            Some(Scripted::CallTool { name, arguments }) => {
+                let call = ToolCall {
+                    id: Some(format!("call_{}", uuid::Uuid::new_v4().simple())),
+                    type_: Some("function".to_string()),
+                    function: Some(FunctionCall { name, arguments }),
+                };
+                (String::new(), Some(vec![call]))
+            }
+            Some(Scripted::Fail(error)) => anyhow::bail!("Mock provider error: {}", error),
+            None => {
+                let last = messages.iter().rev().find(|message| message.role == "user");
+                let content = last.map_or("", |message| message.content.as_str());
+                (format!("echo: {}", content), None)
+            }
+        };
+        // Estimated, so usage and budgets have something to count
+        let usage = Usage {
+            prompt_tokens: messages.iter().map(tokens::message_tokens).sum::<usize>() as u64,
+            completion_tokens: tokens::estimate(&content) as u64,
+        };
+        Ok(ChatCompletionResponse {
+            message: Message {
+                role: "assistant".to_string(),
+                content,
+                tool_calls: tool_calls.clone(),
+                tool_call_id: None,
+            },
+            tool_calls,
+            usage: Some(usage),
+        })
+    }
+}
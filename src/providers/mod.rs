@@ -1,10 +1,66 @@
 pub mod anthropic;
+pub mod embeddings;
+pub mod mock;
 pub mod openai;
+pub mod tokens;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
+use reqwest::{header, Client};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+
+/// Providers get_provider can create
+pub const PROVIDERS: &[&str] = &["openai", "anthropic"];
+
+/// Connection settings for one provider, from the config's `providers` section
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProviderSettings {
+    /// Environment variable holding the API key (default: {PROVIDER}_API_KEY)
+    pub api_key_env: Option<String>,
+
+    /// File to read the API key from
+    pub api_key_file: Option<String>,
+
+    /// Command whose output is the API key, e.g. a secret manager CLI
+    pub api_key_command: Option<String>,
+
+    /// API base URL, e.g. for a proxy or a compatible server (default: the provider's API)
+    pub base_url: Option<String>,
+
+    /// Extra HTTP headers sent with every request, e.g. for a gateway's own auth
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl ProviderSettings {
+    /// The configured base URL, or `default`, without a trailing slash
+    pub fn base_url(&self, default: &str) -> String {
+        self.base_url
+            .as_deref()
+            .unwrap_or(default)
+            .trim_end_matches('/')
+            .to_string()
+    }
+
+    /// An HTTP client sending `headers`, then the configured extra headers, with
+    /// every request; configured headers replace ones of the same name
+    pub fn client(&self, mut headers: header::HeaderMap) -> Result<Client> {
+        for (name, value) in &self.headers {
+            let name = header::HeaderName::from_bytes(name.as_bytes())
+                .context(format!("Invalid header name: {}", name))?;
+            let value = header::HeaderValue::from_str(value)
+                .context(format!("Invalid value for header {}", name))?;
+            headers.insert(name, value);
+        }
+        Ok(Client::builder().default_headers(headers).build()?)
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
@@ -48,6 +104,124 @@ pub struct Function {
 pub struct ChatCompletionResponse {
     pub message: Message,
     pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+/// A model from a provider's model list
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ModelInfo {
+    pub id: String,
+    /// Context window in tokens, from the list or else the known models
+    pub context_window: Option<usize>,
+    /// Day the model was released, as YYYY-MM-DD
+    pub created: Option<String>,
+    /// When or that the model is going away, if the list says
+    pub deprecation: Option<String>,
+}
+
+impl ModelInfo {
+    /// A model known by name only
+    pub fn known(id: String) -> Self {
+        Self {
+            context_window: tokens::context_window(&id),
+            id,
+            ..Self::default()
+        }
+    }
+
+    /// Read an entry of a model list. Besides the `id` and `created` that OpenAI and
+    /// Anthropic give, compatible servers and gateways name the context window and
+    /// deprecation in several ways
+    pub fn from_json(entry: &Value) -> Option<Self> {
+        let id = entry["id"].as_str()?.to_string();
+        let context_window = [
+            "context_window",
+            "context_length",
+            "max_context_length",
+            "max_model_len",
+        ]
+        .iter()
+        .find_map(|field| entry[*field].as_u64())
+        .map(|tokens| tokens as usize)
+        .or_else(|| tokens::context_window(&id));
+        let created = match &entry["created"] {
+            Value::Number(seconds) => seconds
+                .as_i64()
+                .and_then(|seconds| chrono::DateTime::from_timestamp(seconds, 0))
+                .map(|time| time.format("%Y-%m-%d").to_string()),
+            _ => entry["created_at"]
+                .as_str()
+                .and_then(|time| time.get(..10))
+                .map(String::from),
+        };
+        let deprecation = ["deprecation_date", "expiration_date", "shutdown_date"]
+            .iter()
+            .find_map(|field| entry[*field].as_str())
+            .map(|date| format!("deprecated, until {}", date))
+            .or_else(|| {
+                (entry["deprecated"].as_bool() == Some(true)).then(|| "deprecated".to_string())
+            });
+        Some(Self {
+            id,
+            context_window,
+            created,
+            deprecation,
+        })
+    }
+}
+
+/// Token counts reported by the provider for one completion
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+// Running totals for this process
+static PROMPT_TOKENS: AtomicU64 = AtomicU64::new(0);
+static COMPLETION_TOKENS: AtomicU64 = AtomicU64::new(0);
+
+// Usage by local day, agent and model, for budgets
+type LedgerKey = (chrono::NaiveDate, String, String);
+static LEDGER: Mutex<BTreeMap<LedgerKey, Usage>> = Mutex::new(BTreeMap::new());
+
+/// Add a completion's usage by `agent` on `model` to the running totals and the ledger
+pub fn record_usage(agent: &str, model: &str, usage: &Usage) {
+    PROMPT_TOKENS.fetch_add(usage.prompt_tokens, Ordering::Relaxed);
+    COMPLETION_TOKENS.fetch_add(usage.completion_tokens, Ordering::Relaxed);
+    add_to_ledger(chrono::Local::now().date_naive(), agent, model, usage);
+}
+
+/// Count usage from before this process started in the ledger, but not the totals
+pub fn add_to_ledger(day: chrono::NaiveDate, agent: &str, model: &str, usage: &Usage) {
+    let mut ledger = LEDGER.lock().unwrap();
+    let entry = ledger
+        .entry((day, agent.to_string(), model.to_string()))
+        .or_default();
+    entry.prompt_tokens += usage.prompt_tokens;
+    entry.completion_tokens += usage.completion_tokens;
+}
+
+/// Tokens `agent` used on each model from `since` on
+pub fn usage_since(agent: &str, since: chrono::NaiveDate) -> BTreeMap<String, Usage> {
+    let mut usage: BTreeMap<String, Usage> = BTreeMap::new();
+    for ((day, entry_agent, model), used) in LEDGER.lock().unwrap().iter() {
+        if *day >= since && entry_agent == agent {
+            let total = usage.entry(model.clone()).or_default();
+            total.prompt_tokens += used.prompt_tokens;
+            total.completion_tokens += used.completion_tokens;
+        }
+    }
+    usage
+}
+
+/// Tokens used by every completion so far
+pub fn total_usage() -> Usage {
+    Usage {
+        prompt_tokens: PROMPT_TOKENS.load(Ordering::Relaxed),
+        completion_tokens: COMPLETION_TOKENS.load(Ordering::Relaxed),
+    }
 }
 
 #[async_trait]
@@ -58,6 +232,16 @@ pub trait Provider: Send + Sync {
     /// Get the available models for this provider
     fn available_models(&self) -> Vec<String>;
 
+    /// List the models the provider's API offers. Providers without a model list
+    /// give their known models
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        Ok(self
+            .available_models()
+            .into_iter()
+            .map(ModelInfo::known)
+            .collect())
+    }
+
     /// Get a chat completion from the provider
     async fn chat_completion(
         &self,
@@ -65,12 +249,50 @@ pub trait Provider: Send + Sync {
         messages: &[Message],
         tools: Option<&[Tool]>,
     ) -> Result<ChatCompletionResponse>;
+
+    /// Get a chat completion, sending its text to `deltas` as it is generated.
+    /// Providers that can't stream send the whole text once it is complete
+    async fn chat_completion_stream(
+        &self,
+        model: &str,
+        messages: &[Message],
+        tools: Option<&[Tool]>,
+        deltas: mpsc::UnboundedSender<String>,
+    ) -> Result<ChatCompletionResponse> {
+        let response = self.chat_completion(model, messages, tools).await?;
+        if !response.message.content.is_empty() {
+            let _ = deltas.send(response.message.content.clone());
+        }
+        Ok(response)
+    }
+}
+
+/// Infer the provider for a model name, e.g. `gpt-4o` -> `openai`
+pub fn provider_for_model(model: &str) -> Option<&'static str> {
+    let model = model.to_lowercase();
+    if model.starts_with("gpt-")
+        || model.starts_with("o1")
+        || model.starts_with("o3")
+        || model.starts_with("o4")
+    {
+        Some("openai")
+    } else if model.starts_with("claude-") {
+        Some("anthropic")
+    } else {
+        None
+    }
 }
 
-pub fn get_provider(provider_name: &str, api_key: &str) -> Result<Box<dyn Provider>> {
+pub fn get_provider(
+    provider_name: &str,
+    api_key: &str,
+    settings: &ProviderSettings,
+) -> Result<Box<dyn Provider>> {
     match provider_name.to_lowercase().as_str() {
-        "openai" => Ok(Box::new(openai::OpenAIProvider::new(api_key))),
-        "anthropic" => Ok(Box::new(anthropic::AnthropicProvider::new(api_key))),
+        "openai" => Ok(Box::new(openai::OpenAIProvider::new(api_key, settings)?)),
+        "anthropic" => Ok(Box::new(anthropic::AnthropicProvider::new(
+            api_key, settings,
+        )?)),
         _ => anyhow::bail!("Unsupported provider: {}", provider_name),
     }
 }
@@ -3,42 +3,98 @@ use async_trait::async_trait;
 use reqwest::{header, Client};
 use serde::Deserialize;
 use serde_json::json;
+use tokio::sync::mpsc;
 
-use super::{ChatCompletionResponse, FunctionCall, Message, Provider, Tool, ToolCall};
+use super::{
+    ChatCompletionResponse, FunctionCall, Message, ModelInfo, Provider, ProviderSettings, Tool,
+    ToolCall, Usage,
+};
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
 
 pub struct OpenAIProvider {
     client: Client,
     #[allow(dead_code)]
     api_key: String,
+    base_url: String,
 }
 
 impl OpenAIProvider {
-    pub fn new(api_key: &str) -> Self {
+    pub fn new(api_key: &str, settings: &ProviderSettings) -> Result<Self> {
         let mut headers = header::HeaderMap::new();
         headers.insert(
             "Authorization",
-            header::HeaderValue::from_str(&format!("Bearer {}", api_key)).unwrap(),
+            header::HeaderValue::from_str(&format!("Bearer {}", api_key))?,
         );
         headers.insert(
             "Content-Type",
             header::HeaderValue::from_static("application/json"),
         );
 
-        let client = Client::builder().default_headers(headers).build().unwrap();
-
-        OpenAIProvider {
-            client,
+        Ok(OpenAIProvider {
+            client: settings.client(headers)?,
             api_key: api_key.to_string(),
+            base_url: settings.base_url(DEFAULT_BASE_URL),
+        })
+    }
+
+    // Send a chat completion request, failing on an error status
+    async fn post(&self, request: serde_json::Value) -> Result<reqwest::Response> {
+        let response = self
+            .client
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(&request)
+            .send()
+            .await?;
+
+        // Check if the response is successful
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            anyhow::bail!("OpenAI API error: {}", error_text);
         }
+        Ok(response)
     }
 }
 
+// The body of a chat completion request
+fn request(model: &str, messages: &[Message], tools: Option<&[Tool]>) -> serde_json::Value {
+    let mut request = json!({
+        "model": model,
+        "messages": messages,
+    });
+
+    if let Some(tools) = tools {
+        request["tools"] = json!(tools);
+        request["tool_choice"] = json!("auto");
+    }
+    request
+}
+
 #[async_trait]
 impl Provider for OpenAIProvider {
     fn name(&self) -> &str {
         "openai"
     }
 
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        let response = self
+            .client
+            .get(format!("{}/models", self.base_url))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            anyhow::bail!("OpenAI API error: {}", error_text);
+        }
+        let list: serde_json::Value = response.json().await?;
+        Ok(list["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(ModelInfo::from_json)
+            .collect())
+    }
+
     fn available_models(&self) -> Vec<String> {
         vec![
             "gpt-4o".to_string(),
@@ -54,29 +110,7 @@ impl Provider for OpenAIProvider {
         messages: &[Message],
         tools: Option<&[Tool]>,
     ) -> Result<ChatCompletionResponse> {
-        let mut request = json!({
-            "model": model,
-            "messages": messages,
-        });
-
-        if let Some(tools) = tools {
-            request["tools"] = json!(tools);
-            request["tool_choice"] = json!("auto");
-        }
-
-        let response = self
-            .client
-            .post("https://api.openai.com/v1/chat/completions")
-            .json(&request)
-            .send()
-            .await?;
-
-        // Check if the response is successful
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            anyhow::bail!("OpenAI API error: {}", error_text);
-        }
-
+        let response = self.post(request(model, messages, tools)).await?;
         let response_json: OpenAIChatCompletionResponse = response.json().await?;
 
         // Extract the first choice from the response
@@ -106,11 +140,103 @@ impl Provider for OpenAIProvider {
                     tool_call_id: None,
                 },
                 tool_calls: tool_calls_converted,
+                usage: Some(Usage {
+                    prompt_tokens: response_json.usage.prompt_tokens as u64,
+                    completion_tokens: response_json.usage.completion_tokens as u64,
+                }),
             })
         } else {
             anyhow::bail!("No completion choices returned from OpenAI")
         }
     }
+
+    async fn chat_completion_stream(
+        &self,
+        model: &str,
+        messages: &[Message],
+        tools: Option<&[Tool]>,
+        deltas: mpsc::UnboundedSender<String>,
+    ) -> Result<ChatCompletionResponse> {
+        let mut request = request(model, messages, tools);
+        request["stream"] = json!(true);
+        request["stream_options"] = json!({ "include_usage": true });
+        let mut response = self.post(request).await?;
+
+        // Server-sent events, one JSON chunk per `data:` line until `data: [DONE]`
+        let mut buffer = Vec::new();
+        let mut content = String::new();
+        let mut calls: Vec<OpenAIToolCallDelta> = Vec::new();
+        let mut usage = None;
+        'events: while let Some(bytes) = response.chunk().await? {
+            buffer.extend_from_slice(&bytes);
+            while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                let Some(data) = line.trim().strip_prefix("data:") else {
+                    continue;
+                };
+                let data = data.trim();
+                if data == "[DONE]" {
+                    break 'events;
+                }
+
+                let chunk: OpenAIStreamChunk = serde_json::from_str(data)
+                    .map_err(|e| anyhow::anyhow!("Invalid stream chunk from OpenAI: {}", e))?;
+                if let Some(chunk_usage) = chunk.usage {
+                    usage = Some(Usage {
+                        prompt_tokens: chunk_usage.prompt_tokens as u64,
+                        completion_tokens: chunk_usage.completion_tokens as u64,
+                    });
+                }
+                let Some(delta) = chunk.choices.into_iter().next().map(|choice| choice.delta)
+                else {
+                    continue;
+                };
+                if let Some(text) = delta.content.filter(|text| !text.is_empty()) {
+                    content.push_str(&text);
+                    let _ = deltas.send(text);
+                }
+                // A tool call arrives in pieces, its arguments a few characters at a time
+                for call in delta.tool_calls.unwrap_or_default() {
+                    match calls.iter_mut().find(|known| known.index == call.index) {
+                        Some(known) => {
+                            let function = known.function.get_or_insert_with(Default::default);
+                            if let Some(more) = call.function {
+                                function.name.push_str(&more.name);
+                                function.arguments.push_str(&more.arguments);
+                            }
+                            known.id = known.id.take().or(call.id);
+                        }
+                        None => calls.push(call),
+                    }
+                }
+            }
+        }
+
+        let tool_calls: Option<Vec<ToolCall>> = (!calls.is_empty()).then(|| {
+            calls
+                .into_iter()
+                .map(|call| ToolCall {
+                    id: call.id,
+                    type_: Some("function".to_string()),
+                    function: call.function.map(|function| FunctionCall {
+                        name: function.name,
+                        arguments: function.arguments,
+                    }),
+                })
+                .collect()
+        });
+        Ok(ChatCompletionResponse {
+            message: Message {
+                role: "assistant".to_string(),
+                content,
+                tool_calls: tool_calls.clone(),
+                tool_call_id: None,
+            },
+            tool_calls,
+            usage,
+        })
+    }
 }
 
 // OpenAI API response structs
@@ -161,3 +287,37 @@ struct OpenAIUsage {
     completion_tokens: u32,
     total_tokens: u32,
 }
+
+// Streaming response structs
+#[derive(Debug, Deserialize)]
+struct OpenAIStreamChunk {
+    #[serde(default)]
+    choices: Vec<OpenAIStreamChoice>,
+    usage: Option<OpenAIUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIStreamChoice {
+    delta: OpenAIDelta,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIDelta {
+    content: Option<String>,
+    tool_calls: Option<Vec<OpenAIToolCallDelta>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIToolCallDelta {
+    index: u32,
+    id: Option<String>,
+    function: Option<OpenAIFunctionCallDelta>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct OpenAIFunctionCallDelta {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    arguments: String,
+}
@@ -0,0 +1,85 @@
+//! Token estimates for fitting conversations into a model's context window
+//!
+//! Counts are estimates, not the provider's tokenizer: about four characters of
+//! ASCII text to a token, and a token for every other character, which errs on the
+//! side of too many for most languages.
+
+use super::{Message, Tool};
+
+/// Context window assumed for models this module doesn't know
+pub const DEFAULT_CONTEXT_WINDOW: usize = 8192;
+
+/// Tokens kept free for the response unless the config says otherwise
+pub const DEFAULT_RESPONSE_TOKENS: usize = 4096;
+
+// Tokens a message costs beyond its content: role and formatting
+const MESSAGE_OVERHEAD: usize = 4;
+
+// Known context windows, by model name prefix; the first match wins
+const CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-4o", 128_000),
+    ("gpt-4.1", 1_000_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4", 8192),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1-mini", 128_000),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4", 200_000),
+    ("claude-", 200_000),
+];
+
+/// The context window of a known model, in tokens
+pub fn context_window(model: &str) -> Option<usize> {
+    let model = model.to_lowercase();
+    CONTEXT_WINDOWS
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, tokens)| *tokens)
+}
+
+/// Estimated tokens in a piece of text
+pub fn estimate(text: &str) -> usize {
+    let ascii = text.bytes().filter(u8::is_ascii).count();
+    let other = text.chars().filter(|c| !c.is_ascii()).count();
+    ascii.div_ceil(4) + other
+}
+
+/// Estimated tokens a message takes up in a request, including its tool calls
+pub fn message_tokens(message: &Message) -> usize {
+    let calls = message.tool_calls.iter().flatten().map(|call| {
+        call.function
+            .as_ref()
+            .map_or(0, |f| estimate(&f.name) + estimate(&f.arguments))
+            + MESSAGE_OVERHEAD
+    });
+    MESSAGE_OVERHEAD + estimate(&message.content) + calls.sum::<usize>()
+}
+
+/// Estimated tokens the tool definitions add to every request
+pub fn tools_tokens(tools: Option<&[Tool]>) -> usize {
+    tools
+        .and_then(|tools| serde_json::to_string(tools).ok())
+        .map_or(0, |json| estimate(&json))
+}
+
+/// The longest start of `text` estimated at no more than `max_tokens`
+pub fn truncate(text: &str, max_tokens: usize) -> &str {
+    let mut tokens = 0;
+    let mut ascii = 0;
+    for (i, c) in text.char_indices() {
+        if c.is_ascii() {
+            // Every fourth ASCII character starts another token
+            if ascii % 4 == 0 {
+                tokens += 1;
+            }
+            ascii += 1;
+        } else {
+            tokens += 1;
+        }
+        if tokens > max_tokens {
+            return &text[..i];
+        }
+    }
+    text
+}
@@ -0,0 +1,295 @@
+//! The queue between the inputs and the agent's main loop: how many messages wait in
+//! it, what happens to new ones when a burst fills it, and counts for /metrics
+
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use tokio::sync::Notify;
+
+use crate::io::InboundMessage;
+
+/// Messages the queue holds unless `input_queue_size` is set
+pub const DEFAULT_SIZE: usize = 10;
+
+// Queues of the agents in this process, for the metrics
+static QUEUES: Mutex<Vec<Weak<InputQueue>>> = Mutex::new(Vec::new());
+
+/// What to do with a new message when the queue is full
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    // Wait for room, holding up the input until the agent catches up
+    Block,
+    DropOldest,
+    // Turn the new message away and tell its source
+    Reject,
+}
+
+impl OverflowPolicy {
+    pub fn new(policy: Option<&str>) -> Result<Self> {
+        match policy {
+            None | Some("block") => Ok(Self::Block),
+            Some("drop_oldest") => Ok(Self::DropOldest),
+            Some("reject") => Ok(Self::Reject),
+            Some(other) => anyhow::bail!(
+                "Invalid input queue policy {}, expected block, drop_oldest or reject",
+                other
+            ),
+        }
+    }
+}
+
+/// How the queue is doing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueueStats {
+    pub depth: usize,
+    pub capacity: usize,
+    // Most messages waiting at once since the agent started
+    pub peak: usize,
+    pub received: u64,
+    pub dropped: u64,
+    pub rejected: u64,
+}
+
+struct State {
+    items: VecDeque<InboundMessage>,
+    peak: usize,
+    // Inputs still able to add messages
+    senders: usize,
+}
+
+/// Messages waiting for the main loop
+pub struct InputQueue {
+    agent_name: String,
+    capacity: usize,
+    policy: OverflowPolicy,
+    state: Mutex<State>,
+    // Signalled whenever a message is added or removed, or an input stops
+    changed: Notify,
+    received: AtomicU64,
+    dropped: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl InputQueue {
+    pub fn new(agent_name: &str, capacity: usize, policy: OverflowPolicy) -> Arc<Self> {
+        let queue = Arc::new(Self {
+            agent_name: agent_name.to_string(),
+            capacity,
+            policy,
+            state: Mutex::new(State {
+                items: VecDeque::new(),
+                peak: 0,
+                senders: 0,
+            }),
+            changed: Notify::new(),
+            received: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        });
+        let mut queues = QUEUES.lock().unwrap();
+        queues.retain(|queue| queue.strong_count() > 0);
+        queues.push(Arc::downgrade(&queue));
+        queue
+    }
+
+    /// A handle for one input; `recv` returns None once every handle is dropped and
+    /// the queue is empty
+    pub fn sender(self: &Arc<Self>) -> QueueSender {
+        self.state.lock().unwrap().senders += 1;
+        QueueSender {
+            queue: self.clone(),
+        }
+    }
+
+    /// The next message, waiting for one if there is none
+    pub async fn recv(&self) -> Option<InboundMessage> {
+        loop {
+            // Created before checking, so a change in between isn't missed
+            let changed = self.changed.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(message) = state.items.pop_front() {
+                    drop(state);
+                    self.changed.notify_waiters();
+                    return Some(message);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            changed.await;
+        }
+    }
+
+    /// Wait for a message `matches` accepts and move it to the front, so it is the
+    /// next one out; how a newer message cuts the answer to an older one short
+    pub async fn promote(&self, matches: impl Fn(&InboundMessage) -> bool) {
+        loop {
+            let changed = self.changed.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(index) = state.items.iter().position(&matches) {
+                    if let Some(message) = state.items.remove(index) {
+                        state.items.push_front(message);
+                    }
+                    return;
+                }
+            }
+            changed.await;
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let state = self.state.lock().unwrap();
+        QueueStats {
+            depth: state.items.len(),
+            capacity: self.capacity,
+            peak: state.peak,
+            received: self.received.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    async fn push(&self, message: InboundMessage) -> Result<()> {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        loop {
+            let changed = self.changed.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.items.len() < self.capacity {
+                    state.items.push_back(message);
+                    state.peak = state.peak.max(state.items.len());
+                    break;
+                }
+                match self.policy {
+                    OverflowPolicy::Block => {}
+                    OverflowPolicy::DropOldest => {
+                        tracing::warn!("Input queue is full, dropping the oldest message");
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                        state.items.pop_front();
+                        state.items.push_back(message);
+                        break;
+                    }
+                    OverflowPolicy::Reject => {
+                        self.rejected.fetch_add(1, Ordering::Relaxed);
+                        anyhow::bail!(
+                            "The agent is busy with {} waiting messages, try again later",
+                            self.capacity
+                        );
+                    }
+                }
+            }
+            changed.await;
+        }
+        self.changed.notify_waiters();
+        Ok(())
+    }
+}
+
+/// One input's way into the queue
+pub struct QueueSender {
+    queue: Arc<InputQueue>,
+}
+
+impl QueueSender {
+    /// Add a message, as the queue's overflow policy allows; an error means it was
+    /// rejected, with the reason to give its source
+    pub async fn send(&self, message: InboundMessage) -> Result<()> {
+        self.queue.push(message).await
+    }
+}
+
+impl Drop for QueueSender {
+    fn drop(&mut self) {
+        self.queue.state.lock().unwrap().senders -= 1;
+        self.queue.changed.notify_waiters();
+    }
+}
+
+/// The queue of the agent named `agent_name`, if it is running
+pub fn stats(agent_name: &str) -> Option<QueueStats> {
+    QUEUES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .find(|queue| queue.agent_name == agent_name)
+        .map(|queue| queue.stats())
+}
+
+// A metric's name, type and help text, and how to get its value from a queue
+type Family = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&QueueStats) -> u64,
+);
+
+/// Every running agent's queue in the Prometheus text format
+pub fn metrics() -> String {
+    let queues: Vec<(String, QueueStats)> = QUEUES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|queue| (queue.agent_name.clone(), queue.stats()))
+        .collect();
+
+    let families: [Family; 6] = [
+        (
+            "agent_input_queue_depth",
+            "gauge",
+            "Messages waiting for the agent",
+            |stats| stats.depth as u64,
+        ),
+        (
+            "agent_input_queue_capacity",
+            "gauge",
+            "Messages the input queue holds",
+            |stats| stats.capacity as u64,
+        ),
+        (
+            "agent_input_queue_peak",
+            "gauge",
+            "Most messages waiting at once since the agent started",
+            |stats| stats.peak as u64,
+        ),
+        (
+            "agent_input_messages_total",
+            "counter",
+            "Messages received from the inputs",
+            |stats| stats.received,
+        ),
+        (
+            "agent_input_dropped_total",
+            "counter",
+            "Messages dropped from a full input queue",
+            |stats| stats.dropped,
+        ),
+        (
+            "agent_input_rejected_total",
+            "counter",
+            "Messages rejected by a full input queue",
+            |stats| stats.rejected,
+        ),
+    ];
+    let mut text = String::new();
+    for (name, kind, help, value) in families {
+        text.push_str(&format!(
+            "# HELP {} {}\n# TYPE {} {}\n",
+            name, help, name, kind
+        ));
+        for (agent_name, stats) in &queues {
+            let label = agent_name.replace('\\', "\\\\").replace('"', "\\\"");
+            text.push_str(&format!(
+                "{}{{agent=\"{}\"}} {}\n",
+                name,
+                label,
+                value(stats)
+            ));
+        }
+    }
+    text
+}
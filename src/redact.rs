@@ -0,0 +1,277 @@
+//! Redaction of secrets and personal data from messages and tool results before they
+//! reach the history, the outputs or a provider
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use regex::Regex;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::config::Config;
+
+/// Patterns that can be turned on by name with `redact`
+pub const BUILTIN: &[(&str, &str)] = &[
+    (
+        "api_key",
+        r"\b(?:sk-(?:proj-|ant-)?[A-Za-z0-9_-]{20,}|gh[pousr]_[A-Za-z0-9]{36,}|github_pat_[A-Za-z0-9_]{22,}|AKIA[0-9A-Z]{16}|xox[abprs]-[A-Za-z0-9-]{10,}|AIza[0-9A-Za-z_-]{35})",
+    ),
+    (
+        "email",
+        r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}\b",
+    ),
+    // Candidates are checked with the Luhn checksum, so order and phone numbers
+    // mostly pass through
+    ("card_number", r"\b\d(?:[ -]?\d){12,18}\b"),
+];
+
+// A line of the audit log. The matched text itself is never written, only a short
+// hash of it so repeated leaks of the same value can be told apart.
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    timestamp: String,
+    session: &'a str,
+    source: &'a str,
+    pattern: &'a str,
+    fingerprint: String,
+}
+
+/// Replaces matches of the configured patterns with `[REDACTED:<name>]`
+pub struct Redactor {
+    patterns: Vec<(String, Regex)>,
+    audit_log: Option<PathBuf>,
+}
+
+impl Redactor {
+    /// The redactor for the config's `redact` and `redact_patterns`, or `None` when
+    /// neither is set
+    pub fn new(config: &Config) -> Result<Option<Self>> {
+        if config.redact.is_empty() && config.redact_patterns.is_empty() {
+            return Ok(None);
+        }
+
+        let mut patterns = Vec::new();
+        for name in &config.redact {
+            let (_, pattern) = BUILTIN
+                .iter()
+                .find(|(builtin, _)| builtin == name)
+                .context(format!(
+                    "Unknown redact pattern {}; expected one of {}",
+                    name,
+                    builtin_names()
+                ))?;
+            patterns.push((name.clone(), Regex::new(pattern)?));
+        }
+        patterns.extend(compile(&config.redact_patterns)?);
+
+        Ok(Some(Redactor {
+            patterns,
+            audit_log: config.redact_audit_log.as_ref().map(PathBuf::from),
+        }))
+    }
+
+    /// `text` with every match replaced by a placeholder. Each redaction is logged and,
+    /// with `redact_audit_log` set, recorded there against `session` and `source`.
+    pub fn redact(&self, text: &str, session: &str, source: &str) -> String {
+        let mut text = text.to_string();
+        let mut found: Vec<(&str, String)> = Vec::new();
+        for (name, regex) in &self.patterns {
+            let replaced = regex.replace_all(&text, |captures: &regex::Captures| {
+                let matched = &captures[0];
+                if name == "card_number" && !luhn_valid(matched) {
+                    return matched.to_string();
+                }
+                found.push((name, fingerprint(matched)));
+                format!("[REDACTED:{}]", name)
+            });
+            if let std::borrow::Cow::Owned(replaced) = replaced {
+                text = replaced;
+            }
+        }
+
+        if !found.is_empty() {
+            let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+            for (name, _) in &found {
+                *counts.entry(name).or_default() += 1;
+            }
+            let summary: Vec<String> = counts
+                .iter()
+                .map(|(name, count)| format!("{} {}", count, name))
+                .collect();
+            tracing::info!(
+                "Redacted {} from {} in session {}",
+                summary.join(", "),
+                source,
+                session
+            );
+            if let Err(e) = self.audit(session, source, &found) {
+                tracing::warn!("Failed to write redaction audit log: {:#}", e);
+            }
+        }
+        text
+    }
+
+    fn audit(&self, session: &str, source: &str, found: &[(&str, String)]) -> Result<()> {
+        let Some(path) = &self.audit_log else {
+            return Ok(());
+        };
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(format!(
+                "Failed to open redaction audit log: {}",
+                path.display()
+            ))?;
+        let timestamp = Utc::now().to_rfc3339();
+        for (pattern, fingerprint) in found {
+            let entry = AuditEntry {
+                timestamp: timestamp.clone(),
+                session,
+                source,
+                pattern,
+                fingerprint: fingerprint.clone(),
+            };
+            writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compile `redact_patterns`, naming the pattern that fails
+pub fn compile(patterns: &BTreeMap<String, String>) -> Result<Vec<(String, Regex)>> {
+    patterns
+        .iter()
+        .map(|(name, pattern)| {
+            Regex::new(pattern)
+                .map(|regex| (name.clone(), regex))
+                .context(format!("Invalid redact pattern {}", name))
+        })
+        .collect()
+}
+
+/// The names accepted by `redact`, for error messages
+pub fn builtin_names() -> String {
+    BUILTIN
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// First 12 hex digits of the SHA-256 of a redacted value
+fn fingerprint(value: &str) -> String {
+    hex::encode(Sha256::digest(value.as_bytes()))[..12].to_string()
+}
+
+// Whether the digits of `candidate` pass the Luhn checksum card numbers carry
+fn luhn_valid(candidate: &str) -> bool {
+    let digits: Vec<u32> = candidate.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &digit)| {
+            if i % 2 == 1 {
+                let doubled = digit * 2;
+                if doubled > 9 {
+                    doubled - 9
+                } else {
+                    doubled
+                }
+            } else {
+                digit
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor(config: Config) -> Redactor {
+        Redactor::new(&config).unwrap().expect("patterns are set")
+    }
+
+    #[test]
+    fn builtin_patterns_replace_matches() {
+        let redactor = redactor(Config {
+            redact: vec!["api_key".to_string(), "email".to_string()],
+            ..Default::default()
+        });
+        let text = redactor.redact(
+            "Mail alice@example.com the key sk-proj-abcdefghijklmnopqrstuvwx please",
+            "stdin",
+            "user",
+        );
+        assert_eq!(
+            text,
+            "Mail [REDACTED:email] the key [REDACTED:api_key] please"
+        );
+        assert_eq!(
+            redactor.redact("nothing here", "stdin", "user"),
+            "nothing here"
+        );
+    }
+
+    #[test]
+    fn card_numbers_need_a_valid_checksum() {
+        let redactor = redactor(Config {
+            redact: vec!["card_number".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(
+            redactor.redact("Card 4111 1111 1111 1111 expires soon", "s", "user"),
+            "Card [REDACTED:card_number] expires soon"
+        );
+        // Order numbers of the same length fail the checksum
+        assert_eq!(
+            redactor.redact("Order 4111 1111 1111 1112 shipped", "s", "user"),
+            "Order 4111 1111 1111 1112 shipped"
+        );
+    }
+
+    #[test]
+    fn the_audit_log_never_holds_the_value() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("agent-redact-{}.jsonl", std::process::id()));
+        let redactor = redactor(Config {
+            redact_patterns: BTreeMap::from([("ticket".to_string(), r"TICKET-\d+".to_string())]),
+            redact_audit_log: Some(path.to_str().unwrap().to_string()),
+            ..Default::default()
+        });
+        assert_eq!(
+            redactor.redact("See TICKET-4242", "mqtt:alice", "tool:search"),
+            "See [REDACTED:ticket]"
+        );
+
+        let log = std::fs::read_to_string(&path)?;
+        std::fs::remove_file(&path)?;
+        assert!(!log.contains("4242"), "{}", log);
+        let entry: serde_json::Value = serde_json::from_str(log.trim())?;
+        assert_eq!(entry["session"], "mqtt:alice");
+        assert_eq!(entry["source"], "tool:search");
+        assert_eq!(entry["pattern"], "ticket");
+        assert_eq!(entry["fingerprint"], fingerprint("TICKET-4242"));
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_and_invalid_patterns_are_errors() {
+        assert!(Redactor::new(&Config::default()).unwrap().is_none());
+        let unknown = Config {
+            redact: vec!["passport".to_string()],
+            ..Default::default()
+        };
+        assert!(Redactor::new(&unknown).is_err());
+        let invalid = Config {
+            redact_patterns: BTreeMap::from([("broken".to_string(), "(".to_string())]),
+            ..Default::default()
+        };
+        let e = Redactor::new(&invalid).err().unwrap();
+        assert!(format!("{:#}", e).contains("Invalid redact pattern broken"));
+    }
+}
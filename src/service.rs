@@ -0,0 +1,313 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::cli::ServiceAction;
+use crate::supervisor::SupervisorStatus;
+
+/// Everything needed to describe the agent as a platform service
+pub struct ServiceSpec {
+    /// Name of the agent instance the service runs
+    pub agent_name: String,
+
+    /// Absolute path to the agent binary
+    pub binary: PathBuf,
+
+    /// Absolute path to the configuration file
+    pub config: PathBuf,
+
+    /// Config profile the service runs with
+    pub profile: Option<String>,
+
+    /// Working directory for the service
+    pub working_dir: PathBuf,
+}
+
+impl ServiceSpec {
+    /// Build a service spec for the current binary and the given config file and profile
+    pub fn new(agent_name: &str, config_path: &str, profile: Option<&str>) -> Result<Self> {
+        let binary = std::env::current_exe().context("Failed to locate the agent binary")?;
+        let config = std::fs::canonicalize(config_path)
+            .context(format!("Failed to resolve config file: {}", config_path))?;
+        let working_dir = config
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+
+        Ok(ServiceSpec {
+            agent_name: agent_name.to_string(),
+            binary,
+            config,
+            profile: profile.map(str::to_string),
+            working_dir,
+        })
+    }
+
+    /// Service name used by systemd and the Windows service manager
+    pub fn service_name(&self) -> String {
+        service_name(&self.agent_name)
+    }
+
+    /// Label used by launchd
+    pub fn launchd_label(&self) -> String {
+        format!("io.agent.{}", self.agent_name)
+    }
+
+    /// Render a systemd user unit for this agent
+    pub fn systemd_unit(&self) -> String {
+        format!(
+            "[Unit]\n\
+             Description=AI agent ({name})\n\
+             After=network-online.target\n\
+             Wants=network-online.target\n\
+             \n\
+             [Service]\n\
+             Type=notify\n\
+             WorkingDirectory={workdir}\n\
+             EnvironmentFile=-{workdir}/.env\n\
+             ExecStart={binary} --config {config} --agent-name {name}{profile}\n\
+             Restart=on-failure\n\
+             RestartSec=5\n\
+             \n\
+             [Install]\n\
+             WantedBy=default.target\n",
+            name = self.agent_name,
+            workdir = self.working_dir.display(),
+            binary = self.binary.display(),
+            config = self.config.display(),
+            profile = self
+                .profile
+                .as_ref()
+                .map(|profile| format!(" --profile {}", profile))
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Render a launchd property list for this agent
+    pub fn launchd_plist(&self) -> String {
+        let log_dir = self.working_dir.display();
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{binary}</string>
+        <string>--config</string>
+        <string>{config}</string>
+        <string>--agent-name</string>
+        <string>{name}</string>{profile}
+    </array>
+    <key>WorkingDirectory</key>
+    <string>{workdir}</string>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>StandardOutPath</key>
+    <string>{log_dir}/{name}.out</string>
+    <key>StandardErrorPath</key>
+    <string>{log_dir}/{name}.err</string>
+</dict>
+</plist>
+"#,
+            label = self.launchd_label(),
+            binary = self.binary.display(),
+            config = self.config.display(),
+            name = self.agent_name,
+            profile = self
+                .profile
+                .as_ref()
+                .map(|profile| format!(
+                    "\n        <string>--profile</string>\n        <string>{}</string>",
+                    profile
+                ))
+                .unwrap_or_default(),
+            workdir = self.working_dir.display(),
+        )
+    }
+
+    /// Command line the Windows service manager runs
+    pub fn windows_bin_path(&self) -> String {
+        let mut command = format!(
+            "\"{}\" --config \"{}\" --agent-name {} --windows-service",
+            self.binary.display(),
+            self.config.display(),
+            self.agent_name
+        );
+        if let Some(profile) = &self.profile {
+            command.push_str(&format!(" --profile {}", profile));
+        }
+        command
+    }
+
+    /// Render the service definition for the current platform
+    pub fn definition(&self) -> String {
+        if cfg!(target_os = "macos") {
+            self.launchd_plist()
+        } else if cfg!(windows) {
+            format!(
+                "sc.exe create {} binPath= {} start= auto",
+                self.service_name(),
+                self.windows_bin_path()
+            )
+        } else {
+            self.systemd_unit()
+        }
+    }
+
+    /// Location the service definition is installed to, if the platform uses a file
+    pub fn definition_path(&self) -> Result<Option<PathBuf>> {
+        if cfg!(windows) {
+            return Ok(None);
+        }
+
+        let home = std::env::var("HOME").context("HOME environment variable not set")?;
+        let path = if cfg!(target_os = "macos") {
+            PathBuf::from(home)
+                .join("Library/LaunchAgents")
+                .join(format!("{}.plist", self.launchd_label()))
+        } else {
+            PathBuf::from(home)
+                .join(".config/systemd/user")
+                .join(format!("{}.service", self.service_name()))
+        };
+
+        Ok(Some(path))
+    }
+}
+
+/// Name of the service running the agent `agent_name`
+pub fn service_name(agent_name: &str) -> String {
+    format!("agent-{}", agent_name)
+}
+
+/// Run a `service` subcommand
+pub fn run(
+    action: &ServiceAction,
+    agent_name: &str,
+    config_path: &str,
+    profile: Option<&str>,
+) -> Result<()> {
+    let spec = ServiceSpec::new(agent_name, config_path, profile)?;
+
+    match action {
+        ServiceAction::Install { print } => {
+            if *print {
+                print!("{}", spec.definition());
+                return Ok(());
+            }
+            install(&spec)
+        }
+        ServiceAction::Uninstall => uninstall(&spec),
+        ServiceAction::Status => status(&spec),
+    }
+}
+
+fn install(spec: &ServiceSpec) -> Result<()> {
+    if let Some(path) = spec.definition_path()? {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .context(format!("Failed to create directory: {}", dir.display()))?;
+        }
+        std::fs::write(&path, spec.definition())
+            .context(format!("Failed to write service file: {}", path.display()))?;
+        println!("Wrote {}", path.display());
+
+        if cfg!(target_os = "macos") {
+            run_command("launchctl", &["load", "-w", &path.to_string_lossy()])?;
+        } else {
+            run_command("systemctl", &["--user", "daemon-reload"])?;
+            run_command(
+                "systemctl",
+                &["--user", "enable", "--now", &spec.service_name()],
+            )?;
+        }
+    } else {
+        let bin_path = spec.windows_bin_path();
+        run_command(
+            "sc.exe",
+            &[
+                "create",
+                &spec.service_name(),
+                "binPath=",
+                &bin_path,
+                "start=",
+                "auto",
+            ],
+        )?;
+        run_command("sc.exe", &["start", &spec.service_name()])?;
+    }
+
+    println!("Installed service {}", spec.service_name());
+    Ok(())
+}
+
+fn uninstall(spec: &ServiceSpec) -> Result<()> {
+    if let Some(path) = spec.definition_path()? {
+        if cfg!(target_os = "macos") {
+            run_command("launchctl", &["unload", "-w", &path.to_string_lossy()])?;
+        } else {
+            run_command(
+                "systemctl",
+                &["--user", "disable", "--now", &spec.service_name()],
+            )?;
+        }
+
+        if path.exists() {
+            std::fs::remove_file(&path)
+                .context(format!("Failed to remove service file: {}", path.display()))?;
+            println!("Removed {}", path.display());
+        }
+
+        if !cfg!(target_os = "macos") {
+            run_command("systemctl", &["--user", "daemon-reload"])?;
+        }
+    } else {
+        // Stopping fails if the service is not running, which is fine here
+        let _ = run_command("sc.exe", &["stop", &spec.service_name()]);
+        run_command("sc.exe", &["delete", &spec.service_name()])?;
+    }
+
+    println!("Uninstalled service {}", spec.service_name());
+    Ok(())
+}
+
+fn status(spec: &ServiceSpec) -> Result<()> {
+    if let Some(supervisor) = SupervisorStatus::load(&spec.agent_name) {
+        println!("{}\n", supervisor);
+    }
+
+    if cfg!(target_os = "macos") {
+        run_command("launchctl", &["list", &spec.launchd_label()])
+    } else if cfg!(windows) {
+        run_command("sc.exe", &["query", &spec.service_name()])
+    } else {
+        run_command(
+            "systemctl",
+            &["--user", "status", "--no-pager", &spec.service_name()],
+        )
+    }
+}
+
+// Run a service manager command, inheriting stdout and stderr
+fn run_command(program: &str, args: &[&str]) -> Result<()> {
+    tracing::debug!("Running {} {}", program, args.join(" "));
+
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .context(format!("Failed to run {}", program))?;
+
+    if !status.success() {
+        anyhow::bail!("{} {} exited with {}", program, args.join(" "), status);
+    }
+
+    Ok(())
+}
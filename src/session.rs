@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::providers::Message;
+
+/// A conversation's identity: the input it came in on and who it is with
+///
+/// Messages whose input doesn't say who sent them share the input's one conversation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SessionKey {
+    pub source: String,
+    pub user: Option<String>,
+}
+
+impl SessionKey {
+    pub fn new(source: &str, user: Option<String>) -> Self {
+        Self {
+            source: source.to_string(),
+            user,
+        }
+    }
+
+    /// Parse a session ID as written by `to_string`: `<source>` or `<source>:<user>`
+    ///
+    /// Source names can have a colon of their own, as `mqtt:<broker>` does, and so
+    /// can users, so the ID is matched against the longest of `sources` it starts
+    /// with, and split at its first colon if it starts with none.
+    pub fn parse(id: &str, sources: &[String]) -> Self {
+        let source = sources
+            .iter()
+            .filter(|source| {
+                id == source.as_str()
+                    || id
+                        .strip_prefix(source.as_str())
+                        .is_some_and(|rest| rest.starts_with(':'))
+            })
+            .max_by_key(|source| source.len());
+        if let Some(source) = source {
+            let user = id[source.len()..].strip_prefix(':').map(str::to_string);
+            return Self::new(source, user);
+        }
+        match id.split_once(':') {
+            Some((source, user)) => Self::new(source, Some(user.to_string())),
+            None => Self::new(id, None),
+        }
+    }
+}
+
+impl fmt::Display for SessionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.user {
+            Some(user) => write!(f, "{}:{}", self.source, user),
+            None => write!(f, "{}", self.source),
+        }
+    }
+}
+
+/// Independent conversation histories, one per input source and user, each starting
+/// with the system message
+pub struct SessionManager {
+    system_message: String,
+    sessions: HashMap<SessionKey, Vec<Message>>,
+}
+
+impl SessionManager {
+    pub fn new(system_message: &str) -> Self {
+        Self {
+            system_message: system_message.to_string(),
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// The history of a conversation, started from the system message if it is new
+    pub fn history(&mut self, key: &SessionKey) -> &mut Vec<Message> {
+        let system_message = &self.system_message;
+        self.sessions
+            .entry(key.clone())
+            .or_insert_with(|| vec![system(system_message)])
+    }
+
+    pub fn get(&self, key: &SessionKey) -> Option<&Vec<Message>> {
+        self.sessions.get(key)
+    }
+
+    /// Put back a conversation without its system message, e.g. one restored from
+    /// the history database. Tool calls without their results, and results without
+    /// their call, are dropped, since providers reject a conversation that has them.
+    pub fn restore(&mut self, key: SessionKey, mut messages: Vec<Message>) {
+        let dropped = repair_tool_calls(&mut messages);
+        if dropped > 0 {
+            tracing::warn!(
+                "Dropped {} unanswered tool call(s) and orphaned tool result(s) from {}",
+                dropped,
+                key
+            );
+        }
+        let mut history = vec![system(&self.system_message)];
+        history.extend(messages);
+        self.sessions.insert(key, history);
+    }
+
+    /// The key of the conversation with session ID `id`, parsed against the sources
+    /// of the conversations there are
+    pub fn key(&self, id: &str) -> SessionKey {
+        let sources: Vec<String> = self.sessions.keys().map(|key| key.source.clone()).collect();
+        SessionKey::parse(id, &sources)
+    }
+
+    pub fn remove(&mut self, key: &SessionKey) -> Option<Vec<Message>> {
+        self.sessions.remove(key)
+    }
+
+    pub fn clear(&mut self) {
+        self.sessions.clear();
+    }
+
+    /// Replace the system message, including in every existing conversation
+    pub fn set_system_message(&mut self, content: &str) {
+        self.system_message = content.to_string();
+        for history in self.sessions.values_mut() {
+            history[0].content = content.to_string();
+        }
+    }
+
+    /// Every conversation, ordered by key
+    pub fn iter(&self) -> impl Iterator<Item = (&SessionKey, &Vec<Message>)> {
+        let mut sessions: Vec<_> = self.sessions.iter().collect();
+        sessions.sort_by_key(|(key, _)| *key);
+        sessions.into_iter()
+    }
+
+    /// Number of conversations
+    pub fn count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Total messages across every conversation
+    pub fn message_count(&self) -> usize {
+        self.sessions.values().map(Vec::len).sum()
+    }
+}
+
+fn system(content: &str) -> Message {
+    Message {
+        role: "system".to_string(),
+        content: content.to_string(),
+        tool_calls: None,
+        tool_call_id: None,
+    }
+}
+
+/// Make every tool call in `messages` answered and every tool result answer a call:
+/// an assistant message's calls are matched by ID with the tool results right after
+/// it, calls without a result and results without a call are dropped, and an
+/// assistant message left with neither calls nor text goes too. Returns the number
+/// of calls and results dropped.
+pub fn repair_tool_calls(messages: &mut Vec<Message>) -> usize {
+    let original = std::mem::take(messages);
+    let mut dropped = 0;
+    let mut i = 0;
+    while i < original.len() {
+        let message = &original[i];
+        i += 1;
+        let calls = match &message.tool_calls {
+            Some(calls) if message.role == "assistant" && !calls.is_empty() => calls,
+            _ => {
+                // Results are taken with the call they answer, so this one has none
+                if message.role == "tool" {
+                    dropped += 1;
+                } else {
+                    messages.push(message.clone());
+                }
+                continue;
+            }
+        };
+
+        let results_end = i + original[i..]
+            .iter()
+            .take_while(|message| message.role == "tool")
+            .count();
+        let results = &original[i..results_end];
+        i = results_end;
+
+        let answered: Vec<_> = calls
+            .iter()
+            .filter(|call| {
+                call.id.is_some() && results.iter().any(|result| result.tool_call_id == call.id)
+            })
+            .cloned()
+            .collect();
+        let kept: Vec<_> = results
+            .iter()
+            .filter(|result| answered.iter().any(|call| call.id == result.tool_call_id))
+            .cloned()
+            .collect();
+        dropped += calls.len() - answered.len() + results.len() - kept.len();
+
+        if !answered.is_empty() {
+            messages.push(Message {
+                tool_calls: Some(answered),
+                ..message.clone()
+            });
+            messages.extend(kept);
+        } else if !message.content.trim().is_empty() {
+            messages.push(Message {
+                tool_calls: None,
+                ..message.clone()
+            });
+        }
+    }
+    dropped
+}
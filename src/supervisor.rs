@@ -0,0 +1,301 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::process::{Child, Command};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A child that stays up this long resets the backoff
+const HEALTHY_UPTIME: Duration = Duration::from_secs(60);
+
+/// Supervisor state, persisted so `service status` can report it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SupervisorStatus {
+    pub agent_name: String,
+    pub state: String,
+    pub child_pid: Option<u32>,
+    pub restarts: u32,
+    pub last_exit: Option<String>,
+    pub started_at: u64,
+    pub updated_at: u64,
+}
+
+impl SupervisorStatus {
+    /// Path of the status file for an agent
+    pub fn path(agent_name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("agent-{}.supervisor.json", agent_name))
+    }
+
+    /// Load the status file for an agent, if a supervisor has written one
+    pub fn load(agent_name: &str) -> Option<Self> {
+        let contents = std::fs::read_to_string(Self::path(agent_name)).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    fn save(&mut self) {
+        self.updated_at = unix_now();
+        let path = Self::path(&self.agent_name);
+        match serde_json::to_string_pretty(self) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(&path, json) {
+                    tracing::warn!(
+                        "Failed to write supervisor status {}: {}",
+                        path.display(),
+                        e
+                    );
+                }
+            }
+            Err(e) => tracing::warn!("Failed to serialize supervisor status: {}", e),
+        }
+    }
+}
+
+impl std::fmt::Display for SupervisorStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Supervisor: {}", self.state)?;
+        if let Some(pid) = self.child_pid {
+            writeln!(f, "  Child PID: {}", pid)?;
+        }
+        writeln!(f, "  Restarts: {}", self.restarts)?;
+        if let Some(last_exit) = &self.last_exit {
+            writeln!(f, "  Last exit: {}", last_exit)?;
+        }
+        write!(
+            f,
+            "  Updated: {}s ago",
+            unix_now().saturating_sub(self.updated_at)
+        )
+    }
+}
+
+/// Run the agent as a supervised child process.
+///
+/// The child is started with the current arguments minus `--supervise`, and in
+/// the foreground when the supervisor runs as a daemon. It is
+/// restarted with exponential backoff when it exits unsuccessfully, and the
+/// supervisor gives up when more than `max_restarts` crashes happen within
+/// `restart_window`. A clean exit stops the supervisor.
+pub async fn run(agent_name: &str, max_restarts: u32, restart_window: Duration) -> Result<()> {
+    let binary = std::env::current_exe().context("Failed to locate the agent binary")?;
+    let mut child_args: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| arg != "--supervise" && arg != "--daemon")
+        .collect();
+    child_args.extend(["--set".to_string(), "daemon=false".to_string()]);
+
+    let mut status = SupervisorStatus {
+        agent_name: agent_name.to_string(),
+        state: "starting".to_string(),
+        child_pid: None,
+        restarts: 0,
+        last_exit: None,
+        started_at: unix_now(),
+        updated_at: unix_now(),
+    };
+
+    let mut backoff = INITIAL_BACKOFF;
+    let mut recent_crashes: VecDeque<Instant> = VecDeque::new();
+    let mut hangup = Hangup::new();
+
+    loop {
+        let mut child = Command::new(&binary)
+            .args(&child_args)
+            .spawn()
+            .context(format!("Failed to start {}", binary.display()))?;
+
+        let started = Instant::now();
+        status.state = "running".to_string();
+        status.child_pid = child.id();
+        status.save();
+        tracing::info!("Supervisor started agent (pid {:?})", child.id());
+
+        let exit = loop {
+            tokio::select! {
+                exit = child.wait() => break exit?,
+                _ = hangup.recv() => {
+                    tracing::info!("Supervisor received SIGHUP, passing it on to the agent");
+                    forward_hangup(&child);
+                }
+                signal = shutdown_signal() => {
+                    tracing::info!("Supervisor received {}, stopping agent", signal);
+                    forward_signal(&mut child);
+                    let exit = child.wait().await?;
+                    status.state = "stopped".to_string();
+                    status.child_pid = None;
+                    status.last_exit = Some(exit.to_string());
+                    status.save();
+                    return Ok(());
+                }
+            }
+        };
+
+        status.child_pid = None;
+        status.last_exit = Some(exit.to_string());
+
+        if exit.success() {
+            tracing::info!("Agent exited cleanly, supervisor stopping");
+            status.state = "stopped".to_string();
+            status.save();
+            return Ok(());
+        }
+
+        // Crash-loop circuit breaker
+        let now = Instant::now();
+        recent_crashes.push_back(now);
+        while recent_crashes
+            .front()
+            .is_some_and(|crash| now.duration_since(*crash) > restart_window)
+        {
+            recent_crashes.pop_front();
+        }
+
+        if recent_crashes.len() as u32 > max_restarts {
+            status.state = "crash-loop".to_string();
+            status.save();
+            anyhow::bail!(
+                "Agent crashed {} times within {}s, giving up",
+                recent_crashes.len(),
+                restart_window.as_secs()
+            );
+        }
+
+        if started.elapsed() >= HEALTHY_UPTIME {
+            backoff = INITIAL_BACKOFF;
+        }
+
+        tracing::warn!(
+            "Agent exited with {}, restarting in {}s",
+            exit,
+            backoff.as_secs()
+        );
+        status.state = "backoff".to_string();
+        status.save();
+
+        // The restarted agent reads the config afresh, so SIGHUP needs no passing on
+        let sleep = tokio::time::sleep(backoff);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = &mut sleep => break,
+                _ = hangup.recv() => {
+                    tracing::info!("Supervisor received SIGHUP during backoff, ignoring it");
+                }
+                signal = shutdown_signal() => {
+                    tracing::info!("Supervisor received {} during backoff, stopping", signal);
+                    status.state = "stopped".to_string();
+                    status.save();
+                    return Ok(());
+                }
+            }
+        }
+
+        backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+        status.restarts += 1;
+    }
+}
+
+/// Wait for a signal that should stop the supervisor
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+                return "SIGINT";
+            }
+        };
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+            _ = terminate.recv() => "SIGTERM",
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl-C"
+    }
+}
+
+/// SIGHUP, which asks the agent to reload its config. The supervisor listens for it
+/// so that it passes it on instead of dying of it and leaving the agent orphaned
+struct Hangup {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangup {
+    fn new() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let signal = match signal(SignalKind::hangup()) {
+                Ok(signal) => Some(signal),
+                Err(e) => {
+                    tracing::warn!("Failed to listen for SIGHUP: {}", e);
+                    None
+                }
+            };
+            Self { signal }
+        }
+
+        #[cfg(not(unix))]
+        Self {}
+    }
+
+    /// Wait for the next SIGHUP; never returns where there is none
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            if signal.recv().await.is_some() {
+                return;
+            }
+            self.signal = None;
+        }
+        std::future::pending::<()>().await
+    }
+}
+
+/// Pass SIGHUP on to the child, which reloads its config
+fn forward_hangup(child: &Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGHUP);
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = child;
+}
+
+/// Ask the child to shut down gracefully
+fn forward_signal(child: &mut Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // The agent handles SIGINT as its graceful shutdown signal
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGINT);
+        }
+        return;
+    }
+
+    if let Err(e) = child.start_kill() {
+        tracing::error!("Failed to stop agent: {}", e);
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,181 @@
+//! Running a whole agent in-process for tests: messages go in through memory inputs,
+//! what the agent writes comes back from memory outputs, and a [`MockProvider`]
+//! answers instead of a model
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use agent::providers::mock::MockProvider;
+//! use agent::testing::Harness;
+//!
+//! let mut harness = Harness::builder(agent::config::Config::default())
+//!     .provider(MockProvider::new().reply("Hello!"))
+//!     .start()?;
+//! harness.send("hi");
+//! assert_eq!(harness.reply().await?.content, "Hello!");
+//! harness.stop().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{Context, Result};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+
+use crate::config::Config;
+use crate::io::memory::{MemorySink, MemorySource, Written};
+use crate::io::InboundMessage;
+use crate::providers::mock::MockProvider;
+use crate::Agent;
+
+/// How long waiting for the agent to write something may take before it's an error
+pub const WAIT: Duration = Duration::from_secs(10);
+
+/// Builds a [`Harness`]: the agent's config, provider, and the inputs and outputs
+/// it gets besides the config's own
+pub struct HarnessBuilder {
+    config: Config,
+    provider: MockProvider,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+}
+
+impl HarnessBuilder {
+    /// Answer with this provider (default: one that echoes every message)
+    pub fn provider(mut self, provider: MockProvider) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    /// Add a memory input of type `name` (default: one `stdin`)
+    pub fn input(mut self, name: impl Into<String>) -> Self {
+        self.inputs.push(name.into());
+        self
+    }
+
+    /// Add a memory output of type `name` (default: one `stdout`)
+    pub fn output(mut self, name: impl Into<String>) -> Self {
+        self.outputs.push(name.into());
+        self
+    }
+
+    /// Start the agent on the current tokio runtime
+    pub fn start(mut self) -> Result<Harness> {
+        if self.inputs.is_empty() {
+            self.inputs.push("stdin".to_string());
+        }
+        if self.outputs.is_empty() {
+            self.outputs.push("stdout".to_string());
+        }
+
+        let (shutdown, _) = broadcast::channel(1);
+        let (written_tx, written) = mpsc::unbounded_channel();
+        let mut builder = Agent::builder()
+            .config(self.config)
+            .provider(self.provider.clone())
+            .shutdown(shutdown.clone())
+            .admin(false);
+        let mut inputs = Vec::new();
+        for name in self.inputs {
+            let (source, sender) = MemorySource::new(name.clone());
+            builder = builder.input(source);
+            inputs.push((name, sender));
+        }
+        for name in self.outputs {
+            builder = builder.output(MemorySink::sharing(name, written_tx.clone()));
+        }
+        let agent = builder.build()?;
+
+        Ok(Harness {
+            inputs,
+            written,
+            provider: self.provider,
+            shutdown,
+            agent: tokio::spawn(agent.run()),
+        })
+    }
+}
+
+/// An agent running in the background, with memory inputs to send it messages and
+/// the messages it writes to its memory outputs, in order
+pub struct Harness {
+    inputs: Vec<(String, mpsc::UnboundedSender<InboundMessage>)>,
+    written: mpsc::UnboundedReceiver<Written>,
+    provider: MockProvider,
+    shutdown: broadcast::Sender<()>,
+    agent: JoinHandle<Result<()>>,
+}
+
+impl Harness {
+    /// Start building a harness around an agent with `config`
+    pub fn builder(config: Config) -> HarnessBuilder {
+        HarnessBuilder {
+            config,
+            provider: MockProvider::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Send `content` on the first input
+    pub fn send(&self, content: impl Into<String>) {
+        let (name, _) = &self.inputs[0];
+        let name = name.clone();
+        self.send_to(&name, InboundMessage::new(content))
+            .expect("the first input exists");
+    }
+
+    /// Send `message` on the input of type `input`, e.g. to give it a sender
+    pub fn send_to(&self, input: &str, message: InboundMessage) -> Result<()> {
+        let (_, sender) = self
+            .inputs
+            .iter()
+            .find(|(name, _)| name == input)
+            .context(format!("The harness has no {} input", input))?;
+        sender
+            .send(message)
+            .map_err(|_| anyhow::anyhow!("The agent has stopped"))
+    }
+
+    /// The next message written to any output, waiting up to [`WAIT`] for it
+    pub async fn next(&mut self) -> Result<Written> {
+        tokio::time::timeout(WAIT, self.written.recv())
+            .await
+            .context("Nothing was written in time")?
+            .context("The agent has stopped")
+    }
+
+    /// The next assistant message, skipping everything written before it
+    pub async fn reply(&mut self) -> Result<Written> {
+        self.next_with_role("assistant").await
+    }
+
+    /// The next message with `role`, skipping everything written before it
+    pub async fn next_with_role(&mut self, role: &str) -> Result<Written> {
+        loop {
+            let written = self.next().await?;
+            if written.role == role {
+                return Ok(written);
+            }
+        }
+    }
+
+    /// The provider the agent answers with, to look at the requests it was sent
+    pub fn provider(&self) -> &MockProvider {
+        &self.provider
+    }
+
+    /// Stop the agent and wait for it, returning what it wrote that wasn't read yet
+    pub async fn stop(mut self) -> Result<Vec<Written>> {
+        let _ = self.shutdown.send(());
+        tokio::time::timeout(WAIT, &mut self.agent)
+            .await
+            .context("The agent didn't stop in time")?
+            .context("The agent panicked")??;
+        let mut rest = Vec::new();
+        while let Ok(written) = self.written.try_recv() {
+            rest.push(written);
+        }
+        Ok(rest)
+    }
+}
@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::io::MqttSettings;
+
+pub const DEFAULT_MAX_HOPS: u32 = 3;
+pub const DEFAULT_MAX_TURNS: u32 = 5;
+const DEFAULT_REPLY_TIMEOUT_SECS: u64 = 120;
+
+/// Run the ask_agent tool and describe the outcome for the model. `hops` is how many
+/// agents the message being answered passed through, and `asked` counts the questions
+/// put to other agents while answering it
+pub async fn ask_agent(
+    args: &serde_json::Value,
+    config: &Config,
+    session: &str,
+    hops: u32,
+    asked: &mut u32,
+) -> String {
+    let peer = args["agent"].as_str().unwrap_or("");
+    let question = args["message"].as_str().unwrap_or("");
+    if !config.agent_peers.iter().any(|known| known == peer) {
+        return format!(
+            "Unknown agent {}, expected one of {}",
+            peer,
+            config.agent_peers.join(", ")
+        );
+    }
+    let max_hops = config.agent_max_hops.unwrap_or(DEFAULT_MAX_HOPS);
+    if hops >= max_hops {
+        return format!(
+            "Not asking {}: the question is already {} agents deep, the most allowed",
+            peer, hops
+        );
+    }
+    let max_turns = config.agent_max_turns.unwrap_or(DEFAULT_MAX_TURNS);
+    if *asked >= max_turns {
+        return format!(
+            "Not asking {}: other agents were already asked {} times for this message",
+            peer, asked
+        );
+    }
+    *asked += 1;
+
+    let settings = match MqttSettings::from_config(config) {
+        Ok(settings) => settings,
+        Err(e) => return format!("Failed to ask {}: {:#}", peer, e),
+    };
+    // The peer keeps a conversation with each of this agent's, nested in it
+    let session = format!("{}/{}", config.agent_name, session);
+    let timeout = Duration::from_secs(
+        config
+            .agent_reply_timeout
+            .unwrap_or(DEFAULT_REPLY_TIMEOUT_SECS),
+    );
+    tracing::info!("Asking agent {} (hop {}): {}", peer, hops + 1, question);
+    match crate::io::mqtt::ask_agent(&settings, peer, question, &session, hops + 1, timeout).await {
+        Ok(answer) => {
+            tracing::info!("Agent {} answered: {}", peer, answer);
+            answer
+        }
+        Err(e) => format!("No answer from {}: {:#}", peer, e),
+    }
+}
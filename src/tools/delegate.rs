@@ -0,0 +1,21 @@
+use crate::config::Config;
+use crate::providers::Tool;
+
+/// Completions a delegated task gets unless `delegate_max_steps` is set
+pub const DEFAULT_MAX_STEPS: usize = 5;
+
+/// What the session working on a delegated task is told
+pub const SYSTEM_MESSAGE: &str = "You are working on one task handed to you by another \
+assistant. Complete it with the tools you have, then reply with a short summary of the \
+result and anything the other assistant needs to know. Your reply is all it will see.";
+
+/// The tools a delegated task may use: the ones in `delegate_tools` the agent offers,
+/// never delegate_task itself, or None if there are none
+pub fn tools(config: &Config) -> Option<Vec<Tool>> {
+    let tools: Vec<Tool> = super::build_tools(config)?
+        .into_iter()
+        .filter(|tool| tool.function.name != "delegate_task")
+        .filter(|tool| config.delegate_tools.contains(&tool.function.name))
+        .collect();
+    Some(tools).filter(|tools| !tools.is_empty())
+}
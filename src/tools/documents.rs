@@ -0,0 +1,119 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::providers::embeddings::{cosine_similarity, Embedder};
+
+const DEFAULT_TOP_K: usize = 4;
+
+/// Chunks of local files and web pages with their embeddings, written by
+/// `agent ingest` and searched by the `search_documents` tool
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DocumentIndex {
+    /// Embedding model the chunks were embedded with; queries must use the same
+    pub model: String,
+    pub sources: Vec<IndexedSource>,
+}
+
+/// One ingested file or URL
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IndexedSource {
+    pub source: String,
+    /// Modification time of a file, in milliseconds since the epoch
+    pub modified: Option<u64>,
+    /// SHA-256 of the extracted text, to skip re-embedding unchanged content
+    pub hash: String,
+    pub chunks: Vec<Chunk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Chunk {
+    pub text: String,
+    pub embedding: Vec<f32>,
+}
+
+/// The index file in the config, or `{agent_name}-documents.json`
+pub fn index_path(config: &Config) -> PathBuf {
+    PathBuf::from(
+        config
+            .documents_index
+            .clone()
+            .unwrap_or_else(|| format!("{}-documents.json", config.agent_name)),
+    )
+}
+
+impl DocumentIndex {
+    /// Read an index, or start an empty one if the file doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read_to_string(path)
+            .context(format!("Failed to read document index: {}", path.display()))?;
+        serde_json::from_str(&contents).context(format!(
+            "Failed to parse document index: {}",
+            path.display()
+        ))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .context(format!("Failed to create directory: {}", dir.display()))?;
+        }
+        std::fs::write(path, serde_json::to_string(self)?).context(format!(
+            "Failed to write document index: {}",
+            path.display()
+        ))
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.sources.iter().map(|source| source.chunks.len()).sum()
+    }
+
+    /// The `top_k` chunks most similar to `query`, best first, with their source
+    pub fn search(&self, query: &[f32], top_k: usize) -> Vec<(f32, &str, &Chunk)> {
+        let mut scored: Vec<(f32, &str, &Chunk)> = self
+            .sources
+            .iter()
+            .flat_map(|source| {
+                source.chunks.iter().map(|chunk| {
+                    (
+                        cosine_similarity(query, &chunk.embedding),
+                        source.source.as_str(),
+                        chunk,
+                    )
+                })
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.truncate(top_k);
+        scored
+    }
+}
+
+/// Run the `search_documents` tool: the indexed chunks most relevant to `query`
+pub async fn search_documents(config: &Config, query: &str) -> Result<String> {
+    let path = index_path(config);
+    let index = DocumentIndex::load(&path)?;
+    if index.sources.is_empty() {
+        anyhow::bail!("No documents have been indexed; run `agent ingest` first");
+    }
+    if index.model != config.embedding_model() {
+        anyhow::bail!(
+            "The document index was built with {}, not {}; run `agent ingest` again",
+            index.model,
+            config.embedding_model()
+        );
+    }
+
+    let query = Embedder::new(config)?.embed(query).await?;
+    let top_k = config.documents_top_k.unwrap_or(DEFAULT_TOP_K);
+    let results: Vec<String> = index
+        .search(&query, top_k)
+        .into_iter()
+        .map(|(score, source, chunk)| format!("[{} (score {:.2})]\n{}", source, score, chunk.text))
+        .collect();
+    Ok(results.join("\n\n"))
+}
@@ -0,0 +1,211 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use reqwest::{header, Client};
+use serde::Deserialize;
+use serde_json::json;
+use std::path::PathBuf;
+
+use crate::config::Config;
+use crate::credentials;
+
+/// Image sizes accepted by the `generate_image` tool for models it doesn't know, such
+/// as those of compatible servers
+pub const ALLOWED_SIZES: &[&str] = &["256x256", "512x512", "1024x1024", "1792x1024", "1024x1792"];
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+const DEFAULT_MODEL: &str = "dall-e-3";
+const DEFAULT_SIZE: &str = "1024x1024";
+
+/// Hard upper bound on images per call, regardless of configuration
+pub const MAX_COUNT_LIMIT: usize = 4;
+
+/// Sizes an image model accepts, and the most images it makes per call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelLimits {
+    pub sizes: &'static [&'static str],
+    pub max_count: usize,
+}
+
+impl ModelLimits {
+    /// The limits of OpenAI's `model`, or the most the tool allows for other models
+    pub fn of(model: &str) -> Self {
+        match model {
+            "dall-e-3" => Self {
+                sizes: &["1024x1024", "1792x1024", "1024x1792"],
+                max_count: 1,
+            },
+            "dall-e-2" => Self {
+                sizes: &["256x256", "512x512", "1024x1024"],
+                max_count: MAX_COUNT_LIMIT,
+            },
+            _ => Self {
+                sizes: ALLOWED_SIZES,
+                max_count: MAX_COUNT_LIMIT,
+            },
+        }
+    }
+
+    /// The limits of the model `config` generates images with, with the images per
+    /// call capped by `image_max_count`
+    pub fn from_config(config: &Config) -> Self {
+        let model = config.image_model.as_deref().unwrap_or(DEFAULT_MODEL);
+        let limits = Self::of(model);
+        Self {
+            max_count: config
+                .image_max_count
+                .unwrap_or(1)
+                .clamp(1, limits.max_count),
+            ..limits
+        }
+    }
+}
+
+/// Client for an OpenAI-compatible image generation endpoint, using the openai
+/// provider's API key, base URL and headers whichever provider answers messages
+pub struct ImageGenerator {
+    client: Client,
+    api_url: String,
+    model: String,
+    limits: ModelLimits,
+    output_dir: Option<PathBuf>,
+}
+
+impl ImageGenerator {
+    pub fn new(api_key: &str, config: &Config) -> Result<Self> {
+        let settings = config.provider_settings("openai");
+        let mut headers = header::HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            header::HeaderValue::from_str(&format!("Bearer {}", api_key))?,
+        );
+        headers.insert(
+            "Content-Type",
+            header::HeaderValue::from_static("application/json"),
+        );
+
+        Ok(ImageGenerator {
+            client: settings.client(headers)?,
+            api_url: match &config.image_api_url {
+                Some(url) => url.clone(),
+                None => format!("{}/images/generations", settings.base_url(DEFAULT_BASE_URL)),
+            },
+            model: config
+                .image_model
+                .clone()
+                .unwrap_or_else(|| DEFAULT_MODEL.to_string()),
+            limits: ModelLimits::from_config(config),
+            output_dir: config.image_output_dir.as_ref().map(PathBuf::from),
+        })
+    }
+
+    /// Create a generator with the openai provider's API key, however it is configured
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let api_key = credentials::load_api_key_for(config, "openai")
+            .context("Image generation needs an OpenAI API key")?;
+        Self::new(&api_key, config)
+    }
+
+    /// Directory images are saved to, if configured
+    pub fn output_dir(&self) -> Option<&PathBuf> {
+        self.output_dir.as_ref()
+    }
+
+    /// Generate images and return them base64-encoded
+    pub async fn generate(
+        &self,
+        prompt: &str,
+        size: Option<&str>,
+        count: Option<usize>,
+    ) -> Result<Vec<String>> {
+        let size = size.unwrap_or(DEFAULT_SIZE);
+        if !self.limits.sizes.contains(&size) {
+            anyhow::bail!(
+                "Unsupported image size for {}: {} (allowed: {})",
+                self.model,
+                size,
+                self.limits.sizes.join(", ")
+            );
+        }
+
+        let count = count.unwrap_or(1);
+        if !(1..=self.limits.max_count).contains(&count) {
+            anyhow::bail!(
+                "{} can make 1 to {} image(s) per call, not {}",
+                self.model,
+                self.limits.max_count,
+                count
+            );
+        }
+
+        let request = json!({
+            "model": self.model,
+            "prompt": prompt,
+            "n": count,
+            "size": size,
+            "response_format": "b64_json",
+        });
+
+        let response = self
+            .client
+            .post(&self.api_url)
+            .json(&request)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            anyhow::bail!("Image API error: {}", error_text);
+        }
+
+        let response_json: ImageResponse = response.json().await?;
+
+        let images: Vec<String> = response_json
+            .data
+            .into_iter()
+            .filter_map(|image| image.b64_json)
+            .collect();
+
+        if images.is_empty() {
+            anyhow::bail!("No images returned from image API");
+        }
+
+        Ok(images)
+    }
+
+    /// Decode base64 images and write them to the output directory
+    pub fn save(&self, images: &[String]) -> Result<Vec<PathBuf>> {
+        let dir = self
+            .output_dir
+            .as_ref()
+            .context("No image output directory configured")?;
+
+        std::fs::create_dir_all(dir).context(format!(
+            "Failed to create image directory: {}",
+            dir.display()
+        ))?;
+
+        let mut paths = Vec::new();
+        for image in images {
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(image)
+                .context("Failed to decode image data")?;
+            let path = dir.join(format!("image-{}.png", uuid::Uuid::new_v4()));
+            std::fs::write(&path, bytes)
+                .context(format!("Failed to write image: {}", path.display()))?;
+            paths.push(path);
+        }
+
+        Ok(paths)
+    }
+}
+
+// Image API response structs
+#[derive(Debug, Deserialize)]
+struct ImageResponse {
+    data: Vec<ImageData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImageData {
+    b64_json: Option<String>,
+}
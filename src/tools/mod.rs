@@ -0,0 +1,236 @@
+pub mod agents;
+pub mod delegate;
+pub mod documents;
+pub mod image;
+
+use serde_json::json;
+
+use crate::config::Config;
+use crate::providers::{Function, Tool};
+
+/// Every tool the agent can offer, by name
+pub const NAMES: &[&str] = &[
+    "get_current_weather",
+    "calculate",
+    "generate_image",
+    "search_documents",
+    "ask_agent",
+    "delegate_task",
+];
+
+/// Tool definitions offered to the model, or None when tools are disabled
+pub fn build_tools(config: &Config) -> Option<Vec<Tool>> {
+    if config.enable_tools {
+        // The sizes and counts the configured image model takes
+        let image_limits = image::ModelLimits::from_config(config);
+        let mut tools = vec![
+            Tool {
+                type_: "function".to_string(),
+                function: Function {
+                    name: "get_current_weather".to_string(),
+                    description: "Get the current weather".to_string(),
+                    parameters: json!({
+                        "type": "object",
+                        "properties": {
+                            "location": {
+                                "type": "string",
+                                "description": "The location to get weather for, e.g. 'San Francisco, CA'"
+                            }
+                        },
+                        "required": ["location"]
+                    }),
+                },
+            },
+            Tool {
+                type_: "function".to_string(),
+                function: Function {
+                    name: "calculate".to_string(),
+                    description: "Evaluate a mathematical expression".to_string(),
+                    parameters: json!({
+                        "type": "object",
+                        "properties": {
+                            "expression": {
+                                "type": "string",
+                                "description": "The mathematical expression to evaluate, e.g. '2 + 2'"
+                            }
+                        },
+                        "required": ["expression"]
+                    }),
+                },
+            },
+            Tool {
+                type_: "function".to_string(),
+                function: Function {
+                    name: "generate_image".to_string(),
+                    description: "Generate images from a text prompt".to_string(),
+                    parameters: json!({
+                        "type": "object",
+                        "properties": {
+                            "prompt": {
+                                "type": "string",
+                                "description": "A description of the image to generate"
+                            },
+                            "size": {
+                                "type": "string",
+                                "enum": image_limits.sizes,
+                                "description": "The size of the generated image"
+                            },
+                            "n": {
+                                "type": "integer",
+                                "minimum": 1,
+                                "maximum": image_limits.max_count,
+                                "description": "The number of images to generate"
+                            }
+                        },
+                        "required": ["prompt"]
+                    }),
+                },
+            },
+        ];
+        if !config.documents.is_empty() || config.documents_index.is_some() {
+            tools.push(Tool {
+                type_: "function".to_string(),
+                function: Function {
+                    name: "search_documents".to_string(),
+                    description: "Search the indexed documents for passages relevant to a query"
+                        .to_string(),
+                    parameters: json!({
+                        "type": "object",
+                        "properties": {
+                            "query": {
+                                "type": "string",
+                                "description": "What to look for, in words likely to appear in the passages"
+                            }
+                        },
+                        "required": ["query"]
+                    }),
+                },
+            });
+        }
+        if !config.agent_peers.is_empty() {
+            tools.push(Tool {
+                type_: "function".to_string(),
+                function: Function {
+                    name: "ask_agent".to_string(),
+                    description: "Ask another agent a question and get its answer; it remembers earlier questions from this conversation"
+                        .to_string(),
+                    parameters: json!({
+                        "type": "object",
+                        "properties": {
+                            "agent": {
+                                "type": "string",
+                                "enum": config.agent_peers,
+                                "description": "The agent to ask"
+                            },
+                            "message": {
+                                "type": "string",
+                                "description": "The question or request, complete enough to answer on its own"
+                            }
+                        },
+                        "required": ["agent", "message"]
+                    }),
+                },
+            });
+        }
+        if config.delegate_tasks {
+            tools.push(Tool {
+                type_: "function".to_string(),
+                function: Function {
+                    name: "delegate_task".to_string(),
+                    description: "Hand a self-contained sub-task to a helper that works on it separately and returns a summary of the result"
+                        .to_string(),
+                    parameters: json!({
+                        "type": "object",
+                        "properties": {
+                            "task": {
+                                "type": "string",
+                                "description": "The sub-task, with everything needed to do it; the helper doesn't see this conversation"
+                            }
+                        },
+                        "required": ["task"]
+                    }),
+                },
+            });
+        }
+        Some(tools)
+    } else {
+        None
+    }
+}
+
+/// Evaluate the calculate tool's arithmetic expressions
+pub fn evaluate_expression(expression: &str) -> f64 {
+    // This is a simple implementation that handles basic operations
+    // In a real-world scenario, you might want to use a more robust expression evaluator
+
+    // Remove whitespace
+    let expr = expression.replace(" ", "");
+
+    // Try to parse as a simple number first
+    if let Ok(num) = expr.parse::<f64>() {
+        return num;
+    }
+
+    // Handle addition
+    if let Some(idx) = expr.find('+') {
+        let left = &expr[0..idx];
+        let right = &expr[idx + 1..];
+        return evaluate_expression(left) + evaluate_expression(right);
+    }
+
+    // Handle subtraction
+    if let Some(idx) = expr.rfind('-') {
+        // Make sure it's not a negative number
+        if idx > 0 {
+            let left = &expr[0..idx];
+            let right = &expr[idx + 1..];
+            return evaluate_expression(left) - evaluate_expression(right);
+        }
+    }
+
+    // Handle multiplication
+    if let Some(idx) = expr.find('*') {
+        let left = &expr[0..idx];
+        let right = &expr[idx + 1..];
+        return evaluate_expression(left) * evaluate_expression(right);
+    }
+
+    // Handle division
+    if let Some(idx) = expr.find('/') {
+        let left = &expr[0..idx];
+        let right = &expr[idx + 1..];
+        let right_val = evaluate_expression(right);
+        if right_val != 0.0 {
+            return evaluate_expression(left) / right_val;
+        } else {
+            return f64::NAN; // Division by zero
+        }
+    }
+
+    // Handle square root
+    if expr.starts_with("sqrt(") && expr.ends_with(")") {
+        let inner = &expr[5..expr.len() - 1];
+        let inner_val = evaluate_expression(inner);
+        if inner_val >= 0.0 {
+            return inner_val.sqrt();
+        } else {
+            return f64::NAN; // Negative square root
+        }
+    }
+
+    // Handle power
+    if let Some(idx) = expr.find('^') {
+        let left = &expr[0..idx];
+        let right = &expr[idx + 1..];
+        return evaluate_expression(left).powf(evaluate_expression(right));
+    }
+
+    // Handle parentheses
+    if expr.starts_with("(") && expr.ends_with(")") {
+        let inner = &expr[1..expr.len() - 1];
+        return evaluate_expression(inner);
+    }
+
+    // If we can't parse the expression, return NaN
+    f64::NAN
+}
@@ -0,0 +1,227 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::cli::Args;
+use crate::config::Config;
+use crate::{credentials, io, providers, tools};
+
+/// Tally of the checks printed so far, or kept for one JSON report with `--json`
+#[derive(Default, Serialize)]
+struct Report {
+    config: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    valid: bool,
+    problems: usize,
+    warnings: usize,
+    checks: Vec<Check>,
+    #[serde(skip)]
+    json: bool,
+    // The agent being checked, when the config has several
+    #[serde(skip)]
+    agent: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Check {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    agent: Option<String>,
+    subject: String,
+    status: &'static str,
+    detail: String,
+}
+
+impl Report {
+    fn ok(&mut self, subject: &str, detail: impl AsRef<str>) {
+        if self.record("ok", subject, detail.as_ref()) {
+            println!("  ok    {:<24} {}", subject, detail.as_ref());
+        }
+    }
+
+    fn warn(&mut self, subject: &str, detail: impl AsRef<str>) {
+        self.warnings += 1;
+        if self.record("warn", subject, detail.as_ref()) {
+            println!("  warn  {:<24} {}", subject, detail.as_ref());
+        }
+    }
+
+    fn fail(&mut self, subject: &str, detail: impl AsRef<str>) {
+        self.problems += 1;
+        if self.record("fail", subject, detail.as_ref()) {
+            // Multi-line errors, such as the config's list of problems, stay aligned
+            let detail = detail.as_ref().replace('\n', &format!("\n{:33}", ""));
+            println!("  FAIL  {:<24} {}", subject, detail);
+        }
+    }
+
+    // Keep a check for the JSON report; true when it is to be printed instead
+    fn record(&mut self, status: &'static str, subject: &str, detail: &str) -> bool {
+        if !self.json {
+            return true;
+        }
+        self.checks.push(Check {
+            agent: self.agent.clone(),
+            subject: subject.to_string(),
+            status,
+            detail: detail.to_string(),
+        });
+        false
+    }
+
+    // Print a heading, which the JSON report doesn't have
+    fn heading(&self, text: &str) {
+        if !self.json {
+            println!("{}", text);
+        }
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.valid = self.problems == 0;
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&self)?);
+        } else {
+            println!();
+        }
+        if self.problems > 0 {
+            anyhow::bail!(
+                "{} problem(s) and {} warning(s) found",
+                self.problems,
+                self.warnings
+            );
+        }
+        if !self.json {
+            println!("Config is valid ({} warning(s))", self.warnings);
+        }
+        Ok(())
+    }
+}
+
+/// Check a config file as far as possible without starting the agent: it is loaded and
+/// merged with the command line as for running it, the provider, model and API key
+/// are checked, and every input's and output's settings are resolved, without
+/// connecting to anything
+///
+/// Prints a report, as one JSON object with `--json`, and fails if anything would
+/// stop the agent from starting.
+pub fn run(config_path: &str, args: &Args) -> Result<()> {
+    let profile = args.profile.as_deref();
+    let json = args.json;
+    let mut report = Report {
+        config: config_path.to_string(),
+        profile: profile.map(String::from),
+        json,
+        ..Report::default()
+    };
+    match profile {
+        Some(profile) => report.heading(&format!(
+            "Validating {} with profile {}\n",
+            config_path, profile
+        )),
+        None => report.heading(&format!("Validating {}\n", config_path)),
+    }
+
+    let loaded = args.load_config(config_path).and_then(|config| {
+        config.validate()?;
+        Ok(config)
+    });
+    let config = match loaded {
+        Ok(config) => {
+            report.ok("config", "loaded");
+            if !config.included.is_empty() {
+                report.ok("include", config.included.join(", "));
+            }
+            config
+        }
+        Err(e) => {
+            report.fail("config", format!("{:#}", e));
+            return report.finish();
+        }
+    };
+
+    // Each agent in the agents map is checked on its own, with the name on its lines
+    match config.agent_configs() {
+        Ok(agents) => {
+            for agent in agents {
+                if agent.agent.is_some() {
+                    report.heading(&format!("\nAgent {}\n", agent.agent_name));
+                    report.agent = Some(agent.agent_name.clone());
+                }
+                check_agent(&mut report, &agent);
+            }
+        }
+        Err(e) => report.fail("agents", format!("{:#}", e)),
+    }
+
+    report.finish()
+}
+
+// Check one agent's provider, model, API key and inputs and outputs
+fn check_agent(report: &mut Report, config: &Config) {
+    // The provider is only built to check its name; no request is made
+    match providers::get_provider(
+        &config.provider,
+        "",
+        &config.provider_settings(&config.provider),
+    ) {
+        Ok(provider) => {
+            report.ok("provider", &config.provider);
+            let models = provider.available_models();
+            if models.contains(&config.model) {
+                report.ok("model", &config.model);
+            } else {
+                report.warn(
+                    "model",
+                    format!(
+                        "{} is not one of {}'s known models ({})",
+                        config.model,
+                        config.provider,
+                        models.join(", ")
+                    ),
+                );
+            }
+        }
+        Err(e) => report.fail("provider", format!("{:#}", e)),
+    }
+
+    match credentials::load_api_key_with_source(config, &config.provider) {
+        Ok((_, source)) => report.ok("api key", format!("loaded from {}", source)),
+        Err(e) => report.fail("api key", format!("{:#}", e)),
+    }
+
+    if config.enable_tools {
+        let generator =
+            credentials::load_api_key_with_source(config, "openai").and_then(|(key, source)| {
+                tools::image::ImageGenerator::new(&key, config).map(|_| source)
+            });
+        match generator {
+            Ok(source) => report.ok("image tool", format!("API key loaded from {}", source)),
+            Err(e) => report.warn(
+                "image tool",
+                format!("{:#}; generate_image calls will fail", e),
+            ),
+        }
+    }
+
+    if config.memory {
+        match credentials::load_api_key_with_source(config, "openai") {
+            Ok((_, source)) => report.ok(
+                "memory",
+                format!("embedding API key loaded from {}", source),
+            ),
+            Err(e) => report.fail("memory", format!("{:#}", e)),
+        }
+    }
+
+    for (direction, entries) in [
+        ("input", config.input_entries()),
+        ("output", config.output_entries()),
+    ] {
+        for entry in entries {
+            let subject = format!("{} {}", direction, entry.kind);
+            match io::registry::check(config, &entry, direction) {
+                Ok(()) => report.ok(&subject, "settings resolved"),
+                Err(e) => report.fail(&subject, format!("{:#}", e)),
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Running under the Windows service manager, which starts the agent with
+//! `--windows-service` and stops it with its stop and shutdown controls
+
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use windows_service::service::{
+    ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_dispatcher;
+
+/// What the service runs: the agent, until `()` is sent on the shutdown channel
+pub type ServiceMain = Box<dyn FnOnce(broadcast::Sender<()>) -> Result<()> + Send>;
+
+// The service manager calls back into a plain function on a thread of its own, so
+// the name and what to run are handed over here
+static SERVICE: Mutex<Option<(String, ServiceMain)>> = Mutex::new(None);
+
+windows_service::define_windows_service!(ffi_service_main, service_main);
+
+/// Run `main` as the service `name`, blocking until the service has stopped. Fails
+/// when the process wasn't started by the service manager
+pub fn run(name: &str, main: ServiceMain) -> Result<()> {
+    *SERVICE.lock().unwrap() = Some((name.to_string(), main));
+    service_dispatcher::start(name, ffi_service_main).context(
+        "Failed to connect to the Windows service manager; --windows-service is for services \
+         installed with `agent service install`",
+    )?;
+    Ok(())
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    let Some((name, main)) = SERVICE.lock().unwrap().take() else {
+        return;
+    };
+    if let Err(e) = serve(&name, main) {
+        tracing::error!("Windows service {} failed: {:#}", name, e);
+    }
+}
+
+fn serve(name: &str, main: ServiceMain) -> Result<()> {
+    let (shutdown_tx, _) = broadcast::channel::<()>(1);
+    let stop = shutdown_tx.clone();
+    let handler = move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            tracing::info!("Stop requested by the service manager");
+            let _ = stop.send(());
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let status = service_control_handler::register(name, handler)
+        .context("Failed to register with the service manager")?;
+
+    let report = |state, accepted, exit_code| {
+        status.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted: accepted,
+            exit_code,
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        })
+    };
+    report(
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        ServiceExitCode::NO_ERROR,
+    )?;
+
+    let result = main(shutdown_tx);
+    if let Err(e) = &result {
+        tracing::error!("Agent stopped: {:#}", e);
+    }
+    // A nonzero exit code has the service manager apply its recovery actions
+    let exit_code = match result {
+        Ok(()) => ServiceExitCode::NO_ERROR,
+        Err(_) => ServiceExitCode::ServiceSpecific(1),
+    };
+    report(
+        ServiceState::Stopped,
+        ServiceControlAccept::empty(),
+        exit_code,
+    )?;
+    Ok(())
+}
@@ -0,0 +1,257 @@
+//! How the config file, its profiles and the command line are merged into the config
+//! an agent runs with
+
+use agent::cli::Args;
+use agent::config::Config;
+use agent::testing::Harness;
+use clap::Parser;
+
+// A config file of its own for each test, as tests run in parallel
+fn config_file(name: &str, contents: &str) -> String {
+    let path =
+        std::env::temp_dir().join(format!("agent-test-{}-{}.yaml", std::process::id(), name));
+    std::fs::write(&path, contents).expect("test config is written");
+    path.display().to_string()
+}
+
+fn args(list: &[&str]) -> Args {
+    Args::parse_from(std::iter::once("agent").chain(list.iter().copied()))
+}
+
+// The config with its own inputs and outputs dropped, for a harness to run
+fn offline(mut config: Config) -> Config {
+    config.inputs_vec.clear();
+    config.inputs.clear();
+    config.outputs_vec.clear();
+    config.outputs.clear();
+    config
+}
+
+const PROFILES: &str = "\
+agent_name: kitchen
+provider: anthropic
+model: claude-3-5-sonnet-latest
+system_message: Mind the oven.
+profiles:
+  local:
+    provider: openai
+    model: llama3
+";
+
+#[test]
+fn the_file_and_profile_show_through_unset_flags() -> anyhow::Result<()> {
+    let path = config_file("profiles", PROFILES);
+
+    let config = args(&[]).load_config(&path)?;
+    assert_eq!(config.agent_name, "kitchen");
+    assert_eq!(config.provider, "anthropic");
+    assert_eq!(config.model, "claude-3-5-sonnet-latest");
+    assert_eq!(config.system_message, "Mind the oven.");
+
+    let config = args(&["--profile", "local"]).load_config(&path)?;
+    assert_eq!(config.provider, "openai");
+    assert_eq!(config.model, "llama3");
+    Ok(())
+}
+
+#[test]
+fn flags_replace_the_file_and_profile() -> anyhow::Result<()> {
+    let path = config_file("flags", PROFILES);
+
+    let config = args(&[
+        "--profile",
+        "local",
+        "-n",
+        "hall",
+        "-m",
+        "gpt-4o-mini",
+        "-s",
+        "Be brief.",
+    ])
+    .load_config(&path)?;
+    assert_eq!(config.agent_name, "hall");
+    assert_eq!(config.provider, "openai");
+    assert_eq!(config.model, "gpt-4o-mini");
+    assert_eq!(config.system_message, "Be brief.");
+    Ok(())
+}
+
+#[test]
+fn built_in_defaults_fill_what_nothing_sets() -> anyhow::Result<()> {
+    let path = config_file("defaults", "enable_tools: true\n");
+
+    let config = args(&[]).load_config(&path)?;
+    assert_eq!(config.agent_name, "agent");
+    assert_eq!(config.provider, "openai");
+    assert_eq!(config.model, "gpt-4o");
+    assert!(!config.system_message.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn the_agent_runs_with_the_profile() -> anyhow::Result<()> {
+    let path = config_file("running", PROFILES);
+    let config = args(&["--profile", "local"]).load_config(&path)?;
+    let mut harness = Harness::builder(offline(config)).start()?;
+
+    harness.send("/model");
+    let reply = harness.next_with_role("system").await?;
+    assert_eq!(reply.content, "Model: openai:llama3");
+
+    harness.stop().await?;
+    Ok(())
+}
+
+const ALIASES: &str = "\
+model: fast
+model_aliases:
+  fast: gpt-4o-mini
+  smart: anthropic:claude-3-5-sonnet-latest
+";
+
+#[test]
+fn aliases_resolve_after_the_merge() -> anyhow::Result<()> {
+    let path = config_file("aliases", ALIASES);
+
+    // The config file's alias, with no --model to replace it
+    let config = args(&[]).load_config(&path)?;
+    assert_eq!(config.provider, "openai");
+    assert_eq!(config.model, "gpt-4o-mini");
+
+    // An alias given on the command line, naming its own provider
+    let config = args(&["-m", "smart"]).load_config(&path)?;
+    assert_eq!(config.provider, "anthropic");
+    assert_eq!(config.model, "claude-3-5-sonnet-latest");
+
+    // --set lands before aliases are resolved too
+    let config = args(&["--set", "model=smart"]).load_config(&path)?;
+    assert_eq!(config.model, "claude-3-5-sonnet-latest");
+    Ok(())
+}
+
+#[test]
+fn validate_checks_the_merged_config() -> anyhow::Result<()> {
+    let path = config_file(
+        "validate",
+        "\
+api_key_command: echo test-key
+inputs_vec: [stdin]
+outputs_vec: [stdout]
+profiles:
+  broken:
+    provider: nonexistent
+",
+    );
+
+    agent::validate::run(&path, &args(&[]))?;
+    // A bad provider from the profile or the command line fails, as it would at startup
+    assert!(agent::validate::run(&path, &args(&["--profile", "broken"])).is_err());
+    assert!(agent::validate::run(&path, &args(&["-p", "nonexistent"])).is_err());
+    assert!(agent::validate::run(&path, &args(&["--profile", "broken", "-p", "openai"])).is_ok());
+    Ok(())
+}
+
+#[test]
+fn ask_takes_the_same_flags_as_run() -> anyhow::Result<()> {
+    let path = config_file("ask", PROFILES);
+
+    let ask = args(&[
+        "ask",
+        "-p",
+        "openai",
+        "-m",
+        "gpt-4o-mini",
+        "-s",
+        "Be brief.",
+        "What's for dinner?",
+    ]);
+    let config = ask.load_agent_config(&path, None)?;
+    assert_eq!(config.agent_name, "kitchen");
+    assert_eq!(config.provider, "openai");
+    assert_eq!(config.model, "gpt-4o-mini");
+    assert_eq!(config.system_message, "Be brief.");
+
+    // --pipe has no subcommand, so the flags come first
+    let pipe = args(&["-m", "gpt-4o-mini", "--pipe", "Summarize:"]);
+    assert_eq!(pipe.load_agent_config(&path, None)?.model, "gpt-4o-mini");
+    Ok(())
+}
+
+#[test]
+fn ask_picks_an_agent_of_the_merged_config() -> anyhow::Result<()> {
+    let path = config_file(
+        "ask-agents",
+        "\
+model: gpt-4o
+inputs_vec: [webhook]
+agents:
+  kitchen:
+    system_message: Mind the oven.
+  hall:
+    model: gpt-4o-mini
+",
+    );
+
+    let config = args(&["ask", "-m", "gpt-3.5-turbo", "--agent", "kitchen", "hi"])
+        .load_agent_config(&path, Some("kitchen"))?;
+    assert_eq!(config.agent_name, "kitchen");
+    assert_eq!(config.model, "gpt-3.5-turbo");
+    assert_eq!(config.system_message, "Mind the oven.");
+
+    // Without --agent, the agent named on the command line
+    let config = args(&["-n", "hall", "ask", "hi"]).load_agent_config(&path, None)?;
+    assert_eq!(config.agent_name, "hall");
+    assert_eq!(config.model, "gpt-4o-mini");
+    Ok(())
+}
+
+#[test]
+fn reloads_keep_the_command_line() -> anyhow::Result<()> {
+    let path = config_file("reload", "model: gpt-4o\noutputs_vec: [stdout]\n");
+    let running = args(&[
+        "--tui",
+        "-m",
+        "gpt-4o-mini",
+        "--set",
+        "max_history_messages=7",
+    ])
+    .load_config(&path)?;
+    assert_eq!(running.outputs_vec, ["tui"]);
+
+    std::fs::write(
+        &path,
+        "model: gpt-4o\noutputs_vec: [stdout]\nenable_tools: true\n",
+    )?;
+    let reloaded = running.reload(&path)?;
+    // The file's change is picked up, and the command line still wins over the file
+    assert!(reloaded.enable_tools);
+    assert_eq!(reloaded.model, "gpt-4o-mini");
+    assert_eq!(reloaded.max_history_messages, Some(7));
+    assert_eq!(reloaded.outputs_vec, ["tui"]);
+    assert_eq!(reloaded.inputs_vec, running.inputs_vec);
+
+    // And so on for every reload after
+    assert_eq!(reloaded.reload(&path)?.model, "gpt-4o-mini");
+    Ok(())
+}
+
+#[test]
+fn reloads_keep_the_agents_entry() -> anyhow::Result<()> {
+    let contents = "\
+inputs_vec: [webhook]
+agents:
+  kitchen:
+    model: gpt-4o-mini
+  hall: {}
+";
+    let path = config_file("reload-agents", contents);
+    let running = args(&["-p", "openai"])
+        .load_config(&path)?
+        .for_agent("kitchen")?;
+
+    std::fs::write(&path, contents.replace("gpt-4o-mini", "gpt-3.5-turbo"))?;
+    let reloaded = running.reload(&path)?;
+    assert_eq!(reloaded.agent_name, "kitchen");
+    assert_eq!(reloaded.model, "gpt-3.5-turbo");
+    Ok(())
+}
@@ -0,0 +1,209 @@
+//! End-to-end tests of the message pipeline: memory inputs, the agent's main loop with
+//! its routing, history and tools, and memory outputs, with a scripted provider
+
+use agent::config::Config;
+use agent::io::InboundMessage;
+use agent::providers::mock::MockProvider;
+use agent::providers::tokens;
+use agent::testing::Harness;
+use serde_json::json;
+
+fn config(yaml: &str) -> Config {
+    serde_yaml::from_str(yaml).expect("test config parses")
+}
+
+#[tokio::test]
+async fn answers_a_message() -> anyhow::Result<()> {
+    let mut harness = Harness::builder(config("system_message: Be brief."))
+        .provider(MockProvider::new().reply("Hello!"))
+        .start()?;
+
+    harness.send("hi");
+    let reply = harness.reply().await?;
+    assert_eq!(reply.output, "stdout");
+    assert_eq!(reply.content, "Hello!");
+
+    let requests = harness.provider().requests();
+    assert_eq!(requests.len(), 1);
+    let roles: Vec<_> = requests[0]
+        .messages
+        .iter()
+        .map(|m| m.role.as_str())
+        .collect();
+    assert_eq!(roles, ["system", "user"]);
+    assert_eq!(requests[0].messages[0].content, "Be brief.");
+    assert_eq!(requests[0].messages[1].content, "hi");
+
+    harness.stop().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn routes_pick_the_outputs_and_roles() -> anyhow::Result<()> {
+    let config = config("routes:\n  - from: webhook\n    to: [mqtt]\n    roles: [assistant]\n");
+    let mut harness = Harness::builder(config)
+        .input("stdin")
+        .input("webhook")
+        .output("stdout")
+        .output("mqtt")
+        .start()?;
+
+    // Only the route's output gets the webhook's messages, and only the answer
+    harness.send_to("webhook", InboundMessage::new("from webhook"))?;
+    let first = harness.next().await?;
+    assert_eq!(first.output, "mqtt");
+    assert_eq!(first.role, "assistant");
+    assert_eq!(first.content, "echo: from webhook");
+
+    // Without a route of its own, stdin's messages go to every output
+    harness.send("from stdin");
+    let mut answered = Vec::new();
+    while answered.len() < 2 {
+        let written = harness.reply().await?;
+        assert_eq!(written.content, "echo: from stdin");
+        answered.push(written.output);
+    }
+    answered.sort();
+    assert_eq!(answered, ["mqtt", "stdout"]);
+
+    let rest = harness.stop().await?;
+    assert!(rest
+        .iter()
+        .all(|written| !written.content.contains("webhook")));
+    Ok(())
+}
+
+#[tokio::test]
+async fn history_keeps_max_history_messages() -> anyhow::Result<()> {
+    let mut harness = Harness::builder(config("max_history_messages: 4")).start()?;
+
+    for i in 1..=4 {
+        harness.send(format!("message {}", i));
+        harness.reply().await?;
+    }
+
+    let requests = harness.provider().requests();
+    let last = &requests.last().unwrap().messages;
+    let contents: Vec<_> = last.iter().skip(1).map(|m| m.content.as_str()).collect();
+    assert_eq!(last.len(), 4);
+    assert_eq!(last[0].role, "system");
+    assert_eq!(contents, ["message 3", "echo: message 3", "message 4"]);
+
+    harness.stop().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn history_fits_the_context_window() -> anyhow::Result<()> {
+    let config = config("system_message: Be brief.\ncontext_tokens: 300\nresponse_tokens: 50\n");
+    let mut harness = Harness::builder(config).start()?;
+
+    // Each exchange is about 110 tokens, so only the latest ones fit
+    for i in 1..=5 {
+        harness.send(format!("{} {}", i, "x".repeat(200)));
+        harness.reply().await?;
+    }
+
+    for request in harness.provider().requests() {
+        let used: usize = request.messages.iter().map(tokens::message_tokens).sum();
+        assert!(used <= 250, "request of {} tokens", used);
+        assert_eq!(request.messages[0].content, "Be brief.");
+        assert_eq!(request.messages[1].role, "user");
+    }
+    let requests = harness.provider().requests();
+    let last = &requests.last().unwrap().messages;
+    assert!(last.last().unwrap().content.starts_with("5 "));
+    assert!(last.len() < 10);
+
+    harness.stop().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn tool_calls_go_back_to_the_model() -> anyhow::Result<()> {
+    let provider = MockProvider::new()
+        .call_tool("calculate", json!({"expression": "2+2"}))
+        .reply("It is 4");
+    let mut harness = Harness::builder(config("enable_tools: true"))
+        .provider(provider)
+        .start()?;
+
+    harness.send("What is 2+2?");
+    let tool = harness.next_with_role("tool").await?;
+    assert!(tool.content.starts_with("calculate("), "{}", tool.content);
+    assert_eq!(harness.reply().await?.content, "It is 4");
+
+    let requests = harness.provider().requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[0].tools.iter().any(|name| name == "calculate"));
+    let result = requests[1]
+        .messages
+        .iter()
+        .find(|m| m.role == "tool")
+        .expect("the tool result is sent back");
+    assert!(result.content.contains('4'), "{}", result.content);
+
+    harness.stop().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn commands_are_answered_without_the_model() -> anyhow::Result<()> {
+    let mut harness = Harness::builder(config("model: gpt-4o")).start()?;
+
+    harness.send("/model");
+    let reply = harness.next_with_role("system").await?;
+    assert_eq!(reply.content, "Model: openai:gpt-4o");
+    assert!(harness.provider().requests().is_empty());
+
+    harness.stop().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn mqtt_routes_and_roles_cover_named_brokers() -> anyhow::Result<()> {
+    let config = config(
+        "\
+mqtt_brokers:
+  cloud:
+    broker: localhost
+routes:
+  - from: mqtt
+    to: [mqtt]
+output_roles:
+  mqtt: [user, assistant]
+  mqtt:cloud: [assistant]
+",
+    );
+    config.validate()?;
+    let mut harness = Harness::builder(config)
+        .input("mqtt:cloud")
+        .output("stdout")
+        .output("mqtt")
+        .output("mqtt:cloud")
+        .start()?;
+
+    // The bare `mqtt` route takes the named broker's messages to every broker, each
+    // with the roles of its own entry, or else of its type's
+    harness.send_to("mqtt:cloud", InboundMessage::new("hi"))?;
+    let mut written = Vec::new();
+    while written.len() < 3 {
+        let next = harness.next().await?;
+        written.push(format!("{} {}", next.output, next.role));
+    }
+    written.sort();
+    assert_eq!(
+        written,
+        ["mqtt assistant", "mqtt user", "mqtt:cloud assistant"]
+    );
+
+    assert!(harness.stop().await?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn routes_name_known_brokers() {
+    let config = config("routes:\n  - from: mqtt:cloud\n    to: [stdout]\n");
+    let error = config.validate().unwrap_err().to_string();
+    assert!(error.contains("no MQTT broker named `cloud`"), "{}", error);
+}
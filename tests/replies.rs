@@ -0,0 +1,75 @@
+//! Answers go back to the request they answer, however many arrive while the agent
+//! is busy
+
+use agent::config::Config;
+use agent::io::mqtt::ResponseTopic;
+use agent::io::InboundMessage;
+use agent::testing::Harness;
+
+fn request(content: &str, id: &str, response_topic: Option<&str>) -> InboundMessage {
+    let mut message = InboundMessage::new(content);
+    message.correlation_id = id.to_string();
+    message.response_topic = response_topic.map(|topic| ResponseTopic {
+        broker: None,
+        topic: topic.to_string(),
+        correlation_data: Some(id.as_bytes().to_vec()),
+    });
+    message
+}
+
+#[tokio::test]
+async fn each_answer_belongs_to_its_own_request() -> anyhow::Result<()> {
+    let mut harness = Harness::builder(Config::default())
+        .input("mqtt")
+        .output("mqtt")
+        .start()?;
+
+    // All three wait in the queue while the first is answered; the plain one in the
+    // middle must not take the others' response topics with it
+    harness.send_to("mqtt", request("first", "a", Some("replies/alice")))?;
+    harness.send_to("mqtt", request("plain", "b", None))?;
+    harness.send_to("mqtt", request("second", "c", Some("replies/bob")))?;
+
+    for (content, id) in [("first", "a"), ("plain", "b"), ("second", "c")] {
+        let reply = harness.reply().await?;
+        assert_eq!(reply.content, format!("echo: {}", content));
+        assert_eq!(reply.in_reply_to.as_deref(), Some(id));
+    }
+
+    harness.stop().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn each_broker_answers_and_keeps_its_own_conversations() -> anyhow::Result<()> {
+    let mut harness = Harness::builder(Config::default())
+        .input("mqtt")
+        .input("mqtt:cloud")
+        .output("mqtt")
+        .output("mqtt:cloud")
+        .start()?;
+
+    let mut message = InboundMessage::new("from home");
+    message.sender = Some("alice".to_string());
+    harness.send_to("mqtt", message)?;
+    assert_eq!(harness.reply().await?.output, "mqtt");
+
+    let mut message = InboundMessage::new("from the cloud");
+    message.sender = Some("alice".to_string());
+    harness.send_to("mqtt:cloud", message)?;
+    assert_eq!(harness.reply().await?.output, "mqtt:cloud");
+
+    // The same sender on another broker starts a conversation of its own
+    let requests = harness.provider().requests();
+    let contents: Vec<_> = requests[1]
+        .messages
+        .iter()
+        .skip(1)
+        .map(|m| m.content.as_str())
+        .collect();
+    assert_eq!(contents, ["from the cloud"]);
+
+    let rest = harness.stop().await?;
+    assert!(rest.iter().all(|written| written.role != "assistant"));
+    Ok(())
+}
@@ -0,0 +1,30 @@
+//! Session IDs, as the admin API, history database and exports name conversations
+
+use agent::session::SessionKey;
+
+fn sources(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+#[test]
+fn session_ids_round_trip() {
+    let sources = sources(&["stdin", "mqtt", "mqtt:cloud", "matrix"]);
+    for key in [
+        SessionKey::new("stdin", None),
+        SessionKey::new("mqtt", Some("alice".to_string())),
+        SessionKey::new("mqtt:cloud", None),
+        SessionKey::new("mqtt:cloud", Some("alice".to_string())),
+        SessionKey::new("matrix", Some("@alice:example.org".to_string())),
+    ] {
+        assert_eq!(SessionKey::parse(&key.to_string(), &sources), key);
+    }
+}
+
+#[test]
+fn unknown_sources_end_at_the_first_colon() {
+    let key = SessionKey::parse("tcp:127.0.0.1:5000", &[]);
+    assert_eq!(
+        key,
+        SessionKey::new("tcp", Some("127.0.0.1:5000".to_string()))
+    );
+}
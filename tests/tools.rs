@@ -0,0 +1,134 @@
+//! Tools that call APIs of their own, against a local stand-in for the API
+
+use agent::config::Config;
+use agent::tools::image::ImageGenerator;
+use axum::http::HeaderMap;
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+
+// An OpenAI-compatible server answering every request with `answer`, and the path,
+// headers and body of each request it got
+type Requests = Arc<Mutex<Vec<(String, HeaderMap, serde_json::Value)>>>;
+
+async fn openai(answer: serde_json::Value) -> anyhow::Result<(String, Requests)> {
+    let requests: Requests = Arc::default();
+    let seen = requests.clone();
+    let app = axum::Router::new().fallback(
+        move |uri: axum::http::Uri, headers: HeaderMap, body: axum::body::Bytes| {
+            let seen = seen.clone();
+            let answer = answer.clone();
+            async move {
+                let body = serde_json::from_slice(&body).unwrap_or_default();
+                seen.lock()
+                    .unwrap()
+                    .push((uri.path().to_string(), headers, body));
+                axum::Json(answer)
+            }
+        },
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/v1", listener.local_addr()?);
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    Ok((url, requests))
+}
+
+fn config(yaml: &str) -> Config {
+    serde_yaml::from_str(yaml).expect("test config parses")
+}
+
+#[tokio::test]
+async fn images_use_the_openai_provider_settings() -> anyhow::Result<()> {
+    let (url, requests) = openai(json!({ "data": [{ "b64_json": "aGk=" }] })).await?;
+    let config = config(&format!(
+        "\
+provider: anthropic
+providers:
+  openai:
+    api_key_command: echo image-key
+    base_url: {}
+    headers:
+      X-Tenant: kitchen
+",
+        url
+    ));
+
+    let generator = ImageGenerator::from_config(&config)?;
+    assert_eq!(generator.generate("a cat", None, None).await?, ["aGk="]);
+
+    let requests = requests.lock().unwrap();
+    let (path, headers, _) = &requests[0];
+    assert_eq!(path, "/v1/images/generations");
+    assert_eq!(headers["authorization"], "Bearer image-key");
+    assert_eq!(headers["x-tenant"], "kitchen");
+    Ok(())
+}
+
+#[tokio::test]
+async fn image_sizes_and_counts_follow_the_model() -> anyhow::Result<()> {
+    let (url, requests) = openai(json!({ "data": [{ "b64_json": "aGk=" }] })).await?;
+    let config = |model: &str| {
+        config(&format!(
+            "image_api_url: {}/images/generations\nimage_model: {}\nimage_max_count: 4\n",
+            url, model
+        ))
+    };
+
+    // DALL·E 3 makes one image at a time, in its own sizes
+    let generator = ImageGenerator::new("key", &config("dall-e-3"))?;
+    assert!(generator
+        .generate("a cat", Some("256x256"), None)
+        .await
+        .is_err());
+    assert!(generator.generate("a cat", None, Some(2)).await.is_err());
+    generator
+        .generate("a cat", Some("1792x1024"), Some(1))
+        .await?;
+
+    let generator = ImageGenerator::new("key", &config("dall-e-2"))?;
+    assert!(generator
+        .generate("a cat", Some("1792x1024"), None)
+        .await
+        .is_err());
+    generator
+        .generate("a cat", Some("256x256"), Some(4))
+        .await?;
+
+    // Only the valid requests were sent
+    let sent: Vec<_> = requests
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(_, _, body)| {
+            (
+                body["model"].clone(),
+                body["size"].clone(),
+                body["n"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        sent,
+        [
+            (json!("dall-e-3"), json!("1792x1024"), json!(1)),
+            (json!("dall-e-2"), json!("256x256"), json!(4)),
+        ]
+    );
+
+    // The tool's definition offers the model what it takes
+    let tools = agent::tools::build_tools(&Config {
+        enable_tools: true,
+        ..config("dall-e-3")
+    })
+    .unwrap();
+    let image = tools
+        .iter()
+        .find(|tool| tool.function.name == "generate_image")
+        .unwrap();
+    let properties = &image.function.parameters["properties"];
+    assert_eq!(
+        properties["size"]["enum"],
+        json!(["1024x1024", "1792x1024", "1024x1792"])
+    );
+    assert_eq!(properties["n"]["maximum"], 1);
+    Ok(())
+}
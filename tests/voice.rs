@@ -0,0 +1,28 @@
+//! Voice input settings; needs the voice feature and its audio libraries
+#![cfg(feature = "voice")]
+
+use agent::config::Config;
+use agent::io::voice::Transcriber;
+use agent::io::VoiceSettings;
+
+#[test]
+fn transcription_uses_the_openai_provider_settings() -> anyhow::Result<()> {
+    let config: Config = serde_yaml::from_str(
+        "\
+provider: anthropic
+providers:
+  openai:
+    api_key_command: echo voice-key
+    base_url: http://localhost:8000/v1/
+",
+    )?;
+
+    let settings = VoiceSettings::from_config(&config)?;
+    match settings.transcriber {
+        Transcriber::Api { url, .. } => {
+            assert_eq!(url, "http://localhost:8000/v1/audio/transcriptions")
+        }
+        other => panic!("unexpected transcriber {:?}", other),
+    }
+    Ok(())
+}
@@ -0,0 +1,244 @@
+//! The webhook server, with its credentials and the answers it streams to browsers,
+//! and the webhook output
+
+use agent::config::Config;
+use agent::io::http_server::HttpSettings;
+use agent::io::isolation::Isolated;
+use agent::io::webhook::{WebhookAuth, WebhookDestination, WebhookLimits, WebhookSource};
+use agent::io::{retry, OutputDestination, SseDestination};
+use agent::providers::mock::MockProvider;
+use agent::testing::WAIT;
+use agent::Agent;
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn empty_tokens_are_unset() {
+    // Blank variables, as a service file might leave them
+    std::env::set_var("WEBHOOK_TOKEN", "");
+    std::env::set_var("ADMIN_TOKEN", "");
+    let config: Config = serde_yaml::from_str("webhook_token: ''\nadmin_token: ''\n").unwrap();
+
+    let auth = WebhookAuth::from_config(&config);
+    assert_eq!(auth.token, None);
+    assert_eq!(auth.admin_token, None);
+    assert!(!auth.is_enabled());
+
+    // A value set in the config still counts
+    let config: Config = serde_yaml::from_str("admin_token: secret\n").unwrap();
+    let auth = WebhookAuth::from_config(&config);
+    assert_eq!(auth.admin_token.as_deref(), Some("secret"));
+}
+
+#[tokio::test]
+async fn the_event_stream_takes_the_webhook_token() -> anyhow::Result<()> {
+    let auth = WebhookAuth {
+        token: Some("let-me-in".to_string()),
+        ..Default::default()
+    };
+    let source = WebhookSource::new(
+        "127.0.0.1",
+        None,
+        auth,
+        WebhookLimits::from_config(&Config::default()),
+        HttpSettings::default(),
+        None,
+    )
+    .await?;
+    let url = format!("http://{}/events", source.addr());
+    let client = reqwest::Client::new();
+
+    let status = |request: reqwest::RequestBuilder| async move {
+        anyhow::Ok(request.send().await?.status().as_u16())
+    };
+    assert_eq!(status(client.get(&url)).await?, 401);
+    assert_eq!(
+        status(client.get(format!("{}?token=wrong", url))).await?,
+        401
+    );
+    // In a header, or in the query as a browser's EventSource sends it
+    assert_eq!(
+        status(client.get(&url).bearer_auth("let-me-in")).await?,
+        200
+    );
+    assert_eq!(
+        status(client.get(format!("{}?token=let-me-in", url))).await?,
+        200
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn the_event_stream_needs_no_signature() -> anyhow::Result<()> {
+    let start = |token: Option<&str>| {
+        let auth = WebhookAuth {
+            token: token.map(str::to_string),
+            secret: Some("shh".to_string()),
+            ..Default::default()
+        };
+        let limits = WebhookLimits {
+            rate_limit: 2,
+            ..WebhookLimits::from_config(&Config::default())
+        };
+        WebhookSource::new(
+            "127.0.0.1",
+            None,
+            auth,
+            limits,
+            HttpSettings::default(),
+            None,
+        )
+    };
+    let client = reqwest::Client::new();
+    let status = |request: reqwest::RequestBuilder| async move {
+        anyhow::Ok(request.send().await?.status().as_u16())
+    };
+
+    // The browser's token is enough for the stream, though prompts must be signed
+    let source = start(Some("let-me-in")).await?;
+    let url = format!("http://{}/events?token=let-me-in", source.addr());
+    assert_eq!(status(client.get(&url)).await?, 200);
+    let prompt = client
+        .post(format!("http://{}/", source.addr()))
+        .bearer_auth("let-me-in")
+        .json(&serde_json::json!({ "message": "hi" }));
+    assert_eq!(status(prompt).await?, 401);
+    // The stream shares the prompts' rate limit
+    assert_eq!(status(client.get(&url)).await?, 429);
+
+    // With only a secret there's no token to give, so the stream stays closed
+    let source = start(None).await?;
+    let url = format!("http://{}/events", source.addr());
+    assert_eq!(status(client.get(&url)).await?, 401);
+    Ok(())
+}
+
+#[tokio::test]
+async fn events_name_the_session_they_answer() -> anyhow::Result<()> {
+    let source = WebhookSource::new(
+        "127.0.0.1",
+        None,
+        WebhookAuth::default(),
+        WebhookLimits::from_config(&Config::default()),
+        HttpSettings::default(),
+        None,
+    )
+    .await?;
+    let base = format!("http://{}", source.addr());
+    let mut config = Config::default();
+    config.inputs_vec.clear();
+    config.outputs_vec.clear();
+    let (shutdown, _) = tokio::sync::broadcast::channel(1);
+    let agent = Agent::builder()
+        .config(config)
+        .provider(MockProvider::new())
+        .shutdown(shutdown.clone())
+        .admin(false)
+        .input(source)
+        .output(SseDestination::new())
+        .build()?;
+    let agent = tokio::spawn(agent.run());
+
+    let client = reqwest::Client::new();
+    let mut events = client.get(format!("{}/events", base)).send().await?;
+    for (session, prompt) in [("tab-1", "one"), ("tab-2", "two")] {
+        let body = json!({ "message": prompt, "session_id": session });
+        client
+            .post(&base)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+    }
+
+    // Each answer carries the session of the prompt it answers
+    let mut answers = Vec::new();
+    let mut buffer = String::new();
+    while answers.len() < 2 {
+        let chunk = tokio::time::timeout(WAIT, events.chunk())
+            .await?
+            .map_err(anyhow::Error::from)?
+            .expect("the stream stays open");
+        buffer.push_str(&String::from_utf8_lossy(&chunk));
+        while let Some(end) = buffer.find("\n\n") {
+            let event: String = buffer.drain(..end + 2).collect();
+            if !event.contains("event: assistant") {
+                continue;
+            }
+            let data = event
+                .lines()
+                .find_map(|line| line.strip_prefix("data: "))
+                .expect("the event has data");
+            let data: serde_json::Value = serde_json::from_str(data)?;
+            answers.push((data["session_id"].clone(), data["content"].clone()));
+        }
+    }
+    assert_eq!(
+        answers,
+        [
+            (json!("tab-1"), json!("echo: one")),
+            (json!("tab-2"), json!("echo: two")),
+        ]
+    );
+
+    let _ = shutdown.send(());
+    tokio::time::timeout(WAIT, agent).await???;
+    Ok(())
+}
+
+// A webhook receiver answering with `statuses` in turn, then with 200
+async fn receiver(statuses: &[u16]) -> anyhow::Result<String> {
+    let statuses = Arc::new(Mutex::new(statuses.to_vec()));
+    let app = axum::Router::new().route(
+        "/",
+        axum::routing::post(move || {
+            let statuses = statuses.clone();
+            async move {
+                let mut statuses = statuses.lock().unwrap();
+                let status = if statuses.is_empty() {
+                    200
+                } else {
+                    statuses.remove(0)
+                };
+                axum::http::StatusCode::from_u16(status).unwrap()
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/", listener.local_addr()?);
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    Ok(url)
+}
+
+#[tokio::test]
+async fn error_statuses_fail_the_write() -> anyhow::Result<()> {
+    let output = WebhookDestination::new(&receiver(&[503, 429, 400, 404]).await?)?;
+
+    // Busy or failing receivers are worth trying again, and the rest aren't
+    for retryable in [true, true, false, false] {
+        let error = output.write_message("assistant", "hi").await.unwrap_err();
+        assert_eq!(retry::is_retryable(&error), retryable, "{:#}", error);
+    }
+    output.write_message("assistant", "hi").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn only_retryable_failures_are_kept() -> anyhow::Result<()> {
+    let dir = std::env::temp_dir().join(format!("agent-test-{}-retry", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config {
+        retry_dir: Some(dir.display().to_string()),
+        ..Default::default()
+    };
+    let output = WebhookDestination::new(&receiver(&[400, 503]).await?)?;
+    let output = Isolated::new(&config, "webhook", Box::new(output))?;
+
+    assert!(output.write_message("assistant", "rejected").await.is_err());
+    assert!(output.write_message("assistant", "refused").await.is_err());
+
+    let kept = std::fs::read_to_string(dir.join(format!("{}-webhook.jsonl", config.agent_name)))?;
+    assert_eq!(kept.lines().count(), 1);
+    assert!(kept.contains("refused"), "{}", kept);
+    Ok(())
+}
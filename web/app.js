@@ -0,0 +1,192 @@
+// Chat page for the webhook server: prompts are POSTed to the webhook input and
+// answers arrive as events from the sse output. URLs are relative to the page, so
+// the server can sit behind a reverse proxy path.
+(function () {
+  "use strict";
+
+  const TOKEN_KEY = "agent-chat-token";
+  const SESSION_KEY = "agent-chat-session";
+
+  const messages = document.getElementById("messages");
+  const composer = document.getElementById("composer");
+  const input = document.getElementById("input");
+  const send = document.getElementById("send");
+  const status = document.getElementById("status");
+  const settings = document.getElementById("settings");
+  const token = document.getElementById("token");
+
+  token.value = localStorage.getItem(TOKEN_KEY) || "";
+
+  // Each tab is a conversation of its own, kept across reloads of the tab, and only
+  // shows the answers to its own prompts
+  let session = sessionStorage.getItem(SESSION_KEY);
+  if (!session) {
+    session = crypto.randomUUID
+      ? crypto.randomUUID()
+      : Date.now().toString(36) + Math.random().toString(36).slice(2);
+    sessionStorage.setItem(SESSION_KEY, session);
+  }
+
+  function setStatus(text, kind) {
+    status.textContent = text;
+    status.className = "status" + (kind ? " " + kind : "");
+  }
+
+  function addMessage(role, content) {
+    const element = document.createElement("div");
+    element.className = "message " + role;
+    if (role === "image") {
+      const image = document.createElement("img");
+      image.src = "data:image/png;base64," + content;
+      image.alt = "Generated image";
+      element.appendChild(image);
+    } else {
+      // Text is never parsed as HTML
+      element.textContent = content;
+    }
+    messages.appendChild(element);
+    messages.scrollTop = messages.scrollHeight;
+    return element;
+  }
+
+  function addNotice(text, isError) {
+    addMessage("notice" + (isError ? " error" : ""), text);
+  }
+
+  // Answers. EventSource can't send an Authorization header, so the token goes in
+  // the query instead
+  let events = null;
+  const handlers = {};
+  function connect() {
+    if (events) {
+      events.close();
+    }
+    const url = token.value ? "events?token=" + encodeURIComponent(token.value) : "events";
+    events = new EventSource(url);
+    events.onopen = function () {
+      setStatus("Connected", "connected");
+    };
+    events.onerror = function () {
+      // EventSource reconnects by itself
+      setStatus("Disconnected, retrying…", "error");
+    };
+    Object.keys(handlers).forEach(function (name) {
+      events.addEventListener(name, handlers[name]);
+    });
+  }
+  // A streamed answer grows in place; its assistant event then replaces the text
+  let streaming = null;
+  let streamed = null;
+  // Shown from the typing event until the answer starts arriving
+  let typing = null;
+  function stopTyping() {
+    if (typing) {
+      typing.remove();
+      typing = null;
+    }
+  }
+  function onEvent(name, handle) {
+    handlers[name] = function (event) {
+      let message;
+      try {
+        message = JSON.parse(event.data);
+      } catch (e) {
+        console.error("Invalid event", e);
+        return;
+      }
+      if (message.session_id === session) {
+        handle(message);
+      }
+    };
+  }
+  onEvent("typing", function (message) {
+    stopTyping();
+    typing = addMessage("typing", message.content);
+  });
+  onEvent("chunk", function (message) {
+    stopTyping();
+    if (!streaming) {
+      streaming = addMessage(message.role, "");
+    }
+    streaming.textContent += message.content;
+    messages.scrollTop = messages.scrollHeight;
+  });
+  onEvent("end", function () {
+    streamed = streaming;
+    streaming = null;
+  });
+  onEvent("assistant", function (message) {
+    stopTyping();
+    if (streamed) {
+      streamed.textContent = message.content;
+      streamed = null;
+    } else {
+      addMessage(message.role, message.content);
+    }
+  });
+  onEvent("image", function (message) {
+    stopTyping();
+    addMessage(message.role, message.content);
+  });
+  connect();
+
+  // Prompts
+  async function submit() {
+    const text = input.value.trim();
+    if (!text) {
+      return;
+    }
+
+    const headers = { "Content-Type": "application/json" };
+    if (token.value) {
+      headers["Authorization"] = "Bearer " + token.value;
+    }
+
+    send.disabled = true;
+    try {
+      const response = await fetch(".", {
+        method: "POST",
+        headers: headers,
+        body: JSON.stringify({ message: text, session_id: session }),
+      });
+      if (response.status === 401) {
+        addNotice("The server needs a webhook token; set it with ⚙", true);
+        return;
+      }
+      if (!response.ok) {
+        addNotice("The server rejected the message: HTTP " + response.status, true);
+        return;
+      }
+      addMessage("user", text);
+      input.value = "";
+    } catch (e) {
+      addNotice("Could not reach the server: " + e.message, true);
+    } finally {
+      send.disabled = false;
+      input.focus();
+    }
+  }
+
+  composer.addEventListener("submit", function (event) {
+    event.preventDefault();
+    submit();
+  });
+  input.addEventListener("keydown", function (event) {
+    if (event.key === "Enter" && !event.shiftKey) {
+      event.preventDefault();
+      submit();
+    }
+  });
+
+  document.getElementById("settings-toggle").addEventListener("click", function () {
+    settings.hidden = !settings.hidden;
+  });
+  settings.addEventListener("submit", function (event) {
+    event.preventDefault();
+    localStorage.setItem(TOKEN_KEY, token.value);
+    settings.hidden = true;
+    connect();
+  });
+
+  input.focus();
+})();